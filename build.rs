@@ -0,0 +1,32 @@
+use std::{env, fs, path::Path, process::Command};
+
+fn main() {
+    let commit_hash = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_owned())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=GIT_COMMIT_HASH={}", commit_hash);
+
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR should be set");
+    let lock_file = Path::new(&manifest_dir).join("Cargo.lock");
+    let rocksdb_version = fs::read_to_string(&lock_file)
+        .ok()
+        .and_then(|content| {
+            let mut lines = content.lines();
+            lines.find(|line| *line == r#"name = "ckb-rocksdb""#)?;
+            lines
+                .next()?
+                .strip_prefix("version = ")
+                .map(|version| version.trim_matches('"').to_owned())
+        })
+        .unwrap_or_default();
+    println!("cargo:rustc-env=ROCKSDB_CRATE_VERSION={}", rocksdb_version);
+
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
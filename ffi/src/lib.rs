@@ -0,0 +1,118 @@
+//! The Kotlin and Swift bindings of the CKB light client, they are generated by UniFFI.
+//!
+//! The light client runs in its own thread, and the app talks to it through the RPC on the
+//! `listen_address` in the config. The lifecycle hooks of the app should be forwarded to
+//! [`MobileLightClient::on_background`] and [`MobileLightClient::on_foreground`], since the
+//! app could be killed in the background without any notice.
+
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use ckb_async_runtime::new_global_runtime;
+use ckb_light_client::{Error, LightClientBuilder, RunEnv, Storage};
+use ckb_network::NetworkController;
+use ckb_stop_handler::{broadcast_exit_signals, wait_all_ckb_services_exit};
+use ckb_types::{core::BlockNumber, prelude::*};
+
+uniffi::setup_scaffolding!();
+
+#[derive(Debug, thiserror::Error, uniffi::Error)]
+#[uniffi(flat_error)]
+pub enum LightClientError {
+    #[error("{0}")]
+    Config(String),
+    #[error("{0}")]
+    Runtime(String),
+}
+
+impl From<Error> for LightClientError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Config(_) => Self::Config(err.to_string()),
+            _ => Self::Runtime(err.to_string()),
+        }
+    }
+}
+
+/// A light client which is started in the process of the app.
+///
+/// The exit signals are shared by the whole process, so only one light client could be
+/// started in the lifetime of the process.
+#[derive(uniffi::Object)]
+pub struct MobileLightClient {
+    storage: Storage,
+    network_controller: NetworkController,
+    thread: Mutex<Option<JoinHandle<Result<(), LightClientError>>>>,
+}
+
+#[uniffi::export]
+impl MobileLightClient {
+    /// Starts the light client with the content of the config file.
+    #[uniffi::constructor]
+    pub fn start(config: String) -> Result<Arc<Self>, LightClientError> {
+        let run_env: RunEnv = config.parse().map_err(LightClientError::Config)?;
+        let (started_tx, started_rx) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let (mut handle, mut stop_rx, _runtime) = new_global_runtime();
+            let client = match LightClientBuilder::new(run_env).start(&handle) {
+                Ok(client) => client,
+                Err(err) => {
+                    let _ = started_tx.send(Err(err.into()));
+                    return Ok(());
+                }
+            };
+            let parts = (
+                client.storage().clone(),
+                client.network_controller().clone(),
+            );
+            let _ = started_tx.send(Ok(parts));
+
+            wait_all_ckb_services_exit();
+            client.close_rpc();
+            handle.drop_guard();
+            stop_rx.blocking_recv();
+            client.close().map_err(Into::into)
+        });
+        let (storage, network_controller) = started_rx.recv().map_err(|_| {
+            LightClientError::Runtime("the light client exited unexpectedly".to_owned())
+        })??;
+        Ok(Arc::new(Self {
+            storage,
+            network_controller,
+            thread: Mutex::new(Some(thread)),
+        }))
+    }
+
+    /// Pauses the network and flushes the storage, it should be called when the app enters
+    /// the background.
+    pub fn on_background(&self) -> Result<(), LightClientError> {
+        log::info!("The app enters the background, pause the network");
+        self.network_controller.set_active(false);
+        self.storage.flush().map_err(Into::into)
+    }
+
+    /// Resumes the network, it should be called when the app enters the foreground.
+    pub fn on_foreground(&self) {
+        log::info!("The app enters the foreground, resume the network");
+        self.network_controller.set_active(true);
+    }
+
+    /// The block number of the proved tip.
+    pub fn tip_block_number(&self) -> BlockNumber {
+        self.storage.get_tip_header().raw().number().unpack()
+    }
+
+    /// Stops the light client and waits until the storage is flushed.
+    pub fn stop(&self) -> Result<(), LightClientError> {
+        let thread = self.thread.lock().expect("poisoned").take();
+        if let Some(thread) = thread {
+            broadcast_exit_signals();
+            thread.join().map_err(|_| {
+                LightClientError::Runtime("the light client thread panicked".to_owned())
+            })??;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+//! Append the matched cells, the committed transactions and the reorgs into a JSONL file, so
+//! the external accounting pipelines could tail it instead of polling the RPC.
+//!
+//! The file could be a named pipe as well. The lines are written in a dedicated thread,
+//! since opening a named pipe blocks until a reader is connected, and it's reopened if the
+//! reader is gone. The events are received through a dedicated unbounded channel instead of
+//! the shared chain events, so none of them is lost when the writing falls behind.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write as _,
+    path::PathBuf,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+};
+
+use ckb_jsonrpc_types::{BlockNumber, CellOutput, OutPoint, Uint64};
+use ckb_systemtime::unix_time_as_millis;
+use ckb_types::{packed::Byte32, prelude::*, H256};
+use log::{info, warn};
+use serde_json::json;
+
+use crate::{
+    error::{Error, Result},
+    protocols::{ChainEvent, Peers},
+    storage::{MatchedCell, ScriptType},
+};
+
+pub(crate) struct AuditLog {
+    path: PathBuf,
+    events: mpsc::Receiver<ChainEvent>,
+}
+
+impl AuditLog {
+    /// Subscribes the chain events, it should be created before the network service is
+    /// started, otherwise the earlier events are missed.
+    pub(crate) fn new(path: PathBuf, peers: &Peers) -> Self {
+        let events = peers.subscribe_audit_events();
+        Self { path, events }
+    }
+
+    /// The thread exits after all events are written and the channel of the chain events is
+    /// closed, i.e. the light client is closed, join it to wait for the remaining events.
+    pub(crate) fn start(self) -> Result<JoinHandle<()>> {
+        thread::Builder::new()
+            .name("AuditLog".to_owned())
+            .spawn(move || self.run())
+            .map_err(|err| {
+                let errmsg = format!("failed to start the audit log since {}", err);
+                Error::runtime(errmsg)
+            })
+    }
+
+    fn run(self) {
+        let mut file = None;
+        // Returns an error after all events are received and the channel is closed.
+        while let Ok(event) = self.events.recv() {
+            if let Some(line) = format_event(&event, unix_time_as_millis()) {
+                self.append(&mut file, line);
+            }
+        }
+        info!("AuditLog exits since the light client is closed");
+    }
+
+    fn append(&self, file: &mut Option<File>, mut line: String) {
+        if file.is_none() {
+            match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+            {
+                Ok(opened) => *file = Some(opened),
+                Err(err) => {
+                    warn!(
+                        "failed to open the audit log {} since {}, drop the line: {}",
+                        self.path.display(),
+                        err,
+                        line
+                    );
+                    return;
+                }
+            }
+        }
+        if let Some(opened) = file.as_mut() {
+            // Write the whole line at once, so the readers of a named pipe never see a
+            // partial line.
+            line.push('\n');
+            if let Err(err) = opened.write_all(line.as_bytes()) {
+                warn!(
+                    "failed to write the audit log {} since {}, reopen it",
+                    self.path.display(),
+                    err
+                );
+                *file = None;
+            }
+        }
+    }
+}
+
+/// Formats the event as a JSON line, returns none if the event isn't audited.
+///
+/// The numbers are hex strings as the RPC, and the block context is included, e.g.
+/// `{"block_hash":"0x..","block_number":"0x1","event":"cell_created",..}`.
+pub(crate) fn format_event(event: &ChainEvent, timestamp: u64) -> Option<String> {
+    let value = match event {
+        ChainEvent::CellFound {
+            cell,
+            block_number,
+            block_hash,
+        } => format_cell("cell_created", cell, *block_number, block_hash, timestamp),
+        ChainEvent::CellConsumed {
+            cell,
+            block_number,
+            block_hash,
+        } => format_cell("cell_consumed", cell, *block_number, block_hash, timestamp),
+        ChainEvent::TransactionCommitted {
+            hash,
+            block_number,
+            block_hash,
+        } => json!({
+            "timestamp": Uint64::from(timestamp),
+            "event": "transaction_committed",
+            "tx_hash": Unpack::<H256>::unpack(hash),
+            "block_number": BlockNumber::from(*block_number),
+            "block_hash": Unpack::<H256>::unpack(block_hash),
+        }),
+        // The cells and the transactions of the blocks since `rollback_to` are reverted.
+        ChainEvent::Reorg { rollback_to } => json!({
+            "timestamp": Uint64::from(timestamp),
+            "event": "reorg",
+            "rollback_to": BlockNumber::from(*rollback_to),
+        }),
+        _ => return None,
+    };
+    Some(value.to_string())
+}
+
+fn format_cell(
+    name: &str,
+    cell: &MatchedCell,
+    block_number: u64,
+    block_hash: &Byte32,
+    timestamp: u64,
+) -> serde_json::Value {
+    json!({
+        "timestamp": Uint64::from(timestamp),
+        "event": name,
+        "out_point": OutPoint::from(cell.out_point.clone()),
+        "output": CellOutput::from(cell.output.clone()),
+        "script_type": match cell.script_type {
+            ScriptType::Lock => "lock",
+            ScriptType::Type => "type",
+        },
+        "tx_hash": Unpack::<H256>::unpack(&cell.tx_hash),
+        "block_number": BlockNumber::from(block_number),
+        "block_hash": Unpack::<H256>::unpack(block_hash),
+    })
+}
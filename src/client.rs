@@ -0,0 +1,464 @@
+//! Start the light client in-process, it's how the `run` subcommand starts each instance.
+//!
+//! To shut down, call [`LightClient::stop`] and wait all CKB services exit, then call
+//! [`LightClient::close_rpc`], wait all tasks in the runtime finished, and call
+//! [`LightClient::close`] at last.
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use ckb_async_runtime::Handle;
+use ckb_chain_spec::{consensus::Consensus, ChainSpec};
+use ckb_hash::blake2b_256;
+use ckb_network::{
+    CKBProtocol, CKBProtocolHandler, Flags, NetworkController, NetworkService, NetworkState,
+    SupportProtocols,
+};
+use ckb_pow::Pow;
+use ckb_resource::Resource;
+use ckb_stop_handler::broadcast_exit_signals;
+use ckb_types::H256;
+use jsonrpc_http_server::Server;
+use tokio::sync::broadcast;
+
+use crate::{
+    audit_log::AuditLog,
+    error::{Error, Result},
+    memory::MemoryMonitor,
+    protocols::{
+        ChainEvent, FilterProtocol, LightClientProtocol, Peers, PendingTxs, RelayProtocol,
+        RequestLimits, SyncProtocol, CHECK_POINT_INTERVAL,
+    },
+    reload::ConfigReloader,
+    service::Service,
+    storage::{Storage, TransactionMatcher},
+    trusted_node::TrustedNodeChecker,
+    types::RunEnv,
+    utils,
+};
+
+pub struct LightClientBuilder {
+    run_env: RunEnv,
+    skip_pow: bool,
+    /// The config file and the index of the instance in it, to reload the config.
+    config_file: Option<(PathBuf, usize)>,
+    transaction_matcher: Option<Arc<dyn TransactionMatcher>>,
+}
+
+/// A started light client, its network service, background tasks and RPC server run in the
+/// runtime which it's started in.
+pub struct LightClient {
+    storage: Storage,
+    peers: Arc<Peers>,
+    network_controller: NetworkController,
+    reloader: ConfigReloader,
+    rpc_server: Server,
+    audit_log: Option<JoinHandle<()>>,
+}
+
+impl LightClientBuilder {
+    pub fn new(run_env: RunEnv) -> Self {
+        Self {
+            run_env,
+            skip_pow: false,
+            config_file: None,
+            transaction_matcher: None,
+        }
+    }
+
+    /// Skip the PoW verification, it's only for the dev chains.
+    pub fn skip_pow(mut self, skip_pow: bool) -> Self {
+        self.skip_pow = skip_pow;
+        self
+    }
+
+    /// Reload the settings from the instance at `index` in the config file, when SIGHUP is
+    /// received or the `reload_config` RPC is called.
+    pub fn config_file<P: Into<PathBuf>>(mut self, config_file: P, index: usize) -> Self {
+        self.config_file = Some((config_file.into(), index));
+        self
+    }
+
+    /// Skip the cells of the filter scripts which are created by the transactions that the
+    /// matcher rejects, see [`TransactionMatcher`].
+    pub fn transaction_matcher(mut self, matcher: Arc<dyn TransactionMatcher>) -> Self {
+        self.transaction_matcher = Some(matcher);
+        self
+    }
+
+    /// Opens the storage, then starts the network service, the background tasks and the RPC
+    /// server in the runtime.
+    ///
+    /// If it fails after the storage is marked as running, the marker is cleared, since
+    /// nothing is written into the storage by the services yet.
+    pub fn start(self, handle: &Handle) -> Result<LightClient> {
+        let run_env = self.run_env;
+
+        utils::fs::need_directory(&run_env.network.path)?;
+        run_env.light_client.validate().map_err(Error::config)?;
+
+        let mut storage = Storage::open(&run_env.store.path).map_err(|err| {
+            let errmsg = format!(
+                "failed to open the store {} since {}",
+                run_env.store.path.display(),
+                err
+            );
+            Error::runtime(errmsg)
+        })?;
+        if let Some(matcher) = self.transaction_matcher {
+            storage.set_transaction_matcher(matcher);
+        }
+        let consensus = build_consensus(&run_env, self.skip_pow)?;
+        storage.init_genesis_block(consensus.genesis_block().data());
+        if storage.mark_running() {
+            log::warn!(
+                "The light client wasn't shut down cleanly, verify the storage {}",
+                run_env.store.path.display()
+            );
+            storage.verify_integrity().map_err(|err| {
+                let errmsg = format!(
+                    "the storage is corrupted since {}, run the reset-state subcommand with \
+                    --force to wipe the chain data",
+                    err
+                );
+                Error::runtime(errmsg)
+            })?;
+            if let Some(start_number) = storage.rollback_partially_applied_blocks() {
+                log::warn!(
+                    "The matched blocks from block#{} were applied partially, roll back and \
+                    apply them again",
+                    start_number
+                );
+            }
+        }
+
+        Self::start_services(
+            run_env,
+            self.config_file,
+            storage.clone(),
+            consensus,
+            handle,
+        )
+        .map_err(|err| {
+            storage.clear_running_marker();
+            err
+        })
+    }
+
+    fn start_services(
+        run_env: RunEnv,
+        config_file: Option<(PathBuf, usize)>,
+        storage: Storage,
+        consensus: Consensus,
+        handle: &Handle,
+    ) -> Result<LightClient> {
+        let pending_txs = {
+            let mut pending_txs = PendingTxs::new(run_env.light_client.pending_txs_limit);
+            pending_txs.set_ttl(Duration::from_secs(
+                run_env.light_client.pending_tx_ttl_secs,
+            ));
+            pending_txs.set_eviction(run_env.light_client.pending_txs_eviction);
+            pending_txs.set_max_relay_delay(Duration::from_millis(
+                run_env.light_client.relay_transaction_max_delay_ms,
+            ));
+            Arc::new(RwLock::new(pending_txs))
+        };
+        let max_outbound_peers = run_env.network.max_outbound_peers;
+        let network_state = NetworkState::from_config(run_env.network)
+            .map(|network_state| {
+                Arc::new(network_state.required_flags(
+                    Flags::DISCOVERY
+                        | Flags::SYNC
+                        | Flags::RELAY
+                        | Flags::LIGHT_CLIENT
+                        | Flags::BLOCK_FILTER,
+                ))
+            })
+            .map_err(|err| {
+                let errmsg = format!("failed to initialize network state since {}", err);
+                Error::runtime(errmsg)
+            })?;
+        let required_protocol_ids = vec![
+            SupportProtocols::Sync.protocol_id(),
+            SupportProtocols::LightClient.protocol_id(),
+            SupportProtocols::Filter.protocol_id(),
+        ];
+
+        let peers = Arc::new(Peers::new(
+            max_outbound_peers,
+            CHECK_POINT_INTERVAL,
+            storage.get_last_check_point(),
+            run_env.light_client.min_proved_peers,
+            run_env.light_client.min_block_filter_peers,
+            RequestLimits {
+                per_peer: run_env.light_client.max_outstanding_requests_per_peer,
+                blocks_proof: run_env.light_client.max_outstanding_blocks_proof_requests,
+                txs_proof: run_env.light_client.max_outstanding_txs_proof_requests,
+            },
+        ));
+        peers.set_filter_sync_paused(run_env.light_client.pause_filter_sync);
+        peers.set_max_download_rate(run_env.light_client.max_download_rate);
+        peers.set_memory_soft_limit(run_env.light_client.memory_soft_limit_mb * 1024 * 1024);
+        let audit_log = if let Some(path) = run_env.audit_log.path.clone() {
+            if let Some(dir) = path.parent() {
+                utils::fs::need_directory(dir)?;
+            }
+            Some(AuditLog::new(path, &peers).start()?)
+        } else {
+            None
+        };
+        let sync_protocol = SyncProtocol::new(
+            storage.clone(),
+            Arc::clone(&peers),
+            pending_txs.clone(),
+            &run_env.light_client,
+        );
+        let relay_protocol_v2 = RelayProtocol::new(
+            pending_txs.clone(),
+            Arc::clone(&peers),
+            consensus.clone(),
+            storage.clone(),
+            false,
+            &run_env.light_client,
+        );
+        let relay_protocol_v3 = RelayProtocol::new(
+            pending_txs.clone(),
+            Arc::clone(&peers),
+            consensus.clone(),
+            storage.clone(),
+            true,
+            &run_env.light_client,
+        );
+        let light_client: Box<dyn CKBProtocolHandler> = Box::new(LightClientProtocol::new(
+            storage.clone(),
+            Arc::clone(&peers),
+            consensus.clone(),
+            &run_env.light_client,
+        ));
+        let filter_protocol =
+            FilterProtocol::new(storage.clone(), Arc::clone(&peers), &run_env.light_client);
+
+        let protocols = vec![
+            CKBProtocol::new_with_support_protocol(
+                SupportProtocols::Sync,
+                Box::new(sync_protocol),
+                Arc::clone(&network_state),
+            ),
+            CKBProtocol::new_with_support_protocol(
+                SupportProtocols::RelayV2,
+                Box::new(relay_protocol_v2),
+                Arc::clone(&network_state),
+            ),
+            CKBProtocol::new_with_support_protocol(
+                SupportProtocols::RelayV3,
+                Box::new(relay_protocol_v3),
+                Arc::clone(&network_state),
+            ),
+            CKBProtocol::new_with_support_protocol(
+                SupportProtocols::LightClient,
+                light_client,
+                Arc::clone(&network_state),
+            ),
+            CKBProtocol::new_with_support_protocol(
+                SupportProtocols::Filter,
+                Box::new(filter_protocol),
+                Arc::clone(&network_state),
+            ),
+        ];
+
+        let network_controller = NetworkService::new(
+            Arc::clone(&network_state),
+            protocols,
+            required_protocol_ids,
+            (
+                consensus.identify_name(),
+                clap::crate_version!().to_owned(),
+                Flags::DISCOVERY,
+            ),
+        )
+        .start(handle)
+        .map_err(|err| {
+            let errmsg = format!("failed to start network since {}", err);
+            Error::runtime(errmsg)
+        })?;
+
+        if let Some(rpc_url) = run_env.light_client.trusted_node_rpc_url.clone() {
+            let interval =
+                Duration::from_secs(run_env.light_client.trusted_node_check_interval_secs);
+            TrustedNodeChecker::new(rpc_url, interval, storage.clone(), Arc::clone(&peers))
+                .start(handle);
+        }
+
+        MemoryMonitor::new(
+            storage.clone(),
+            Arc::clone(&peers),
+            Arc::clone(&pending_txs),
+        )
+        .start(handle);
+
+        let service = Service::new(&run_env.rpc);
+        let max_tx_cycles = run_env
+            .light_client
+            .max_tx_verify_cycles
+            .unwrap_or_else(|| consensus.max_block_cycles());
+        let (config_file, index) = config_file.unzip();
+        let reloader = ConfigReloader::new(
+            config_file,
+            index.unwrap_or_default(),
+            Arc::clone(&peers),
+            Arc::clone(&pending_txs),
+        );
+        let rpc_server = service.start(
+            network_controller.clone(),
+            storage.clone(),
+            Arc::clone(&peers),
+            pending_txs,
+            consensus,
+            max_tx_cycles,
+            reloader.clone(),
+        );
+
+        Ok(LightClient {
+            storage,
+            peers,
+            network_controller,
+            reloader,
+            rpc_server,
+            audit_log,
+        })
+    }
+}
+
+impl LightClient {
+    pub fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    pub fn peers(&self) -> &Arc<Peers> {
+        &self.peers
+    }
+
+    pub fn network_controller(&self) -> &NetworkController {
+        &self.network_controller
+    }
+
+    /// Subscribes the events of the chain, e.g. the tip is updated, a cell of the filter
+    /// scripts is found, a pending transaction is committed or a peer is banned.
+    ///
+    /// The slow subscribers lose the oldest events when the channel is full.
+    pub fn subscribe_chain_events(&self) -> broadcast::Receiver<ChainEvent> {
+        self.peers.subscribe_chain_events()
+    }
+
+    pub(crate) fn reloader(&self) -> &ConfigReloader {
+        &self.reloader
+    }
+
+    /// Sends the exit signals to the network service and the background tasks.
+    ///
+    /// The exit signals are shared by all CKB services in the process, so all light clients
+    /// in the process are stopped.
+    pub fn stop(&self) {
+        broadcast_exit_signals();
+    }
+
+    /// Stops accepting the RPC requests, it should be called before waiting the tasks in the
+    /// runtime, since the RPC requests could write the storage.
+    pub fn close_rpc(&self) {
+        self.rpc_server.close_handle().close();
+    }
+
+    /// Flushes the storage and marks it as shut down cleanly, then waits for the audit log
+    /// to write the remaining events. It should be called after all tasks in the runtime are
+    /// finished.
+    pub fn close(self) -> Result<()> {
+        // The prove state is persisted when it's committed, and the prove requests are
+        // persisted when they are sent, so only the data in memory needs to be flushed.
+        self.storage.clear_running_marker();
+        self.storage.flush().map_err(|err| {
+            let errmsg = format!("failed to flush the storage since {}", err);
+            Error::runtime(errmsg)
+        })?;
+        self.peers.close_chain_events();
+        if let Some(audit_log) = self.audit_log {
+            audit_log
+                .join()
+                .map_err(|_| Error::runtime("the audit log thread panicked"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Skip the PoW verification if `skip_pow` is set, it's only for the dev chains.
+pub(crate) fn build_consensus(run_env: &RunEnv, skip_pow: bool) -> Result<Consensus> {
+    let mut chain_spec = load_chain_spec(run_env)?;
+    if skip_pow {
+        chain_spec.pow = Pow::Dummy;
+    }
+    run_env
+        .light_client
+        .apply_hardfork_overrides(&mut chain_spec)
+        .map_err(Error::config)?;
+    chain_spec.build_consensus().map_err(|err| {
+        let errmsg = format!("failed to build consensus since {}", err);
+        Error::config(errmsg)
+    })
+}
+
+/// Loads the chain spec from the bundled specs, a local file or a URL.
+///
+/// The spec from a URL should be pinned by `chain_spec_hash`, which is the blake2b-256 hash
+/// of the content of the spec file. The specs from other places are checked if it's set.
+fn load_chain_spec(run_env: &RunEnv) -> Result<ChainSpec> {
+    let chain = run_env.chain.as_str();
+    if chain.starts_with("https://") || chain.starts_with("http://") {
+        let expected_hash = run_env
+            .chain_spec_hash
+            .as_ref()
+            .ok_or_else(|| Error::config("chain_spec_hash is required when chain is a URL"))?;
+        let content = utils::http::download(chain).map_err(|err| {
+            let errmsg = format!("failed to download the chain spec {} since {}", chain, err);
+            Error::config(errmsg)
+        })?;
+        check_chain_spec_hash(&content, expected_hash)?;
+        // The relative paths of the resources in the spec aren't resolved, since it's not
+        // loaded from a directory, so only the bundled resources should be used in it.
+        return toml::from_slice(&content).map_err(|err| {
+            let errmsg = format!("failed to parse the chain spec {} since {}", chain, err);
+            Error::config(errmsg)
+        });
+    }
+
+    let resource = match chain {
+        "mainnet" => Resource::bundled("specs/mainnet.toml".to_string()),
+        "testnet" => Resource::bundled("specs/testnet.toml".to_string()),
+        path => Resource::file_system(path.into()),
+    };
+    if let Some(expected_hash) = run_env.chain_spec_hash.as_ref() {
+        let content = resource.get().map_err(|err| {
+            let errmsg = format!("failed to read the chain spec {} since {}", chain, err);
+            Error::config(errmsg)
+        })?;
+        check_chain_spec_hash(&content, expected_hash)?;
+    }
+    ChainSpec::load_from(&resource).map_err(|err| {
+        let errmsg = format!("failed to load the chain spec {} since {}", chain, err);
+        Error::config(errmsg)
+    })
+}
+
+fn check_chain_spec_hash(content: &[u8], expected_hash: &H256) -> Result<()> {
+    let actual_hash = H256::from(blake2b_256(content));
+    if &actual_hash != expected_hash {
+        let errmsg = format!(
+            "the hash of the chain spec is {:#x}, but {:#x} is expected",
+            actual_hash, expected_hash
+        );
+        return Err(Error::config(errmsg));
+    }
+    Ok(())
+}
@@ -1,18 +1,77 @@
-use std::{convert::TryFrom, fmt::Display, fs::OpenOptions, io::Read as _, str::FromStr};
+use std::{
+    convert::TryFrom,
+    env,
+    fmt::Display,
+    fs::OpenOptions,
+    io::Read as _,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use ckb_types::core::BlockNumber;
 
 use crate::{
     error::{Error, Result},
-    types::RunEnv,
+    logger,
+    types::{LoggerConfig, MultiRunEnv, RunEnv},
 };
 
 pub(crate) enum AppConfig {
+    Init(InitConfig),
     Run(RunConfig),
+    ResetState(ResetStateConfig),
+    Status(StatusConfig),
+    CheckConfig(CheckConfigConfig),
+    Service(ServiceConfig),
+}
+
+pub(crate) struct InitConfig {
+    pub(crate) chain: String,
+    pub(crate) config_file: PathBuf,
+    pub(crate) data_dir: PathBuf,
+    pub(crate) force: bool,
 }
 
 pub(crate) struct RunConfig {
+    pub(crate) config_file: PathBuf,
+    pub(crate) multi_run_env: MultiRunEnv,
+    /// The RPC URL of the local full node, it's set only in the dev mode.
+    pub(crate) dev_node_rpc_url: Option<String>,
+}
+
+pub(crate) struct ResetStateConfig {
+    pub(crate) run_env: RunEnv,
+    pub(crate) from_block: BlockNumber,
+    /// Wipe the chain data even if the light client wasn't shut down cleanly.
+    pub(crate) force: bool,
+}
+
+pub(crate) struct StatusConfig {
     pub(crate) run_env: RunEnv,
 }
 
+/// The config file is parsed when it's checked, so all problems in it could be reported.
+pub(crate) struct CheckConfigConfig {
+    pub(crate) config_file: PathBuf,
+    pub(crate) data_dir: Option<PathBuf>,
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) enum ServiceConfig {
+    Install {
+        name: String,
+        config_file: PathBuf,
+    },
+    Uninstall {
+        name: String,
+    },
+    /// It's started by the service control manager.
+    Run {
+        name: String,
+        run_config: RunConfig,
+    },
+}
+
 impl AppConfig {
     pub(crate) fn load() -> Result<Self> {
         let yaml = clap::load_yaml!("cli.yaml");
@@ -24,10 +83,27 @@ impl AppConfig {
         Self::try_from(&matches)
     }
 
+    /// Only the `run` subcommand writes the logs as the `[logger]` section in its config
+    /// file, others write the logs into the standard output.
+    pub(crate) fn init_logger(&self) -> Result<()> {
+        match self {
+            Self::Run(cfg)
+            | Self::Service(ServiceConfig::Run {
+                run_config: cfg, ..
+            }) => logger::init(&cfg.multi_run_env.logger),
+            _ => logger::init(&LoggerConfig::default()),
+        }
+    }
+
     pub(crate) fn execute(self) -> Result<()> {
         log::info!("Executing ...");
         match self {
+            Self::Init(cfg) => cfg.execute(),
             Self::Run(cfg) => cfg.execute(),
+            Self::ResetState(cfg) => cfg.execute(),
+            Self::Status(cfg) => cfg.execute(),
+            Self::CheckConfig(cfg) => cfg.execute(),
+            Self::Service(cfg) => cfg.execute(),
         }
     }
 }
@@ -36,41 +112,243 @@ impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for AppConfig {
     type Error = Error;
     fn try_from(matches: &'a clap::ArgMatches) -> Result<Self> {
         match matches.subcommand() {
+            ("init", Some(submatches)) => InitConfig::try_from(submatches).map(AppConfig::Init),
             ("run", Some(submatches)) => RunConfig::try_from(submatches).map(AppConfig::Run),
+            ("reset-state", Some(submatches)) => {
+                ResetStateConfig::try_from(submatches).map(AppConfig::ResetState)
+            }
+            ("status", Some(submatches)) => {
+                StatusConfig::try_from(submatches).map(AppConfig::Status)
+            }
+            ("check-config", Some(submatches)) => {
+                CheckConfigConfig::try_from(submatches).map(AppConfig::CheckConfig)
+            }
+            ("service", Some(submatches)) => {
+                ServiceConfig::try_from(submatches).map(AppConfig::Service)
+            }
             (subcmd, _) => Err(Error::config(format!("subcommand {}", subcmd))),
         }
     }
 }
 
+impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for InitConfig {
+    type Error = Error;
+    fn try_from(matches: &'a clap::ArgMatches) -> Result<Self> {
+        let value_of = |name: &str| {
+            matches
+                .value_of(name)
+                .ok_or_else(|| Error::argument_should_exist(name))
+        };
+        let (config_file, data_dir) = if let Some(profile) = matches.value_of("profile") {
+            // The data directory has a default value, so check the occurrences instead.
+            if matches.occurrences_of("data-dir") > 0 {
+                let errmsg = "--data-dir and --profile should not be set at the same time";
+                return Err(Error::config(errmsg));
+            }
+            let data_dir = profile_dir(profile)?;
+            let config_file = if matches.occurrences_of("config-file") > 0 {
+                value_of("config-file")?.into()
+            } else {
+                data_dir.join("config.toml")
+            };
+            (config_file, data_dir)
+        } else {
+            (
+                value_of("config-file")?.into(),
+                value_of("data-dir")?.into(),
+            )
+        };
+        Ok(Self {
+            chain: value_of("chain")?.to_owned(),
+            config_file,
+            data_dir,
+            force: matches.is_present("force"),
+        })
+    }
+}
+
 impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for RunConfig {
     type Error = Error;
     fn try_from(matches: &'a clap::ArgMatches) -> Result<Self> {
-        let run_env = parse_from_file::<RunEnv>(matches, "config-file")?;
+        let (config_file, multi_run_env) = parse_multi_run_env(matches)?;
+        let dev_node_rpc_url = if matches.is_present("dev") {
+            let url = matches
+                .value_of("dev-node-rpc")
+                .ok_or_else(|| Error::argument_should_exist("dev-node-rpc"))?;
+            Some(url.to_owned())
+        } else {
+            None
+        };
+        Ok(Self {
+            config_file,
+            multi_run_env,
+            dev_node_rpc_url,
+        })
+    }
+}
+
+impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for ResetStateConfig {
+    type Error = Error;
+    fn try_from(matches: &'a clap::ArgMatches) -> Result<Self> {
+        let (_, run_env) = parse_run_env(matches)?;
+        let from_block = matches
+            .value_of("from-block")
+            .ok_or_else(|| Error::argument_should_exist("from-block"))?
+            .parse::<BlockNumber>()
+            .map_err(|err| Error::config(format!("invalid from-block since {}", err)))?;
+        Ok(Self {
+            run_env,
+            from_block,
+            force: matches.is_present("force"),
+        })
+    }
+}
+
+impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for StatusConfig {
+    type Error = Error;
+    fn try_from(matches: &'a clap::ArgMatches) -> Result<Self> {
+        let (_, run_env) = parse_run_env(matches)?;
         Ok(Self { run_env })
     }
 }
 
-fn parse_from_file<T: FromStr>(matches: &clap::ArgMatches, name: &str) -> Result<T>
+impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for CheckConfigConfig {
+    type Error = Error;
+    fn try_from(matches: &'a clap::ArgMatches) -> Result<Self> {
+        let (data_dir, config_file) = data_dir_and_config_file(matches)?;
+        Ok(Self {
+            config_file,
+            data_dir,
+        })
+    }
+}
+
+impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for ServiceConfig {
+    type Error = Error;
+    fn try_from(matches: &'a clap::ArgMatches) -> Result<Self> {
+        let (subcmd, submatches) = matches.subcommand();
+        let submatches = submatches.ok_or_else(|| Error::config("subcommand is required"))?;
+        let name = submatches
+            .value_of("name")
+            .ok_or_else(|| Error::argument_should_exist("name"))?
+            .to_owned();
+        match subcmd {
+            "install" => {
+                // The service is started in the system directory, so the config file should
+                // be found by the absolute path.
+                let config_file = submatches
+                    .value_of("config-file")
+                    .map(PathBuf::from)
+                    .ok_or_else(|| Error::argument_should_exist("config-file"))?
+                    .canonicalize()
+                    .map_err(|err| {
+                        let errmsg = format!("failed to find the config file since {}", err);
+                        Error::config(errmsg)
+                    })?;
+                Ok(Self::Install { name, config_file })
+            }
+            "uninstall" => Ok(Self::Uninstall { name }),
+            "run" => {
+                let mut run_config = RunConfig::try_from(submatches)?;
+                // Resolve the relative paths in the config file, e.g. the store, the network
+                // and the log file, from the directory of the config file.
+                run_config.config_file = run_config.config_file.canonicalize().map_err(|err| {
+                    let errmsg = format!("failed to find the config file since {}", err);
+                    Error::config(errmsg)
+                })?;
+                if let Some(dir) = run_config.config_file.parent() {
+                    env::set_current_dir(dir).map_err(|err| {
+                        let errmsg = format!("failed to enter {} since {}", dir.display(), err);
+                        Error::config(errmsg)
+                    })?;
+                }
+                Ok(Self::Run { name, run_config })
+            }
+            _ => Err(Error::config(format!("subcommand service {}", subcmd))),
+        }
+    }
+}
+
+/// Returns the config file and the parsed config.
+///
+/// If the data directory is set by `--data-dir` or `--profile`, the config file is in it by
+/// default, and the paths of the store and the network are scoped in it.
+fn parse_run_env(matches: &clap::ArgMatches) -> Result<(PathBuf, RunEnv)> {
+    let (data_dir, config_file) = data_dir_and_config_file(matches)?;
+    let mut run_env: RunEnv = read_from_file(&config_file)?;
+    if let Some(dir) = data_dir {
+        scope_in_data_dir(&mut run_env, &dir);
+    }
+    Ok((config_file, run_env))
+}
+
+/// The same as [`parse_run_env`], but the config could have multiple instances.
+///
+/// The data directory could only be set for a single instance.
+fn parse_multi_run_env(matches: &clap::ArgMatches) -> Result<(PathBuf, MultiRunEnv)> {
+    let (data_dir, config_file) = data_dir_and_config_file(matches)?;
+    let mut multi_run_env: MultiRunEnv = read_from_file(&config_file)?;
+    if let Some(dir) = data_dir {
+        if multi_run_env.instances.len() > 1 {
+            let errmsg = "--data-dir and --profile are not allowed for multiple instances";
+            return Err(Error::config(errmsg));
+        }
+        scope_in_data_dir(&mut multi_run_env.instances[0], &dir);
+    }
+    Ok((config_file, multi_run_env))
+}
+
+fn data_dir_and_config_file(matches: &clap::ArgMatches) -> Result<(Option<PathBuf>, PathBuf)> {
+    let data_dir = if let Some(profile) = matches.value_of("profile") {
+        Some(profile_dir(profile)?)
+    } else {
+        matches.value_of("data-dir").map(PathBuf::from)
+    };
+    let config_file = matches
+        .value_of("config-file")
+        .map(PathBuf::from)
+        .or_else(|| data_dir.as_ref().map(|dir| dir.join("config.toml")))
+        .ok_or_else(|| Error::argument_should_exist("config-file"))?;
+    Ok((data_dir, config_file))
+}
+
+pub(crate) fn scope_in_data_dir(run_env: &mut RunEnv, dir: &Path) {
+    run_env.store.path = dir.join("store");
+    run_env.network.path = dir.join("network");
+}
+
+/// The data directory of the profile, it's `~/.ckb-light-client/<profile>`.
+fn profile_dir(profile: &str) -> Result<PathBuf> {
+    if profile.is_empty()
+        || !profile
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        let errmsg = format!(
+            "invalid profile \"{}\", only letters, digits, '-' and '_' are allowed",
+            profile
+        );
+        return Err(Error::config(errmsg));
+    }
+    env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join(".ckb-light-client").join(profile))
+        .ok_or_else(|| Error::config("HOME is not set, use --data-dir instead of --profile"))
+}
+
+pub(crate) fn read_from_file<T: FromStr>(file: &Path) -> Result<T>
 where
     <T as FromStr>::Err: Display,
 {
-    matches
-        .value_of(name)
-        .map(|file| {
-            OpenOptions::new()
-                .read(true)
-                .open(file)
-                .map_err(|err| Error::config(format!("failed to open {} since {}", file, err)))
-                .and_then(|mut f| {
-                    let mut buffer = String::new();
-                    f.read_to_string(&mut buffer)
-                        .map_err(|err| {
-                            Error::config(format!("failed to read {} since {}", file, err))
-                        })
-                        .map(|_| buffer)
-                })
-                .and_then(|data| T::from_str(&data).map_err(Error::config))
+    let file = file.display();
+    OpenOptions::new()
+        .read(true)
+        .open(file.to_string())
+        .map_err(|err| Error::config(format!("failed to open {} since {}", file, err)))
+        .and_then(|mut f| {
+            let mut buffer = String::new();
+            f.read_to_string(&mut buffer)
+                .map_err(|err| Error::config(format!("failed to read {} since {}", file, err)))
+                .map(|_| buffer)
         })
-        .transpose()?
-        .ok_or_else(|| Error::argument_should_exist(name))
+        .and_then(|data| T::from_str(&data).map_err(Error::config))
 }
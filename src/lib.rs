@@ -0,0 +1,58 @@
+//! The CKB light client, it could be run as the binary `ckb-light-client`, or be embedded in
+//! other applications by [`LightClientBuilder`].
+
+#![allow(clippy::mutable_key_type)]
+
+#[cfg(test)]
+#[macro_use]
+mod tests;
+
+mod audit_log;
+mod client;
+mod config;
+mod error;
+mod logger;
+mod memory;
+mod protocols;
+mod reload;
+mod service;
+mod storage;
+mod subcmds;
+#[cfg(unix)]
+mod systemd;
+mod telemetry;
+#[cfg(any(test, feature = "test-support"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod test_support;
+mod trusted_node;
+mod types;
+mod utils;
+pub mod verify;
+#[cfg(windows)]
+mod win_service;
+
+pub use client::{LightClient, LightClientBuilder};
+pub use error::{Error, Result};
+#[cfg(not(feature = "jemalloc"))]
+pub use memory::CountingAllocator;
+pub use protocols::{ChainEvent, Peers};
+pub use storage::{MatchedCell, ScriptType, Storage, TransactionMatcher};
+pub use types::RunEnv;
+
+use config::AppConfig;
+
+/// Parses the command line arguments and executes the subcommand, it's the entry of the
+/// binary.
+#[doc(hidden)]
+pub fn run_cli() -> anyhow::Result<()> {
+    let config = AppConfig::load()?;
+    config.init_logger()?;
+
+    log::info!("Starting ...");
+
+    config.execute()?;
+
+    log::info!("Done.");
+
+    Ok(())
+}
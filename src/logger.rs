@@ -0,0 +1,326 @@
+//! Initialize the logger with the `[logger]` section of the config file.
+
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    result::Result as StdResult,
+    str::FromStr as _,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use ckb_systemtime::unix_time_as_millis;
+use env_logger::{
+    filter::{self, Filter},
+    Builder, Logger, Target,
+};
+use log::{
+    kv::{self, Key, Source, VisitSource},
+    Level, LevelFilter, Log, Metadata, Record,
+};
+
+use crate::{
+    error::{Error, Result},
+    types::{LogFormat, LoggerConfig},
+};
+
+/// The filter of the installed logger, it could be replaced by [`reload_filter`].
+static FILTER: RwLock<Option<Filter>> = RwLock::new(None);
+
+pub(crate) fn init(config: &LoggerConfig) -> Result<()> {
+    let filter = build_filter(config)?;
+    // All records are filtered by `FILTER` before they are passed to the inner logger.
+    let mut builder = Builder::new();
+    builder.filter_level(LevelFilter::Trace);
+
+    if config.format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = format_json(
+                &buf.timestamp_millis().to_string(),
+                record.level(),
+                record.module_path().unwrap_or_else(|| record.target()),
+                &record.args().to_string(),
+                record.key_values(),
+            );
+            writeln!(buf, "{}", line)
+        });
+    }
+
+    if let Some(path) = &config.file {
+        let file = RotatingFile::open(
+            path,
+            config.max_file_size_mb * 1024 * 1024,
+            Duration::from_secs(config.rotate_interval_secs),
+            config.max_rotated_files,
+        )
+        .map_err(|err| {
+            let errmsg = format!(
+                "failed to open the log file {} since {}",
+                path.display(),
+                err
+            );
+            Error::config(errmsg)
+        })?;
+        let writer = LogWriter {
+            stdout: config.log_to_stdout,
+            file,
+        };
+        builder.target(Target::Pipe(Box::new(writer)));
+    } else if config.log_to_stdout {
+        builder.target(Target::Stdout);
+    } else {
+        let errmsg = "the logs should be written into the standard output or a file";
+        return Err(Error::config(errmsg));
+    }
+
+    let max_level = filter.filter();
+    *FILTER.write().expect("poisoned") = Some(filter);
+    let logger = ReloadableLogger {
+        inner: builder.build(),
+    };
+    log::set_boxed_logger(Box::new(logger)).map_err(|err| {
+        let errmsg = format!("failed to initialize the logger since {}", err);
+        Error::runtime(errmsg)
+    })?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// Replaces the filter of the installed logger with the `filter` and the `modules` in the
+/// config, other settings of the logger can't be changed without restarting.
+pub(crate) fn reload_filter(config: &LoggerConfig) -> Result<()> {
+    let filter = build_filter(config)?;
+    let max_level = filter.filter();
+    *FILTER.write().expect("poisoned") = Some(filter);
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+fn build_filter(config: &LoggerConfig) -> Result<Filter> {
+    let mut builder = filter::Builder::new();
+    if let Some(filter) = env::var("RUST_LOG").ok().or_else(|| config.filter.clone()) {
+        builder.parse(&filter);
+    }
+    for (module, level) in &config.modules {
+        let level = LevelFilter::from_str(level).map_err(|_| {
+            let errmsg = format!("invalid log level \"{}\" for module {}", level, module);
+            Error::config(errmsg)
+        })?;
+        builder.filter_module(module, level);
+    }
+    Ok(builder.build())
+}
+
+struct ReloadableLogger {
+    inner: Logger,
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        FILTER
+            .read()
+            .expect("poisoned")
+            .as_ref()
+            .map(|filter| filter.enabled(metadata))
+            .unwrap_or(false)
+    }
+
+    fn log(&self, record: &Record) {
+        let matched = FILTER
+            .read()
+            .expect("poisoned")
+            .as_ref()
+            .map(|filter| filter.matches(record))
+            .unwrap_or(false);
+        if matched {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Formats a log record as a JSON line.
+///
+/// The key-value fields of the record are added as the fields of the line, e.g. the peer index
+/// and the block number in `warn!(peer_index = 3, block_number = 1024; "...")`.
+pub(crate) fn format_json(
+    timestamp: &str,
+    level: Level,
+    module: &str,
+    message: &str,
+    key_values: &dyn Source,
+) -> serde_json::Value {
+    let mut line = serde_json::json!({
+        "timestamp": timestamp,
+        "level": level.to_string(),
+        "module": module,
+        "message": message,
+    });
+    if let serde_json::Value::Object(fields) = &mut line {
+        // A field can't be written, so there is no error to handle.
+        let _ = key_values.visit(&mut JsonFields(fields));
+    }
+    line
+}
+
+/// Collects the key-value fields of a log record, the numbers are kept as numbers.
+struct JsonFields<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> StdResult<(), kv::Error> {
+        let value: serde_json::Value = value
+            .to_u64()
+            .map(Into::into)
+            .unwrap_or_else(|| value.to_string().into());
+        // The fixed fields can't be overridden.
+        self.0.entry(key.as_str().to_owned()).or_insert(value);
+        Ok(())
+    }
+}
+
+/// Writes the logs into the log file, and into the standard output optionally.
+struct LogWriter {
+    stdout: bool,
+    file: RotatingFile,
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.stdout {
+            io::stdout().write_all(buf)?;
+        }
+        self.file.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.stdout {
+            io::stdout().flush()?;
+        }
+        self.file.flush()
+    }
+}
+
+/// A log file which is rotated by its size or by the time since it's opened.
+///
+/// The rotated files are renamed with the suffix of the rotation time (in milliseconds), e.g.
+/// `run.log.1700000000000`, and the oldest ones are removed if there are too many.
+pub(crate) struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened_at: Instant,
+    max_size: u64,
+    interval: Duration,
+    max_rotated_files: usize,
+}
+
+impl RotatingFile {
+    /// Zero `max_size`, `interval` or `max_rotated_files` disables the corresponding limit.
+    pub(crate) fn open<P: AsRef<Path>>(
+        path: P,
+        max_size: u64,
+        interval: Duration,
+        max_rotated_files: usize,
+    ) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = Self::open_file(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            opened_at: Instant::now(),
+            max_size,
+            interval,
+            max_rotated_files,
+        })
+    }
+
+    fn open_file(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn should_rotate(&self, incoming: usize) -> bool {
+        (self.max_size != 0 && self.size != 0 && self.size + incoming as u64 > self.max_size)
+            || (!self.interval.is_zero() && self.opened_at.elapsed() >= self.interval)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(format!(".{}", unix_time_as_millis()));
+        fs::rename(&self.path, rotated)?;
+        self.file = Self::open_file(&self.path)?;
+        self.remove_old_files()
+    }
+
+    /// Returns the rotated files, the oldest first.
+    pub(crate) fn rotated_files(&self) -> io::Result<Vec<PathBuf>> {
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let prefix = format!(
+            "{}.",
+            self.path
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_default()
+        );
+        let mut rotated = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(timestamp) = name
+                .strip_prefix(&prefix)
+                .and_then(|suffix| suffix.parse::<u64>().ok())
+            {
+                rotated.push((timestamp, entry.path()));
+            }
+        }
+        rotated.sort();
+        Ok(rotated.into_iter().map(|(_, path)| path).collect())
+    }
+
+    fn remove_old_files(&self) -> io::Result<()> {
+        if self.max_rotated_files == 0 {
+            return Ok(());
+        }
+        let rotated = self.rotated_files()?;
+        let count = rotated.len().saturating_sub(self.max_rotated_files);
+        for path in rotated.into_iter().take(count) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.should_rotate(buf.len()) {
+            // Keep writing into the current file if it fails to rotate, and try again
+            // after the next period.
+            if let Err(err) = self.rotate() {
+                eprintln!("failed to rotate the log file since {}", err);
+            }
+            self.size = 0;
+            self.opened_at = Instant::now();
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -1,34 +1,11 @@
-#![allow(clippy::mutable_key_type)]
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static ALLOCATOR: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
-#[cfg(test)]
-#[macro_use]
-mod tests;
-
-mod config;
-mod error;
-mod protocols;
-mod service;
-mod storage;
-mod subcmds;
-mod types;
-mod utils;
-mod verify;
-
-use config::AppConfig;
-use env_logger::{Builder, Env, Target};
+#[cfg(not(feature = "jemalloc"))]
+#[global_allocator]
+static ALLOCATOR: ckb_light_client::CountingAllocator = ckb_light_client::CountingAllocator;
 
 fn main() -> anyhow::Result<()> {
-    let mut builder = Builder::from_env(Env::default());
-    builder.target(Target::Stdout);
-    builder
-        .try_init()
-        .expect("env_logger builder init should be ok");
-
-    log::info!("Starting ...");
-
-    AppConfig::load()?.execute()?;
-
-    log::info!("Done.");
-
-    Ok(())
+    ckb_light_client::run_cli()
 }
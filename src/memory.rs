@@ -0,0 +1,213 @@
+//! Account the memory of the process, and relieve the memory pressure when it exceeds the
+//! soft limit.
+//!
+//! The heap of the light client is counted by the global allocator, it's jemalloc if the
+//! feature `jemalloc` is enabled, otherwise it's [`CountingAllocator`]. They are installed by
+//! the binary, so the applications which embed the light client could use their own.
+//! RocksDB allocates its memory by itself, so the resident memory of the process is used for
+//! the soft limit if it's available.
+
+#[cfg(not(feature = "jemalloc"))]
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use ckb_async_runtime::Handle;
+use ckb_stop_handler::new_tokio_exit_rx;
+use log::{info, warn};
+
+use crate::{
+    protocols::{Peers, PendingTxs},
+    storage::Storage,
+};
+
+/// Check the memory in this interval.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// The unit tests don't run in the binary.
+#[cfg(all(test, feature = "jemalloc"))]
+#[global_allocator]
+static ALLOCATOR: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[cfg(all(test, not(feature = "jemalloc")))]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[cfg(not(feature = "jemalloc"))]
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator which counts the allocated bytes.
+#[cfg(not(feature = "jemalloc"))]
+pub struct CountingAllocator;
+
+#[cfg(not(feature = "jemalloc"))]
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+/// The bytes which are allocated by the global allocator.
+#[cfg(not(feature = "jemalloc"))]
+pub(crate) fn allocated() -> u64 {
+    ALLOCATED.load(Ordering::Relaxed) as u64
+}
+
+/// The bytes which are allocated by the global allocator.
+#[cfg(feature = "jemalloc")]
+pub(crate) fn allocated() -> u64 {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    // The statistics are cached, refresh them first.
+    if let Err(err) = epoch::advance() {
+        warn!("failed to refresh the jemalloc statistics since {}", err);
+    }
+    stats::allocated::read()
+        .map(|allocated| allocated as u64)
+        .unwrap_or_else(|err| {
+            warn!("failed to read the jemalloc statistics since {}", err);
+            0
+        })
+}
+
+/// The resident memory (in bytes) of the process, it's only available on Linux.
+pub(crate) fn resident() -> Option<u64> {
+    if cfg!(target_os = "linux") {
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmRSS:"))
+            .and_then(|value| value.trim().strip_suffix("kB"))
+            .and_then(|kb| kb.trim().parse::<u64>().ok())
+            .map(|kb| kb * 1024)
+    } else {
+        None
+    }
+}
+
+/// Checks the memory periodically, if it exceeds the soft limit:
+/// - Stop downloading the matched blocks and the block filters ahead.
+/// - Drop the downloaded matched blocks which are not applied yet, they are downloaded
+///   again later.
+/// - Drop the cached cell deps, and the transactions which are only kept in the pending
+///   pool to report their statuses.
+/// - Flush the memtables of the storage.
+///
+/// The downloading is resumed after the heap drops below 90% of the soft limit. The resident
+/// memory isn't used for it, since the allocator and RocksDB keep the freed pages, it may
+/// never drop, and the downloading would be paused forever.
+pub(crate) struct MemoryMonitor {
+    storage: Storage,
+    peers: Arc<Peers>,
+    pending_txs: Arc<RwLock<PendingTxs>>,
+}
+
+impl MemoryMonitor {
+    pub(crate) fn new(
+        storage: Storage,
+        peers: Arc<Peers>,
+        pending_txs: Arc<RwLock<PendingTxs>>,
+    ) -> Self {
+        Self {
+            storage,
+            peers,
+            pending_txs,
+        }
+    }
+
+    pub(crate) fn start(self, handle: &Handle) {
+        let stop_rx = new_tokio_exit_rx();
+        handle.spawn(async move {
+            let mut interval = tokio::time::interval(CHECK_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => self.check(),
+                    _ = stop_rx.cancelled() => {
+                        info!("MemoryMonitor received exit signal, exit now");
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    pub(crate) fn check(&self) {
+        let soft_limit = self.peers.get_memory_soft_limit();
+        if self.peers.is_under_memory_pressure() {
+            let heap = allocated();
+            if soft_limit == 0 || heap < soft_limit / 10 * 9 {
+                self.peers.set_under_memory_pressure(false);
+                info!(
+                    "The heap {} MiB drops below the soft limit, resume downloading the \
+                    matched blocks",
+                    heap / 1024 / 1024
+                );
+            } else {
+                self.relieve();
+            }
+        } else {
+            // If the resident memory is still above the soft limit after resumed, it's paused
+            // again in the next check, so the downloading makes progress in between.
+            let used = resident().unwrap_or_else(allocated);
+            if soft_limit != 0 && used > soft_limit {
+                self.peers.set_under_memory_pressure(true);
+                warn!(
+                    "The memory {} MiB exceeds the soft limit {} MiB, stop downloading the \
+                    matched blocks",
+                    used / 1024 / 1024,
+                    soft_limit / 1024 / 1024
+                );
+                self.relieve();
+            }
+        }
+    }
+
+    fn relieve(&self) {
+        let dropped = self.peers.drop_downloaded_matched_blocks();
+        if dropped > 0 {
+            info!(
+                "Drop {} downloaded matched blocks to relieve the memory pressure",
+                dropped
+            );
+        }
+        self.storage.clear_cached_cell_deps();
+        self.pending_txs
+            .write()
+            .expect("pending_txs lock is poisoned")
+            .shrink();
+        if let Err(err) = self.storage.flush() {
+            warn!("failed to flush the storage since {}", err);
+        }
+    }
+}
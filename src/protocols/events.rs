@@ -0,0 +1,83 @@
+//! Events of the chain, which are emitted by the protocols.
+//!
+//! Subscribe them through `LightClient::subscribe_chain_events` instead of polling
+//! the RPC when the light client is embedded.
+
+use ckb_network::PeerIndex;
+use ckb_types::{core::BlockNumber, packed::Byte32};
+
+use crate::storage::MatchedCell;
+
+// The slow subscribers will lose the oldest events when the channel is full.
+pub(crate) const CHAIN_EVENTS_CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainEvent {
+    /// The proved tip is updated.
+    TipUpdated { number: BlockNumber, hash: Byte32 },
+    /// A reorg happened, the blocks since `rollback_to` are rolled back.
+    Reorg { rollback_to: BlockNumber },
+    /// A matched block is applied into the storage.
+    MatchedBlockApplied { number: BlockNumber, hash: Byte32 },
+    /// A cell of the filter scripts is created in a matched block, it's emitted before the
+    /// `MatchedBlockApplied` of the block.
+    CellFound {
+        cell: MatchedCell,
+        block_number: BlockNumber,
+        block_hash: Byte32,
+    },
+    /// A cell of the filter scripts is consumed in a matched block, it's emitted before the
+    /// `MatchedBlockApplied` of the block.
+    CellConsumed {
+        cell: MatchedCell,
+        block_number: BlockNumber,
+        block_hash: Byte32,
+    },
+    /// A peer is proved for the first time.
+    PeerProved(PeerIndex),
+    /// A peer is disconnected.
+    PeerLost(PeerIndex),
+    /// A peer is banned since it misbehaves.
+    PeerBanned { peer: PeerIndex, reason: String },
+    /// The proved tip falls too far behind the wall-clock time.
+    TipStale { number: BlockNumber, timestamp: u64 },
+    /// A pending transaction is evicted before it's committed, since the pending pool is
+    /// full or it expires.
+    PendingTxEvicted { hash: Byte32 },
+    /// A transaction which is submitted asynchronously passes the verification, and becomes
+    /// pending.
+    TransactionVerified { hash: Byte32 },
+    /// A transaction which is submitted asynchronously is rejected.
+    TransactionRejected { hash: Byte32, reason: String },
+    /// A pending transaction is proposed in a matched block.
+    TransactionProposed {
+        hash: Byte32,
+        block_number: BlockNumber,
+        block_hash: Byte32,
+    },
+    /// A pending transaction is committed in a matched block.
+    TransactionCommitted {
+        hash: Byte32,
+        block_number: BlockNumber,
+        block_hash: Byte32,
+    },
+    /// The proved tip diverges from the header at the same height in the trusted node.
+    TrustedNodeDiverged {
+        number: BlockNumber,
+        hash: Byte32,
+        trusted_hash: Byte32,
+    },
+}
+
+impl ChainEvent {
+    /// Whether the event is written into the audit log, they are never lost.
+    pub(crate) fn is_audited(&self) -> bool {
+        matches!(
+            self,
+            Self::CellFound { .. }
+                | Self::CellConsumed { .. }
+                | Self::TransactionCommitted { .. }
+                | Self::Reorg { .. }
+        )
+    }
+}
@@ -1,17 +1,25 @@
 use super::{components, BAD_MESSAGE_BAN_TIME};
-use crate::protocols::{Peers, Status, StatusCode};
+use crate::protocols::{Peers, Status, StatusCode, BLOCK_FILTERS_BATCH_SIZE};
 use crate::storage::Storage;
+use crate::types::LightClientConfig;
 use crate::utils::network::prove_or_download_matched_blocks;
-use ckb_constant::sync::INIT_BLOCKS_IN_TRANSIT_PER_PEER;
 use ckb_network::{async_trait, bytes::Bytes, CKBProtocolContext, CKBProtocolHandler, PeerIndex};
 use ckb_types::{core::BlockNumber, packed, prelude::*};
 use golomb_coded_set::{GCSFilterReader, SipHasher24Builder, M, P};
 use log::{debug, error, info, log_enabled, trace, warn, Level};
 use rand::seq::SliceRandom as _;
+use rayon::prelude::{
+    IndexedParallelIterator as _, IntoParallelRefIterator as _, ParallelIterator as _,
+};
+use std::collections::{BTreeMap, HashMap};
 use std::io::Cursor;
-use std::sync::RwLock;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex, RwLock,
+};
 use std::time::Instant;
-use std::{sync::Arc, time::Duration};
+use std::{cmp, sync::Arc, time::Duration};
+use tracing::instrument;
 
 pub(crate) const GET_BLOCK_FILTERS_TOKEN: u64 = 0;
 pub(crate) const GET_BLOCK_FILTER_HASHES_TOKEN: u64 = 1;
@@ -22,22 +30,54 @@ pub(crate) const GET_BLOCK_FILTER_HASHES_DURATION: Duration = Duration::from_sec
 pub(crate) const GET_BLOCK_FILTER_CHECK_POINTS_DURATION: Duration = Duration::from_secs(30);
 
 const GET_BLOCK_FILTERS_TIMEOUT: Duration = Duration::from_secs(15);
+// Shrink the lookahead if the block filters which are requested in parallel arrive slower
+// than this, to avoid timeouts.
+const SLOW_BLOCK_FILTERS_RESPONSE: Duration = Duration::from_secs(5);
 
 pub struct FilterProtocol {
     pub(crate) storage: Storage,
     pub(crate) peers: Arc<Peers>,
     pub(crate) last_ask_time: Arc<RwLock<Option<Instant>>>,
+    pub(crate) matched_blocks_in_transit_per_peer: usize,
+    // The max count of block filters which are processed in each `BlockFilters` message.
+    pub(crate) block_filters_batch_size: BlockNumber,
+    // How far ahead of the next block to be filtered the block filters could be requested.
+    block_filters_lookahead: BlockNumber,
+    // The lookahead which is in use, it's adjusted by the latencies and the errors of the
+    // responses, up to the configured one.
+    adaptive_lookahead: AtomicU64,
+    // The following ranges of block filters which are requested from other peers, the key
+    // is the start number.
+    parallel_requests: Mutex<HashMap<BlockNumber, (PeerIndex, Instant)>>,
+    // The outstanding `GetBlockFilters` and `GetBlockFilterHashes` requests of each peer,
+    // the values are the times when they are sent.
+    block_filters_requests: Mutex<HashMap<PeerIndex, Instant>>,
+    block_filter_hashes_requests: Mutex<HashMap<PeerIndex, Instant>>,
+    // The block filters which arrive ahead, they are processed in order.
+    pending_block_filters: Mutex<BTreeMap<BlockNumber, (PeerIndex, packed::BlockFilters)>>,
 }
 
 impl FilterProtocol {
-    pub fn new(storage: Storage, peers: Arc<Peers>) -> Self {
+    pub fn new(storage: Storage, peers: Arc<Peers>, config: &LightClientConfig) -> Self {
         Self {
             storage,
             peers,
             last_ask_time: Arc::new(RwLock::new(None)),
+            matched_blocks_in_transit_per_peer: config.matched_blocks_in_transit_per_peer,
+            block_filters_batch_size: config
+                .block_filters_batch_size
+                .clamp(1, BLOCK_FILTERS_BATCH_SIZE),
+            block_filters_lookahead: config.block_filters_lookahead,
+            adaptive_lookahead: AtomicU64::new(config.block_filters_lookahead),
+            parallel_requests: Default::default(),
+            block_filters_requests: Default::default(),
+            block_filter_hashes_requests: Default::default(),
+            pending_block_filters: Default::default(),
         }
     }
 
+    /// Matches the block filters against the filter scripts, the blocks are matched in
+    /// parallel, since it's CPU-bound when there are a lot of scripts.
     pub fn check_filters_data(
         &self,
         block_filters: packed::BlockFilters,
@@ -48,15 +88,25 @@ impl FilterProtocol {
         let script_hashes = self
             .storage
             .get_scripts_hash(start_number + limit as BlockNumber);
-        block_filters
+        let filters = block_filters
             .filters()
             .into_iter()
             .take(limit)
+            .collect::<Vec<_>>();
+        filters
+            .par_iter()
             .enumerate()
             .filter_map(|(index, block_filter)| {
+                // A script is only filtered in the blocks after its block number, so the
+                // blocks which are filtered again don't match the other scripts.
+                let block_number = start_number + index as BlockNumber;
+                let mut script_hashes = script_hashes
+                    .iter()
+                    .filter(|(number, _)| *number < block_number)
+                    .map(|(_, hash)| hash.as_slice());
                 let mut input = Cursor::new(block_filter.raw_data());
                 if reader
-                    .match_any(&mut input, &mut script_hashes.iter().map(|v| v.as_slice()))
+                    .match_any(&mut input, &mut script_hashes)
                     .expect("GCSFilterReader#match_any should be ok")
                 {
                     let block_hash = block_filters
@@ -132,9 +182,16 @@ impl FilterProtocol {
             .peers
             .get_all_prove_states()
             .iter()
-            .max_by_key(|(_, prove_state)| prove_state.get_last_header().total_difficulty())
+            .filter(|(peer, _)| !self.peers.is_filter_unreliable(peer))
+            // Fail over to the peers whose requests don't time out.
+            .max_by_key(|(peer, prove_state)| {
+                (
+                    prove_state.get_last_header().total_difficulty(),
+                    cmp::Reverse(self.peers.get_filter_timeouts(peer)),
+                )
+            })
         {
-            debug!("found best proved peer {}", peer);
+            debug!(peer_index = peer.value(); "found best proved peer {}", peer);
 
             let mut matched_blocks = self.peers.matched_blocks().write().expect("poisoned");
             if let Some((db_start_number, blocks_count, db_blocks)) =
@@ -157,22 +214,36 @@ impl FilterProtocol {
                         &tip_header,
                         &matched_blocks,
                         nc.as_ref(),
-                        INIT_BLOCKS_IN_TRANSIT_PER_PEER,
+                        self.matched_blocks_in_transit_per_peer,
                     );
                     if could_ask_more {
                         debug!(
+                            peer_index = peer.value();
                             "send get block filters to {}, start_number={}",
                             peer, start_number
                         );
-                        self.send_get_block_filters(nc, *peer, start_number);
+                        self.send_get_block_filters(Arc::clone(&nc), *peer, start_number);
+                        self.send_get_block_filters_to_other_peers(
+                            &nc,
+                            *peer,
+                            start_number,
+                            finalized_check_point_index,
+                        );
                     }
                 }
             } else if self.should_ask(immediately) && could_ask_more {
                 debug!(
+                    peer_index = peer.value();
                     "send get block filters to {}, start_number={}",
                     peer, start_number
                 );
-                self.send_get_block_filters(nc, *peer, start_number);
+                self.send_get_block_filters(Arc::clone(&nc), *peer, start_number);
+                self.send_get_block_filters_to_other_peers(
+                    &nc,
+                    *peer,
+                    start_number,
+                    finalized_check_point_index,
+                );
             } else {
                 trace!("no block filters is required to download");
             }
@@ -181,6 +252,162 @@ impl FilterProtocol {
         }
     }
 
+    /// Splits the following ranges of block filters across other best proved peers, to
+    /// download them in parallel.
+    ///
+    /// The range which starts at `start_number` is requested from `peer` already, each
+    /// following range is requested from another peer, as long as the block filter hashes
+    /// are available to check the block filters in it.
+    pub(crate) fn send_get_block_filters_to_other_peers(
+        &self,
+        nc: &Arc<dyn CKBProtocolContext + Sync>,
+        peer: PeerIndex,
+        start_number: BlockNumber,
+        finalized_check_point_index: u32,
+    ) {
+        if self.peers.is_filter_sync_paused()
+            || !self.peers.could_download()
+            || self.peers.is_under_memory_pressure()
+        {
+            return;
+        }
+        let tip_header = self.storage.get_tip_header();
+        let mut other_peers = self
+            .peers
+            .get_best_filter_peers(&tip_header)
+            .into_iter()
+            .filter(|other| *other != peer)
+            .collect::<Vec<_>>();
+        if other_peers.is_empty() {
+            return;
+        }
+        other_peers.shuffle(&mut rand::thread_rng());
+        let mut other_peers = other_peers.into_iter();
+
+        let now = Instant::now();
+        let mut parallel_requests = self.parallel_requests.lock().expect("poisoned");
+        let mut has_timeout = false;
+        parallel_requests.retain(|number, (_, when)| {
+            let is_timeout = now.duration_since(*when) >= GET_BLOCK_FILTERS_TIMEOUT;
+            has_timeout |= is_timeout;
+            *number > start_number && !is_timeout
+        });
+        if has_timeout {
+            self.decrease_block_filters_lookahead();
+        }
+        let lookahead = self.get_block_filters_lookahead();
+        let pending_block_filters = self.pending_block_filters.lock().expect("poisoned");
+        let mut range_start = start_number;
+        loop {
+            range_start += self.block_filters_batch_size;
+            if range_start - start_number > lookahead {
+                break;
+            }
+            if !self
+                .peers
+                .could_request_more_block_filters(finalized_check_point_index, range_start - 1)
+            {
+                break;
+            }
+            if parallel_requests.contains_key(&range_start)
+                || pending_block_filters.contains_key(&range_start)
+            {
+                continue;
+            }
+            if let Some(other) = other_peers.next() {
+                debug!(
+                    "send get block filters to {} in parallel, start_number={}",
+                    other, range_start
+                );
+                self.send_get_block_filters(Arc::clone(nc), other, range_start);
+                parallel_requests.insert(range_start, (other, now));
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Keeps the block filters which arrive ahead, if they are requested in parallel.
+    pub(crate) fn add_pending_block_filters(
+        &self,
+        peer: PeerIndex,
+        block_filters: packed::BlockFilters,
+    ) -> bool {
+        let start_number: BlockNumber = block_filters.start_number().unpack();
+        let mut parallel_requests = self.parallel_requests.lock().expect("poisoned");
+        let is_requested = matches!(
+            parallel_requests.get(&start_number),
+            Some((requested_peer, _)) if *requested_peer == peer
+        );
+        if is_requested {
+            if let Some((_, when)) = parallel_requests.remove(&start_number) {
+                if when.elapsed() > SLOW_BLOCK_FILTERS_RESPONSE {
+                    self.decrease_block_filters_lookahead();
+                } else {
+                    self.increase_block_filters_lookahead();
+                }
+            }
+            self.pending_block_filters
+                .lock()
+                .expect("poisoned")
+                .insert(start_number, (peer, block_filters));
+        }
+        is_requested
+    }
+
+    pub(crate) fn get_block_filters_lookahead(&self) -> BlockNumber {
+        self.adaptive_lookahead.load(Ordering::Relaxed)
+    }
+
+    // Request one more range in parallel, since the peers respond fast.
+    fn increase_block_filters_lookahead(&self) {
+        let lookahead = (self.get_block_filters_lookahead() + self.block_filters_batch_size)
+            .min(self.block_filters_lookahead);
+        self.adaptive_lookahead.store(lookahead, Ordering::Relaxed);
+    }
+
+    // Halve the ranges which are requested in parallel, since the peers respond slow or fail.
+    pub(crate) fn decrease_block_filters_lookahead(&self) {
+        let lookahead = self.get_block_filters_lookahead() / 2;
+        debug!("decrease the lookahead of block filters to {}", lookahead);
+        self.adaptive_lookahead.store(lookahead, Ordering::Relaxed);
+    }
+
+    /// Removes the filter requests which time out, and counts the timeouts for the peers.
+    ///
+    /// Returns true if any request times out.
+    pub(crate) fn check_filter_requests_timeout(&self, now: Instant) -> bool {
+        let mut timeout_peers = Vec::new();
+        for requests in [
+            &self.block_filters_requests,
+            &self.block_filter_hashes_requests,
+        ] {
+            requests.lock().expect("poisoned").retain(|peer, when| {
+                let is_timeout = now.duration_since(*when) >= GET_BLOCK_FILTERS_TIMEOUT;
+                if is_timeout {
+                    timeout_peers.push(*peer);
+                }
+                !is_timeout
+            });
+        }
+        for peer in &timeout_peers {
+            debug!(peer_index = peer.value(); "filter request to peer {} is timeout", peer);
+            self.peers.add_filter_timeout(*peer);
+        }
+        !timeout_peers.is_empty()
+    }
+
+    /// Takes the pending block filters which start at the next block to be filtered,
+    /// the outdated ones are removed.
+    pub(crate) fn take_pending_block_filters(
+        &self,
+        start_number: BlockNumber,
+    ) -> Option<(PeerIndex, packed::BlockFilters)> {
+        let mut pending_block_filters = self.pending_block_filters.lock().expect("poisoned");
+        *pending_block_filters = pending_block_filters.split_off(&start_number);
+        pending_block_filters.remove(&start_number)
+    }
+
     pub(crate) fn try_send_get_block_filter_hashes(&self, nc: Arc<dyn CKBProtocolContext + Sync>) {
         let min_filtered_block_number = self.storage.get_min_filtered_block_number();
         self.peers
@@ -196,14 +423,27 @@ impl FilterProtocol {
                 .get_all_proved_check_points()
                 .into_iter()
                 .filter_map(|(peer_index, (cpindex, _check_points))| {
-                    if cpindex >= finalized_check_point_index {
+                    if cpindex >= finalized_check_point_index
+                        && !self.peers.is_filter_unreliable(&peer_index)
+                    {
                         Some(peer_index)
                     } else {
                         None
                     }
                 })
                 .collect::<Vec<_>>();
-            if let Some(peer) = best_peers.choose(&mut rand::thread_rng()).cloned() {
+            // Fail over to the peers whose requests don't time out.
+            let responsive_peers = best_peers
+                .iter()
+                .filter(|peer| self.peers.get_filter_timeouts(peer) == 0)
+                .cloned()
+                .collect::<Vec<_>>();
+            let peer_opt = if responsive_peers.is_empty() {
+                best_peers.choose(&mut rand::thread_rng())
+            } else {
+                responsive_peers.choose(&mut rand::thread_rng())
+            };
+            if let Some(peer) = peer_opt.cloned() {
                 self.send_get_block_filter_hashes(Arc::clone(&nc), peer, start_number);
             }
         } else if cached_check_point_index >= finalized_check_point_index {
@@ -220,6 +460,11 @@ impl FilterProtocol {
 }
 
 impl FilterProtocol {
+    #[instrument(
+        name = "block_filter_message",
+        skip_all,
+        fields(peer = %peer, message = message.item_name())
+    )]
     fn try_process(
         &self,
         nc: Arc<dyn CKBProtocolContext + Sync>,
@@ -246,7 +491,24 @@ impl FilterProtocol {
         peer: PeerIndex,
         start_number: BlockNumber,
     ) {
+        if self.peers.is_filter_sync_paused() {
+            trace!(
+                peer_index = peer.value();
+                "filter sync is paused, don't request block filter from peer {}",
+                peer
+            );
+            return;
+        }
+        if !self.peers.could_download() {
+            trace!(
+                peer_index = peer.value();
+                "download rate exceeds the limit, don't request block filter from peer {}",
+                peer
+            );
+            return;
+        }
         trace!(
+            peer_index = peer.value();
             "request block filter from peer {}, starts at {}",
             peer,
             start_number
@@ -257,9 +519,17 @@ impl FilterProtocol {
         let message = packed::BlockFilterMessage::new_builder()
             .set(content)
             .build();
+        self.peers
+            .record_sent_message(peer, nc.protocol_id(), message.as_slice().len());
         if let Err(err) = nc.send_message_to(peer, message.as_bytes()) {
             let error_message = format!("nc.send_message GetBlockFilters, error: {:?}", err);
             error!("{}", error_message);
+        } else {
+            self.block_filters_requests
+                .lock()
+                .expect("poisoned")
+                .entry(peer)
+                .or_insert_with(Instant::now);
         }
     }
 
@@ -269,7 +539,24 @@ impl FilterProtocol {
         peer: PeerIndex,
         start_number: BlockNumber,
     ) {
+        if self.peers.is_filter_sync_paused() {
+            trace!(
+                peer_index = peer.value();
+                "filter sync is paused, don't request block filter hashes from peer {}",
+                peer
+            );
+            return;
+        }
+        if !self.peers.could_download() {
+            trace!(
+                peer_index = peer.value();
+                "download rate exceeds the limit, don't request block filter hashes from peer {}",
+                peer
+            );
+            return;
+        }
         trace!(
+            peer_index = peer.value();
             "request block filter hashes from peer {}, starts at {}",
             peer,
             start_number
@@ -280,9 +567,17 @@ impl FilterProtocol {
         let message = packed::BlockFilterMessage::new_builder()
             .set(content)
             .build();
+        self.peers
+            .record_sent_message(peer, nc.protocol_id(), message.as_slice().len());
         if let Err(err) = nc.send_message_to(peer, message.as_bytes()) {
             let error_message = format!("nc.send_message GetBlockFilterHashes, error: {:?}", err);
             error!("{}", error_message);
+        } else {
+            self.block_filter_hashes_requests
+                .lock()
+                .expect("poisoned")
+                .entry(peer)
+                .or_insert_with(Instant::now);
         }
     }
 
@@ -292,7 +587,16 @@ impl FilterProtocol {
         peer: PeerIndex,
         start_number: BlockNumber,
     ) {
+        if self.peers.is_filter_sync_paused() {
+            trace!(
+                peer_index = peer.value();
+                "filter sync is paused, don't request check points from peer {}",
+                peer
+            );
+            return;
+        }
         trace!(
+            peer_index = peer.value();
             "request check points from peer {}, starts at {}",
             peer,
             start_number
@@ -303,6 +607,8 @@ impl FilterProtocol {
         let message = packed::BlockFilterMessage::new_builder()
             .set(content)
             .build();
+        self.peers
+            .record_sent_message(peer, nc.protocol_id(), message.as_slice().len());
         if let Err(err) = nc.send_message_to(peer, message.as_bytes()) {
             let error_message = format!(
                 "nc.send_message GetBlockFilterCheckPoints, error: {:?}",
@@ -339,11 +645,19 @@ impl CKBProtocolHandler for FilterProtocol {
         peer: PeerIndex,
         version: &str,
     ) {
-        debug!("FilterProtocol({}).connected peer={}", version, peer);
+        debug!(peer_index = peer.value(); "FilterProtocol({}).connected peer={}", version, peer);
     }
 
     async fn disconnected(&mut self, _nc: Arc<dyn CKBProtocolContext + Sync>, peer: PeerIndex) {
-        debug!("FilterProtocol.disconnected peer={}", peer);
+        debug!(peer_index = peer.value(); "FilterProtocol.disconnected peer={}", peer);
+        self.block_filters_requests
+            .lock()
+            .expect("poisoned")
+            .remove(&peer);
+        self.block_filter_hashes_requests
+            .lock()
+            .expect("poisoned")
+            .remove(&peer);
     }
 
     async fn received(
@@ -352,14 +666,20 @@ impl CKBProtocolHandler for FilterProtocol {
         peer: PeerIndex,
         data: Bytes,
     ) {
+        self.peers
+            .record_received_message(peer, nc.protocol_id(), data.len());
         let msg = match packed::BlockFilterMessageReader::from_slice(&data) {
             Ok(msg) => msg.to_enum(),
             _ => {
                 warn!(
+                    peer_index = peer.value();
                     "FilterProtocol.received a malformed message from Peer({})",
                     peer
                 );
-                nc.ban_peer(
+                self.peers
+                    .add_failure(peer, StatusCode::MalformedProtocolMessage);
+                self.peers.ban_peer(
+                    nc.as_ref(),
                     peer,
                     BAD_MESSAGE_BAN_TIME,
                     String::from("send us a malformed message"),
@@ -368,15 +688,59 @@ impl CKBProtocolHandler for FilterProtocol {
             }
         };
 
+        self.peers.record_downloaded_bytes(data.len());
         let item_name = msg.item_name();
+        let is_block_filters =
+            matches!(msg, packed::BlockFilterMessageUnionReader::BlockFilters(_));
+        let is_block_filter_hashes = matches!(
+            msg,
+            packed::BlockFilterMessageUnionReader::BlockFilterHashes(_)
+        );
+        let requests_opt = if is_block_filters {
+            Some(&self.block_filters_requests)
+        } else if is_block_filter_hashes {
+            Some(&self.block_filter_hashes_requests)
+        } else {
+            None
+        };
+        if let Some(requests) = requests_opt {
+            requests.lock().expect("poisoned").remove(&peer);
+        }
         let status = self.try_process(Arc::clone(&nc), peer, msg);
-        status.process(nc, peer, "BlockFilter", item_name);
+        if status.is_ok() {
+            if requests_opt.is_some() {
+                self.peers.reset_filter_timeouts(peer);
+            }
+        } else {
+            self.peers.add_failure(peer, status.code());
+            if is_block_filters {
+                self.decrease_block_filters_lookahead();
+            }
+        }
+        status.process(nc, &self.peers, peer, "BlockFilter", item_name);
     }
 
     async fn notify(&mut self, nc: Arc<dyn CKBProtocolContext + Sync>, token: u64) {
         match token {
             GET_BLOCK_FILTERS_TOKEN => {
-                self.try_send_get_block_filters(nc, false);
+                // Request the block filters from other peers if any request is timeout.
+                let timeout = self.check_filter_requests_timeout(Instant::now());
+                let rescan = self.peers.take_filter_rescan_request();
+                if rescan {
+                    info!(
+                        "filter scripts are changed, request block filters from {}",
+                        self.storage.get_min_filtered_block_number() + 1
+                    );
+                    self.parallel_requests.lock().expect("poisoned").clear();
+                    self.pending_block_filters.lock().expect("poisoned").clear();
+                    self.try_send_get_block_filter_hashes(Arc::clone(&nc));
+                } else if self.peers.is_under_memory_pressure() {
+                    // Drop the block filters which arrive ahead, they are requested again
+                    // after the memory pressure is relieved.
+                    self.parallel_requests.lock().expect("poisoned").clear();
+                    self.pending_block_filters.lock().expect("poisoned").clear();
+                }
+                self.try_send_get_block_filters(nc, rescan || timeout);
             }
             GET_BLOCK_FILTER_HASHES_TOKEN => {
                 self.try_send_get_block_filter_hashes(nc);
@@ -52,6 +52,7 @@ impl<'a> BlockFilterCheckPointsProcess<'a> {
             .collect::<Vec<_>>();
 
         trace!(
+            peer_index = self.peer_index.value();
             "peer {}: last-state: {}, add check points (start: {}, len: {})",
             self.peer_index,
             peer_state,
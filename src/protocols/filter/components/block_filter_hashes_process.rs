@@ -54,6 +54,7 @@ impl<'a> BlockFilterHashesProcess<'a> {
             .collect::<Vec<_>>();
 
         trace!(
+            peer_index = self.peer_index.value();
             "peer {}: last-state: {}, add block filter hashes (start: {}, len: {}) \
             and parent block filter hash is {:#x}",
             self.peer_index,
@@ -1,7 +1,6 @@
 use crate::protocols::FilterProtocol;
 use crate::protocols::{Status, StatusCode};
 use crate::utils::network::prove_or_download_matched_blocks;
-use ckb_constant::sync::INIT_BLOCKS_IN_TRANSIT_PER_PEER;
 use ckb_network::{CKBProtocolContext, PeerIndex};
 use ckb_types::core::BlockNumber;
 use ckb_types::utilities::calc_filter_hash;
@@ -39,7 +38,7 @@ impl<'a> BlockFiltersProcess<'a> {
         }
         let peer_state_opt = self.filter.peers.get_state(&self.peer);
         if peer_state_opt.is_none() {
-            info!("ignoring, peer {} is disconnected", self.peer);
+            info!(peer_index = self.peer.value(); "ignoring, peer {} is disconnected", self.peer);
             return Status::ok();
         }
         let peer_state = peer_state_opt.expect("checked Some");
@@ -50,7 +49,11 @@ impl<'a> BlockFiltersProcess<'a> {
         {
             header.hash()
         } else {
-            warn!("ignoring, peer {} prove state is none", self.peer);
+            warn!(
+                peer_index = self.peer.value();
+                "ignoring, peer {} prove state is none",
+                self.peer
+            );
             return Status::ok();
         };
 
@@ -70,9 +73,27 @@ impl<'a> BlockFiltersProcess<'a> {
             "recieved block filters: start number: {start_number}, \
             filters count: {filters_count}, blocks count: {blocks_count}."
         );
+        if blocks_count > 0 {
+            self.filter.peers.update_filter_downloaded_number(
+                self.peer,
+                start_number + blocks_count as BlockNumber - 1,
+            );
+        }
 
         let min_filtered_block_number = self.filter.storage.get_min_filtered_block_number();
         debug!("current min filtered block number: {min_filtered_block_number}");
+        if start_number > min_filtered_block_number + 1
+            && self
+                .filter
+                .add_pending_block_filters(self.peer, block_filters.clone())
+        {
+            debug!(
+                peer_index = self.peer.value();
+                "keep block filters (start number: {}) from peer {} until previous ones arrive",
+                start_number, self.peer
+            );
+            return Status::ok();
+        }
         if min_filtered_block_number + 1 != start_number {
             info!(
                 "ignoring, the start_number of block_filters message {} is not continuous with min_filtered_block_number: {}",
@@ -97,7 +118,7 @@ impl<'a> BlockFiltersProcess<'a> {
         }
 
         if filters_count == 0 {
-            info!("no new filters, ignore peer: {}", self.peer);
+            info!(peer_index = self.peer.value(); "no new filters, ignore peer: {}", self.peer);
             return Status::ok();
         }
 
@@ -185,7 +206,8 @@ impl<'a> BlockFiltersProcess<'a> {
                 }
             };
 
-        let limit = cmp::min(filters_count, expected_block_filter_hashes.len());
+        let limit = cmp::min(filters_count, expected_block_filter_hashes.len())
+            .min(self.filter.block_filters_batch_size as usize);
 
         for (index, (filter, expected_hash)) in block_filters
             .filters()
@@ -208,9 +230,14 @@ impl<'a> BlockFiltersProcess<'a> {
             parent_block_filter_hash = current_hash;
         }
 
+        let block_hashes = block_filters.block_hashes();
         let possible_match_blocks = self.filter.check_filters_data(block_filters, limit);
         let possible_match_blocks_len = possible_match_blocks.len();
+        self.filter
+            .peers
+            .add_matched_block_sources(self.peer, &possible_match_blocks);
         trace!(
+            peer_index = self.peer.value();
             "peer {}, matched blocks: {}",
             self.peer,
             possible_match_blocks_len
@@ -218,6 +245,9 @@ impl<'a> BlockFiltersProcess<'a> {
         let actual_blocks_count = blocks_count.min(limit);
         let tip_header = self.filter.storage.get_tip_header();
         let filtered_block_number = start_number - 1 + actual_blocks_count as BlockNumber;
+        self.filter
+            .peers
+            .add_filtered_blocks(actual_blocks_count as u64, possible_match_blocks_len as u64);
 
         if possible_match_blocks_len != 0 {
             let blocks = possible_match_blocks
@@ -241,7 +271,7 @@ impl<'a> BlockFiltersProcess<'a> {
                         &tip_header,
                         &matched_blocks,
                         self.nc.as_ref(),
-                        INIT_BLOCKS_IN_TRANSIT_PER_PEER,
+                        self.filter.matched_blocks_in_transit_per_peer,
                     );
                 }
             }
@@ -253,6 +283,40 @@ impl<'a> BlockFiltersProcess<'a> {
 
         self.filter
             .update_min_filtered_block_number(filtered_block_number);
+        if let Some(block_hash) = actual_blocks_count
+            .checked_sub(1)
+            .and_then(|index| block_hashes.get(index))
+        {
+            self.filter
+                .storage
+                .update_filtered_block_hash(filtered_block_number, &block_hash);
+        }
+
+        // Process the block filters which arrive ahead, in order.
+        if let Some((peer, block_filters)) = self
+            .filter
+            .take_pending_block_filters(filtered_block_number + 1)
+        {
+            drop(matched_blocks);
+            let status = BlockFiltersProcess::new(
+                block_filters.as_reader(),
+                self.filter,
+                Arc::clone(&self.nc),
+                peer,
+            )
+            .execute();
+            if !status.is_ok() {
+                self.filter.peers.add_failure(peer, status.code());
+                status.process(
+                    self.nc,
+                    &self.filter.peers,
+                    peer,
+                    "BlockFilter",
+                    "BlockFilters",
+                );
+            }
+            return Status::ok();
+        }
 
         let could_request_more_block_filters = self
             .filter
@@ -263,15 +327,24 @@ impl<'a> BlockFiltersProcess<'a> {
             let best_peer = self
                 .filter
                 .peers
-                .get_best_proved_peers(&tip_header)
+                .get_best_filter_peers(&tip_header)
                 .into_iter()
                 .filter(|peer| *peer != self.peer)
                 .collect::<Vec<_>>()
                 .choose(&mut rand::thread_rng())
                 .cloned()
                 .unwrap_or(self.peer);
-            self.filter
-                .send_get_block_filters(self.nc, best_peer, filtered_block_number + 1);
+            self.filter.send_get_block_filters(
+                Arc::clone(&self.nc),
+                best_peer,
+                filtered_block_number + 1,
+            );
+            self.filter.send_get_block_filters_to_other_peers(
+                &self.nc,
+                best_peer,
+                filtered_block_number + 1,
+                finalized_check_point_index,
+            );
         } else {
             // if couldn't request more block filters,
             // check if could request more block filter hashes.
@@ -8,5 +8,7 @@ mod tests;
 
 pub(crate) use send_blocks_proof::{verify_extra_hash, SendBlocksProofProcess};
 pub(crate) use send_last_state::SendLastStateProcess;
-pub(crate) use send_last_state_proof::{verify_mmr_proof, SendLastStateProofProcess};
+pub(crate) use send_last_state_proof::{
+    check_response_caps, verify_mmr_proof, SendLastStateProofProcess,
+};
 pub(crate) use send_transactions_proof::SendTransactionsProofProcess;
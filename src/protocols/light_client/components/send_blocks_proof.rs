@@ -11,7 +11,7 @@ use rand::seq::SliceRandom;
 use crate::storage::HeaderWithExtension;
 
 use super::{
-    super::{LightClientProtocol, Status, StatusCode},
+    super::{LightClientProtocol, RequestKind, Status, StatusCode},
     verify_mmr_proof,
 };
 
@@ -51,7 +51,11 @@ impl<'a> SendBlocksProofProcess<'a> {
         let original_request = if let Some(original_request) = peer.get_blocks_proof_request() {
             original_request
         } else {
-            error!("peer {} isn't waiting for a proof", self.peer_index);
+            error!(
+                peer_index = self.peer_index.value();
+                "peer {} isn't waiting for a proof",
+                self.peer_index
+            );
             return StatusCode::PeerIsNotOnProcess.into();
         };
 
@@ -73,6 +77,7 @@ impl<'a> SendBlocksProofProcess<'a> {
             } else {
                 // Since the last state is different, then no data should be contained.
                 error!(
+                    peer_index = self.peer_index.value();
                     "peer {} send a proof with different last state",
                     self.peer_index
                 );
@@ -99,7 +104,11 @@ impl<'a> SendBlocksProofProcess<'a> {
             .into_iter()
             .collect::<Vec<_>>();
         if !original_request.check_block_hashes(&received_block_hashes, &missing_block_hashes) {
-            error!("peer {} send an unknown proof", self.peer_index);
+            error!(
+                peer_index = self.peer_index.value();
+                "peer {} send an unknown proof",
+                self.peer_index
+            );
             return StatusCode::UnexpectedResponse.into();
         }
 
@@ -107,6 +116,7 @@ impl<'a> SendBlocksProofProcess<'a> {
         if self.message.headers().is_empty() {
             if !self.message.proof().is_empty() {
                 error!(
+                    peer_index = self.peer_index.value();
                     "peer {} send a proof when all blocks are missing",
                     self.peer_index
                 );
@@ -114,7 +124,7 @@ impl<'a> SendBlocksProofProcess<'a> {
             }
         } else {
             // Check PoW for blocks
-            return_if_failed!(self.protocol.check_pow_for_headers(headers.iter()));
+            return_if_failed!(self.protocol.check_pow_for_headers(&headers));
 
             // Check extra hash for blocks
             let is_v1 = self.message.count_extra_fields() >= 2;
@@ -149,8 +159,11 @@ impl<'a> SendBlocksProofProcess<'a> {
 
             // Get blocks
             if original_request.should_get_blocks() {
-                let block_hashes: Vec<packed::Byte32> =
-                    headers.iter().map(|header| header.hash()).collect();
+                let block_hashes: Vec<packed::Byte32> = headers
+                    .iter()
+                    .map(|header| header.hash())
+                    .filter(|hash| !original_request.is_header_only(hash))
+                    .collect();
                 {
                     let mut matched_blocks = self
                         .protocol
@@ -163,52 +176,65 @@ impl<'a> SendBlocksProofProcess<'a> {
                         .mark_matched_blocks_proved(&mut matched_blocks, &block_hashes);
                 }
 
-                let best_peers: Vec<_> = self
-                    .protocol
-                    .peers
-                    .get_best_proved_peers(&last_header.header().data())
-                    .into_iter()
-                    .filter_map(|peer_index| {
-                        self.protocol
-                            .peers
-                            .get_peer(&peer_index)
-                            .map(|peer| (peer_index, peer))
-                    })
-                    .collect();
-
-                if let Some((peer_index, _)) = best_peers
-                    .iter()
-                    .filter(|(_peer_index, peer)| peer.get_blocks_request().is_none())
-                    .collect::<Vec<_>>()
-                    .choose(&mut rand::thread_rng())
-                {
-                    self.protocol
+                // All blocks could be header-only blocks.
+                if !block_hashes.is_empty() {
+                    let best_peers: Vec<_> = self
+                        .protocol
                         .peers
-                        .update_blocks_request(*peer_index, Some(block_hashes.clone()));
-                    debug!(
-                        "send get blocks request to peer: {}, matched_count: {}",
-                        peer_index,
-                        block_hashes.len()
-                    );
-                    for hashes in
-                        block_hashes.chunks(self.protocol.init_blocks_in_transit_per_peer())
+                        .get_best_proved_peers(&last_header.header().data())
+                        .into_iter()
+                        .filter_map(|peer_index| {
+                            self.protocol
+                                .peers
+                                .get_peer(&peer_index)
+                                .map(|peer| (peer_index, peer))
+                        })
+                        .collect();
+
+                    if let Some((peer_index, _)) = best_peers
+                        .iter()
+                        .filter(|(peer_index, _peer)| {
+                            self.protocol
+                                .peers
+                                .could_request(peer_index, RequestKind::Blocks)
+                        })
+                        .collect::<Vec<_>>()
+                        .choose(&mut rand::thread_rng())
                     {
-                        let content = packed::GetBlocks::new_builder()
-                            .block_hashes(hashes.to_vec().pack())
-                            .build();
-                        let message = packed::SyncMessage::new_builder()
-                            .set(content)
-                            .build()
-                            .as_bytes();
-                        if let Err(err) = self.nc.send_message(
-                            SupportProtocols::Sync.protocol_id(),
-                            *peer_index,
-                            message,
-                        ) {
-                            let error_message =
-                                format!("nc.send_message SyncMessage, error: {:?}", err);
-                            error!("{}", error_message);
-                            return StatusCode::Network.with_context(error_message);
+                        self.protocol
+                            .peers
+                            .update_blocks_request(*peer_index, Some(block_hashes.clone()));
+                        debug!(
+                            peer_index = peer_index.value();
+                            "send get blocks request to peer: {}, matched_count: {}",
+                            peer_index,
+                            block_hashes.len()
+                        );
+                        for hashes in
+                            block_hashes.chunks(self.protocol.init_blocks_in_transit_per_peer())
+                        {
+                            let content = packed::GetBlocks::new_builder()
+                                .block_hashes(hashes.to_vec().pack())
+                                .build();
+                            let message = packed::SyncMessage::new_builder()
+                                .set(content)
+                                .build()
+                                .as_bytes();
+                            self.protocol.peers().record_sent_message(
+                                *peer_index,
+                                SupportProtocols::Sync.protocol_id(),
+                                message.len(),
+                            );
+                            if let Err(err) = self.nc.send_message(
+                                SupportProtocols::Sync.protocol_id(),
+                                *peer_index,
+                                message,
+                            ) {
+                                let error_message =
+                                    format!("nc.send_message SyncMessage, error: {:?}", err);
+                                error!("{}", error_message);
+                                return StatusCode::Network.with_context(error_message);
+                            }
                         }
                     }
                 }
@@ -39,6 +39,7 @@ impl<'a> SendLastStateProcess<'a> {
         if let Some(prev_last_state) = peer_state.get_last_state() {
             if last_state.is_same_as(prev_last_state) {
                 trace!(
+                    peer_index = self.peer_index.value();
                     "peer {}: receive the same last state as previous {}",
                     self.peer_index,
                     last_state,
@@ -47,6 +48,7 @@ impl<'a> SendLastStateProcess<'a> {
                 // so it could be banned after timeout check.
             } else {
                 trace!(
+                    peer_index = self.peer_index.value();
                     "peer {}: update last state from {} to {}",
                     self.peer_index,
                     prev_last_state,
@@ -61,7 +63,11 @@ impl<'a> SendLastStateProcess<'a> {
                 if prev_last_state.total_difficulty() < last_state.total_difficulty() {
                     if let Some(prove_state) = peer_state.get_prove_state() {
                         if prove_state.is_parent_of(&last_state) {
-                            trace!("peer {}: new last state could be trusted", self.peer_index);
+                            trace!(
+                                peer_index = self.peer_index.value();
+                                "peer {}: new last state could be trusted",
+                                self.peer_index
+                            );
                             let last_n_blocks = self.protocol.last_n_blocks() as usize;
                             let child_prove_state =
                                 prove_state.new_child(last_state, last_n_blocks);
@@ -74,6 +80,7 @@ impl<'a> SendLastStateProcess<'a> {
             }
         } else {
             trace!(
+                peer_index = self.peer_index.value();
                 "peer {}: initialize last state {}",
                 self.peer_index,
                 last_state
@@ -88,6 +95,7 @@ impl<'a> SendLastStateProcess<'a> {
                 return_if_failed!(self.protocol.get_last_state_proof(self.nc, self.peer_index));
             if !is_sent {
                 debug!(
+                    peer_index = self.peer_index.value();
                     "peer {} skip sending a request for last state proof",
                     self.peer_index
                 );
@@ -13,10 +13,14 @@ use ckb_types::{
     },
     U256,
 };
-use log::{debug, error, log_enabled, trace, warn, Level};
+use log::{debug, error, info, log_enabled, trace, warn, Level};
+use tracing::instrument;
 
 use super::super::{
-    peers::ProveRequest, prelude::*, LastState, LightClientProtocol, ProveState, Status, StatusCode,
+    constant::{MAX_LAST_STATE_PROOF_HEADERS, MAX_PROOF_ITEMS, SAMPLING_AUDIT_LOG_TARGET},
+    peers::ProveRequest,
+    prelude::*,
+    LastState, LightClientProtocol, ProveState, Status, StatusCode,
 };
 
 pub(crate) struct SendLastStateProofProcess<'a> {
@@ -42,12 +46,22 @@ impl<'a> SendLastStateProofProcess<'a> {
     }
 
     pub(crate) fn execute(self) -> Status {
+        return_if_failed!(check_response_caps(
+            self.message.headers().len(),
+            MAX_LAST_STATE_PROOF_HEADERS,
+            self.message.proof().len(),
+        ));
+
         let peer_state = return_if_failed!(self.protocol.get_peer_state(&self.peer_index));
 
         let original_request = if let Some(original_request) = peer_state.get_prove_request() {
             original_request
         } else {
-            warn!("peer {} isn't waiting for a proof", self.peer_index);
+            warn!(
+                peer_index = self.peer_index.value();
+                "peer {} isn't waiting for a proof",
+                self.peer_index
+            );
             return Status::ok();
         };
 
@@ -63,12 +77,17 @@ impl<'a> SendLastStateProofProcess<'a> {
                     return_if_failed!(self.protocol.get_last_state_proof(self.nc, self.peer_index));
                 if !is_sent {
                     debug!(
+                        peer_index = self.peer_index.value();
                         "peer {} skip sending a request for last state proof",
                         self.peer_index
                     );
                 }
             } else {
-                warn!("peer {} send an unknown proof", self.peer_index);
+                warn!(
+                    peer_index = self.peer_index.value();
+                    "peer {} send an unknown proof",
+                    self.peer_index
+                );
             }
             return Status::ok();
         }
@@ -82,6 +101,7 @@ impl<'a> SendLastStateProofProcess<'a> {
         let last_n_blocks = self.protocol.last_n_blocks() as usize;
 
         trace!(
+            peer_index = self.peer_index.value();
             "peer {}: last_number: {}, last_hash: {:#x}, headers_count: {}, last_n_config: {last_n_blocks}",
             self.peer_index,
             last_header.header().number(),
@@ -102,6 +122,7 @@ impl<'a> SendLastStateProofProcess<'a> {
                 &last_header
             ));
         trace!(
+            peer_index = self.peer_index.value();
             "peer {}: headers count: reorg: {}, sampled: {}, last_n: {}",
             self.peer_index,
             reorg_count,
@@ -112,17 +133,29 @@ impl<'a> SendLastStateProofProcess<'a> {
         // Check chain root for all headers.
         return_if_failed!(self.protocol.check_chain_root_for_headers(headers.iter()));
 
+        let audit_record = if self.protocol.audit_sampling() {
+            let sampled_headers = &headers[reorg_count..(reorg_count + sampled_count)];
+            Some(SamplingAuditRecord::new(
+                original_request.get_content(),
+                sampled_headers,
+                &last_header,
+            ))
+        } else {
+            None
+        };
+
         let headers = headers
             .iter()
             .map(|item| item.header().to_owned())
             .collect::<Vec<_>>();
 
         // Check POW for all headers.
-        return_if_failed!(self.protocol.check_pow_for_headers(headers.iter()));
+        return_if_failed!(self.protocol.check_pow_for_headers(&headers));
 
         // Check tau with epoch difficulties of samples.
-        let failed_to_verify_tau = if original_request.if_skip_check_tau() {
+        let mut failed_to_verify_tau = if original_request.if_skip_check_tau() {
             trace!(
+                peer_index = self.peer_index.value();
                 "peer {} skip checking TAU since the flag is set",
                 self.peer_index
             );
@@ -142,6 +175,7 @@ impl<'a> SendLastStateProofProcess<'a> {
             }
         } else {
             trace!(
+                peer_index = self.peer_index.value();
                 "peer {} skip checking TAU since no sampled headers",
                 self.peer_index
             );
@@ -156,6 +190,26 @@ impl<'a> SendLastStateProofProcess<'a> {
             &headers[(reorg_count + sampled_count)..]
         ));
 
+        // Check the epoch transitions in continuous headers.
+        //
+        // The difficulty adjustments are checked against TAU, unless the check is skipped,
+        // same as the TAU check of the samples.
+        let tau = if original_request.if_skip_check_tau() {
+            None
+        } else {
+            Some(TAU)
+        };
+        if reorg_count != 0 {
+            let is_in_tau =
+                return_if_failed!(check_epoch_transitions(&headers[..reorg_count], tau));
+            failed_to_verify_tau |= !is_in_tau;
+        }
+        let is_in_tau = return_if_failed!(check_epoch_transitions(
+            &headers[(reorg_count + sampled_count)..],
+            tau
+        ));
+        failed_to_verify_tau |= !is_in_tau;
+
         // Verify MMR proof
         return_if_failed!(verify_mmr_proof(
             self.protocol.mmr_activated_epoch(),
@@ -196,6 +250,8 @@ impl<'a> SendLastStateProofProcess<'a> {
                 let mut prove_request =
                     ProveRequest::new(LastState::new(last_header), content.clone());
                 prove_request.skip_check_tau();
+                self.protocol
+                    .persist_prove_request(&peer_state, &prove_request);
                 return_if_failed!(self
                     .protocol
                     .peers()
@@ -204,12 +260,21 @@ impl<'a> SendLastStateProofProcess<'a> {
                 let message = packed::LightClientMessage::new_builder()
                     .set(content)
                     .build();
+                self.protocol.peers().record_sent_message(
+                    self.peer_index,
+                    self.nc.protocol_id(),
+                    message.as_slice().len(),
+                );
                 self.nc.reply(self.peer_index, &message);
 
                 let errmsg = "failed to verify TAU";
                 return StatusCode::RequireRecheck.with_context(errmsg);
             } else {
-                warn!("peer {}, build prove request failed", self.peer_index);
+                warn!(
+                    peer_index = self.peer_index.value();
+                    "peer {}, build prove request failed",
+                    self.peer_index
+                );
             }
         } else {
             let reorg_last_headers = headers[..reorg_count]
@@ -267,10 +332,13 @@ impl<'a> SendLastStateProofProcess<'a> {
                             .map(ToOwned::to_owned)
                             .chain(new_last_headers)
                             .collect::<Vec<_>>();
-                        debug!("peer {}: reorg but no enough blocks, so all headers should be continuous, \
+                        debug!(
+                            peer_index = self.peer_index.value();
+                            "peer {}: reorg but no enough blocks, so all headers should be continuous, \
                             reorg: {reorg_count}, sampled: {sampled_count}, last_n_calculate: {last_n_count}, \
                             last_n_real: {}, last_n_param: {last_n_blocks}, original_request: {original_request}",
-                            self.peer_index, tmp_headers.len()
+                            self.peer_index,
+                            tmp_headers.len()
                         );
                         tmp_headers
                     } else {
@@ -283,6 +351,7 @@ impl<'a> SendLastStateProofProcess<'a> {
                         //
                         // TODO This branch should be unreachable.
                         warn!(
+                            peer_index = self.peer_index.value();
                             "peer {}: no previous prove state but has reorg blocks, \
                             reorg: {reorg_count}, sampled: {sampled_count}, last_n_real: {last_n_count}, \
                             last_n_param: {last_n_blocks}, original_request: {original_request}",
@@ -324,6 +393,8 @@ impl<'a> SendLastStateProofProcess<'a> {
                     let mut prove_request =
                         ProveRequest::new(LastState::new(last_header.clone()), content.clone());
                     prove_request.long_fork_detected();
+                    self.protocol
+                        .persist_prove_request(&peer_state, &prove_request);
                     return_if_failed!(self
                         .protocol
                         .peers()
@@ -332,24 +403,102 @@ impl<'a> SendLastStateProofProcess<'a> {
                     let message = packed::LightClientMessage::new_builder()
                         .set(content)
                         .build();
+                    self.protocol.peers().record_sent_message(
+                        self.peer_index,
+                        self.nc.protocol_id(),
+                        message.as_slice().len(),
+                    );
                     self.nc.reply(self.peer_index, &message);
 
                     let errmsg = "long fork detected";
                     return StatusCode::RequireRecheck.with_context(errmsg);
                 } else {
                     warn!(
+                        peer_index = self.peer_index.value();
                         "peer {}, build prove request from genesis failed",
                         self.peer_index
                     );
                 }
+            } else if let Some(record) = audit_record {
+                info!(
+                    peer_index = self.peer_index.value();
+                    target: SAMPLING_AUDIT_LOG_TARGET,
+                    "peer {}: {}", self.peer_index, record
+                );
             }
         }
 
-        debug!("block proof verify passed for peer: {}", self.peer_index);
+        debug!(
+            peer_index = self.peer_index.value();
+            "block proof verify passed for peer: {}",
+            self.peer_index
+        );
         Status::ok()
     }
 }
 
+// The sampling of a committed proof, which is recorded for auditing.
+//
+// With the requested difficulties and the difficulty boundary, anyone could check
+// whether the sampled headers which are returned by the peer are honest or not.
+pub(crate) struct SamplingAuditRecord {
+    last_number: BlockNumber,
+    last_hash: packed::Byte32,
+    difficulty_boundary: U256,
+    difficulties: Vec<U256>,
+    sampled_headers: Vec<(BlockNumber, packed::Byte32, U256)>,
+}
+
+impl SamplingAuditRecord {
+    pub(crate) fn new(
+        request: &packed::GetLastStateProof,
+        sampled_headers: &[VerifiableHeader],
+        last_header: &VerifiableHeader,
+    ) -> Self {
+        Self {
+            last_number: last_header.header().number(),
+            last_hash: last_header.header().hash(),
+            difficulty_boundary: request.difficulty_boundary().unpack(),
+            difficulties: request
+                .difficulties()
+                .into_iter()
+                .map(|item| item.unpack())
+                .collect(),
+            sampled_headers: sampled_headers
+                .iter()
+                .map(|item| {
+                    let header = item.header();
+                    (header.number(), header.hash(), item.total_difficulty())
+                })
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for SamplingAuditRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "last: {} ({:#x}), boundary: {:#x}, difficulties: [",
+            self.last_number, self.last_hash, self.difficulty_boundary
+        )?;
+        for (i, difficulty) in self.difficulties.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:#x}", difficulty)?;
+        }
+        write!(f, "], sampled: [")?;
+        for (i, (number, hash, total_difficulty)) in self.sampled_headers.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} ({:#x}, {:#x})", number, hash, total_difficulty)?;
+        }
+        write!(f, "]")
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) enum EpochDifficultyTrend {
     Unchanged,
@@ -1075,6 +1224,26 @@ pub(crate) fn verify_total_difficulty(
     Ok(())
 }
 
+// Check the caps of the items in a response before processing it.
+pub(crate) fn check_response_caps(
+    headers_count: usize,
+    headers_limit: usize,
+    proof_items_count: usize,
+) -> Result<(), Status> {
+    if headers_count > headers_limit {
+        let errmsg = format!("headers count {} exceeds {}", headers_count, headers_limit);
+        return Err(StatusCode::ResponseIsTooLarge.with_context(errmsg));
+    }
+    if proof_items_count > MAX_PROOF_ITEMS {
+        let errmsg = format!(
+            "proof items count {} exceeds {}",
+            proof_items_count, MAX_PROOF_ITEMS
+        );
+        return Err(StatusCode::ResponseIsTooLarge.with_context(errmsg));
+    }
+    Ok(())
+}
+
 pub(crate) fn check_continuous_headers(headers: &[HeaderView]) -> Result<(), Status> {
     for pair in headers.windows(2) {
         if !pair[0].is_parent_of(&pair[1]) {
@@ -1095,6 +1264,104 @@ pub(crate) fn check_continuous_headers(headers: &[HeaderView]) -> Result<(), Sta
     Ok(())
 }
 
+// The epoch difficulty is rounded when it's converted to the compact target.
+const COMPACT_TARGET_ROUNDING_DIVISOR: u64 = 1 << 16;
+
+// Check the epoch transitions in continuous headers.
+//
+// The exact difficulty adjustment, as `Consensus::next_epoch_ext` computes it, can't be
+// verified with headers only. It requires the uncles count of the previous epoch, which is
+// kept in the block extensions of the full nodes instead of the headers, and the hash rate
+// of the epoch before it, which is derived from the same data recursively. Neither is
+// proved by the MMR, so only the following rules are checked:
+// - In a same epoch, the epoch length and the compact target are unchanged, and the
+//   epoch index is increased one by one.
+// - At an epoch boundary, the previous epoch should be finished.
+//
+// Returns false if the change of the epoch difficulty at an epoch boundary isn't limited by
+// TAU, it's not checked if TAU isn't provided. Same as `verify_tau`, it's not a proof of an
+// invalid chain, so the caller should ask for a recheck instead of punishing the peer.
+pub(crate) fn check_epoch_transitions(
+    headers: &[HeaderView],
+    tau: Option<u64>,
+) -> Result<bool, Status> {
+    let mut is_in_tau = true;
+    for pair in headers.windows(2) {
+        let (prev, curr) = (pair[0].epoch(), pair[1].epoch());
+        if prev.number() == curr.number() {
+            if prev.length() != curr.length() || prev.index() + 1 != curr.index() {
+                let errmsg = format!(
+                    "failed to verify epoch {:#} for block#{} since its parent's epoch is {:#}",
+                    curr,
+                    pair[1].number(),
+                    prev
+                );
+                return Err(StatusCode::InvalidEpoch.with_context(errmsg));
+            }
+            if pair[0].compact_target() != pair[1].compact_target() {
+                let errmsg = format!(
+                    "failed to verify compact target {:#x} for block#{} since it's changed \
+                    in epoch {}, the compact target of its parent is {:#x}",
+                    pair[1].compact_target(),
+                    pair[1].number(),
+                    curr.number(),
+                    pair[0].compact_target()
+                );
+                return Err(StatusCode::InvalidCompactTarget.with_context(errmsg));
+            }
+        } else {
+            if prev.number() + 1 != curr.number()
+                || prev.index() + 1 != prev.length()
+                || curr.index() != 0
+            {
+                let errmsg = format!(
+                    "failed to verify epoch {:#} for block#{} since its parent's epoch is {:#}",
+                    curr,
+                    pair[1].number(),
+                    prev
+                );
+                return Err(StatusCode::InvalidEpoch.with_context(errmsg));
+            }
+            let tau = if let Some(tau) = tau {
+                tau
+            } else {
+                continue;
+            };
+            let prev_epoch_difficulty =
+                compact_to_difficulty(pair[0].compact_target()) * prev.length();
+            let curr_epoch_difficulty =
+                compact_to_difficulty(pair[1].compact_target()) * curr.length();
+            let is_in_limit =
+                match EpochDifficultyTrend::new(&prev_epoch_difficulty, &curr_epoch_difficulty) {
+                    EpochDifficultyTrend::Unchanged => true,
+                    EpochDifficultyTrend::Increased { start, end } => {
+                        let upper_limit = start * tau;
+                        let tolerance = &upper_limit / COMPACT_TARGET_ROUNDING_DIVISOR;
+                        end <= &upper_limit + &tolerance
+                    }
+                    EpochDifficultyTrend::Decreased { start, end } => {
+                        let lower_limit = start / tau;
+                        let tolerance = &lower_limit / COMPACT_TARGET_ROUNDING_DIVISOR;
+                        end >= &lower_limit - &tolerance
+                    }
+                };
+            if !is_in_limit {
+                debug!(
+                    "the difficulty adjustment at epoch {} (block#{}) exceeds TAU, \
+                    the epoch difficulty is changed from {:#x} to {:#x}",
+                    curr.number(),
+                    pair[1].number(),
+                    prev_epoch_difficulty,
+                    curr_epoch_difficulty
+                );
+                is_in_tau = false;
+            }
+        }
+    }
+    Ok(is_in_tau)
+}
+
+#[instrument(level = "debug", skip_all)]
 pub(crate) fn verify_mmr_proof<'a, T: Iterator<Item = &'a HeaderView>>(
     mmr_activated_epoch: EpochNumber,
     last_header: &VerifiableHeader,
@@ -6,11 +6,14 @@ use ckb_types::{
 };
 use log::{debug, error};
 
-use crate::{protocols::light_client::components::verify_extra_hash, storage::HeaderWithExtension};
+use crate::{
+    protocols::{light_client::components::verify_extra_hash, GET_TRANSACTIONS_PROOF_LIMIT},
+    storage::HeaderWithExtension,
+};
 
 use super::{
     super::{LightClientProtocol, Status, StatusCode},
-    verify_mmr_proof,
+    check_response_caps, verify_mmr_proof,
 };
 
 pub(crate) struct SendTransactionsProofProcess<'a> {
@@ -44,12 +47,23 @@ impl<'a> SendTransactionsProofProcess<'a> {
     }
 
     fn execute_internally(&self) -> Status {
+        // Each transaction is contained in one block.
+        return_if_failed!(check_response_caps(
+            self.message.filtered_blocks().len(),
+            GET_TRANSACTIONS_PROOF_LIMIT,
+            self.message.proof().len(),
+        ));
+
         let peer = return_if_failed!(self.protocol.get_peer(&self.peer_index));
 
         let original_request = if let Some(original_request) = peer.get_txs_proof_request() {
             original_request
         } else {
-            error!("peer {} isn't waiting for a proof", self.peer_index);
+            error!(
+                peer_index = self.peer_index.value();
+                "peer {} isn't waiting for a proof",
+                self.peer_index
+            );
             return StatusCode::PeerIsNotOnProcess.into();
         };
 
@@ -71,6 +85,7 @@ impl<'a> SendTransactionsProofProcess<'a> {
             } else {
                 // Since the last state is different, then no data should be contained.
                 error!(
+                    peer_index = self.peer_index.value();
                     "peer {} send a proof with different last state",
                     self.peer_index
                 );
@@ -101,7 +116,11 @@ impl<'a> SendTransactionsProofProcess<'a> {
             .into_iter()
             .collect::<Vec<_>>();
         if !original_request.check_tx_hashes(&received_tx_hashes, &missing_tx_hashes) {
-            error!("peer {} send an unknown proof", self.peer_index);
+            error!(
+                peer_index = self.peer_index.value();
+                "peer {} send an unknown proof",
+                self.peer_index
+            );
             return StatusCode::UnexpectedResponse.into();
         }
 
@@ -109,6 +128,7 @@ impl<'a> SendTransactionsProofProcess<'a> {
         if self.message.filtered_blocks().is_empty() {
             if !self.message.proof().is_empty() {
                 error!(
+                    peer_index = self.peer_index.value();
                     "peer {} send a proof when all transactions are missing",
                     self.peer_index
                 );
@@ -116,7 +136,7 @@ impl<'a> SendTransactionsProofProcess<'a> {
             }
         } else {
             // Check PoW for blocks
-            return_if_failed!(self.protocol.check_pow_for_headers(headers.iter()));
+            return_if_failed!(self.protocol.check_pow_for_headers(&headers));
 
             // Check extra hash for blocks
             let is_v1 = self.message.count_extra_fields() >= 2;
@@ -201,7 +221,7 @@ impl<'a> SendTransactionsProofProcess<'a> {
         }
         self.protocol
             .peers()
-            .mark_fetching_txs_missing(&missing_tx_hashes);
+            .mark_fetching_txs_missing(self.peer_index, &missing_tx_hashes);
         Status::ok()
     }
 }
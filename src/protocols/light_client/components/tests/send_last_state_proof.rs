@@ -1,12 +1,16 @@
 use ckb_constant::consensus::TAU;
 use ckb_types::{
+    core::{EpochNumberWithFraction, HeaderBuilder, HeaderView},
+    prelude::*,
     utilities::difficulty_to_compact,
     {u256, U256},
 };
 
 use super::super::send_last_state_proof::{
-    verify_tau, verify_total_difficulty, EpochDifficultyTrend, EstimatedLimit,
+    check_epoch_transitions, check_response_caps, verify_tau, verify_total_difficulty,
+    EpochDifficultyTrend, EstimatedLimit,
 };
+use crate::protocols::{light_client::constant::MAX_PROOF_ITEMS, StatusCode};
 
 #[test]
 fn test_calculate_tau_exponent() {
@@ -622,3 +626,65 @@ fn test_verify_total_difficulty_during_more_than_two_epochs() {
         }
     }
 }
+
+#[test]
+fn test_check_epoch_transitions() {
+    let build_header = |number: u64, epoch: (u64, u64, u64), difficulty: u64| -> HeaderView {
+        let epoch = EpochNumberWithFraction::new(epoch.0, epoch.1, epoch.2);
+        let compact_target = difficulty_to_compact(U256::from(difficulty));
+        HeaderBuilder::default()
+            .number(number.pack())
+            .epoch(epoch.pack())
+            .compact_target(compact_target.pack())
+            .build()
+    };
+
+    let headers = vec![
+        build_header(100, (1, 98, 100), 0x1000),
+        build_header(101, (1, 99, 100), 0x1000),
+        build_header(102, (2, 0, 100), 0x2000),
+        build_header(103, (2, 1, 100), 0x2000),
+    ];
+    assert!(check_epoch_transitions(&headers, Some(TAU)).unwrap());
+
+    // The compact target is changed in a same epoch.
+    let headers = vec![
+        build_header(100, (1, 98, 100), 0x1000),
+        build_header(101, (1, 99, 100), 0x1001),
+    ];
+    let status = check_epoch_transitions(&headers, None).unwrap_err();
+    assert_eq!(status.code(), StatusCode::InvalidCompactTarget);
+
+    // The epoch is switched before the previous epoch finished.
+    let headers = vec![
+        build_header(100, (1, 97, 100), 0x1000),
+        build_header(101, (2, 0, 100), 0x1000),
+    ];
+    let status = check_epoch_transitions(&headers, None).unwrap_err();
+    assert_eq!(status.code(), StatusCode::InvalidEpoch);
+
+    // The epoch difficulty is increased too much, it's not checked if TAU is skipped.
+    let headers = vec![
+        build_header(100, (1, 99, 100), 0x1000),
+        build_header(101, (2, 0, 100), 0x1000 * (TAU + 1)),
+    ];
+    assert!(!check_epoch_transitions(&headers, Some(TAU)).unwrap());
+    assert!(check_epoch_transitions(&headers, None).unwrap());
+
+    // The epoch difficulty is decreased too much.
+    let headers = vec![
+        build_header(100, (1, 99, 100), 0x1000 * (TAU + 1)),
+        build_header(101, (2, 0, 100), 0x1000),
+    ];
+    assert!(!check_epoch_transitions(&headers, Some(TAU)).unwrap());
+    assert!(check_epoch_transitions(&headers, None).unwrap());
+}
+
+#[test]
+fn test_check_response_caps() {
+    assert!(check_response_caps(10, 10, MAX_PROOF_ITEMS).is_ok());
+    for (headers_count, proof_items_count) in [(11, MAX_PROOF_ITEMS), (10, MAX_PROOF_ITEMS + 1)] {
+        let status = check_response_caps(headers_count, 10, proof_items_count).unwrap_err();
+        assert_eq!(status.code(), StatusCode::ResponseIsTooLarge);
+    }
+}
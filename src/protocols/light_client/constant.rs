@@ -1,10 +1,41 @@
 use std::time::Duration;
 
+use crate::protocols::LAST_N_BLOCKS;
+
 pub const REFRESH_PEERS_TOKEN: u64 = 0;
 pub const FETCH_HEADER_TX_TOKEN: u64 = 1;
 // notify token to send GetBlocksProof and GetBlocks for previously timeout requests
 pub const GET_IDLE_BLOCKS_TOKEN: u64 = 2;
+// notify token to request new last states or proofs from the peers which announced new tips
+pub const CHECK_TIP_ANNOUNCEMENTS_TOKEN: u64 = 3;
 
 pub const REFRESH_PEERS_DURATION: Duration = Duration::from_secs(8);
 pub const FETCH_HEADER_TX_DURATION: Duration = Duration::from_secs(3);
 pub const GET_IDLE_BLOCKS_DURATION: Duration = Duration::from_secs(3);
+pub const CHECK_TIP_ANNOUNCEMENTS_DURATION: Duration = Duration::from_secs(1);
+
+// The interval of requesting new last states from peers is adapted between
+// `REFRESH_PEERS_DURATION` and this, base on whether the proved tip advances.
+pub const MAX_REFRESH_LAST_STATE_INTERVAL: Duration = Duration::from_secs(64);
+
+// The caps of the items in responses, to prevent memory exhaustion via oversized responses.
+//
+// The total size of a response isn't checked here, it's limited by the max frame length of
+// the light client protocol, which is enforced by the network before decoding.
+//
+// The estimated samples count is less than 1000 for chains which have billions of blocks,
+// it's capped by this when sampling.
+pub const MAX_SAMPLES_COUNT: usize = 1000;
+// The reorg blocks, the sampled blocks and the last n blocks.
+pub const MAX_LAST_STATE_PROOF_HEADERS: usize = 2 * LAST_N_BLOCKS as usize + MAX_SAMPLES_COUNT;
+pub const MAX_PROOF_ITEMS: usize = 16 * 1024;
+
+// The log target for the records of the sampling of committed proofs.
+pub const SAMPLING_AUDIT_LOG_TARGET: &str = "sampling_audit";
+
+// The count of peers which a request for fetching transactions is sent to.
+pub const FETCH_TXS_FANOUT: usize = 3;
+
+// The negative cache of the headers which failed the verification.
+pub const INVALID_HEADERS_CACHE_SIZE: usize = 1024;
+pub const INVALID_HEADERS_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
@@ -3,15 +3,14 @@
 //! TODO(light-client) More documentation.
 
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use ckb_chain_spec::consensus::Consensus;
-use ckb_constant::{
-    hardfork::{mainnet, testnet},
-    sync::INIT_BLOCKS_IN_TRANSIT_PER_PEER,
-};
+use ckb_constant::hardfork::{mainnet, testnet};
+use ckb_hash::new_blake2b;
 use ckb_network::{
-    async_trait, bytes::Bytes, CKBProtocolContext, CKBProtocolHandler, PeerIndex, SupportProtocols,
+    async_trait, bytes::Bytes, extract_peer_id, CKBProtocolContext, CKBProtocolHandler, PeerIndex,
+    SupportProtocols,
 };
 use ckb_types::{
     core::{BlockNumber, EpochNumber, HeaderView},
@@ -22,7 +21,10 @@ use ckb_types::{
 };
 
 use ckb_systemtime::unix_time_as_millis;
+use linked_hash_map::LinkedHashMap;
 use log::{debug, error, info, log_enabled, trace, warn, Level};
+use rayon::prelude::{IntoParallelRefIterator as _, ParallelIterator as _};
+use tracing::instrument;
 
 mod components;
 pub mod constant;
@@ -37,16 +39,22 @@ pub(crate) use self::peers::FetchInfo;
 
 use prelude::*;
 
-pub(crate) use self::peers::{LastState, Peer, PeerState, Peers, ProveRequest, ProveState};
+pub use self::peers::Peers;
+pub(crate) use self::peers::{
+    LastState, Peer, PeerState, ProveRequest, ProveState, RequestKind, RequestLimits,
+};
 use super::{
     status::{Status, StatusCode},
-    BAD_MESSAGE_BAN_TIME,
+    ChainEvent, BAD_MESSAGE_BAN_TIME,
 };
 
 use crate::protocols::{GET_BLOCKS_PROOF_LIMIT, GET_TRANSACTIONS_PROOF_LIMIT, LAST_N_BLOCKS};
 use crate::storage::Storage;
+use crate::types::{ClientVersion, LightClientConfig};
 use crate::utils::network::prove_or_download_matched_blocks;
 
+type InvalidHeaders = LinkedHashMap<packed::Byte32, (Option<[u8; 32]>, u64)>;
+
 pub struct LightClientProtocol {
     storage: Storage,
     peers: Arc<Peers>,
@@ -54,6 +62,18 @@ pub struct LightClientProtocol {
     mmr_activated_epoch: EpochNumber,
     last_n_blocks: BlockNumber,
     init_blocks_in_transit_per_peer: usize,
+    stale_peer_timeout: u64,
+    stale_tip_timeout: u64,
+    audit_sampling: bool,
+    min_remote_client_version: Option<ClientVersion>,
+    // The interval of requesting new last states from peers, in milliseconds.
+    refresh_last_state_interval: u64,
+    // The total difficulty of the proved tip when last refreshed.
+    refreshed_tip_total_difficulty: U256,
+    // The headers which failed the verification recently, the key is the header hash,
+    // the value is the hash of the verifiable data if the chain root is invalid (none
+    // if the PoW is invalid), and the timestamp when it's added.
+    invalid_headers: Mutex<InvalidHeaders>,
 }
 
 #[async_trait]
@@ -73,6 +93,10 @@ impl CKBProtocolHandler for LightClientProtocol {
                 constant::GET_IDLE_BLOCKS_DURATION,
                 constant::GET_IDLE_BLOCKS_TOKEN,
             ),
+            (
+                constant::CHECK_TIP_ANNOUNCEMENTS_DURATION,
+                constant::CHECK_TIP_ANNOUNCEMENTS_TOKEN,
+            ),
         ] {
             nc.set_notify(duration, token)
                 .await
@@ -86,10 +110,56 @@ impl CKBProtocolHandler for LightClientProtocol {
         peer_index: PeerIndex,
         version: &str,
     ) {
-        info!("LightClient({}).connected peer={}", version, peer_index);
+        info!(
+            peer_index = peer_index.value();
+            "LightClient({}).connected peer={}",
+            version,
+            peer_index
+        );
         self.peers().add_peer(peer_index);
+        let remote_peer = nc.get_peer(peer_index);
+        if let Some(ref min_version) = self.min_remote_client_version {
+            let client_version = remote_peer
+                .as_ref()
+                .and_then(|peer| peer.identify_info.as_ref())
+                .map(|info| info.client_version.as_str());
+            if let Some(client_version) = client_version {
+                match client_version.parse::<ClientVersion>() {
+                    Ok(version) if version < *min_version => {
+                        info!(
+                            peer_index = peer_index.value();
+                            "peer {}: client version {} is less than {}, skip proof syncing",
+                            peer_index, version, min_version
+                        );
+                        self.peers().mark_outdated(peer_index);
+                        return;
+                    }
+                    Ok(_) => {}
+                    Err(err) => warn!(
+                        peer_index = peer_index.value();
+                        "peer {}: {}",
+                        peer_index,
+                        err
+                    ),
+                }
+            }
+        }
+        if let Some(node_id) = remote_peer
+            .as_ref()
+            .and_then(|peer| extract_peer_id(&peer.connected_addr))
+        {
+            if let Some(existed) = self.peers().update_node_id(peer_index, node_id) {
+                info!(
+                    peer_index = peer_index.value();
+                    "peer {}: a duplicate session of peer {}, skip proof syncing",
+                    peer_index, existed
+                );
+                return;
+            }
+        }
         if let Err(err) = self.get_last_state(nc.as_ref(), peer_index) {
             error!(
+                peer_index = peer_index.value();
                 "failed to request last state from peer={} since {}",
                 peer_index, err
             );
@@ -98,11 +168,25 @@ impl CKBProtocolHandler for LightClientProtocol {
 
     async fn disconnected(
         &mut self,
-        _nc: Arc<dyn CKBProtocolContext + Sync>,
+        nc: Arc<dyn CKBProtocolContext + Sync>,
         peer_index: PeerIndex,
     ) {
-        info!("LightClient.disconnected peer={}", peer_index);
+        info!(peer_index = peer_index.value(); "LightClient.disconnected peer={}", peer_index);
+        let substitute = self.peers().take_over_by_duplicate_session(peer_index);
         self.peers().remove_peer(peer_index);
+        if let Some(substitute) = substitute {
+            info!(
+                peer_index = peer_index.value();
+                "peer {}: take over the proof syncing from peer {}",
+                substitute, peer_index
+            );
+            if let Err(err) = self.get_last_state(nc.as_ref(), substitute) {
+                error!(
+                    "failed to request last state from peer={} since {}",
+                    substitute, err
+                );
+            }
+        }
     }
 
     async fn received(
@@ -111,14 +195,20 @@ impl CKBProtocolHandler for LightClientProtocol {
         peer_index: PeerIndex,
         data: Bytes,
     ) {
+        self.peers()
+            .record_received_message(peer_index, nc.protocol_id(), data.len());
         let msg = match packed::LightClientMessageReader::from_compatible_slice(&data) {
             Ok(msg) => msg.to_enum(),
             _ => {
                 warn!(
+                    peer_index = peer_index.value();
                     "LightClient.received a malformed message from Peer({})",
                     peer_index
                 );
-                nc.ban_peer(
+                self.peers()
+                    .add_failure(peer_index, StatusCode::MalformedProtocolMessage);
+                self.peers().ban_peer(
+                    nc.as_ref(),
                     peer_index,
                     BAD_MESSAGE_BAN_TIME,
                     String::from("send us a malformed message"),
@@ -129,7 +219,10 @@ impl CKBProtocolHandler for LightClientProtocol {
 
         let item_name = msg.item_name();
         let status = self.try_process(nc.as_ref(), peer_index, msg);
-        status.process(nc, peer_index, "LightClient", item_name);
+        if !status.is_ok() {
+            self.peers().add_failure(peer_index, status.code());
+        }
+        status.process(nc, self.peers(), peer_index, "LightClient", item_name);
     }
 
     async fn notify(&mut self, nc: Arc<dyn CKBProtocolContext + Sync>, token: u64) {
@@ -143,12 +236,20 @@ impl CKBProtocolHandler for LightClientProtocol {
             constant::GET_IDLE_BLOCKS_TOKEN => {
                 self.get_idle_blocks(nc.as_ref());
             }
+            constant::CHECK_TIP_ANNOUNCEMENTS_TOKEN => {
+                self.check_tip_announcements(nc.as_ref());
+            }
             _ => unreachable!(),
         }
     }
 }
 
 impl LightClientProtocol {
+    #[instrument(
+        name = "light_client_message",
+        skip_all,
+        fields(peer = %peer_index, message = message.item_name())
+    )]
     fn try_process(
         &mut self,
         nc: &dyn CKBProtocolContext,
@@ -185,6 +286,8 @@ impl LightClientProtocol {
             .set(content)
             .build();
         self.peers().request_last_state(peer_index)?;
+        self.peers()
+            .record_sent_message(peer_index, nc.protocol_id(), message.as_slice().len());
         nc.reply(peer_index, &message);
         Ok(())
     }
@@ -226,6 +329,7 @@ impl LightClientProtocol {
                 self.peers().find_if_a_header_is_proved(last_header)
             {
                 trace!(
+                    peer_index = peer_index.value();
                     "peer {}: copy prove state from peer {}",
                     peer_index,
                     peer_index_copied_from
@@ -234,33 +338,113 @@ impl LightClientProtocol {
                 return Ok(false);
             }
 
+            // Resume the prove request which was sent before restart.
+            if let Some(prove_request) = self.resume_prove_request(&peer_state, last_state) {
+                trace!(
+                    peer_index = peer_index.value();
+                    "peer {}: resume get last state proof",
+                    peer_index
+                );
+                let message = packed::LightClientMessage::new_builder()
+                    .set(prove_request.get_content().clone())
+                    .build();
+                self.peers().record_sent_message(
+                    peer_index,
+                    nc.protocol_id(),
+                    message.as_slice().len(),
+                );
+                nc.reply(peer_index, &message);
+                self.peers()
+                    .update_prove_request(peer_index, prove_request)?;
+                return Ok(true);
+            }
+
             if let Some(content) = self.build_prove_request_content(&peer_state, last_header) {
-                trace!("peer {}: send get last state proof", peer_index);
+                trace!(
+                    peer_index = peer_index.value();
+                    "peer {}: send get last state proof",
+                    peer_index
+                );
                 let message = packed::LightClientMessage::new_builder()
                     .set(content.clone())
                     .build();
+                self.peers().record_sent_message(
+                    peer_index,
+                    nc.protocol_id(),
+                    message.as_slice().len(),
+                );
                 nc.reply(peer_index, &message);
                 let prove_request = ProveRequest::new(last_state.clone(), content);
+                self.persist_prove_request(&peer_state, &prove_request);
                 self.peers()
                     .update_prove_request(peer_index, prove_request)?;
                 Ok(true)
             } else {
-                warn!("peer {}: build prove request failed", peer_index);
+                warn!(
+                    peer_index = peer_index.value();
+                    "peer {}: build prove request failed",
+                    peer_index
+                );
                 Ok(false)
             }
         } else {
-            warn!("peer {}: no last state for building request", peer_index);
+            warn!(
+                peer_index = peer_index.value();
+                "peer {}: no last state for building request",
+                peer_index
+            );
             Ok(false)
         }
     }
 
+    /// Persists the prove request if it's built base on the last state in the storage.
+    ///
+    /// After restart, no peers have prove states, so all prove requests are built
+    /// base on the last state in the storage, the persisted one could be resumed.
+    pub(crate) fn persist_prove_request(&self, peer_state: &PeerState, request: &ProveRequest) {
+        if peer_state.get_prove_state().is_none() {
+            self.storage.update_prove_request(
+                request.get_content(),
+                request.if_skip_check_tau(),
+                request.if_long_fork_detected(),
+            );
+        }
+    }
+
+    fn resume_prove_request(
+        &self,
+        peer_state: &PeerState,
+        last_state: &LastState,
+    ) -> Option<ProveRequest> {
+        if peer_state.get_prove_state().is_some() {
+            return None;
+        }
+        self.storage.get_prove_request().and_then(
+            |(content, skip_check_tau, long_fork_detected)| {
+                if content.last_hash() != last_state.as_ref().header().hash() {
+                    return None;
+                }
+                let mut prove_request = ProveRequest::new(last_state.clone(), content);
+                if skip_check_tau {
+                    prove_request.skip_check_tau();
+                }
+                if long_fork_detected {
+                    prove_request.long_fork_detected();
+                }
+                Some(prove_request)
+            },
+        )
+    }
+
     pub(crate) fn check_chain_root_for_headers<'a, T: Iterator<Item = &'a VerifiableHeader>>(
         &self,
         headers: T,
     ) -> Result<(), Status> {
         let mmr_activated_epoch = self.mmr_activated_epoch();
         for header in headers {
+            self.check_invalid_headers_cache(header.header(), Some(header))?;
             if !header.patched_is_valid(mmr_activated_epoch) {
+                self.add_invalid_header(header.header(), Some(header));
                 let header = header.header();
                 let errmsg = format!(
                     "failed to verify chain root for block#{}, hash: {:#x}",
@@ -275,8 +459,10 @@ impl LightClientProtocol {
 
     fn check_verifiable_header(&self, verifiable_header: &VerifiableHeader) -> Result<(), Status> {
         let header = verifiable_header.header();
+        self.check_invalid_headers_cache(header, Some(verifiable_header))?;
         // Check PoW
         if !self.consensus.pow_engine().verify(&header.data()) {
+            self.add_invalid_header(header, None);
             let errmsg = format!(
                 "failed to verify nonce for block#{}, hash: {:#x}",
                 header.number(),
@@ -286,6 +472,7 @@ impl LightClientProtocol {
         }
         // Check Chain Root
         if !verifiable_header.patched_is_valid(self.mmr_activated_epoch()) {
+            self.add_invalid_header(header, Some(verifiable_header));
             let errmsg = format!(
                 "failed to verify chain root for block#{}, hash: {:#x}",
                 header.number(),
@@ -304,7 +491,7 @@ impl LightClientProtocol {
     ) -> Result<(), Status> {
         self.check_verifiable_header(&last_header)?;
         let last_state = LastState::new(last_header);
-        trace!("peer {}: update last state", peer_index);
+        trace!(peer_index = peer_index.value(); "peer {}: update last state", peer_index);
         self.peers().update_last_state(peer_index, last_state)?;
         Ok(())
     }
@@ -320,11 +507,16 @@ impl LightClientProtocol {
         let (old_total_difficulty, _) = self.storage.get_last_state();
         let new_total_difficulty = new_prove_state.get_last_header().total_difficulty();
         if new_total_difficulty > old_total_difficulty {
+            let new_last_header = new_prove_state.get_last_header().header();
             self.storage.update_last_state(
                 &new_total_difficulty,
-                &new_prove_state.get_last_header().header().data(),
+                &new_last_header.data(),
                 new_prove_state.get_last_headers(),
             );
+            self.peers.emit_chain_event(ChainEvent::TipUpdated {
+                number: new_last_header.number(),
+                hash: new_last_header.hash(),
+            });
         }
         self.peers().update_prove_state(peer_index, new_prove_state)
     }
@@ -347,7 +539,10 @@ impl LightClientProtocol {
                 // headers, so we could NOT distinguish whether the block#1 is a fork block or not.
                 // For safety, just remove the block#1.
                 if prev_last_header_number == 1 {
-                    info!("rollback to block#1 since previous last header number is 1");
+                    info!(
+                        block_number = 1u64;
+                        "rollback to block#1 since previous last header number is 1"
+                    );
                     let mut matched_blocks = self.peers.matched_blocks().write().expect("poisoned");
                     while let Some((start_number, _, _)) = self.storage.get_latest_matched_blocks()
                     {
@@ -357,6 +552,8 @@ impl LightClientProtocol {
                     }
                     self.storage.rollback_to_block(1);
                     matched_blocks.clear();
+                    self.peers
+                        .emit_chain_event(ChainEvent::Reorg { rollback_to: 1 });
                 }
             } else {
                 let old_last_headers: HashMap<_, _> =
@@ -389,20 +586,27 @@ impl LightClientProtocol {
                         }
                     }
                     let rollback_to = start_number_opt.unwrap_or(to_number) + 1;
-                    info!("rollback to block#{}", rollback_to);
+                    info!(block_number = rollback_to; "rollback to block#{}", rollback_to);
                     self.storage.rollback_to_block(rollback_to);
                     matched_blocks.clear();
+                    self.peers
+                        .emit_chain_event(ChainEvent::Reorg { rollback_to });
                 } else {
                     warn!("long fork detected");
                     return Ok(false);
                 }
             }
 
+            let new_last_header = new_prove_state.get_last_header().header();
             self.storage.update_last_state(
                 &new_total_difficulty,
-                &new_prove_state.get_last_header().header().data(),
+                &new_last_header.data(),
                 new_prove_state.get_last_headers(),
             );
+            self.peers.emit_chain_event(ChainEvent::TipUpdated {
+                number: new_last_header.number(),
+                hash: new_last_header.hash(),
+            });
         }
         self.peers()
             .update_prove_state(peer_index, new_prove_state)?;
@@ -410,24 +614,70 @@ impl LightClientProtocol {
     }
 }
 
+// The hash of the data which is not committed by the header hash but is required to
+// verify the chain root.
+fn calc_verifiable_data_hash(verifiable_header: &VerifiableHeader) -> [u8; 32] {
+    let mut hasher = new_blake2b();
+    hasher.update(verifiable_header.uncles_hash().as_slice());
+    if let Some(extension) = verifiable_header.extension() {
+        hasher.update(&[1]);
+        hasher.update(extension.as_slice());
+    } else {
+        hasher.update(&[0]);
+    }
+    hasher.update(verifiable_header.parent_chain_root().as_slice());
+    let mut hash = [0u8; 32];
+    hasher.finalize(&mut hash);
+    hash
+}
+
+/// The first epoch which the chain root MMR is activated at.
+///
+/// The activation epochs are fixed for the mainnet and the testnet, as the table in
+/// `Consensus::rfc0044_active`. It's activated since the genesis for any other chain, a
+/// custom chain which activates it later should set `hardfork_mmr_activated_epoch` in the
+/// config file.
+///
+/// Ref: https://github.com/nervosnetwork/rfcs/blob/01f3bc64ef8f54c94c7b0dcf9d30c84b6c8418b0/rfcs/0044-ckb-light-client/0044-ckb-light-client.md#deployment
+pub(crate) fn mmr_activated_epoch(consensus: &Consensus) -> EpochNumber {
+    match consensus.id.as_str() {
+        mainnet::CHAIN_SPEC_NAME => 8651,
+        testnet::CHAIN_SPEC_NAME => 5711,
+        _ => 0,
+    }
+}
+
 impl LightClientProtocol {
-    pub(crate) fn new(storage: Storage, peers: Arc<Peers>, consensus: Consensus) -> Self {
-        // Ref: https://github.com/nervosnetwork/rfcs/blob/01f3bc64ef8f54c94c7b0dcf9d30c84b6c8418b0/rfcs/0044-ckb-light-client/0044-ckb-light-client.md#deployment
-        let mmr_activated_epoch = match consensus.id.as_str() {
-            mainnet::CHAIN_SPEC_NAME => 8651,
-            testnet::CHAIN_SPEC_NAME => 5711,
-            _ => 0,
-        };
+    pub(crate) fn new(
+        storage: Storage,
+        peers: Arc<Peers>,
+        consensus: Consensus,
+        config: &LightClientConfig,
+    ) -> Self {
+        let mmr_activated_epoch = config
+            .hardfork_mmr_activated_epoch
+            .unwrap_or_else(|| mmr_activated_epoch(&consensus));
         Self {
             storage,
             peers,
             consensus,
             mmr_activated_epoch,
             last_n_blocks: LAST_N_BLOCKS,
-            init_blocks_in_transit_per_peer: INIT_BLOCKS_IN_TRANSIT_PER_PEER,
+            init_blocks_in_transit_per_peer: config.matched_blocks_in_transit_per_peer,
+            stale_peer_timeout: config.stale_peer_timeout_secs * 1000,
+            stale_tip_timeout: config.stale_tip_timeout_secs * 1000,
+            audit_sampling: config.audit_sampling,
+            min_remote_client_version: config.min_remote_client_version,
+            refresh_last_state_interval: constant::REFRESH_PEERS_DURATION.as_millis() as u64,
+            refreshed_tip_total_difficulty: U256::zero(),
+            invalid_headers: Default::default(),
         }
     }
 
+    pub(crate) fn audit_sampling(&self) -> bool {
+        self.audit_sampling
+    }
+
     pub(crate) fn last_n_blocks(&self) -> BlockNumber {
         self.last_n_blocks
     }
@@ -437,6 +687,11 @@ impl LightClientProtocol {
         self.last_n_blocks = last_n_blocks;
     }
 
+    #[cfg(test)]
+    pub(crate) fn refresh_last_state_interval(&self) -> u64 {
+        self.refresh_last_state_interval
+    }
+
     pub(crate) fn init_blocks_in_transit_per_peer(&self) -> usize {
         self.init_blocks_in_transit_per_peer
     }
@@ -455,22 +710,79 @@ impl LightClientProtocol {
         self.mmr_activated_epoch
     }
 
-    pub(crate) fn check_pow_for_headers<'a, T: Iterator<Item = &'a HeaderView>>(
+    /// Verifies the PoW of the headers in parallel.
+    pub(crate) fn check_pow_for_headers(&self, headers: &[HeaderView]) -> Result<(), Status> {
+        for header in headers {
+            self.check_invalid_headers_cache(header, None)?;
+        }
+        let pow_engine = self.consensus.pow_engine();
+        if let Some(header) = headers
+            .par_iter()
+            .find_any(|header| !pow_engine.verify(&header.data()))
+        {
+            self.add_invalid_header(header, None);
+            let errmsg = format!(
+                "failed to verify nonce for block#{}, hash: {:#x}",
+                header.number(),
+                header.hash()
+            );
+            return Err(StatusCode::InvalidNonce.with_context(errmsg));
+        }
+        Ok(())
+    }
+
+    /// Returns the cached failure if the header failed the verification recently,
+    /// to avoid verifying it again when a misbehaving peer resends it.
+    ///
+    /// The PoW only depends on the header, but the chain root also depends on the
+    /// verifiable data, so a chain root failure is only matched by the same data.
+    pub(crate) fn check_invalid_headers_cache(
         &self,
-        headers: T,
+        header: &HeaderView,
+        verifiable_header: Option<&VerifiableHeader>,
     ) -> Result<(), Status> {
-        let pow_engine = self.consensus.pow_engine();
-        for header in headers {
-            if !pow_engine.verify(&header.data()) {
-                let errmsg = format!(
-                    "failed to verify nonce for block#{}, hash: {:#x}",
-                    header.number(),
-                    header.hash()
-                );
-                return Err(StatusCode::InvalidNonce.with_context(errmsg));
+        let now = unix_time_as_millis();
+        let ttl = constant::INVALID_HEADERS_CACHE_TTL.as_millis() as u64;
+        let mut invalid_headers = self.invalid_headers.lock().expect("poisoned");
+        // The headers are ordered by the added timestamps.
+        while let Some((_, (_, added_ts))) = invalid_headers.front() {
+            if added_ts + ttl < now {
+                invalid_headers.pop_front();
+            } else {
+                break;
             }
         }
-        Ok(())
+        let code = match invalid_headers.get(&header.hash()) {
+            Some((None, _)) => StatusCode::InvalidNonce,
+            Some((Some(invalid_data_hash), _))
+                if verifiable_header.map(calc_verifiable_data_hash).as_ref()
+                    == Some(invalid_data_hash) =>
+            {
+                StatusCode::InvalidChainRoot
+            }
+            _ => return Ok(()),
+        };
+        let errmsg = format!(
+            "block#{}, hash: {:#x} failed the verification recently",
+            header.number(),
+            header.hash()
+        );
+        Err(code.with_context(errmsg))
+    }
+
+    // Add a header which failed the verification into the cache, the verifiable header
+    // is none if the PoW is invalid.
+    pub(crate) fn add_invalid_header(
+        &self,
+        header: &HeaderView,
+        verifiable_header: Option<&VerifiableHeader>,
+    ) {
+        let data_hash = verifiable_header.map(calc_verifiable_data_hash);
+        let mut invalid_headers = self.invalid_headers.lock().expect("poisoned");
+        invalid_headers.insert(header.hash(), (data_hash, unix_time_as_millis()));
+        if invalid_headers.len() > constant::INVALID_HEADERS_CACHE_SIZE {
+            invalid_headers.pop_front();
+        }
     }
 
     pub fn storage(&self) -> &Storage {
@@ -499,16 +811,41 @@ impl LightClientProtocol {
 
     fn refresh_all_peers(&mut self, nc: &dyn CKBProtocolContext) {
         let now = unix_time_as_millis();
+        self.peers().set_last_refreshed_at(now);
         for peer_index in self.peers().get_peers_which_have_timeout(now) {
             self.peers().mark_fetching_headers_timeout(peer_index);
             self.peers().mark_fetching_txs_timeout(peer_index);
+            self.peers()
+                .add_failure(peer_index, StatusCode::RequestIsTimeout);
 
-            warn!("peer {}: reach timeout", peer_index);
+            warn!(peer_index = peer_index.value(); "peer {}: reach timeout", peer_index);
             if let Err(err) = nc.disconnect(peer_index, "reach timeout") {
-                error!("disconnect peer({}) error: {}", peer_index, err);
+                error!(
+                    peer_index = peer_index.value();
+                    "disconnect peer({}) error: {}",
+                    peer_index,
+                    err
+                );
             };
         }
-        let before_ts = now - constant::REFRESH_PEERS_DURATION.as_millis() as u64;
+        for peer_index in self
+            .peers()
+            .get_peers_which_are_stale(now, self.stale_peer_timeout)
+        {
+            self.peers().mark_fetching_headers_timeout(peer_index);
+            self.peers().mark_fetching_txs_timeout(peer_index);
+
+            warn!(peer_index = peer_index.value(); "peer {}: proved tip is stale", peer_index);
+            if let Err(err) = nc.disconnect(peer_index, "proved tip is stale") {
+                error!(
+                    peer_index = peer_index.value();
+                    "disconnect peer({}) error: {}",
+                    peer_index,
+                    err
+                );
+            };
+        }
+        let before_ts = now.saturating_sub(self.refresh_last_state_interval);
         for index in self.peers().get_peers_which_require_new_state(before_ts) {
             if let Err(err) = self.get_last_state(nc, index) {
                 error!(
@@ -517,6 +854,7 @@ impl LightClientProtocol {
                 );
             }
         }
+        self.adapt_refresh_last_state_interval();
         for index in self.peers().get_peers_which_require_new_proof() {
             if let Err(err) = self.get_last_state_proof(nc, index) {
                 error!(
@@ -526,6 +864,76 @@ impl LightClientProtocol {
             }
         }
         self.finalize_check_points(nc);
+        self.check_stale_tip(now);
+    }
+
+    // Requests the new last states or proofs from the peers which announced new tips, instead
+    // of waiting for the next refresh.
+    pub(crate) fn check_tip_announcements(&self, nc: &dyn CKBProtocolContext) {
+        let (require_new_state, require_new_proof) = self.peers().take_tip_announcements();
+        for index in require_new_state {
+            if let Err(err) = self.get_last_state(nc, index) {
+                error!(
+                    "failed to request last state from peer={} since {}",
+                    index, err
+                );
+            }
+        }
+        for index in require_new_proof {
+            if let Err(err) = self.get_last_state_proof(nc, index) {
+                error!(
+                    "failed to request last state proof from peer={} since {}",
+                    index, err
+                );
+            }
+        }
+    }
+
+    pub(crate) fn check_stale_tip(&self, now: u64) {
+        let tip_header = self.storage.get_tip_header().into_view();
+        let is_stale = tip_header
+            .timestamp()
+            .saturating_add(self.stale_tip_timeout)
+            < now;
+        let was_stale = self.peers().set_tip_is_stale(is_stale);
+        if is_stale && !was_stale {
+            warn!(
+                block_number = tip_header.number();
+                "proved tip#{} is stale, its timestamp {} is behind the wall-clock time {} ms",
+                tip_header.number(),
+                tip_header.timestamp(),
+                now - tip_header.timestamp()
+            );
+            self.peers.emit_chain_event(ChainEvent::TipStale {
+                number: tip_header.number(),
+                timestamp: tip_header.timestamp(),
+            });
+        } else if !is_stale && was_stale {
+            info!(
+                block_number = tip_header.number();
+                "proved tip#{} is not stale anymore",
+                tip_header.number()
+            );
+        }
+    }
+
+    // Poll peers aggressively when the proved tip advances (e.g. catching up),
+    // and back off gradually when the proved tip doesn't advance.
+    fn adapt_refresh_last_state_interval(&mut self) {
+        let (tip_total_difficulty, _) = self.storage.get_last_state();
+        let min_interval = constant::REFRESH_PEERS_DURATION.as_millis() as u64;
+        let max_interval = constant::MAX_REFRESH_LAST_STATE_INTERVAL.as_millis() as u64;
+        self.refresh_last_state_interval =
+            if tip_total_difficulty > self.refreshed_tip_total_difficulty {
+                min_interval
+            } else {
+                (self.refresh_last_state_interval * 2).min(max_interval)
+            };
+        trace!(
+            "refresh last state interval is {} ms",
+            self.refresh_last_state_interval
+        );
+        self.refreshed_tip_total_difficulty = tip_total_difficulty;
     }
 
     fn finalize_check_points(&mut self, nc: &dyn CKBProtocolContext) {
@@ -535,6 +943,7 @@ impl LightClientProtocol {
         if log_enabled!(Level::Trace) {
             for (peer_index, (start_cpindex, check_points)) in peers_with_data.iter() {
                 trace!(
+                    peer_index = peer_index.value();
                     "check points for peer {} in [{},{}]",
                     peer_index,
                     start_cpindex,
@@ -570,6 +979,7 @@ impl LightClientProtocol {
                 if *start_cpindex > last_cpindex {
                     // Impossible, in fact.
                     error!(
+                        peer_index = peer_index.value();
                         "peer {} will be banned \
                         since start check point {} is later than finalized {}",
                         peer_index, start_cpindex, last_cpindex
@@ -584,6 +994,7 @@ impl LightClientProtocol {
                 }
                 if check_points[index] != last_check_point {
                     info!(
+                        peer_index = peer_index.value();
                         "peer {} will be banned \
                         since its {}-th check point is {:#x} but finalized is {:#x}",
                         peer_index, last_cpindex, check_points[index], last_check_point
@@ -596,6 +1007,7 @@ impl LightClientProtocol {
                     *start_cpindex = last_cpindex;
                     peers.remove_first_n_check_points(*peer_index, index);
                     trace!(
+                        peer_index = peer_index.value();
                         "peer {} remove first {} check points, \
                         new start check point is {}, {:#x}",
                         peer_index,
@@ -607,7 +1019,8 @@ impl LightClientProtocol {
             }
             for (peer_index, should_ban) in peers_should_be_skipped {
                 if should_ban {
-                    nc.ban_peer(
+                    self.peers().ban_peer(
+                        nc,
                         peer_index,
                         BAD_MESSAGE_BAN_TIME,
                         String::from("incorrect check points"),
@@ -710,7 +1123,7 @@ impl LightClientProtocol {
         }
 
         let tip_header = self.storage.get_tip_header();
-        let best_peers: Vec<PeerIndex> = self.peers.get_best_proved_peers(&tip_header);
+        let mut best_peers: Vec<PeerIndex> = self.peers.get_best_proved_peers(&tip_header);
         if best_peers.is_empty() {
             debug!("no peers found for fetch headers and transactions");
             return;
@@ -718,6 +1131,9 @@ impl LightClientProtocol {
 
         let now = unix_time_as_millis();
         let last_hash = tip_header.calc_header_hash();
+        // Prefer the peers which respond faster.
+        self.peers
+            .sort_by_latency(&mut best_peers, RequestKind::BlocksProof);
         for block_hashes in self
             .peers
             .get_headers_to_fetch()
@@ -725,11 +1141,13 @@ impl LightClientProtocol {
         {
             if let Some(peer_index) = best_peers.iter().find(|peer_index| {
                 self.peers
-                    .get_peer(peer_index)
-                    .map(|peer| peer.get_blocks_proof_request().is_none())
-                    .unwrap_or(false)
+                    .could_request(peer_index, RequestKind::BlocksProof)
             }) {
-                debug!("send block proof request to peer: {}", peer_index);
+                debug!(
+                    peer_index = peer_index.value();
+                    "send block proof request to peer: {}",
+                    peer_index
+                );
                 if !block_hashes.is_empty() {
                     let content = packed::GetBlocksProof::new_builder()
                         .block_hashes(block_hashes.to_vec().pack())
@@ -742,6 +1160,11 @@ impl LightClientProtocol {
 
                     self.peers
                         .update_blocks_proof_request(*peer_index, Some(content), false);
+                    self.peers.record_sent_message(
+                        *peer_index,
+                        SupportProtocols::LightClient.protocol_id(),
+                        message.len(),
+                    );
                     if let Err(err) = nc.send_message(
                         SupportProtocols::LightClient.protocol_id(),
                         *peer_index,
@@ -759,28 +1182,51 @@ impl LightClientProtocol {
             }
         }
 
+        self.peers
+            .sort_by_latency(&mut best_peers, RequestKind::TransactionsProof);
         for tx_hashes in self
             .peers
             .get_txs_to_fetch()
             .chunks(GET_TRANSACTIONS_PROOF_LIMIT)
         {
-            if let Some(peer_index) = best_peers.iter().find(|peer_index| {
+            // Send the same request to several peers, since some peers may not have the data.
+            let fanout = constant::FETCH_TXS_FANOUT.min(
                 self.peers
-                    .get_peer(peer_index)
-                    .map(|peer| peer.get_txs_proof_request().is_none())
-                    .unwrap_or(false)
-            }) {
-                debug!("send transaction proof request to peer: {}", peer_index);
-                let content = packed::GetTransactionsProof::new_builder()
-                    .tx_hashes(tx_hashes.to_vec().pack())
-                    .last_hash(last_hash.clone())
-                    .build();
-                let message = packed::LightClientMessage::new_builder()
-                    .set(content.clone())
-                    .build();
-
+                    .get_remaining_requests_count(RequestKind::TransactionsProof),
+            );
+            let peer_indexes = best_peers
+                .iter()
+                .filter(|peer_index| {
+                    self.peers
+                        .could_request(peer_index, RequestKind::TransactionsProof)
+                })
+                .take(fanout)
+                .cloned()
+                .collect::<Vec<_>>();
+            if peer_indexes.is_empty() {
+                debug!("all valid peers are busy for fetching transactions");
+                break;
+            }
+            let content = packed::GetTransactionsProof::new_builder()
+                .tx_hashes(tx_hashes.to_vec().pack())
+                .last_hash(last_hash.clone())
+                .build();
+            let message = packed::LightClientMessage::new_builder()
+                .set(content.clone())
+                .build();
+            for peer_index in &peer_indexes {
+                debug!(
+                    peer_index = peer_index.value();
+                    "send transaction proof request to peer: {}",
+                    peer_index
+                );
                 self.peers
-                    .update_txs_proof_request(*peer_index, Some(content));
+                    .update_txs_proof_request(*peer_index, Some(content.clone()));
+                self.peers.record_sent_message(
+                    *peer_index,
+                    SupportProtocols::LightClient.protocol_id(),
+                    message.as_slice().len(),
+                );
                 if let Err(err) = nc.send_message(
                     SupportProtocols::LightClient.protocol_id(),
                     *peer_index,
@@ -790,11 +1236,8 @@ impl LightClientProtocol {
                         format!("nc.send_message LightClientMessage, error: {:?}", err);
                     error!("{}", error_message);
                 }
-                self.peers.fetching_idle_txs(tx_hashes, now);
-            } else {
-                debug!("all valid peers are busy for fetching transactions");
-                break;
             }
+            self.peers.fetching_idle_txs(tx_hashes, &peer_indexes, now);
         }
     }
 
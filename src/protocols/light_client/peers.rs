@@ -1,4 +1,4 @@
-use ckb_network::PeerIndex;
+use ckb_network::{CKBProtocolContext, PeerId, PeerIndex, ProtocolId};
 use ckb_systemtime::unix_time_as_millis;
 use ckb_types::{
     core::{BlockNumber, HeaderView},
@@ -9,14 +9,35 @@ use ckb_types::{
     H256, U256,
 };
 use dashmap::DashMap;
+use linked_hash_map::LinkedHashMap;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fmt, mem,
-    sync::RwLock,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc, Mutex, RwLock,
+    },
+    time::Duration,
 };
+use tokio::sync::broadcast;
 
 use super::prelude::*;
-use crate::protocols::{Status, StatusCode, MESSAGE_TIMEOUT};
+use crate::protocols::{
+    events::CHAIN_EVENTS_CHANNEL_CAPACITY, rate_limiter::DownloadRateLimiter, ChainEvent, Status,
+    StatusCode, LAST_N_BLOCKS, MESSAGE_TIMEOUT,
+};
+
+// The count of the matched blocks whose sources (the peers which sent the block filters)
+// are kept, to check the false positives of them.
+const MATCHED_BLOCK_SOURCES_SIZE: usize = 4096;
+// Don't use a peer for block filters if it has this count of false positives, and more than
+// half of the blocks matched by its block filters are false positives.
+//
+// The false positive rate of a block filter is about 1/784931 for each script, so a
+// legitimate peer almost never reaches it.
+const MAX_FILTER_FALSE_POSITIVES_PER_PEER: u64 = 8;
+// Don't use a peer for block filters if its filter requests time out this count in a row.
+const MAX_FILTER_TIMEOUTS_PER_PEER: u32 = 3;
 
 pub struct Peers {
     inner: DashMap<PeerIndex, Peer>,
@@ -45,6 +66,53 @@ pub struct Peers {
 
     check_point_interval: BlockNumber,
     start_check_point: (u32, packed::Byte32),
+
+    // The count of proved peers which are required to treat the proved data as final.
+    min_proved_peers: usize,
+    // The min count of peers which should agree on the check points and the block filter
+    // hashes, it's capped by the max outbound peers.
+    min_block_filter_peers: usize,
+    // The limits of the outstanding requests.
+    request_limits: RequestLimits,
+
+    // The proved tip falls too far behind the wall-clock time.
+    tip_is_stale: AtomicBool,
+    // The proved tip diverges from the chain of the trusted node.
+    trusted_node_diverged: AtomicBool,
+    // Don't download the block filters and the matched blocks, but keep proving the headers.
+    filter_sync_paused: AtomicBool,
+    // The min filtered block number is lowered by the new filter scripts, the block filters
+    // should be requested again from it immediately.
+    filter_rescan_requested: AtomicBool,
+    // The peers which announced the blocks higher than the proved tip through the relay
+    // protocol, request the new last states or proofs from them immediately.
+    tip_announcements: Mutex<HashSet<PeerIndex>>,
+    // Limit the download rate of the block filters and the matched blocks.
+    download_rate_limiter: DownloadRateLimiter,
+    // The soft limit (in bytes) of the memory of the process, zero means no limit.
+    memory_soft_limit: AtomicU64,
+    // The memory exceeds the soft limit, don't download the matched blocks until it drops.
+    under_memory_pressure: AtomicBool,
+    // The count of the matched blocks which contain no transactions of the filter scripts,
+    // they're matched by the false positives of the block filters.
+    filter_false_positives: AtomicU64,
+    // The peers which sent the block filters of the matched blocks.
+    matched_block_sources: Mutex<LinkedHashMap<Byte32, PeerIndex>>,
+    // The counts since started, to measure the throughput of the filter sync.
+    started_at: u64,
+    filtered_blocks: AtomicU64,
+    filter_matched_blocks: AtomicU64,
+    downloaded_bytes: AtomicU64,
+    // The last time (in milliseconds) when the sync loop refreshed the peers, it's used to
+    // tell whether the sync loop is alive.
+    last_refreshed_at: AtomicU64,
+
+    // It's taken when the light client is closed, so the subscribers know there are no more
+    // events after the remaining ones.
+    chain_events: RwLock<Option<broadcast::Sender<ChainEvent>>>,
+    // The audited events are sent through an unbounded channel, so they are never lost even
+    // if the audit log falls behind. It's taken when the light client is closed, too.
+    audit_events: RwLock<Option<mpsc::Sender<ChainEvent>>>,
 }
 
 #[derive(Clone)]
@@ -56,6 +124,78 @@ pub struct Peer {
     txs_proof_request: Option<TransactionsProofRequest>,
     check_points: CheckPoints,
     latest_block_filter_hashes: LatestBlockFilterHashes,
+    // The timestamp when the proved tip of this peer advanced last time.
+    advanced_ts: u64,
+    // The counts of failed statuses when process messages from this peer.
+    failure_counts: BTreeMap<StatusCode, u64>,
+    // The id of the remote node.
+    node_id: Option<PeerId>,
+    // Another session to the same remote node is used for proof syncing.
+    is_duplicate: bool,
+    // The client version of the remote node is too old to be used for proof syncing.
+    is_outdated: bool,
+    // The smoothed latencies (in milliseconds) of the responses, per kind of request.
+    latencies: HashMap<RequestKind, u64>,
+    // The counts of the blocks which are matched by the block filters from this peer, and
+    // the false positives of them.
+    filter_matched_blocks: u64,
+    filter_false_positives: u64,
+    // The peer serves wrong block filters or block filter hashes, don't use it for filters.
+    is_filter_unreliable: bool,
+    // The count of the filter requests which time out in a row.
+    filter_timeouts: u32,
+    // The highest block number of the block filters which are downloaded from this peer.
+    filter_downloaded_number: BlockNumber,
+    // The highest block number which is announced by this peer through the sync or the relay
+    // protocol (not verified), and the timestamp since when it's ahead of the proved tip.
+    announced_tip: Option<(BlockNumber, u64)>,
+    // The count of the messages from this peer which are ignored by the sync protocol.
+    sync_misbehaviors: u32,
+    // The counts of the messages which are exchanged with this peer, per protocol.
+    message_counts: HashMap<ProtocolId, MessageCounts>,
+}
+
+/// The counts of the messages and their bytes which are received from or sent to a peer
+/// through a protocol.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct MessageCounts {
+    pub(crate) received_messages: u64,
+    pub(crate) received_bytes: u64,
+    pub(crate) sent_messages: u64,
+    pub(crate) sent_bytes: u64,
+}
+
+/// The limits of the outstanding requests, to avoid tripping the rate limits of full nodes.
+///
+/// Each peer has at most one outstanding request for each kind of request, so `per_peer`
+/// only limits something if it's less than the count of the kinds.
+#[derive(Clone, Debug)]
+pub struct RequestLimits {
+    /// The max count of outstanding requests of all kinds for each peer, the requests of the
+    /// filter protocol aren't counted.
+    pub(crate) per_peer: usize,
+    /// The max count of outstanding `GetBlocksProof` requests for all peers.
+    pub(crate) blocks_proof: usize,
+    /// The max count of outstanding `GetTransactionsProof` requests for all peers.
+    pub(crate) txs_proof: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            per_peer: 2,
+            blocks_proof: 16,
+            txs_proof: 16,
+        }
+    }
+}
+
+/// The kinds of requests which are used to fetch data from peers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum RequestKind {
+    BlocksProof,
+    Blocks,
+    TransactionsProof,
 }
 
 pub struct FetchInfo {
@@ -67,6 +207,10 @@ pub struct FetchInfo {
     timeout: bool,
     // whether the data to fetch is not on chain
     missing: bool,
+    // the peers which the request is sent to
+    sent_peers: HashSet<PeerIndex>,
+    // the peers which respond that the data is missing
+    missing_peers: HashSet<PeerIndex>,
 }
 
 #[derive(Clone)]
@@ -112,18 +256,19 @@ pub(crate) enum PeerState {
         request: ProveRequest,
         when_sent: u64,
     },
+    // The prove states are shared, so clone the peer state is cheap.
     Ready {
         last_state: LastState,
-        prove_state: ProveState,
+        prove_state: Arc<ProveState>,
     },
     RequestNewLastState {
         last_state: LastState,
-        prove_state: ProveState,
+        prove_state: Arc<ProveState>,
         when_sent: u64,
     },
     RequestNewLastStateProof {
         last_state: LastState,
-        prove_state: ProveState,
+        prove_state: Arc<ProveState>,
         request: ProveRequest,
         when_sent: u64,
     },
@@ -149,6 +294,8 @@ pub(crate) struct BlocksProofRequest {
     content: packed::GetBlocksProof,
     when_sent: u64,
     should_get_blocks: bool,
+    // The blocks which only headers are required, they are coalesced into the request.
+    headers_only: HashSet<Byte32>,
 }
 
 #[derive(Clone)]
@@ -189,6 +336,8 @@ impl FetchInfo {
             first_sent,
             timeout,
             missing,
+            sent_peers: Default::default(),
+            missing_peers: Default::default(),
         }
     }
     #[cfg(test)]
@@ -205,6 +354,8 @@ impl FetchInfo {
             first_sent: 0,
             timeout: false,
             missing: false,
+            sent_peers: Default::default(),
+            missing_peers: Default::default(),
         }
     }
 }
@@ -415,6 +566,7 @@ impl BlocksProofRequest {
             content,
             when_sent,
             should_get_blocks,
+            headers_only: Default::default(),
         }
     }
 
@@ -454,6 +606,10 @@ impl BlocksProofRequest {
     pub(crate) fn should_get_blocks(&self) -> bool {
         self.should_get_blocks
     }
+
+    pub(crate) fn is_header_only(&self, block_hash: &Byte32) -> bool {
+        self.headers_only.contains(block_hash)
+    }
 }
 
 impl BlocksRequest {
@@ -885,6 +1041,10 @@ impl PeerState {
     }
 
     pub(crate) fn get_prove_state(&self) -> Option<&ProveState> {
+        self.get_shared_prove_state().map(AsRef::as_ref)
+    }
+
+    pub(crate) fn get_shared_prove_state(&self) -> Option<&Arc<ProveState>> {
         match self {
             Self::Ready {
                 ref prove_state, ..
@@ -1010,7 +1170,7 @@ impl PeerState {
         }
     }
 
-    fn receive_last_state_proof(self, new_prove_state: ProveState) -> Result<Self, Status> {
+    fn receive_last_state_proof(self, new_prove_state: Arc<ProveState>) -> Result<Self, Status> {
         match self {
             Self::OnlyHasLastState { last_state }
             | Self::RequestFirstLastStateProof { last_state, .. }
@@ -1084,9 +1244,55 @@ impl Peer {
             txs_proof_request: None,
             check_points,
             latest_block_filter_hashes,
+            advanced_ts: unix_time_as_millis(),
+            failure_counts: Default::default(),
+            node_id: None,
+            is_duplicate: false,
+            is_outdated: false,
+            latencies: Default::default(),
+            filter_matched_blocks: 0,
+            filter_false_positives: 0,
+            is_filter_unreliable: false,
+            filter_timeouts: 0,
+            filter_downloaded_number: 0,
+            announced_tip: None,
+            sync_misbehaviors: 0,
+            message_counts: Default::default(),
         }
     }
 
+    fn has_request(&self, kind: RequestKind) -> bool {
+        match kind {
+            RequestKind::BlocksProof => self.blocks_proof_request.is_some(),
+            RequestKind::Blocks => self.blocks_request.is_some(),
+            RequestKind::TransactionsProof => self.txs_proof_request.is_some(),
+        }
+    }
+
+    fn outstanding_requests_count(&self) -> usize {
+        [
+            RequestKind::BlocksProof,
+            RequestKind::Blocks,
+            RequestKind::TransactionsProof,
+        ]
+        .into_iter()
+        .filter(|kind| self.has_request(*kind))
+        .count()
+    }
+
+    pub(crate) fn get_latency(&self, kind: RequestKind) -> Option<u64> {
+        self.latencies.get(&kind).cloned()
+    }
+
+    fn record_latency(&mut self, kind: RequestKind, when_sent: u64) {
+        let latency = unix_time_as_millis().saturating_sub(when_sent);
+        self.latencies
+            .entry(kind)
+            // Smooth the latency, to avoid being affected by a single response too much.
+            .and_modify(|value| *value = (*value * 3 + latency) / 4)
+            .or_insert(latency);
+    }
+
     pub(crate) fn get_blocks_proof_request(&self) -> Option<&BlocksProofRequest> {
         self.blocks_proof_request.as_ref()
     }
@@ -1096,6 +1302,42 @@ impl Peer {
     pub(crate) fn get_txs_proof_request(&self) -> Option<&TransactionsProofRequest> {
         self.txs_proof_request.as_ref()
     }
+    pub(crate) fn get_failure_counts(&self) -> &BTreeMap<StatusCode, u64> {
+        &self.failure_counts
+    }
+    pub(crate) fn get_message_counts(&self) -> &HashMap<ProtocolId, MessageCounts> {
+        &self.message_counts
+    }
+    pub(crate) fn get_announced_tip_number(&self) -> Option<BlockNumber> {
+        self.announced_tip.map(|(number, _)| number)
+    }
+
+    fn get_proved_tip_number(&self) -> Option<BlockNumber> {
+        self.state
+            .get_prove_state()
+            .map(|state| state.get_last_header().header().number())
+    }
+
+    // Checks whether the announced tip is ahead of the proved tip.
+    fn is_announced_tip_ahead(&self) -> bool {
+        self.announced_tip
+            .map(|(number, _)| {
+                self.get_proved_tip_number()
+                    .map(|proved_number| number > proved_number)
+                    .unwrap_or(true)
+            })
+            .unwrap_or(false)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_duplicate(&self) -> bool {
+        self.is_duplicate
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_outdated(&self) -> bool {
+        self.is_outdated
+    }
 
     fn add_block(&mut self, block_hash: &Byte32) {
         let finished = if let Some(request) = self.blocks_request.as_mut() {
@@ -1107,7 +1349,9 @@ impl Peer {
             false
         };
         if finished {
-            self.blocks_request = None;
+            if let Some(request) = self.blocks_request.take() {
+                self.record_latency(RequestKind::Blocks, request.when_sent);
+            }
         }
     }
 }
@@ -1117,10 +1361,15 @@ impl Peers {
         max_outbound_peers: u32,
         check_point_interval: BlockNumber,
         start_check_point: (u32, packed::Byte32),
+        min_proved_peers: usize,
+        min_block_filter_peers: usize,
+        request_limits: RequestLimits,
     ) -> Self {
         #[cfg(test)]
         let max_outbound_peers = RwLock::new(max_outbound_peers);
 
+        let (chain_events, _) = broadcast::channel(CHAIN_EVENTS_CHANNEL_CAPACITY);
+
         Self {
             inner: Default::default(),
             fetching_headers: DashMap::new(),
@@ -1130,15 +1379,249 @@ impl Peers {
             max_outbound_peers,
             check_point_interval,
             start_check_point,
+            min_proved_peers,
+            min_block_filter_peers,
+            request_limits,
+            tip_is_stale: AtomicBool::new(false),
+            trusted_node_diverged: AtomicBool::new(false),
+            filter_sync_paused: AtomicBool::new(false),
+            filter_rescan_requested: AtomicBool::new(false),
+            tip_announcements: Default::default(),
+            download_rate_limiter: Default::default(),
+            memory_soft_limit: AtomicU64::new(0),
+            under_memory_pressure: AtomicBool::new(false),
+            filter_false_positives: AtomicU64::new(0),
+            matched_block_sources: Default::default(),
+            started_at: unix_time_as_millis(),
+            filtered_blocks: AtomicU64::new(0),
+            filter_matched_blocks: AtomicU64::new(0),
+            downloaded_bytes: AtomicU64::new(0),
+            last_refreshed_at: AtomicU64::new(unix_time_as_millis()),
+            chain_events: RwLock::new(Some(chain_events)),
+            audit_events: RwLock::new(None),
+        }
+    }
+
+    /// Subscribes the events of the chain.
+    pub fn subscribe_chain_events(&self) -> broadcast::Receiver<ChainEvent> {
+        if let Some(sender) = self
+            .chain_events
+            .read()
+            .expect("poisoned chain events lock")
+            .as_ref()
+        {
+            sender.subscribe()
+        } else {
+            // The channel is closed, the receiver gets no events.
+            broadcast::channel(1).1
+        }
+    }
+
+    /// Subscribes the audited events, only the last subscriber receives them.
+    pub(crate) fn subscribe_audit_events(&self) -> mpsc::Receiver<ChainEvent> {
+        let (sender, receiver) = mpsc::channel();
+        let mut audit_events = self
+            .audit_events
+            .write()
+            .expect("poisoned audit events lock");
+        // If the light client is closed, the sender is dropped, the receiver gets no events.
+        if self
+            .chain_events
+            .read()
+            .expect("poisoned chain events lock")
+            .is_some()
+        {
+            *audit_events = Some(sender);
+        }
+        receiver
+    }
+
+    pub(crate) fn emit_chain_event(&self, event: ChainEvent) {
+        if event.is_audited() {
+            if let Some(sender) = self
+                .audit_events
+                .read()
+                .expect("poisoned audit events lock")
+                .as_ref()
+            {
+                // Failed only when the audit log exits, just ignore it.
+                let _ = sender.send(event.clone());
+            }
         }
+        if let Some(sender) = self
+            .chain_events
+            .read()
+            .expect("poisoned chain events lock")
+            .as_ref()
+        {
+            // Failed only when there are no subscribers, just ignore it.
+            let _ = sender.send(event);
+        }
+    }
+
+    /// Closes the channel of the chain events, the subscribers receive the remaining events,
+    /// then they are notified that the channel is closed.
+    pub(crate) fn close_chain_events(&self) {
+        self.chain_events
+            .write()
+            .expect("poisoned chain events lock")
+            .take();
+        self.audit_events
+            .write()
+            .expect("poisoned audit events lock")
+            .take();
+    }
+
+    pub(crate) fn is_tip_stale(&self) -> bool {
+        self.tip_is_stale.load(Ordering::Relaxed)
+    }
+
+    // Returns the previous flag.
+    pub(crate) fn set_tip_is_stale(&self, is_stale: bool) -> bool {
+        self.tip_is_stale.swap(is_stale, Ordering::Relaxed)
+    }
+
+    pub(crate) fn is_trusted_node_diverged(&self) -> bool {
+        self.trusted_node_diverged.load(Ordering::Relaxed)
+    }
+
+    // Returns the previous flag.
+    pub(crate) fn set_trusted_node_diverged(&self, is_diverged: bool) -> bool {
+        self.trusted_node_diverged
+            .swap(is_diverged, Ordering::Relaxed)
+    }
+
+    pub(crate) fn is_filter_sync_paused(&self) -> bool {
+        self.filter_sync_paused.load(Ordering::Relaxed)
     }
 
+    pub(crate) fn set_filter_sync_paused(&self, paused: bool) {
+        self.filter_sync_paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub(crate) fn request_filter_rescan(&self) {
+        self.filter_rescan_requested.store(true, Ordering::Relaxed);
+    }
+
+    // Returns if a rescan is requested, and resets the flag.
+    pub(crate) fn take_filter_rescan_request(&self) -> bool {
+        self.filter_rescan_requested.swap(false, Ordering::Relaxed)
+    }
+
+    pub(crate) fn add_tip_announcement(&self, index: PeerIndex) {
+        self.tip_announcements
+            .lock()
+            .expect("poisoned")
+            .insert(index);
+    }
+
+    /// Takes the peers which announced new tips, returns the ones which require new last
+    /// states and the ones which require new last state proofs.
+    pub(crate) fn take_tip_announcements(&self) -> (Vec<PeerIndex>, Vec<PeerIndex>) {
+        let announced = mem::take(&mut *self.tip_announcements.lock().expect("poisoned"));
+        let mut require_new_state = Vec::new();
+        let mut require_new_proof = Vec::new();
+        for index in announced {
+            if let Some(peer) = self.inner.get(&index) {
+                if peer.is_duplicate || peer.is_outdated {
+                    continue;
+                }
+                if peer.state.require_new_last_state_proof() {
+                    require_new_proof.push(index);
+                } else if peer.state.require_new_last_state(u64::MAX) {
+                    require_new_state.push(index);
+                }
+            }
+        }
+        (require_new_state, require_new_proof)
+    }
+
+    /// Sets the max download rate (in bytes per second) of the block filters and the
+    /// matched blocks, zero means no limit.
+    pub(crate) fn set_max_download_rate(&self, max_rate: u64) {
+        self.download_rate_limiter.set_max_rate(max_rate);
+    }
+
+    pub(crate) fn record_downloaded_bytes(&self, bytes: usize) {
+        self.downloaded_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        self.download_rate_limiter
+            .consume(bytes, unix_time_as_millis());
+    }
+
+    /// Checks if the block filters or the matched blocks could be requested, without
+    /// exceeding the max download rate.
+    pub(crate) fn could_download(&self) -> bool {
+        self.download_rate_limiter
+            .is_available(unix_time_as_millis())
+    }
+
+    pub(crate) fn get_memory_soft_limit(&self) -> u64 {
+        self.memory_soft_limit.load(Ordering::Relaxed)
+    }
+
+    /// Sets the soft limit (in bytes) of the memory of the process, zero means no limit.
+    pub(crate) fn set_memory_soft_limit(&self, limit: u64) {
+        self.memory_soft_limit.store(limit, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_under_memory_pressure(&self) -> bool {
+        self.under_memory_pressure.load(Ordering::Relaxed)
+    }
+
+    // Returns the previous flag.
+    pub(crate) fn set_under_memory_pressure(&self, under_pressure: bool) -> bool {
+        self.under_memory_pressure
+            .swap(under_pressure, Ordering::Relaxed)
+    }
+
+    pub(crate) fn get_filter_false_positives(&self) -> u64 {
+        self.filter_false_positives.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn add_filter_false_positive(&self) {
+        self.filter_false_positives.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the elapsed time (in milliseconds) since started, the count of the filtered
+    /// blocks, the count of the matched blocks and the downloaded bytes in the meantime.
+    pub(crate) fn get_filter_sync_counts(&self) -> (u64, u64, u64, u64) {
+        (
+            unix_time_as_millis().saturating_sub(self.started_at),
+            self.filtered_blocks.load(Ordering::Relaxed),
+            self.filter_matched_blocks.load(Ordering::Relaxed),
+            self.downloaded_bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    pub(crate) fn get_last_refreshed_at(&self) -> u64 {
+        self.last_refreshed_at.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_last_refreshed_at(&self, timestamp: u64) {
+        self.last_refreshed_at.store(timestamp, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_filtered_blocks(&self, filtered_count: u64, matched_count: u64) {
+        self.filtered_blocks
+            .fetch_add(filtered_count, Ordering::Relaxed);
+        self.filter_matched_blocks
+            .fetch_add(matched_count, Ordering::Relaxed);
+    }
+
+    /// The count of peers which should agree on the check points and the block filter
+    /// hashes before trusting them.
+    ///
+    /// It's the majority of the max outbound peers, and not less than the min block
+    /// filter peers (if there are enough outbound peers), so a single lying peer can't
+    /// hide transactions from the client.
     pub(crate) fn required_peers_count(&self) -> usize {
-        let required_peers_count = ((self.get_max_outbound_peers() + 1) / 2) as usize;
+        let max_outbound_peers = self.get_max_outbound_peers() as usize;
+        let required_peers_count = (max_outbound_peers + 1) / 2;
         if required_peers_count == 0 {
             panic!("max outbound peers shouldn't be zero!");
         }
-        required_peers_count
+        required_peers_count.max(self.min_block_filter_peers.min(max_outbound_peers))
     }
 
     pub(crate) fn calc_check_point_number(&self, index: u32) -> BlockNumber {
@@ -1193,10 +1676,14 @@ impl Peers {
             }
         }
     }
-    pub(crate) fn mark_fetching_txs_missing(&self, tx_hashes: &[Byte32]) {
+    // mark transactions as missing only when all peers which the request is sent to respond missing
+    pub(crate) fn mark_fetching_txs_missing(&self, peer_index: PeerIndex, tx_hashes: &[Byte32]) {
         for tx_hash in tx_hashes {
             if let Some(mut value) = self.fetching_txs.get_mut(tx_hash) {
-                value.missing = true;
+                value.missing_peers.insert(peer_index);
+                if value.sent_peers.is_subset(&value.missing_peers) {
+                    value.missing = true;
+                }
             }
         }
     }
@@ -1217,7 +1704,13 @@ impl Peers {
             if let Some(request) = peer.get_txs_proof_request() {
                 for tx_hash in request.tx_hashes() {
                     if let Some(mut pair) = self.fetching_txs.get_mut(&tx_hash.pack()) {
-                        pair.value_mut().timeout = true;
+                        let info = pair.value_mut();
+                        info.sent_peers.remove(&peer_index);
+                        if info.sent_peers.is_empty() {
+                            info.timeout = true;
+                        } else if info.sent_peers.is_subset(&info.missing_peers) {
+                            info.missing = true;
+                        }
                     }
                 }
             }
@@ -1233,13 +1726,19 @@ impl Peers {
             }
         }
     }
-    pub(crate) fn fetching_idle_txs(&self, tx_hashes: &[Byte32], now: u64) {
+    pub(crate) fn fetching_idle_txs(
+        &self,
+        tx_hashes: &[Byte32],
+        peer_indexes: &[PeerIndex],
+        now: u64,
+    ) {
         for tx_hash in tx_hashes {
             if let Some(mut value) = self.fetching_txs.get_mut(tx_hash) {
                 if value.first_sent == 0 {
                     value.first_sent = now;
                 }
                 value.timeout = false;
+                value.sent_peers.extend(peer_indexes.iter().cloned());
             }
         }
     }
@@ -1248,6 +1747,19 @@ impl Peers {
         &self.matched_blocks
     }
 
+    /// Drops the downloaded matched blocks which are not applied yet, they are downloaded
+    /// again later. Returns the count of the dropped blocks.
+    pub(crate) fn drop_downloaded_matched_blocks(&self) -> usize {
+        let mut matched_blocks = self.matched_blocks.write().expect("poisoned");
+        let mut dropped = 0;
+        for (_, block_opt) in matched_blocks.values_mut() {
+            if block_opt.take().is_some() {
+                dropped += 1;
+            }
+        }
+        dropped
+    }
+
     #[cfg(not(test))]
     pub(crate) fn get_max_outbound_peers(&self) -> u32 {
         self.max_outbound_peers
@@ -1271,7 +1783,9 @@ impl Peers {
     pub(crate) fn remove_peer(&self, index: PeerIndex) {
         self.mark_fetching_headers_timeout(index);
         self.mark_fetching_txs_timeout(index);
-        self.inner.remove(&index);
+        if self.inner.remove(&index).is_some() {
+            self.emit_chain_event(ChainEvent::PeerLost(index));
+        }
     }
 
     pub(crate) fn get_peers_index(&self) -> Vec<PeerIndex> {
@@ -1323,6 +1837,239 @@ impl Peers {
         self.update_prove_state(index, prove_state)
     }
 
+    // Update the node id of the peer.
+    //
+    // If there is another session to the same node, this peer is marked as a duplicate
+    // session and it will not be used for proof syncing; returns the other session.
+    pub(crate) fn update_node_id(&self, index: PeerIndex, node_id: PeerId) -> Option<PeerIndex> {
+        let existed = self.inner.iter().find_map(|item| {
+            let (peer_index, peer) = item.pair();
+            if *peer_index != index && !peer.is_duplicate && peer.node_id.as_ref() == Some(&node_id)
+            {
+                Some(*peer_index)
+            } else {
+                None
+            }
+        });
+        if let Some(mut peer) = self.inner.get_mut(&index) {
+            peer.node_id = Some(node_id);
+            peer.is_duplicate = existed.is_some();
+        }
+        existed
+    }
+
+    // Let a duplicate session take over the proof syncing, before the peer is removed.
+    pub(crate) fn take_over_by_duplicate_session(&self, index: PeerIndex) -> Option<PeerIndex> {
+        let node_id = self
+            .inner
+            .get(&index)
+            .filter(|peer| !peer.is_duplicate)
+            .and_then(|peer| peer.node_id.clone())?;
+        self.inner.iter_mut().find_map(|mut item| {
+            let (peer_index, peer) = item.pair_mut();
+            if *peer_index != index && peer.is_duplicate && peer.node_id.as_ref() == Some(&node_id)
+            {
+                peer.is_duplicate = false;
+                Some(*peer_index)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub(crate) fn is_filter_unreliable(&self, index: &PeerIndex) -> bool {
+        self.inner
+            .get(index)
+            .map(|peer| peer.is_filter_unreliable)
+            .unwrap_or(false)
+    }
+
+    // Stop using the peer for filters, the failure is only counted once.
+    pub(crate) fn mark_filter_unreliable(&self, index: PeerIndex, code: StatusCode) {
+        if let Some(mut peer) = self.inner.get_mut(&index) {
+            if !peer.is_filter_unreliable {
+                peer.is_filter_unreliable = true;
+                *peer.failure_counts.entry(code).or_default() += 1;
+            }
+        }
+    }
+
+    pub(crate) fn get_filter_timeouts(&self, index: &PeerIndex) -> u32 {
+        self.inner
+            .get(index)
+            .map(|peer| peer.filter_timeouts)
+            .unwrap_or(0)
+    }
+
+    /// Counts a filter request which times out, the peer isn't used for filters if its
+    /// requests time out too many times in a row.
+    pub(crate) fn add_filter_timeout(&self, index: PeerIndex) {
+        let too_many = if let Some(mut peer) = self.inner.get_mut(&index) {
+            peer.filter_timeouts += 1;
+            peer.filter_timeouts >= MAX_FILTER_TIMEOUTS_PER_PEER
+        } else {
+            return;
+        };
+        if too_many {
+            self.mark_filter_unreliable(index, StatusCode::RequestIsTimeout);
+        } else {
+            self.add_failure(index, StatusCode::RequestIsTimeout);
+        }
+    }
+
+    pub(crate) fn reset_filter_timeouts(&self, index: PeerIndex) {
+        if let Some(mut peer) = self.inner.get_mut(&index) {
+            peer.filter_timeouts = 0;
+        }
+    }
+
+    pub(crate) fn update_filter_downloaded_number(&self, index: PeerIndex, number: BlockNumber) {
+        if let Some(mut peer) = self.inner.get_mut(&index) {
+            peer.filter_downloaded_number = peer.filter_downloaded_number.max(number);
+        }
+    }
+
+    /// Records the best tip which is announced by the peer, through the headers in the sync
+    /// protocol or the compact blocks in the relay protocol.
+    pub(crate) fn update_announced_tip(&self, index: PeerIndex, number: BlockNumber) {
+        if let Some(mut peer) = self.inner.get_mut(&index) {
+            if peer
+                .get_announced_tip_number()
+                .map(|announced_number| announced_number >= number)
+                .unwrap_or(false)
+            {
+                return;
+            }
+            // Keep the timestamp if the announced tip is already ahead of the proved tip.
+            let ts = if peer.is_announced_tip_ahead() {
+                peer.announced_tip.map(|(_, ts)| ts)
+            } else {
+                None
+            }
+            .unwrap_or_else(unix_time_as_millis);
+            peer.announced_tip = Some((number, ts));
+        }
+    }
+
+    /// Returns the node id and the highest downloaded block filter number of each peer.
+    pub(crate) fn get_filter_downloaded_numbers(&self) -> Vec<(Option<PeerId>, BlockNumber)> {
+        self.inner
+            .iter()
+            .map(|item| {
+                let peer = item.value();
+                (peer.node_id.clone(), peer.filter_downloaded_number)
+            })
+            .collect()
+    }
+
+    /// Records the peer which sent the block filters of the matched blocks.
+    pub(crate) fn add_matched_block_sources(&self, index: PeerIndex, block_hashes: &[Byte32]) {
+        if let Some(mut peer) = self.inner.get_mut(&index) {
+            peer.filter_matched_blocks += block_hashes.len() as u64;
+        }
+        let mut sources = self.matched_block_sources.lock().expect("poisoned");
+        for block_hash in block_hashes {
+            sources.insert(block_hash.clone(), index);
+        }
+        while sources.len() > MATCHED_BLOCK_SOURCES_SIZE {
+            sources.pop_front();
+        }
+    }
+
+    /// Checks the peer which sent the block filter of the downloaded matched block, it's
+    /// marked as unreliable if too many of its matched blocks are false positives.
+    pub(crate) fn check_matched_block_source(&self, block_hash: &Byte32, is_false_positive: bool) {
+        let index_opt = self
+            .matched_block_sources
+            .lock()
+            .expect("poisoned")
+            .remove(block_hash);
+        if !is_false_positive {
+            return;
+        }
+        if let Some(index) = index_opt {
+            let too_many = if let Some(mut peer) = self.inner.get_mut(&index) {
+                peer.filter_false_positives += 1;
+                peer.filter_false_positives >= MAX_FILTER_FALSE_POSITIVES_PER_PEER
+                    && peer.filter_false_positives * 2 > peer.filter_matched_blocks
+            } else {
+                false
+            };
+            if too_many {
+                self.mark_filter_unreliable(
+                    index,
+                    StatusCode::BlockFiltersHaveTooManyFalsePositives,
+                );
+            }
+        }
+    }
+
+    pub(crate) fn mark_outdated(&self, index: PeerIndex) {
+        if let Some(mut peer) = self.inner.get_mut(&index) {
+            peer.is_outdated = true;
+        }
+    }
+
+    /// Bans the peer, and emits the event.
+    pub(crate) fn ban_peer(
+        &self,
+        nc: &dyn CKBProtocolContext,
+        index: PeerIndex,
+        duration: Duration,
+        reason: String,
+    ) {
+        nc.ban_peer(index, duration, reason.clone());
+        self.emit_chain_event(ChainEvent::PeerBanned {
+            peer: index,
+            reason,
+        });
+    }
+
+    pub(crate) fn add_failure(&self, index: PeerIndex, code: StatusCode) {
+        if let Some(mut peer) = self.inner.get_mut(&index) {
+            *peer.failure_counts.entry(code).or_default() += 1;
+        }
+    }
+
+    /// Counts a message which is received from the peer through the protocol.
+    pub(crate) fn record_received_message(
+        &self,
+        index: PeerIndex,
+        protocol_id: ProtocolId,
+        bytes: usize,
+    ) {
+        if let Some(mut peer) = self.inner.get_mut(&index) {
+            let counts = peer.message_counts.entry(protocol_id).or_default();
+            counts.received_messages += 1;
+            counts.received_bytes += bytes as u64;
+        }
+    }
+
+    /// Counts a message which is sent to the peer through the protocol.
+    pub(crate) fn record_sent_message(
+        &self,
+        index: PeerIndex,
+        protocol_id: ProtocolId,
+        bytes: usize,
+    ) {
+        if let Some(mut peer) = self.inner.get_mut(&index) {
+            let counts = peer.message_counts.entry(protocol_id).or_default();
+            counts.sent_messages += 1;
+            counts.sent_bytes += bytes as u64;
+        }
+    }
+
+    /// Counts a misbehavior of the peer in the sync protocol, returns the total count.
+    pub(crate) fn add_sync_misbehavior(&self, index: PeerIndex) -> u32 {
+        self.inner
+            .get_mut(&index)
+            .map(|mut peer| {
+                peer.sync_misbehaviors += 1;
+                peer.sync_misbehaviors
+            })
+            .unwrap_or_default()
+    }
+
     pub(crate) fn request_last_state(&self, index: PeerIndex) -> Result<(), Status> {
         if let Some(mut peer) = self.inner.get_mut(&index) {
             let now = unix_time_as_millis();
@@ -1354,17 +2101,33 @@ impl Peers {
         Ok(())
     }
 
-    pub(crate) fn update_prove_state(
+    pub(crate) fn update_prove_state<T: Into<Arc<ProveState>>>(
         &self,
         index: PeerIndex,
-        state: ProveState,
+        state: T,
     ) -> Result<(), Status> {
         if let Some(mut peer) = self.inner.get_mut(&index) {
+            let state = state.into();
             let has_reorg = !state.reorg_last_headers.is_empty();
+            let has_advanced = peer
+                .state
+                .get_prove_state()
+                .map(|prev_state| {
+                    prev_state.get_last_header().total_difficulty()
+                        < state.get_last_header().total_difficulty()
+                })
+                .unwrap_or(true);
+            if has_advanced {
+                peer.advanced_ts = unix_time_as_millis();
+            }
+            let is_first_proved = peer.state.get_prove_state().is_none();
             peer.state = peer.state.take().receive_last_state_proof(state)?;
             if has_reorg {
                 peer.latest_block_filter_hashes.clear();
             }
+            if is_first_proved {
+                self.emit_chain_event(ChainEvent::PeerProved(index));
+            }
         }
         Ok(())
     }
@@ -1536,9 +2299,30 @@ impl Peers {
         should_get_blocks: bool,
     ) {
         if let Some(mut peer) = self.inner.get_mut(&index) {
-            peer.blocks_proof_request = request.map(|content| {
-                BlocksProofRequest::new(content, unix_time_as_millis(), should_get_blocks)
-            });
+            // The request is cleared when the response is received.
+            let is_responded = request.is_none();
+            let prev_request = mem::replace(
+                &mut peer.blocks_proof_request,
+                request.map(|content| {
+                    BlocksProofRequest::new(content, unix_time_as_millis(), should_get_blocks)
+                }),
+            );
+            if let Some(prev_request) = prev_request.filter(|_| is_responded) {
+                peer.record_latency(RequestKind::BlocksProof, prev_request.when_sent);
+            }
+        }
+    }
+    // Request proofs for matched blocks, also fetch headers by the same request.
+    pub(crate) fn update_coalesced_blocks_proof_request(
+        &self,
+        index: PeerIndex,
+        request: packed::GetBlocksProof,
+        headers_only: &[Byte32],
+    ) {
+        if let Some(mut peer) = self.inner.get_mut(&index) {
+            let mut request = BlocksProofRequest::new(request, unix_time_as_millis(), true);
+            request.headers_only.extend(headers_only.iter().cloned());
+            peer.blocks_proof_request = Some(request);
         }
     }
     pub(crate) fn update_blocks_request(&self, index: PeerIndex, hashes: Option<Vec<Byte32>>) {
@@ -1553,8 +2337,16 @@ impl Peers {
         request: Option<packed::GetTransactionsProof>,
     ) {
         if let Some(mut peer) = self.inner.get_mut(&index) {
-            peer.txs_proof_request = request
-                .map(|content| TransactionsProofRequest::new(content, unix_time_as_millis()));
+            // The request is cleared when the response is received.
+            let is_responded = request.is_none();
+            let prev_request = mem::replace(
+                &mut peer.txs_proof_request,
+                request
+                    .map(|content| TransactionsProofRequest::new(content, unix_time_as_millis())),
+            );
+            if let Some(prev_request) = prev_request.filter(|_| is_responded) {
+                peer.record_latency(RequestKind::TransactionsProof, prev_request.when_sent);
+            }
         }
     }
 
@@ -1669,7 +2461,10 @@ impl Peers {
             .iter()
             .filter_map(|item| {
                 let (peer_index, peer) = item.pair();
-                if peer.state.require_new_last_state(before_ts) {
+                if !peer.is_duplicate
+                    && !peer.is_outdated
+                    && peer.state.require_new_last_state(before_ts)
+                {
                     Some(*peer_index)
                 } else {
                     None
@@ -1755,6 +2550,9 @@ impl Peers {
             .iter()
             .filter_map(|item| {
                 let (peer_index, peer) = item.pair();
+                if peer.is_filter_unreliable {
+                    return None;
+                }
                 peer.state.get_prove_state().and_then(|_| {
                     let latest_block_filter_hashes = &item.value().latest_block_filter_hashes;
                     let check_point_number = latest_block_filter_hashes.get_check_point_number();
@@ -1778,6 +2576,12 @@ impl Peers {
             hashes_sizes.sort();
             hashes_sizes[required_peers_count - 1]
         };
+        // The hashes of the blocks which are deeper than this couldn't be changed by reorgs.
+        let stable_number = self
+            .get_best_proved_tip_number()
+            .unwrap_or(0)
+            .saturating_sub(LAST_N_BLOCKS);
+        let mut diverged_peers = Vec::new();
         let mut result = Vec::new();
         for index in 0..length_max {
             let map = peers_with_data
@@ -1803,14 +2607,27 @@ impl Peers {
                     );
                 let hash = hash_opt.expect("checked: must be found");
                 if count_max != peers_with_data.len() {
-                    peers_with_data
-                        .retain(|_, hashes| matches!(hashes.get(index), Some(tmp) if *tmp == hash));
+                    let is_stable =
+                        finalized_check_point_number + index as BlockNumber + 1 <= stable_number;
+                    peers_with_data.retain(|peer_index, hashes| match hashes.get(index) {
+                        Some(tmp) if *tmp == hash => true,
+                        Some(_) => {
+                            if is_stable {
+                                diverged_peers.push(*peer_index);
+                            }
+                            false
+                        }
+                        None => false,
+                    });
                 }
                 result.push(hash);
             } else {
                 break;
             }
         }
+        for peer_index in diverged_peers {
+            self.mark_filter_unreliable(peer_index, StatusCode::BlockFilterHashesIsDiverged);
+        }
         result
     }
 
@@ -1894,6 +2711,62 @@ impl Peers {
             .collect()
     }
 
+    // Find the proved peers, which proved tips don't advance in a long time,
+    // but other proved peers have better tips, or they announce better tips by themselves.
+    pub(crate) fn get_peers_which_are_stale(&self, now: u64, timeout: u64) -> Vec<PeerIndex> {
+        let best_total_difficulty_opt = self
+            .inner
+            .iter()
+            .filter_map(|item| {
+                item.value()
+                    .state
+                    .get_prove_state()
+                    .map(|state| state.get_last_header().total_difficulty())
+            })
+            .max();
+        let best_total_difficulty = if let Some(total_difficulty) = best_total_difficulty_opt {
+            total_difficulty
+        } else {
+            return Vec::new();
+        };
+        self.inner
+            .iter()
+            .filter_map(|item| {
+                let (peer_index, peer) = item.pair();
+                peer.state.get_prove_state().and_then(|state| {
+                    let is_behind_others =
+                        state.get_last_header().total_difficulty() < best_total_difficulty;
+                    // The peer announces a better tip by itself, but doesn't prove it.
+                    let is_behind_announced = peer.is_announced_tip_ahead()
+                        && peer
+                            .announced_tip
+                            .map(|(_, ts)| now > ts.max(peer.advanced_ts) + timeout)
+                            .unwrap_or(false);
+                    if (is_behind_others && now > peer.advanced_ts + timeout) || is_behind_announced
+                    {
+                        Some(*peer_index)
+                    } else {
+                        None
+                    }
+                })
+            })
+            .collect()
+    }
+
+    // Get the number of the proved tip which has the most total difficulty among all peers.
+    pub(crate) fn get_best_proved_tip_number(&self) -> Option<BlockNumber> {
+        self.inner
+            .iter()
+            .filter_map(|item| {
+                item.value().state.get_prove_state().map(|state| {
+                    let header = state.get_last_header();
+                    (header.total_difficulty(), header.header().number())
+                })
+            })
+            .max()
+            .map(|(_, number)| number)
+    }
+
     pub(crate) fn get_all_proved_check_points(
         &self,
     ) -> HashMap<PeerIndex, (u32, Vec<packed::Byte32>)> {
@@ -1910,27 +2783,42 @@ impl Peers {
             .collect()
     }
 
-    pub(crate) fn get_all_prove_states(&self) -> Vec<(PeerIndex, ProveState)> {
+    pub(crate) fn get_all_prove_states(&self) -> Vec<(PeerIndex, Arc<ProveState>)> {
         self.inner
             .iter()
             .filter_map(|item| {
                 let (peer_index, peer) = item.pair();
                 peer.state
-                    .get_prove_state()
-                    .map(|state| (*peer_index, state.to_owned()))
+                    .get_shared_prove_state()
+                    .map(|state| (*peer_index, Arc::clone(state)))
             })
             .collect()
     }
 
+    pub(crate) fn get_proved_peers_count(&self) -> usize {
+        self.inner
+            .iter()
+            .filter(|item| item.value().state.get_prove_state().is_some())
+            .count()
+    }
+
+    pub(crate) fn get_min_proved_peers(&self) -> usize {
+        self.min_proved_peers
+    }
+
+    pub(crate) fn has_enough_proved_peers(&self) -> bool {
+        self.get_proved_peers_count() >= self.min_proved_peers
+    }
+
     pub(crate) fn find_if_a_header_is_proved(
         &self,
         header: &VerifiableHeader,
-    ) -> Option<(PeerIndex, ProveState)> {
+    ) -> Option<(PeerIndex, Arc<ProveState>)> {
         self.inner.iter().find_map(|item| {
             let (peer_index, peer) = item.pair();
-            peer.state.get_prove_state().and_then(|prove_state| {
+            peer.state.get_shared_prove_state().and_then(|prove_state| {
                 if prove_state.is_same_as(header) {
-                    Some((*peer_index, prove_state.clone()))
+                    Some((*peer_index, Arc::clone(prove_state)))
                 } else {
                     None
                 }
@@ -1952,6 +2840,53 @@ impl Peers {
         })
     }
 
+    // Returns how many requests of the kind could be sent to all peers.
+    pub(crate) fn get_remaining_requests_count(&self, kind: RequestKind) -> usize {
+        let limit = match kind {
+            RequestKind::BlocksProof => self.request_limits.blocks_proof,
+            RequestKind::TransactionsProof => self.request_limits.txs_proof,
+            RequestKind::Blocks => return usize::MAX,
+        };
+        let count = self
+            .inner
+            .iter()
+            .filter(|item| item.value().has_request(kind))
+            .count();
+        limit.saturating_sub(count)
+    }
+
+    // Whether a request of the kind could be sent to the peer.
+    pub(crate) fn could_request(&self, index: &PeerIndex, kind: RequestKind) -> bool {
+        let is_peer_idle = self
+            .inner
+            .get(index)
+            .map(|peer| {
+                !peer.has_request(kind)
+                    && peer.outstanding_requests_count() < self.request_limits.per_peer
+            })
+            .unwrap_or(false);
+        is_peer_idle && self.get_remaining_requests_count(kind) > 0
+    }
+
+    // Sort the peers by the latencies of the responses for the kind of request, the peers
+    // without any responses yet are put in the front, so they have chances to be measured.
+    pub(crate) fn sort_by_latency(&self, peer_indexes: &mut [PeerIndex], kind: RequestKind) {
+        peer_indexes.sort_by_key(|peer_index| {
+            self.inner
+                .get(peer_index)
+                .and_then(|peer| peer.get_latency(kind))
+                .unwrap_or(0)
+        });
+    }
+
+    /// The best proved peers which could be used for filters.
+    pub(crate) fn get_best_filter_peers(&self, best_tip: &packed::Header) -> Vec<PeerIndex> {
+        self.get_best_proved_peers(best_tip)
+            .into_iter()
+            .filter(|peer_index| !self.is_filter_unreliable(peer_index))
+            .collect()
+    }
+
     pub(crate) fn get_best_proved_peers(&self, best_tip: &packed::Header) -> Vec<PeerIndex> {
         self.get_all_prove_states()
             .into_iter()
@@ -5,6 +5,8 @@ use log::{trace, warn};
 use numext_fixed_uint::{prelude::UintConvert as _, U512};
 use rand::{thread_rng, Rng as _};
 
+use super::constant::MAX_SAMPLES_COUNT;
+
 const C_FRACTION: f64 = 0.5;
 const LAMBDA: u32 = 50;
 
@@ -94,7 +96,8 @@ pub(crate) fn sample_blocks(
 ) -> (U256, Vec<U256>) {
     let blocks_count = last_number - start_number;
     let k = estimate_k(last_n_blocks, blocks_count, C_FRACTION);
-    let samples_count = estimate_samples_count(blocks_count, last_n_blocks, k, LAMBDA);
+    let samples_count = estimate_samples_count(blocks_count, last_n_blocks, k, LAMBDA)
+        .min(MAX_SAMPLES_COUNT as BlockNumber);
 
     let delta = C_FRACTION.powf(k);
     let difficulty_range = last_difficulty - start_difficulty;
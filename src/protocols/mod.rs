@@ -5,8 +5,10 @@ use ckb_types::core::BlockNumber;
 #[macro_use]
 mod status;
 
+mod events;
 mod filter;
 pub(crate) mod light_client;
+mod rate_limiter;
 mod relayer;
 mod synchronizer;
 
@@ -15,8 +17,10 @@ pub(crate) use filter::GET_BLOCK_FILTERS_TOKEN;
 #[cfg(test)]
 pub(crate) use light_client::{FetchInfo, LastState, PeerState, ProveRequest, ProveState};
 
+pub use events::ChainEvent;
 pub(crate) use filter::FilterProtocol;
-pub(crate) use light_client::{LightClientProtocol, Peers};
+pub use light_client::Peers;
+pub(crate) use light_client::{LightClientProtocol, RequestKind, RequestLimits};
 pub(crate) use relayer::{PendingTxs, RelayProtocol};
 pub(crate) use status::{Status, StatusCode};
 pub(crate) use synchronizer::SyncProtocol;
@@ -31,5 +35,7 @@ pub const LAST_N_BLOCKS: BlockNumber = 100;
 pub const GET_BLOCKS_PROOF_LIMIT: usize = 1000;
 // Copy from ckb/util/light-client-protocol-server
 pub const GET_TRANSACTIONS_PROOF_LIMIT: usize = 1000;
+// Copy from ckb/sync/src/filter
+pub const BLOCK_FILTERS_BATCH_SIZE: BlockNumber = 1000;
 // Copy from ckb/sync
 pub const CHECK_POINT_INTERVAL: BlockNumber = 2000;
@@ -0,0 +1,75 @@
+//! Limit the download rate of the block filters and the matched blocks.
+//!
+//! The sizes of the responses are unknown before they are received, so the
+//! received bytes are counted after the fact, and no new requests are sent
+//! until the bytes over the limit are paid off.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
+#[derive(Default)]
+pub(crate) struct DownloadRateLimiter {
+    // Bytes per second, zero means no limit.
+    max_rate: AtomicU64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Default)]
+struct Bucket {
+    // The available bytes, it's negative if the received bytes are over the limit.
+    tokens: i64,
+    // The timestamp (in milliseconds) when the tokens are refilled.
+    refilled_at: u64,
+}
+
+impl DownloadRateLimiter {
+    pub(crate) fn set_max_rate(&self, max_rate: u64) {
+        self.max_rate.store(max_rate, Ordering::Relaxed);
+    }
+
+    pub(crate) fn get_max_rate(&self) -> u64 {
+        self.max_rate.load(Ordering::Relaxed)
+    }
+
+    /// Counts the received bytes.
+    pub(crate) fn consume(&self, bytes: usize, now: u64) {
+        let max_rate = self.get_max_rate();
+        if max_rate == 0 {
+            return;
+        }
+        let mut bucket = self.bucket.lock().expect("poisoned");
+        bucket.refill(max_rate, now);
+        bucket.tokens = bucket.tokens.saturating_sub(bytes as i64);
+    }
+
+    /// Checks if new requests could be sent.
+    pub(crate) fn is_available(&self, now: u64) -> bool {
+        let max_rate = self.get_max_rate();
+        if max_rate == 0 {
+            return true;
+        }
+        let mut bucket = self.bucket.lock().expect("poisoned");
+        bucket.refill(max_rate, now);
+        bucket.tokens >= 0
+    }
+}
+
+impl Bucket {
+    fn refill(&mut self, max_rate: u64, now: u64) {
+        if self.refilled_at == 0 {
+            // The bucket is full at the beginning, for a burst of one second.
+            self.tokens = max_rate as i64;
+        } else if now > self.refilled_at {
+            let refilled = (now - self.refilled_at).saturating_mul(max_rate) / 1000;
+            self.tokens = self
+                .tokens
+                .saturating_add(refilled as i64)
+                .min(max_rate as i64);
+        } else {
+            return;
+        }
+        self.refilled_at = now;
+    }
+}
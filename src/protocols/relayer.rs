@@ -3,19 +3,38 @@ use ckb_network::{
     async_trait, bytes::Bytes, extract_peer_id, CKBProtocolContext, CKBProtocolHandler, PeerId,
     PeerIndex,
 };
-use ckb_types::core::{Cycle, TransactionView};
+use ckb_systemtime::unix_time_as_millis;
+use ckb_types::core::{BlockNumber, Cycle, TransactionView};
 use ckb_types::{packed, prelude::*};
+use ckb_verification::NonContextualTransactionVerifier;
 use linked_hash_map::LinkedHashMap;
-use log::{debug, trace, warn};
+use log::{debug, info, trace, warn};
+use rand::{seq::SliceRandom as _, Rng as _};
+use std::cmp;
 use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
+use tracing::instrument;
 
-use crate::protocols::{Peers, BAD_MESSAGE_BAN_TIME};
+use crate::protocols::{ChainEvent, Peers, BAD_MESSAGE_BAN_TIME};
 use crate::storage::Storage;
+use crate::types::{LightClientConfig, PendingTxsEviction};
 
 const CHECK_PENDING_TXS_TOKEN: u64 = 0;
 
+// The pending transactions are rebroadcast with exponential backoff, in case the peers which
+// they were announced to are disconnected.
+const REBROADCAST_INITIAL_INTERVAL: Duration = Duration::from_secs(30);
+const REBROADCAST_MAX_INTERVAL: Duration = Duration::from_secs(30 * 60);
+// The pending transactions are dropped if they are not committed in this duration, by default.
+const PENDING_TX_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+// The max count of the transaction hashes which are requested from peers, but not received yet.
+const MAX_REQUESTED_TX_HASHES: usize = 1024;
+// The max count of the transaction hashes which are requested from each peer in an interval,
+// the rest of the relayed hashes are ignored, so a busy mempool isn't downloaded entirely.
+const MAX_REQUESTED_TX_HASHES_PER_PEER: usize = 64;
+const REQUESTED_TX_HASHES_INTERVAL: Duration = Duration::from_secs(10);
+
 pub(crate) struct RelayProtocol {
     connected_peers: Arc<Peers>,
     // Record the peers which have opened the relay protocol, value is used to close the protocol in the inactive period
@@ -25,13 +44,84 @@ pub(crate) struct RelayProtocol {
     consensus: Consensus,
     storage: Storage,
     v3: bool,
+    // The count of peers which each pending transaction is announced to, zero means all.
+    fanout: usize,
+    // Whether to request the transactions which are relayed by peers, to find the ones which
+    // are related to the filter scripts before they are committed.
+    track_relayed_txs: bool,
+    // The hashes of the transactions which are relayed by peers and requested from them.
+    requested_tx_hashes: LinkedHashMap<packed::Byte32, ()>,
+    // The start of the current interval and the count of the hashes requested in it, for
+    // each peer.
+    requested_budgets: HashMap<PeerIndex, (Instant, usize)>,
 }
 
 // a simple struct to store the pending transactions in memory with size limit
 pub struct PendingTxs {
-    txs: LinkedHashMap<packed::Byte32, (packed::Transaction, Cycle, HashSet<PeerId>)>,
+    txs: LinkedHashMap<packed::Byte32, PendingTx>,
+    // The transactions which are relayed by peers and related to the filter scripts, with the
+    // time when they are seen. They are not verified by scripts, since the input cells of
+    // others can't be resolved.
+    seen_txs: LinkedHashMap<packed::Byte32, (packed::Transaction, Instant)>,
+    // The transactions which are just committed in the matched blocks, with the hashes of
+    // the blocks. The transactions which are unrelated to the filter scripts are not indexed
+    // by the storage, so keep them here to report their statuses.
+    committed_txs: LinkedHashMap<packed::Byte32, (packed::Transaction, packed::Byte32)>,
+    // The transactions which are submitted asynchronously and are being verified.
+    verifying_txs: HashMap<packed::Byte32, packed::Transaction>,
+    // The transactions which are submitted asynchronously but rejected, with the reasons.
+    rejected_txs: LinkedHashMap<packed::Byte32, (packed::Transaction, String)>,
     updated_at: Instant,
     limit: usize,
+    ttl: Duration,
+    eviction: PendingTxsEviction,
+    max_relay_delay: Duration,
+    counts: PendingTxsCounts,
+}
+
+// A pending transaction which is waiting for relay.
+struct PendingTx {
+    tx: packed::Transaction,
+    cycles: Cycle,
+    // The fee rate in shannons per KB.
+    fee_rate: u64,
+    // The peers which it's announced to since the last rebroadcast.
+    announced_to: HashSet<PeerId>,
+    // The rebroadcast schedule: the time when it's submitted, the time of the next
+    // rebroadcast and the current backoff interval.
+    submitted_at: Instant,
+    rebroadcast_at: Instant,
+    rebroadcast_interval: Duration,
+    // The time when it could be announced for the first time, it's delayed randomly to make
+    // it harder to link the transaction to this client.
+    relay_after: Option<Instant>,
+    // The hash of the matched block which it's proposed in.
+    proposed_in: Option<packed::Byte32>,
+    relay_stats: RelayStats,
+}
+
+/// The relay statistics of a pending transaction.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct RelayStats {
+    /// The count of the peers which it's announced to.
+    pub(crate) announced: u64,
+    /// The count of the requests for it from peers.
+    pub(crate) requested: u64,
+    /// The time (in milliseconds) when it's announced last time.
+    pub(crate) announced_at: Option<u64>,
+}
+
+/// The counts of the pending pool since the light client is started.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct PendingTxsCounts {
+    /// The count of the pending transactions, it's the current one.
+    pub(crate) pending: usize,
+    /// The count of the rebroadcasts.
+    pub(crate) rebroadcasted: u64,
+    /// The counts of the transactions which are committed, expired or replaced.
+    pub(crate) committed: u64,
+    pub(crate) expired: u64,
+    pub(crate) replaced: u64,
 }
 
 impl Default for PendingTxs {
@@ -44,32 +134,320 @@ impl PendingTxs {
     pub fn new(limit: usize) -> Self {
         Self {
             txs: LinkedHashMap::new(),
+            seen_txs: LinkedHashMap::new(),
+            committed_txs: LinkedHashMap::new(),
+            verifying_txs: HashMap::new(),
+            rejected_txs: LinkedHashMap::new(),
             updated_at: Instant::now(),
             limit,
+            ttl: PENDING_TX_TTL,
+            eviction: PendingTxsEviction::default(),
+            max_relay_delay: Duration::ZERO,
+            counts: PendingTxsCounts::default(),
         }
     }
 
-    pub fn push(&mut self, tx: TransactionView, cycles: Cycle) {
-        self.txs
-            .insert(tx.hash(), (tx.data(), cycles, HashSet::new()));
+    pub(crate) fn set_ttl(&mut self, ttl: Duration) {
+        self.ttl = ttl;
+    }
+
+    pub(crate) fn set_eviction(&mut self, eviction: PendingTxsEviction) {
+        self.eviction = eviction;
+    }
+
+    /// Sets the max delay before a pending transaction is announced for the first time, the
+    /// actual delay is chosen randomly. Zero means no delay.
+    pub(crate) fn set_max_relay_delay(&mut self, max_delay: Duration) {
+        self.max_relay_delay = max_delay;
+    }
+
+    /// Pushes a pending transaction, returns the evicted ones if the pending pool is full.
+    pub fn push(
+        &mut self,
+        tx: TransactionView,
+        cycles: Cycle,
+        fee_rate: u64,
+    ) -> Vec<packed::Byte32> {
+        let now = Instant::now();
+        let relay_after = if self.max_relay_delay.is_zero() {
+            None
+        } else {
+            let delay = rand::thread_rng().gen_range(Duration::ZERO..=self.max_relay_delay);
+            Some(now + delay)
+        };
+        self.txs.insert(
+            tx.hash(),
+            PendingTx {
+                tx: tx.data(),
+                cycles,
+                fee_rate,
+                announced_to: HashSet::new(),
+                submitted_at: now,
+                rebroadcast_at: now + REBROADCAST_INITIAL_INTERVAL,
+                rebroadcast_interval: REBROADCAST_INITIAL_INTERVAL,
+                relay_after,
+                proposed_in: None,
+                relay_stats: RelayStats::default(),
+            },
+        );
+        self.updated_at = now;
         if self.txs.len() > self.limit {
-            self.txs.pop_front();
+            // The transaction which is just pushed and its ancestors are never evicted.
+            let mut kept = HashSet::new();
+            kept.insert(tx.hash());
+            for (hash, pending_tx) in self.txs.iter().rev() {
+                if kept.contains(hash) {
+                    kept.extend(self.get_parents(&pending_tx.tx));
+                }
+            }
+            let mut candidates = self.txs.iter().filter(|(hash, _)| !kept.contains(hash));
+            let evicted = match self.eviction {
+                PendingTxsEviction::Oldest => candidates.next(),
+                PendingTxsEviction::LowestFeeRate => {
+                    candidates.min_by_key(|(_, pending_tx)| pending_tx.fee_rate)
+                }
+            }
+            .map(|(hash, _)| hash.clone());
+            evicted
+                .map(|hash| self.remove_with_descendants(&hash))
+                .unwrap_or_default()
+        } else {
+            Vec::new()
         }
-        self.updated_at = Instant::now();
     }
 
-    pub fn get(
+    // Removes the transaction and the transactions which spend its outputs directly or
+    // indirectly, since they are invalid without it. Returns all removed transactions.
+    fn remove_with_descendants(&mut self, hash: &packed::Byte32) -> Vec<packed::Byte32> {
+        if !self.txs.contains_key(hash) {
+            return Vec::new();
+        }
+        let mut removed = vec![hash.clone()];
+        let mut removed_set = HashSet::new();
+        removed_set.insert(hash.clone());
+        for (child_hash, child) in self.txs.iter() {
+            if self
+                .get_parents(&child.tx)
+                .iter()
+                .any(|parent| removed_set.contains(parent))
+            {
+                removed_set.insert(child_hash.clone());
+                removed.push(child_hash.clone());
+            }
+        }
+        for hash in &removed {
+            self.txs.remove(hash);
+        }
+        removed
+    }
+
+    // Returns the pending transactions whose outputs are spent by the transaction.
+    fn get_parents(&self, tx: &packed::Transaction) -> Vec<packed::Byte32> {
+        tx.raw()
+            .inputs()
+            .into_iter()
+            .map(|input| input.previous_output().tx_hash())
+            .filter(|hash| self.txs.contains_key(hash))
+            .collect()
+    }
+
+    /// Returns the pending transactions which spend any same input as the transaction, with
+    /// their fee rates.
+    pub(crate) fn get_conflicts(&self, tx: &TransactionView) -> Vec<(packed::Byte32, u64)> {
+        let inputs = tx.input_pts_iter().collect::<HashSet<_>>();
+        self.txs
+            .iter()
+            .filter(|(_, pending_tx)| {
+                pending_tx
+                    .tx
+                    .raw()
+                    .inputs()
+                    .into_iter()
+                    .any(|input| inputs.contains(&input.previous_output()))
+            })
+            .map(|(hash, pending_tx)| (hash.clone(), pending_tx.fee_rate))
+            .collect()
+    }
+
+    /// Removes the pending transactions which are replaced by a transaction with higher fee
+    /// rate, and their descendants. Returns all removed transactions.
+    pub(crate) fn remove_replaced(&mut self, hashes: &[packed::Byte32]) -> Vec<packed::Byte32> {
+        let mut removed = Vec::new();
+        for hash in hashes {
+            removed.extend(self.remove_with_descendants(hash));
+        }
+        self.counts.replaced += removed.len() as u64;
+        removed
+    }
+
+    /// Returns the pending transaction and its cycles.
+    pub fn get(&self, hash: &packed::Byte32) -> Option<(packed::Transaction, Cycle)> {
+        self.txs
+            .get(hash)
+            .map(|pending_tx| (pending_tx.tx.clone(), pending_tx.cycles))
+    }
+
+    pub(crate) fn add_seen(&mut self, tx: packed::Transaction) {
+        self.seen_txs
+            .insert(tx.calc_tx_hash(), (tx, Instant::now()));
+        if self.seen_txs.len() > self.limit {
+            self.seen_txs.pop_front();
+        }
+    }
+
+    pub(crate) fn get_seen(&self, hash: &packed::Byte32) -> Option<packed::Transaction> {
+        self.seen_txs.get(hash).map(|(tx, _)| tx.clone())
+    }
+
+    /// Drops the transactions which are only kept to report their statuses, i.e. the seen,
+    /// the just committed and the rejected ones, to relieve the memory pressure.
+    pub(crate) fn shrink(&mut self) {
+        self.seen_txs.clear();
+        self.committed_txs.clear();
+        self.rejected_txs.clear();
+    }
+
+    /// Marks the pending transactions which are proposed in the block or its uncles as
+    /// proposed. Returns the newly proposed transactions.
+    pub(crate) fn propose_block(&mut self, block: &packed::Block) -> Vec<packed::Byte32> {
+        let block_hash = block.header().calc_header_hash();
+        let proposals = block
+            .proposals()
+            .into_iter()
+            .chain(
+                block
+                    .uncles()
+                    .into_iter()
+                    .flat_map(|uncle| uncle.proposals().into_iter()),
+            )
+            .collect::<HashSet<_>>();
+        let mut proposed = Vec::new();
+        for (hash, pending_tx) in self.txs.iter_mut() {
+            if pending_tx.proposed_in.is_none()
+                && proposals.contains(&packed::ProposalShortId::from_tx_hash(hash))
+            {
+                pending_tx.proposed_in = Some(block_hash.clone());
+                proposed.push(hash.clone());
+            }
+        }
+        proposed
+    }
+
+    /// Returns the hash of the block which the pending transaction is proposed in.
+    pub(crate) fn get_proposed(&self, hash: &packed::Byte32) -> Option<packed::Byte32> {
+        self.txs
+            .get(hash)
+            .and_then(|pending_tx| pending_tx.proposed_in.clone())
+    }
+
+    /// Removes the pending or seen transactions which are committed in the block, and
+    /// records the hash of the block for them. Returns the removed transactions.
+    pub(crate) fn commit_block(&mut self, block: &packed::Block) -> Vec<packed::Byte32> {
+        let block_hash = block.header().calc_header_hash();
+        let mut committed = Vec::new();
+        for tx in block.transactions().into_iter() {
+            let hash = tx.calc_tx_hash();
+            if self.txs.remove(&hash).is_some() {
+                self.counts.committed += 1;
+            } else if self.seen_txs.remove(&hash).is_none() {
+                continue;
+            }
+            self.committed_txs
+                .insert(hash.clone(), (tx, block_hash.clone()));
+            if self.committed_txs.len() > self.limit {
+                self.committed_txs.pop_front();
+            }
+            committed.push(hash);
+        }
+        committed
+    }
+
+    pub(crate) fn get_committed(
         &self,
         hash: &packed::Byte32,
-    ) -> Option<(packed::Transaction, Cycle, HashSet<PeerId>)> {
-        self.txs.get(hash).cloned()
+    ) -> Option<(packed::Transaction, packed::Byte32)> {
+        self.committed_txs.get(hash).cloned()
     }
 
-    fn fetch_transaction_hashes_for_broadcast(&mut self, peer_id: PeerId) -> Vec<packed::Byte32> {
+    /// Marks the transaction as being verified, returns false if it's already verifying or
+    /// pending, and an error if too many transactions are being verified.
+    pub(crate) fn start_verifying(&mut self, tx: packed::Transaction) -> Result<bool, String> {
+        let hash = tx.calc_tx_hash();
+        if self.txs.contains_key(&hash) || self.verifying_txs.contains_key(&hash) {
+            return Ok(false);
+        }
+        if self.verifying_txs.len() >= self.limit {
+            return Err(format!(
+                "{} transactions are being verified, retry later",
+                self.verifying_txs.len()
+            ));
+        }
+        self.rejected_txs.remove(&hash);
+        self.verifying_txs.insert(hash, tx);
+        Ok(true)
+    }
+
+    /// Removes the transaction from the verifying ones, records the reason if it's rejected.
+    pub(crate) fn finish_verifying(&mut self, hash: &packed::Byte32, rejected: Option<String>) {
+        if let Some(tx) = self.verifying_txs.remove(hash) {
+            if let Some(reason) = rejected {
+                self.rejected_txs.insert(hash.clone(), (tx, reason));
+                if self.rejected_txs.len() > self.limit {
+                    self.rejected_txs.pop_front();
+                }
+            }
+        }
+    }
+
+    pub(crate) fn get_verifying(&self, hash: &packed::Byte32) -> Option<packed::Transaction> {
+        self.verifying_txs.get(hash).cloned()
+    }
+
+    pub(crate) fn get_rejected(
+        &self,
+        hash: &packed::Byte32,
+    ) -> Option<(packed::Transaction, String)> {
+        self.rejected_txs.get(hash).cloned()
+    }
+
+    /// Checks whether the transaction is pending or seen.
+    pub(crate) fn contains(&self, hash: &packed::Byte32) -> bool {
+        self.txs.contains_key(hash) || self.seen_txs.contains_key(hash)
+    }
+
+    pub(crate) fn fetch_transaction_hashes_for_broadcast(
+        &mut self,
+        peer_id: PeerId,
+        fanout: usize,
+    ) -> Vec<packed::Byte32> {
+        let now = unix_time_as_millis();
+        let instant = Instant::now();
+        let mut selected = self
+            .txs
+            .iter()
+            .filter(|(_, pending_tx)| {
+                !pending_tx.announced_to.contains(&peer_id)
+                    && (fanout == 0 || pending_tx.announced_to.len() < fanout)
+                    && pending_tx
+                        .relay_after
+                        .map(|relay_after| instant >= relay_after)
+                        .unwrap_or(true)
+            })
+            .map(|(hash, _)| hash.clone())
+            .collect::<HashSet<_>>();
+        // Announce the pending parents along with the transactions, so they are accepted in
+        // order. The parents are always submitted before their children.
+        for (hash, pending_tx) in self.txs.iter().rev() {
+            if selected.contains(hash) {
+                selected.extend(self.get_parents(&pending_tx.tx));
+            }
+        }
         self.txs
             .iter_mut()
-            .filter_map(|(hash, (_, _, peers))| {
-                if peers.insert(peer_id.clone()) {
+            .filter_map(|(hash, pending_tx)| {
+                if selected.contains(hash) && pending_tx.announced_to.insert(peer_id.clone()) {
+                    pending_tx.relay_stats.announced += 1;
+                    pending_tx.relay_stats.announced_at = Some(now);
                     Some(hash.clone())
                 } else {
                     None
@@ -78,6 +456,86 @@ impl PendingTxs {
             .collect()
     }
 
+    /// Counts a request for the pending transaction from peers.
+    pub(crate) fn add_requested(&mut self, hash: &packed::Byte32) {
+        if let Some(pending_tx) = self.txs.get_mut(hash) {
+            pending_tx.relay_stats.requested += 1;
+        }
+    }
+
+    /// Returns the relay statistics of each pending transaction, in the order of submission.
+    pub(crate) fn get_relay_stats(&self) -> Vec<(packed::Byte32, RelayStats)> {
+        self.txs
+            .iter()
+            .map(|(hash, pending_tx)| (hash.clone(), pending_tx.relay_stats.clone()))
+            .collect()
+    }
+
+    /// Removes the transactions which are committed or expired, and schedules the
+    /// rebroadcasts of the others, they will be announced to all opened peers again.
+    ///
+    /// Returns the count of the rebroadcast transactions, and the expired transactions.
+    pub(crate) fn check_rebroadcast<F>(
+        &mut self,
+        now: Instant,
+        is_committed: F,
+    ) -> (usize, Vec<packed::Byte32>)
+    where
+        F: Fn(&packed::Byte32) -> bool,
+    {
+        let mut rebroadcasted = 0;
+        let mut removed = Vec::new();
+        let mut expired = Vec::new();
+        for (hash, pending_tx) in self.txs.iter_mut() {
+            if is_committed(hash) {
+                self.counts.committed += 1;
+                removed.push(hash.clone());
+            } else if now.saturating_duration_since(pending_tx.submitted_at) >= self.ttl {
+                expired.push(hash.clone());
+            } else if now >= pending_tx.rebroadcast_at {
+                pending_tx.announced_to.clear();
+                pending_tx.rebroadcast_interval = cmp::min(
+                    pending_tx.rebroadcast_interval * 2,
+                    REBROADCAST_MAX_INTERVAL,
+                );
+                pending_tx.rebroadcast_at = now + pending_tx.rebroadcast_interval;
+                rebroadcasted += 1;
+            }
+        }
+        for hash in &removed {
+            self.txs.remove(hash);
+        }
+        // The descendants of the expired transactions are invalid without them.
+        let expired = expired
+            .iter()
+            .flat_map(|hash| self.remove_with_descendants(hash))
+            .collect::<Vec<_>>();
+        self.counts.expired += expired.len() as u64;
+        let removed_seen = self
+            .seen_txs
+            .iter()
+            .filter(|(hash, (_, seen_at))| {
+                is_committed(hash) || now.saturating_duration_since(*seen_at) >= self.ttl
+            })
+            .map(|(hash, _)| hash.clone())
+            .collect::<Vec<_>>();
+        for hash in &removed_seen {
+            self.seen_txs.remove(hash);
+        }
+        if rebroadcasted > 0 {
+            self.counts.rebroadcasted += rebroadcasted as u64;
+            self.updated_at = now;
+        }
+        (rebroadcasted, expired)
+    }
+
+    pub(crate) fn get_counts(&self) -> PendingTxsCounts {
+        PendingTxsCounts {
+            pending: self.txs.len(),
+            ..self.counts.clone()
+        }
+    }
+
     fn is_not_empty_and_updated_at(&self, seconds: u64) -> bool {
         !self.txs.is_empty() && self.updated_at.elapsed() < Duration::from_secs(seconds)
     }
@@ -90,6 +548,7 @@ impl RelayProtocol {
         consensus: Consensus,
         storage: Storage,
         v3: bool,
+        config: &LightClientConfig,
     ) -> Self {
         Self {
             opened_peers: HashMap::new(),
@@ -98,6 +557,119 @@ impl RelayProtocol {
             consensus,
             storage,
             v3,
+            fanout: config.relay_transaction_fanout,
+            track_relayed_txs: config.track_relayed_transactions,
+            requested_tx_hashes: LinkedHashMap::new(),
+            requested_budgets: HashMap::new(),
+        }
+    }
+}
+
+impl RelayProtocol {
+    // Requests the transactions which are relayed by peers, to find the ones which are
+    // related to the filter scripts before they are committed.
+    fn request_relay_transactions(
+        &mut self,
+        nc: Arc<dyn CKBProtocolContext + Sync>,
+        peer: PeerIndex,
+        reader: packed::RelayTransactionHashesReader,
+    ) {
+        if !self.track_relayed_txs || self.storage.is_filter_scripts_empty() {
+            return;
+        }
+        let now = Instant::now();
+        let (started_at, requested) = self.requested_budgets.entry(peer).or_insert((now, 0));
+        if now.duration_since(*started_at) >= REQUESTED_TX_HASHES_INTERVAL {
+            *started_at = now;
+            *requested = 0;
+        }
+        let budget = MAX_REQUESTED_TX_HASHES_PER_PEER.saturating_sub(*requested);
+        if budget == 0 {
+            trace!(
+                peer_index = peer.value();
+                "RelayProtocol ignore relayed tx hashes from peer={} since the budget is used up",
+                peer
+            );
+            return;
+        }
+        let tx_hashes = {
+            let pending_txs = self.pending_txs.read().expect("read access should be OK");
+            reader
+                .tx_hashes()
+                .iter()
+                .map(|tx_hash| tx_hash.to_entity())
+                .filter(|tx_hash| {
+                    !pending_txs.contains(tx_hash)
+                        && !self.requested_tx_hashes.contains_key(tx_hash)
+                        && self.storage.get_transaction_with_header(tx_hash).is_none()
+                })
+                .take(budget)
+                .collect::<Vec<_>>()
+        };
+        if tx_hashes.is_empty() {
+            return;
+        }
+        *requested += tx_hashes.len();
+        for tx_hash in &tx_hashes {
+            self.requested_tx_hashes.insert(tx_hash.clone(), ());
+            if self.requested_tx_hashes.len() > MAX_REQUESTED_TX_HASHES {
+                self.requested_tx_hashes.pop_front();
+            }
+        }
+        let content = packed::GetRelayTransactions::new_builder()
+            .tx_hashes(tx_hashes.pack())
+            .build();
+        let message = packed::RelayMessage::new_builder().set(content).build();
+        self.connected_peers
+            .record_sent_message(peer, nc.protocol_id(), message.as_slice().len());
+        if let Err(err) = nc.send_message_to(peer, message.as_bytes()) {
+            warn!(
+                peer_index = peer.value();
+                "RelayProtocol failed to send GetRelayTransactions message to peer={} since {:?}",
+                peer, err
+            );
+        }
+    }
+
+    // Keeps the requested transactions which are related to the filter scripts as seen.
+    #[instrument(name = "relay_transactions", skip_all, fields(peer = %peer))]
+    fn process_relay_transactions(
+        &mut self,
+        peer: PeerIndex,
+        reader: packed::RelayTransactionsReader,
+    ) {
+        // Loaded once for the whole batch, since there may be many transactions in it.
+        let scripts = self.storage.get_filter_script_set();
+        for relay_tx in reader.transactions().iter() {
+            let tx = relay_tx.transaction().to_entity();
+            let tx_hash = tx.calc_tx_hash();
+            if self.requested_tx_hashes.remove(&tx_hash).is_none() {
+                continue;
+            }
+            if !self.storage.is_related_transaction_with(&scripts, &tx) {
+                continue;
+            }
+            let tx_view = tx.clone().into_view();
+            if let Err(err) =
+                NonContextualTransactionVerifier::new(&tx_view, &self.consensus).verify()
+            {
+                debug!(
+                    peer_index = peer.value();
+                    "RelayProtocol ignore invalid tx {:#x} from peer={} since {:?}",
+                    tx_hash, peer, err
+                );
+                continue;
+            }
+            debug!(
+                peer_index = peer.value();
+                "RelayProtocol found tx {:#x} from peer={}",
+                tx_hash,
+                peer
+            );
+            self.pending_txs
+                .write()
+                .expect("write access should be OK")
+                .add_seen(tx);
         }
     }
 }
@@ -135,6 +707,7 @@ impl CKBProtocolHandler for RelayProtocol {
             .is_vm_version_2_and_syscalls_3_enabled(epoch);
 
         debug!(
+            peer_index = peer.value();
             "RelayProtocol V{}({}).connected peer={}, epoch={}",
             if self.v3 { '3' } else { '2' },
             version,
@@ -143,12 +716,12 @@ impl CKBProtocolHandler for RelayProtocol {
         );
 
         if self.v3 && !ckb2023 {
-            debug!("peer={} is not ckb2023 enabled, ignore", peer);
+            debug!(peer_index = peer.value(); "peer={} is not ckb2023 enabled, ignore", peer);
             return;
         }
 
         if !self.v3 && ckb2023 {
-            debug!("peer={} is ckb2023 enabled, ignore", peer);
+            debug!(peer_index = peer.value(); "peer={} is ckb2023 enabled, ignore", peer);
             return;
         }
 
@@ -166,14 +739,20 @@ impl CKBProtocolHandler for RelayProtocol {
                 .pending_txs
                 .write()
                 .unwrap()
-                .fetch_transaction_hashes_for_broadcast(peer_id);
+                .fetch_transaction_hashes_for_broadcast(peer_id, self.fanout);
             if !tx_hashes.is_empty() {
                 let content = packed::RelayTransactionHashes::new_builder()
                     .tx_hashes(tx_hashes.pack())
                     .build();
                 let message = packed::RelayMessage::new_builder().set(content).build();
+                self.connected_peers.record_sent_message(
+                    peer,
+                    nc.protocol_id(),
+                    message.as_slice().len(),
+                );
                 if let Err(err) = nc.send_message_to(peer, message.as_bytes()) {
                     warn!(
+                        peer_index = peer.value();
                         "RelayProtocol failed to send RelayTransactionHashes message to peer={} since {:?}",
                         peer, err
                     );
@@ -188,8 +767,9 @@ impl CKBProtocolHandler for RelayProtocol {
     }
 
     async fn disconnected(&mut self, _nc: Arc<dyn CKBProtocolContext + Sync>, peer: PeerIndex) {
-        debug!("RelayProtocol.disconnected peer={}", peer);
+        debug!(peer_index = peer.value(); "RelayProtocol.disconnected peer={}", peer);
         self.opened_peers.remove(&peer);
+        self.requested_budgets.remove(&peer);
     }
 
     async fn received(
@@ -198,14 +778,18 @@ impl CKBProtocolHandler for RelayProtocol {
         peer: PeerIndex,
         data: Bytes,
     ) {
+        self.connected_peers
+            .record_received_message(peer, nc.protocol_id(), data.len());
         let message = match packed::RelayMessageReader::from_compatible_slice(&data) {
             Ok(msg) => msg.to_enum(),
             _ => {
                 warn!(
+                    peer_index = peer.value();
                     "RelayProtocol.received a malformed message from Peer({})",
                     peer
                 );
-                nc.ban_peer(
+                self.connected_peers.ban_peer(
+                    nc.as_ref(),
                     peer,
                     BAD_MESSAGE_BAN_TIME,
                     String::from("send us a malformed message"),
@@ -215,58 +799,110 @@ impl CKBProtocolHandler for RelayProtocol {
         };
 
         trace!(
+            peer_index = peer.value();
             "RelayProtocol.received peer={}, message={}",
             peer,
             message.item_name()
         );
-        if let packed::RelayMessageUnionReader::GetRelayTransactions(reader) = message {
-            let pending_txs = self.pending_txs.read().expect("read access should be OK");
-            let relay_txs: Vec<_> = reader
-                .tx_hashes()
-                .iter()
-                .filter_map(|tx_hash| {
-                    pending_txs
-                        .get(&tx_hash.to_entity())
-                        .map(|(tx, cycles, _)| {
+        match message {
+            packed::RelayMessageUnionReader::GetRelayTransactions(reader) => {
+                let mut pending_txs = self.pending_txs.write().expect("write access should be OK");
+                let relay_txs: Vec<_> = reader
+                    .tx_hashes()
+                    .iter()
+                    .filter_map(|tx_hash| {
+                        let tx_hash = tx_hash.to_entity();
+                        pending_txs.add_requested(&tx_hash);
+                        pending_txs.get(&tx_hash).map(|(tx, cycles)| {
                             packed::RelayTransaction::new_builder()
                                 .transaction(tx)
                                 .cycles(cycles.pack())
                                 .build()
                         })
-                })
-                .collect();
+                    })
+                    .collect();
 
-            let content = packed::RelayTransactions::new_builder()
-                .transactions(relay_txs.pack())
-                .build();
-            let msg = packed::RelayMessage::new_builder().set(content).build();
-            if let Err(err) = nc.send_message_to(peer, msg.as_bytes()) {
-                warn!(
-                    "RelayProtocol failed to send RelayTransactions message to peer={} since {:?}",
-                    peer, err
+                let content = packed::RelayTransactions::new_builder()
+                    .transactions(relay_txs.pack())
+                    .build();
+                let msg = packed::RelayMessage::new_builder().set(content).build();
+                self.connected_peers.record_sent_message(
+                    peer,
+                    nc.protocol_id(),
+                    msg.as_slice().len(),
                 );
+                if let Err(err) = nc.send_message_to(peer, msg.as_bytes()) {
+                    warn!(
+                        peer_index = peer.value();
+                        "RelayProtocol failed to send RelayTransactions message to peer={} since {:?}",
+                        peer, err
+                    );
+                }
+            }
+            packed::RelayMessageUnionReader::RelayTransactionHashes(reader) => {
+                self.request_relay_transactions(nc, peer, reader);
+            }
+            packed::RelayMessageUnionReader::RelayTransactions(reader) => {
+                self.process_relay_transactions(peer, reader);
+            }
+            packed::RelayMessageUnionReader::CompactBlock(reader) => {
+                let number: BlockNumber = reader.header().raw().number().unpack();
+                let tip_number: BlockNumber = self.storage.get_tip_header().raw().number().unpack();
+                self.connected_peers.update_announced_tip(peer, number);
+                if number > tip_number {
+                    trace!(
+                        peer_index = peer.value(), block_number = number;
+                        "RelayProtocol peer={} announced block#{} higher than tip#{}",
+                        peer,
+                        number,
+                        tip_number
+                    );
+                    self.connected_peers.add_tip_announcement(peer);
+                }
+            }
+            _ => {
+                // ignore other messages
             }
-        } else {
-            // ignore other messages
         }
     }
 
     async fn notify(&mut self, nc: Arc<dyn CKBProtocolContext + Sync>, token: u64) {
         match token {
             CHECK_PENDING_TXS_TOKEN => {
-                // we check pending txs every 2 seconds, if the timestamp of the pending txs is updated in the last minute
-                // and connected relay protocol peers is empty, we try to open the protocol and broadcast the pending txs
-                if self
+                let (rebroadcasted, expired) = self
                     .pending_txs
-                    .read()
+                    .write()
                     .unwrap()
-                    .is_not_empty_and_updated_at(60)
+                    .check_rebroadcast(Instant::now(), |tx_hash| {
+                        self.storage.get_transaction_with_header(tx_hash).is_some()
+                    });
+                if rebroadcasted > 0 {
+                    debug!("RelayProtocol rebroadcast {} pending txs", rebroadcasted);
+                }
+                for hash in expired {
+                    info!("pending tx {:#x} is expired before committed", hash);
+                    self.connected_peers
+                        .emit_chain_event(ChainEvent::PendingTxEvicted { hash });
+                }
+                // we check pending txs every 2 seconds, if the timestamp of the pending txs is updated in the last minute
+                // and connected relay protocol peers is empty, we try to open the protocol and broadcast the pending txs
+                //
+                // keep the protocol opened while there are filter scripts if the relayed txs are tracked, to find the
+                // related txs relayed by peers before they are committed, even if there are no pending txs
+                let keep_opened = self.track_relayed_txs && !self.storage.is_filter_scripts_empty();
+                if (keep_opened
+                    || self
+                        .pending_txs
+                        .read()
+                        .unwrap()
+                        .is_not_empty_and_updated_at(60))
                     && self.opened_peers.is_empty()
                 {
                     let p2p_control = nc.p2p_control().expect("p2p_control should be exist");
                     for peer in self.connected_peers.get_peers_index() {
                         if let Err(err) = p2p_control.open_protocol(peer, nc.protocol_id()) {
                             warn!(
+                                peer_index = peer.value();
                                 "RelayProtocol failed to open protocol to peer={} since {:?}",
                                 peer, err
                             );
@@ -274,13 +910,17 @@ impl CKBProtocolHandler for RelayProtocol {
                     }
                 } else {
                     let mut pending_txs = self.pending_txs.write().unwrap();
-                    for (&peer, instant) in self.opened_peers.iter_mut() {
+                    // Shuffle the peers, so the pending transactions are announced to random
+                    // peers when the fan-out is limited.
+                    let mut opened_peers = self.opened_peers.iter_mut().collect::<Vec<_>>();
+                    opened_peers.shuffle(&mut rand::thread_rng());
+                    for (&peer, instant) in opened_peers {
                         if let Some(peer_id) = nc
                             .get_peer(peer)
                             .and_then(|p| extract_peer_id(&p.connected_addr))
                         {
-                            let tx_hashes =
-                                pending_txs.fetch_transaction_hashes_for_broadcast(peer_id);
+                            let tx_hashes = pending_txs
+                                .fetch_transaction_hashes_for_broadcast(peer_id, self.fanout);
                             if !tx_hashes.is_empty() {
                                 let content = packed::RelayTransactionHashes::new_builder()
                                     .tx_hashes(tx_hashes.pack())
@@ -288,18 +928,26 @@ impl CKBProtocolHandler for RelayProtocol {
                                 let message =
                                     packed::RelayMessage::new_builder().set(content).build();
 
+                                self.connected_peers.record_sent_message(
+                                    peer,
+                                    nc.protocol_id(),
+                                    message.as_slice().len(),
+                                );
                                 if let Err(err) = nc.send_message_to(peer, message.as_bytes()) {
                                     warn!(
+                                        peer_index = peer.value();
                                         "RelayProtocol failed to send RelayTransactionHashes message to peer={} since {:?}",
                                         peer, err
                                     );
                                 }
                                 instant.replace(Instant::now());
-                            } else if instant
-                                .map(|i| i.elapsed() > Duration::from_secs(60))
-                                .unwrap_or(true)
+                            } else if !keep_opened
+                                && instant
+                                    .map(|i| i.elapsed() > Duration::from_secs(60))
+                                    .unwrap_or(true)
                             {
                                 debug!(
+                                    peer_index = peer.value();
                                     "RelayProtocol.notify peer={} is inactive, close the protocol",
                                     peer
                                 );
@@ -3,7 +3,7 @@ use std::{fmt, sync::Arc, time::Duration};
 use ckb_network::{CKBProtocolContext, PeerIndex};
 use log::{debug, error, trace, warn};
 
-use super::BAD_MESSAGE_BAN_TIME;
+use super::{Peers, BAD_MESSAGE_BAN_TIME};
 
 /// StatusCodes indicate whether a specific operation has been successfully completed.
 ///
@@ -15,7 +15,7 @@ use super::BAD_MESSAGE_BAN_TIME;
 ///   - 4xx: Remote errors - The error seems to have been caused by the remote (the server).
 ///   - 5xx: Local errors - The client failed to process a response.
 #[repr(u16)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[allow(dead_code)]
 pub enum StatusCode {
     /// OK
@@ -41,6 +41,10 @@ pub enum StatusCode {
     PeerIsNotOnProcess = 421,
     /// The response is not match our request.
     UnexpectedResponse = 422,
+    /// The peer doesn't send the response in time.
+    RequestIsTimeout = 423,
+    /// The response contains more items than the caps.
+    ResponseIsTooLarge = 424,
 
     // Common errors for all verifications.
     /// Failed to verify chain root.
@@ -53,6 +57,8 @@ pub enum StatusCode {
     InvalidTotalDifficulty = 434,
     /// Failed to verify the parent block.
     InvalidParentBlock = 435,
+    /// Failed to verify the epoch.
+    InvalidEpoch = 436,
     /// Failed to verify the proof.
     InvalidProof = 439,
 
@@ -75,6 +81,10 @@ pub enum StatusCode {
     BlockFilterHashesIsUnexpected = 482,
     /// Block filter data is unexpected.
     BlockFilterDataIsUnexpected = 483,
+    /// Block filter hashes diverge from the majority of peers.
+    BlockFilterHashesIsDiverged = 484,
+    /// Too many blocks which are matched by the block filters are false positives.
+    BlockFiltersHaveTooManyFalsePositives = 485,
 
     /// Throws an internal error.
     InternalError = 500,
@@ -180,6 +190,7 @@ impl Status {
     pub fn process(
         &self,
         nc: Arc<dyn CKBProtocolContext + Sync>,
+        peers: &Peers,
         index: PeerIndex,
         protocol: &str,
         message: &str,
@@ -189,7 +200,7 @@ impl Status {
                 "{}Protocol.received {} from {}, result {}, ban {:?}",
                 protocol, message, index, self, ban_time
             );
-            nc.ban_peer(index, ban_time, self.to_string());
+            peers.ban_peer(nc.as_ref(), index, ban_time, self.to_string());
         } else if self.should_warn() {
             warn!(
                 "{}Protocol.received {} from {}, result {}",
@@ -1,23 +1,42 @@
-use ckb_constant::sync::INIT_BLOCKS_IN_TRANSIT_PER_PEER;
 use ckb_network::{async_trait, bytes::Bytes, CKBProtocolContext, CKBProtocolHandler, PeerIndex};
-use ckb_types::{packed, prelude::*};
-use log::{info, trace, warn};
+use ckb_types::{core::BlockNumber, packed, prelude::*};
+use log::{debug, error, info, trace, warn};
 use std::collections::HashSet;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use tracing::instrument;
 
-use super::BAD_MESSAGE_BAN_TIME;
-use crate::protocols::Peers;
+use super::{Status, StatusCode, BAD_MESSAGE_BAN_TIME};
+use crate::protocols::{ChainEvent, Peers, PendingTxs};
 use crate::storage::Storage;
+use crate::types::LightClientConfig;
 use crate::utils::network::prove_or_download_matched_blocks;
 
+// Disconnect the peer if it sends too many messages which are ignored, e.g. the blocks which
+// are not requested.
+const MAX_SYNC_MISBEHAVIORS: u32 = 16;
+
 pub(crate) struct SyncProtocol {
     storage: Storage,
     peers: Arc<Peers>,
+    pending_txs: Arc<RwLock<PendingTxs>>,
+    store_matched_blocks: bool,
+    matched_blocks_in_transit_per_peer: usize,
 }
 
 impl SyncProtocol {
-    pub fn new(storage: Storage, peers: Arc<Peers>) -> Self {
-        Self { storage, peers }
+    pub fn new(
+        storage: Storage,
+        peers: Arc<Peers>,
+        pending_txs: Arc<RwLock<PendingTxs>>,
+        config: &LightClientConfig,
+    ) -> Self {
+        Self {
+            storage,
+            peers,
+            pending_txs,
+            store_matched_blocks: config.store_matched_blocks,
+            matched_blocks_in_transit_per_peer: config.matched_blocks_in_transit_per_peer,
+        }
     }
 }
 
@@ -31,11 +50,11 @@ impl CKBProtocolHandler for SyncProtocol {
         peer: PeerIndex,
         version: &str,
     ) {
-        info!("SyncProtocol({}).connected peer={}", version, peer);
+        info!(peer_index = peer.value(); "SyncProtocol({}).connected peer={}", version, peer);
     }
 
     async fn disconnected(&mut self, _nc: Arc<dyn CKBProtocolContext + Sync>, peer: PeerIndex) {
-        info!("SyncProtocol.disconnected peer={}", peer);
+        info!(peer_index = peer.value(); "SyncProtocol.disconnected peer={}", peer);
     }
 
     async fn received(
@@ -44,14 +63,20 @@ impl CKBProtocolHandler for SyncProtocol {
         peer: PeerIndex,
         data: Bytes,
     ) {
+        self.peers
+            .record_received_message(peer, nc.protocol_id(), data.len());
         let message = match packed::SyncMessageReader::from_compatible_slice(&data) {
             Ok(msg) => msg.to_enum(),
             _ => {
                 warn!(
+                    peer_index = peer.value();
                     "SyncProtocol.received a malformed message from Peer({})",
                     peer
                 );
-                nc.ban_peer(
+                self.peers
+                    .add_failure(peer, StatusCode::MalformedProtocolMessage);
+                self.peers.ban_peer(
+                    nc.as_ref(),
                     peer,
                     BAD_MESSAGE_BAN_TIME,
                     String::from("send us a malformed message"),
@@ -61,15 +86,57 @@ impl CKBProtocolHandler for SyncProtocol {
         };
 
         trace!(
+            peer_index = peer.value();
             "SyncProtocol.received peer={}, message={}",
             peer,
             message.item_name()
         );
+        let item_name = message.item_name();
+        let status = self.try_process(nc.as_ref(), peer, message, data.len());
+        if !status.is_ok() {
+            self.peers.add_failure(peer, status.code());
+            if status.should_ban().is_none()
+                && self.peers.add_sync_misbehavior(peer) >= MAX_SYNC_MISBEHAVIORS
+            {
+                warn!(
+                    peer_index = peer.value();
+                    "SyncProtocol disconnect peer={} since too many misbehaviors",
+                    peer
+                );
+                if let Err(err) = nc.disconnect(peer, "too many misbehaviors in sync protocol") {
+                    error!(peer_index = peer.value(); "disconnect peer({}) error: {}", peer, err);
+                }
+            }
+        }
+        status.process(nc, &self.peers, peer, "Sync", item_name);
+    }
+}
+
+impl SyncProtocol {
+    #[instrument(
+        name = "sync_message",
+        skip_all,
+        fields(peer = %peer, message = message.item_name())
+    )]
+    fn try_process(
+        &mut self,
+        nc: &dyn CKBProtocolContext,
+        peer: PeerIndex,
+        message: packed::SyncMessageUnionReader<'_>,
+        data_len: usize,
+    ) -> Status {
         match message {
             packed::SyncMessageUnionReader::SendBlock(reader) => {
+                self.peers.record_downloaded_bytes(data_len);
                 let new_block = reader.to_entity().block();
                 let mut matched_blocks = self.peers.matched_blocks().write().expect("poisoned");
-                self.peers.add_block(&mut matched_blocks, new_block);
+                if self
+                    .peers
+                    .add_block(&mut matched_blocks, new_block)
+                    .is_none()
+                {
+                    return StatusCode::Ignore.with_context("the block is not requested");
+                }
 
                 if !matched_blocks.is_empty()
                     && self.peers.all_matched_blocks_downloaded(&matched_blocks)
@@ -81,7 +148,6 @@ impl CKBProtocolHandler for SyncProtocol {
                     let db_blocks: HashSet<_> =
                         db_blocks.into_iter().map(|(hash, _)| hash).collect();
 
-                    self.storage.remove_matched_blocks(start_number);
                     let blocks = self.peers.clear_matched_blocks(&mut matched_blocks);
                     assert_eq!(blocks.len(), db_blocks.len());
                     info!(
@@ -94,10 +160,87 @@ impl CKBProtocolHandler for SyncProtocol {
                     // update storage
                     for block in blocks {
                         assert!(db_blocks.contains(&block.header().calc_header_hash()));
-                        self.storage.filter_block(block);
+                        if self.store_matched_blocks {
+                            self.storage.add_block(&block);
+                        }
+                        let header = block.header().into_view();
+                        // The pending transactions are proposed or committed once the block
+                        // is applied, don't wait for the next check of the relay protocol.
+                        let (proposed_txs, committed_txs) = {
+                            let mut pending_txs = self
+                                .pending_txs
+                                .write()
+                                .expect("pending_txs lock is poisoned");
+                            (
+                                pending_txs.propose_block(&block),
+                                pending_txs.commit_block(&block),
+                            )
+                        };
+                        let filtered = self.storage.filter_block_cells(block);
+                        let is_false_positive = filtered.is_empty();
+                        if is_false_positive {
+                            debug!(
+                                "matched block#{} {:#x} is a false positive",
+                                header.number(),
+                                header.hash()
+                            );
+                            self.peers.add_filter_false_positive();
+                        }
+                        self.peers
+                            .check_matched_block_source(&header.hash(), is_false_positive);
+                        for cell in filtered.created {
+                            self.peers.emit_chain_event(ChainEvent::CellFound {
+                                cell,
+                                block_number: header.number(),
+                                block_hash: header.hash(),
+                            });
+                        }
+                        for cell in filtered.consumed {
+                            self.peers.emit_chain_event(ChainEvent::CellConsumed {
+                                cell,
+                                block_number: header.number(),
+                                block_hash: header.hash(),
+                            });
+                        }
+                        self.peers
+                            .emit_chain_event(ChainEvent::MatchedBlockApplied {
+                                number: header.number(),
+                                hash: header.hash(),
+                            });
+                        for hash in proposed_txs {
+                            debug!(
+                                "pending transaction {:#x} is proposed in block#{} {:#x}",
+                                hash,
+                                header.number(),
+                                header.hash()
+                            );
+                            self.peers
+                                .emit_chain_event(ChainEvent::TransactionProposed {
+                                    hash,
+                                    block_number: header.number(),
+                                    block_hash: header.hash(),
+                                });
+                        }
+                        for hash in committed_txs {
+                            debug!(
+                                "pending transaction {:#x} is committed in block#{} {:#x}",
+                                hash,
+                                header.number(),
+                                header.hash()
+                            );
+                            self.peers
+                                .emit_chain_event(ChainEvent::TransactionCommitted {
+                                    hash,
+                                    block_number: header.number(),
+                                    block_hash: header.hash(),
+                                });
+                        }
                     }
+                    // Remove the matched blocks after they are applied, so they could be
+                    // rolled back and applied again if it's interrupted.
                     self.storage
                         .update_block_number(start_number + blocks_count - 1);
+                    self.storage.remove_matched_blocks(start_number);
 
                     // send more GetBlocksProof/GetBlocks requests
                     if let Some((_start_number, _blocks_count, db_blocks)) =
@@ -110,22 +253,60 @@ impl CKBProtocolHandler for SyncProtocol {
                             Arc::clone(&self.peers),
                             &tip_header,
                             &matched_blocks,
-                            nc.as_ref(),
-                            INIT_BLOCKS_IN_TRANSIT_PER_PEER,
+                            nc,
+                            self.matched_blocks_in_transit_per_peer,
                         );
                     }
                 }
             }
+            packed::SyncMessageUnionReader::SendHeaders(reader) => {
+                if let Some(number) = reader
+                    .headers()
+                    .iter()
+                    .map(|header| -> BlockNumber { header.raw().number().unpack() })
+                    .max()
+                {
+                    trace!(
+                        peer_index = peer.value(), block_number = number;
+                        "SyncProtocol peer={} announced headers up to block#{}",
+                        peer,
+                        number
+                    );
+                    self.peers.update_announced_tip(peer, number);
+                }
+            }
+            packed::SyncMessageUnionReader::GetHeaders(_) => {
+                // Full nodes request headers once connected, and evict the peers which never
+                // respond. An empty response is valid, it means no better headers.
+                let content = packed::SendHeaders::new_builder().build();
+                let msg = packed::SyncMessage::new_builder().set(content).build();
+                self.peers
+                    .record_sent_message(peer, nc.protocol_id(), msg.as_slice().len());
+                if let Err(err) = nc.send_message_to(peer, msg.as_bytes()) {
+                    warn!(
+                        peer_index = peer.value();
+                        "SyncProtocol.received failed to send SendHeaders message to peer={} since {:?}",
+                        peer, err
+                    );
+                }
+            }
+            packed::SyncMessageUnionReader::InIBD(_) => {
+                debug!(peer_index = peer.value(); "SyncProtocol.received peer={} is in IBD", peer);
+            }
             _ => {
                 let content = packed::InIBD::new_builder().build();
                 let msg = packed::SyncMessage::new_builder().set(content).build();
+                self.peers
+                    .record_sent_message(peer, nc.protocol_id(), msg.as_slice().len());
                 if let Err(err) = nc.send_message_to(peer, msg.as_bytes()) {
                     warn!(
+                        peer_index = peer.value();
                         "SyncProtocol.received failed to send InIBD message to peer={} since {:?}",
                         peer, err
                     );
                 }
             }
         }
+        Status::ok()
     }
 }
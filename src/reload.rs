@@ -0,0 +1,90 @@
+//! Reload the settings which are safe to change at runtime, from the config file.
+//!
+//! It's triggered by SIGHUP or the `reload_config` RPC.
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use log::info;
+
+use crate::{
+    config::read_from_file,
+    error::{Error, Result},
+    logger,
+    protocols::{Peers, PendingTxs},
+    types::MultiRunEnv,
+};
+
+#[derive(Clone)]
+pub struct ConfigReloader {
+    /// It's `None` if the light client is started without a config file, e.g. it's
+    /// embedded in another application.
+    config_file: Option<PathBuf>,
+    /// The index of the instance in the config file.
+    index: usize,
+    peers: Arc<Peers>,
+    pending_txs: Arc<RwLock<PendingTxs>>,
+}
+
+impl ConfigReloader {
+    pub(crate) fn new(
+        config_file: Option<PathBuf>,
+        index: usize,
+        peers: Arc<Peers>,
+        pending_txs: Arc<RwLock<PendingTxs>>,
+    ) -> Self {
+        Self {
+            config_file,
+            index,
+            peers,
+            pending_txs,
+        }
+    }
+
+    /// Reloads the settings:
+    /// - The log filter and the level overrides for modules.
+    /// - The max download rate.
+    /// - The soft limit of the memory.
+    /// - The TTL and the eviction policy of the pending transactions.
+    ///
+    /// The new config is validated first, nothing is applied if it's invalid.
+    ///
+    /// Other changes in the config file, including all network settings such as
+    /// `whitelist_peers` and `whitelist_only`, are ignored until the light client restarts.
+    pub(crate) fn reload(&self) -> Result<()> {
+        let config_file = self
+            .config_file
+            .as_ref()
+            .ok_or_else(|| Error::config("the light client is started without a config file"))?;
+        let multi_run_env: MultiRunEnv = read_from_file(config_file)?;
+        let config = multi_run_env
+            .instances
+            .into_iter()
+            .nth(self.index)
+            .map(|run_env| run_env.light_client)
+            .ok_or_else(|| {
+                let errmsg = format!("the instance #{} is removed", self.index);
+                Error::config(errmsg)
+            })?;
+        // Nothing is applied if the new config is invalid.
+        config.validate().map_err(Error::config)?;
+        logger::reload_filter(&multi_run_env.logger)?;
+        self.peers.set_max_download_rate(config.max_download_rate);
+        self.peers
+            .set_memory_soft_limit(config.memory_soft_limit_mb * 1024 * 1024);
+        {
+            let mut pending_txs = self.pending_txs.write().expect("poisoned");
+            pending_txs.set_ttl(Duration::from_secs(config.pending_tx_ttl_secs));
+            pending_txs.set_eviction(config.pending_txs_eviction);
+        }
+        info!(
+            "Reloaded the config file {} for the instance #{}",
+            config_file.display(),
+            self.index
+        );
+        Ok(())
+    }
+}
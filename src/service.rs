@@ -1,34 +1,47 @@
 use ckb_chain_spec::consensus::Consensus;
 use ckb_jsonrpc_types::{
-    BlockNumber, BlockView, Capacity, CellOutput, Cycle, EstimateCycles, HeaderView, JsonBytes,
-    NodeAddress, OutPoint, RemoteNodeProtocol, Script, Transaction, TransactionView, Uint32,
-    Uint64,
+    BlockNumber, BlockView, Capacity, CellOutput, Cycle, HeaderView, JsonBytes, NodeAddress,
+    OutPoint, RemoteNodeProtocol, Script, Transaction, TransactionView, Uint32, Uint64,
 };
 use ckb_network::{extract_peer_id, NetworkController};
+use ckb_script::ScriptGroupType;
 use ckb_systemtime::unix_time_as_millis;
 use ckb_traits::HeaderProvider;
 use ckb_types::{core, packed, prelude::*, H256};
-use jsonrpc_core::{Error, IoHandler, Result};
+use jsonrpc_core::{
+    futures::future::Either, middleware, Call, Error, ErrorCode, MetaIoHandler, Metadata,
+    Middleware, Output, Result,
+};
 use jsonrpc_derive::rpc;
 use jsonrpc_http_server::{Server, ServerBuilder};
 use jsonrpc_server_utils::cors::AccessControlAllowOrigin;
 use jsonrpc_server_utils::hosts::DomainsValidation;
+use log::info;
 use rocksdb::{
     ops::{Get, Iterate},
     Direction, IteratorMode,
 };
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
+    future::Future,
     net::ToSocketAddrs,
     sync::{Arc, RwLock},
 };
+use tracing::Instrument as _;
 
 use crate::{
-    protocols::{Peers, PendingTxs},
+    memory,
+    protocols::{ChainEvent, Peers, PendingTxs},
+    reload::ConfigReloader,
     storage::{
         self, extract_raw_data, Key, KeyPrefix, Storage, StorageWithChainData, LAST_STATE_KEY,
     },
-    verify::verify_tx,
+    types::RpcConfig,
+    verify::{
+        calc_fee_rate, diagnose_script_failure, verify_tx, verify_tx_with_debug_output,
+        verify_tx_without_scripts, ScriptDebugOutput, ScriptFailure, TxVerifyContext,
+    },
 };
 
 #[rpc(server)]
@@ -64,18 +77,44 @@ pub trait BlockFilterRpc {
 
     #[rpc(name = "get_cells_capacity")]
     fn get_cells_capacity(&self, search_key: SearchKey) -> Result<CellsCapacity>;
+
+    #[rpc(name = "pause_filter_sync")]
+    fn pause_filter_sync(&self) -> Result<()>;
+
+    #[rpc(name = "resume_filter_sync")]
+    fn resume_filter_sync(&self) -> Result<()>;
+
+    #[rpc(name = "get_filter_sync_stats")]
+    fn get_filter_sync_stats(&self) -> Result<FilterSyncStats>;
+
+    #[rpc(name = "get_filter_sync_state")]
+    fn get_filter_sync_state(&self) -> Result<FilterSyncState>;
+
+    #[rpc(name = "get_matched_blocks")]
+    fn get_matched_blocks(&self) -> Result<Option<MatchedBlocks>>;
 }
 
 #[rpc(server)]
 pub trait TransactionRpc {
     #[rpc(name = "send_transaction")]
-    fn send_transaction(&self, tx: Transaction) -> Result<H256>;
+    fn send_transaction(
+        &self,
+        tx: Transaction,
+        verify_async: Option<bool>,
+        trusted_cycles: Option<Cycle>,
+    ) -> Result<H256>;
 
     #[rpc(name = "get_transaction")]
     fn get_transaction(&self, tx_hash: H256) -> Result<TransactionWithStatus>;
 
     #[rpc(name = "fetch_transaction")]
     fn fetch_transaction(&self, tx_hash: H256) -> Result<FetchStatus<TransactionWithStatus>>;
+
+    #[rpc(name = "tx_pool_info")]
+    fn tx_pool_info(&self) -> Result<TxPoolInfo>;
+
+    #[rpc(name = "get_pending_transactions")]
+    fn get_pending_transactions(&self) -> Result<Vec<PendingTransaction>>;
 }
 
 #[rpc(server)]
@@ -92,8 +131,16 @@ pub trait ChainRpc {
     #[rpc(name = "fetch_header")]
     fn fetch_header(&self, block_hash: H256) -> Result<FetchStatus<HeaderView>>;
 
+    #[rpc(name = "get_block")]
+    fn get_block(&self, block_hash: H256) -> Result<Option<BlockView>>;
+
     #[rpc(name = "estimate_cycles")]
-    fn estimate_cycles(&self, tx: Transaction) -> Result<EstimateCycles>;
+    fn estimate_cycles(
+        &self,
+        tx: Transaction,
+        context: Option<VerifyContext>,
+        placeholder_signatures: Option<bool>,
+    ) -> Result<EstimateCycles>;
 }
 
 #[rpc(server)]
@@ -105,6 +152,49 @@ pub trait NetRpc {
     fn get_peers(&self) -> Result<Vec<RemoteNode>>;
 }
 
+#[rpc(server)]
+pub trait AdminRpc {
+    #[rpc(name = "reload_config")]
+    fn reload_config(&self) -> Result<()>;
+
+    #[rpc(name = "get_node_info")]
+    fn get_node_info(&self) -> Result<NodeInfo>;
+
+    #[rpc(name = "get_memory_info")]
+    fn get_memory_info(&self) -> Result<MemoryInfo>;
+}
+
+/// The methods of ckb-indexer, they are registered only if `rpc.indexer_compatible` is enabled,
+/// so the tools which are written for ckb-indexer could talk to the light client unchanged.
+///
+/// They override the methods with the same names in [`BlockFilterRpc`].
+#[rpc(server)]
+pub trait IndexerRpc {
+    #[rpc(name = "get_indexer_tip")]
+    fn get_indexer_tip(&self) -> Result<Option<IndexerTip>>;
+
+    #[rpc(name = "get_cells")]
+    fn get_cells(
+        &self,
+        search_key: SearchKey,
+        order: Order,
+        limit: Uint32,
+        after: Option<JsonBytes>,
+    ) -> Result<Pagination<Cell>>;
+
+    #[rpc(name = "get_transactions")]
+    fn get_transactions(
+        &self,
+        search_key: SearchKey,
+        order: Order,
+        limit: Uint32,
+        after: Option<JsonBytes>,
+    ) -> Result<Pagination<IndexerTx>>;
+
+    #[rpc(name = "get_cells_capacity")]
+    fn get_cells_capacity(&self, search_key: SearchKey) -> Result<Option<CellsCapacity>>;
+}
+
 #[derive(Deserialize, Serialize, Eq, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum SetScriptsCommand {
@@ -203,6 +293,18 @@ pub struct LocalNode {
     pub protocols: Vec<LocalNodeProtocol>,
     /// Count of currently connected peers.
     pub connections: Uint64,
+    /// Whether the proved tip falls too far behind the wall-clock time.
+    ///
+    /// The balances shouldn't be treated as current when it's true.
+    pub tip_is_stale: bool,
+    /// Whether the proved tip diverges from the chain of the trusted node.
+    ///
+    /// It's always false if no trusted node is configured.
+    pub trusted_node_diverged: bool,
+    /// Count of the matched blocks which contain no transactions of the filter scripts.
+    ///
+    /// They're matched by the false positives of the block filters, and discarded.
+    pub filter_false_positives: Uint64,
 }
 
 /// The information of a P2P protocol that is supported by the local node.
@@ -235,12 +337,32 @@ pub struct RemoteNode {
     /// CKB uses Tentacle multiplexed network framework. Multiple protocols are running
     /// simultaneously in the connection.
     pub protocols: Vec<RemoteNodeProtocol>,
+    /// Counts of the failed statuses when processing messages from the remote node,
+    /// the key is the name of the status code.
+    pub failure_counts: BTreeMap<String, Uint64>,
+    /// Counts of the messages and their bytes which are exchanged with the remote node,
+    /// per protocol.
+    pub message_counts: Vec<ProtocolMessageCounts>,
     // TODO: maybe add this field later.
     // /// Elapsed time in milliseconds since receiving the ping response from this remote node.
     // ///
     // /// Null means no ping responses have been received yet.
     // pub last_ping_duration: Option<Uint64>,
 }
+#[derive(Deserialize, Serialize)]
+pub struct ProtocolMessageCounts {
+    /// The protocol id.
+    pub id: Uint64,
+    /// Count of the messages which are received from the remote node.
+    pub received_messages: Uint64,
+    /// Bytes of the messages which are received from the remote node.
+    pub received_bytes: Uint64,
+    /// Count of the messages which are sent to the remote node.
+    pub sent_messages: Uint64,
+    /// Bytes of the messages which are sent to the remote node.
+    pub sent_bytes: Uint64,
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct PeerSyncState {
     /// Requested best known header of remote peer.
@@ -249,12 +371,23 @@ pub struct PeerSyncState {
     pub requested_best_known_header: Option<HeaderView>,
     /// Proved best known header of remote peer.
     pub proved_best_known_header: Option<HeaderView>,
+    /// The count of blocks which the proved best known header of remote peer
+    /// falls behind the best proved tip among all peers.
+    ///
+    /// Null means no proof is received from this remote peer yet.
+    pub proved_tip_lag: Option<BlockNumber>,
+    /// The best tip number which is announced by remote peer through the headers or the
+    /// compact blocks, it's not verified.
+    ///
+    /// Null means no headers or compact blocks are received from this remote peer yet.
+    pub announced_tip_number: Option<BlockNumber>,
 }
 
 #[derive(Deserialize)]
 pub struct SearchKey {
     pub(crate) script: Script,
     pub(crate) script_type: ScriptType,
+    pub(crate) script_search_mode: Option<SearchMode>,
     pub(crate) filter: Option<SearchKeyFilter>,
     pub(crate) with_data: Option<bool>,
     pub(crate) group_by_transaction: Option<bool>,
@@ -265,6 +398,7 @@ impl Default for SearchKey {
         Self {
             script: Script::default(),
             script_type: ScriptType::Lock,
+            script_search_mode: None,
             filter: None,
             with_data: None,
             group_by_transaction: None,
@@ -276,6 +410,8 @@ impl Default for SearchKey {
 pub struct SearchKeyFilter {
     pub(crate) script: Option<Script>,
     pub(crate) script_len_range: Option<[Uint64; 2]>,
+    pub(crate) output_data: Option<JsonBytes>,
+    pub(crate) output_data_filter_mode: Option<SearchMode>,
     pub(crate) output_data_len_range: Option<[Uint64; 2]>,
     pub(crate) output_capacity_range: Option<[Uint64; 2]>,
     pub(crate) block_range: Option<[BlockNumber; 2]>,
@@ -288,6 +424,19 @@ pub enum ScriptType {
     Type,
 }
 
+/// How the script or the output data is matched, it's the same as ckb-indexer.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    /// Match the prefix.
+    #[default]
+    Prefix,
+    /// Match the whole content.
+    Exact,
+    /// Match a part of the content, it's only supported by the output data.
+    Partial,
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Order {
@@ -304,6 +453,76 @@ pub struct Cell {
     tx_index: Uint32,
 }
 
+/// The throughput of the filter sync since started.
+#[derive(Deserialize, Serialize)]
+pub struct FilterSyncStats {
+    /// Elapsed time in milliseconds since started.
+    pub elapsed: Uint64,
+    /// Count of the blocks which are checked with the block filters.
+    pub filtered_blocks: Uint64,
+    /// Average count of the filtered blocks per second.
+    pub filtered_blocks_per_second: Uint64,
+    /// Bytes of the block filters and the matched blocks which are downloaded.
+    pub downloaded_bytes: Uint64,
+    /// Average downloaded bytes per second.
+    pub downloaded_bytes_per_second: Uint64,
+    /// Count of the blocks which are matched by the block filters.
+    pub matched_blocks: Uint64,
+    /// Count of the matched blocks which contain no transactions of the filter scripts.
+    pub false_positives: Uint64,
+}
+
+/// The progress of the filter sync.
+#[derive(Deserialize, Serialize)]
+pub struct FilterSyncState {
+    /// The number of the block which all filter scripts are filtered up to.
+    pub filtered_block_number: BlockNumber,
+    /// The number of the proved tip block.
+    pub tip_block_number: BlockNumber,
+    /// Count of the matched blocks which are not processed yet.
+    pub matched_blocks_in_queue: Uint64,
+    /// Count of the matched blocks which contain no transactions of the filter scripts.
+    pub false_positives: Uint64,
+    /// Estimated time in milliseconds to catch up with the tip block, by the average count
+    /// of the filtered blocks per second.
+    ///
+    /// Null means no blocks are filtered since started.
+    pub estimated_time_to_catch_up: Option<Uint64>,
+    /// The downloaded block filters of each peer.
+    pub peers: Vec<PeerFilterState>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PeerFilterState {
+    /// The id of the remote node, null if it's unknown yet.
+    pub node_id: Option<String>,
+    /// The highest number of the block filters which are downloaded from the remote node.
+    ///
+    /// Zero means no block filters are downloaded from it.
+    pub downloaded_block_number: BlockNumber,
+}
+
+/// The matched blocks which are processing.
+#[derive(Deserialize, Serialize)]
+pub struct MatchedBlocks {
+    /// The number of the first block which is checked with the block filters.
+    pub start_number: BlockNumber,
+    /// Count of the blocks which are checked with the block filters.
+    pub blocks_count: Uint64,
+    pub blocks: Vec<MatchedBlock>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct MatchedBlock {
+    pub block_hash: H256,
+    /// The block number is known after the block is downloaded.
+    pub block_number: Option<BlockNumber>,
+    /// The block is proved or not.
+    pub proved: bool,
+    /// The block is downloaded or not.
+    pub downloaded: bool,
+}
+
 #[derive(Serialize)]
 pub struct CellsCapacity {
     pub capacity: Capacity,
@@ -352,12 +571,208 @@ pub enum CellType {
     Output,
 }
 
+/// The block which all filter scripts are filtered up to.
+#[derive(Serialize)]
+pub struct IndexerTip {
+    pub block_hash: H256,
+    pub block_number: BlockNumber,
+}
+
+/// The transaction in the result of `get_transactions` of ckb-indexer, it has the hash
+/// instead of the whole transaction.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum IndexerTx {
+    Ungrouped(IndexerTxWithCell),
+    Grouped(IndexerTxWithCells),
+}
+
+impl From<Tx> for IndexerTx {
+    fn from(tx: Tx) -> Self {
+        match tx {
+            Tx::Ungrouped(tx) => Self::Ungrouped(IndexerTxWithCell {
+                tx_hash: tx.transaction.hash,
+                block_number: tx.block_number,
+                tx_index: tx.tx_index,
+                io_index: tx.io_index,
+                io_type: tx.io_type,
+            }),
+            Tx::Grouped(tx) => Self::Grouped(IndexerTxWithCells {
+                tx_hash: tx.transaction.hash,
+                block_number: tx.block_number,
+                tx_index: tx.tx_index,
+                cells: tx.cells,
+            }),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct IndexerTxWithCell {
+    pub(crate) tx_hash: H256,
+    block_number: BlockNumber,
+    tx_index: Uint32,
+    io_index: Uint32,
+    io_type: CellType,
+}
+
+#[derive(Serialize)]
+pub struct IndexerTxWithCells {
+    pub(crate) tx_hash: H256,
+    block_number: BlockNumber,
+    tx_index: Uint32,
+    cells: Vec<(CellType, Uint32)>,
+}
+
 #[derive(Serialize)]
 pub struct Pagination<T> {
     pub(crate) objects: Vec<T>,
     pub(crate) last_cursor: JsonBytes,
 }
 
+/// The build and runtime information of the light client.
+#[derive(Deserialize, Serialize)]
+pub struct NodeInfo {
+    /// The version of the light client.
+    pub version: String,
+    /// The git commit which the light client is built from, empty if it's unknown.
+    pub commit: String,
+    /// The enabled cargo features.
+    pub features: Vec<String>,
+    /// The version of the RocksDB binding `ckb-rocksdb`, empty if it's unknown.
+    pub rocksdb_version: String,
+    /// The name of the chain.
+    pub chain: String,
+    /// The time (in milliseconds) when the light client started.
+    pub started_at: Uint64,
+}
+
+/// The memory of the light client.
+#[derive(Deserialize, Serialize)]
+pub struct MemoryInfo {
+    /// The bytes which are allocated by the global allocator, excludes the memory of RocksDB.
+    pub allocated: Uint64,
+    /// The resident memory (in bytes) of the process, it's only available on Linux.
+    pub resident: Option<Uint64>,
+    /// The soft limit (in bytes) of the memory, zero means no limit.
+    pub soft_limit: Uint64,
+    /// The memory exceeds the soft limit, the matched blocks are not downloaded.
+    pub under_pressure: bool,
+}
+
+/// The pending transactions which are waiting for relay.
+#[derive(Deserialize, Serialize)]
+pub struct TxPoolInfo {
+    /// Count of the pending transactions.
+    pub pending: Uint64,
+    /// Count of the rebroadcasts of the pending transactions.
+    pub rebroadcasts: Uint64,
+    /// Count of the pending transactions which are committed.
+    pub committed: Uint64,
+    /// Count of the pending transactions which are dropped since they are not committed in time.
+    pub expired: Uint64,
+    /// Count of the pending transactions which are replaced by conflicting transactions with
+    /// higher fee rates.
+    pub replaced: Uint64,
+}
+
+/// The details of the script which fails to verify a transaction, as the data of the error.
+#[derive(Deserialize, Serialize)]
+pub struct ScriptFailureDetails {
+    /// The type of the script group, "lock" or "type".
+    pub script_group_type: String,
+    /// The index of the first cell in the script group.
+    pub cell_index: Uint64,
+    /// Where the cell is, "input" or "output".
+    pub cell_source: String,
+    pub script: Script,
+    /// The exit code of the script, null if the script doesn't return a non-zero code.
+    pub exit_code: Option<i8>,
+    /// The consumed cycles, only available when the script exceeds the max cycles.
+    pub cycles: Option<Cycle>,
+    pub error: String,
+    /// The last debug messages which are printed by the script.
+    pub debug_messages: Vec<String>,
+}
+
+impl From<ScriptFailure> for ScriptFailureDetails {
+    fn from(failure: ScriptFailure) -> Self {
+        Self {
+            script_group_type: match failure.group_type {
+                ScriptGroupType::Lock => "lock".to_owned(),
+                ScriptGroupType::Type => "type".to_owned(),
+            },
+            cell_index: (failure.cell_index as u64).into(),
+            cell_source: if failure.is_input { "input" } else { "output" }.to_owned(),
+            script: failure.script.into(),
+            exit_code: failure.exit_code,
+            cycles: failure.cycles.map(Into::into),
+            error: failure.error,
+            debug_messages: failure.debug_messages,
+        }
+    }
+}
+
+/// A message which is printed by a script via the debug syscall.
+#[derive(Deserialize, Serialize)]
+pub struct ScriptDebugMessage {
+    pub script_hash: H256,
+    pub message: String,
+}
+
+/// The block against which the `since` and the epoch related rules of a transaction are
+/// evaluated.
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum VerifyContext {
+    /// The transaction will be submitted after the proved tip, it's the default.
+    Tip,
+    /// The transaction is committed in the next block of the proved tip.
+    NextBlock,
+    /// The transaction is committed in the block of the header.
+    Header(H256),
+}
+
+/// The result of `estimate_cycles`.
+#[derive(Deserialize, Serialize)]
+pub struct EstimateCycles {
+    pub cycles: Cycle,
+    /// The debug messages which are printed by the scripts, in order of the script groups.
+    pub debug_messages: Vec<ScriptDebugMessage>,
+    /// Whether some debug messages are dropped since the total size exceeds the cap.
+    pub debug_messages_truncated: bool,
+}
+
+impl EstimateCycles {
+    fn new(cycles: core::Cycle, debug_output: ScriptDebugOutput) -> Self {
+        Self {
+            cycles: cycles.into(),
+            debug_messages: debug_output
+                .messages
+                .into_iter()
+                .map(|(script_hash, message)| ScriptDebugMessage {
+                    script_hash: script_hash.unpack(),
+                    message,
+                })
+                .collect(),
+            debug_messages_truncated: debug_output.truncated,
+        }
+    }
+}
+
+/// The relay statistics of a pending transaction.
+#[derive(Deserialize, Serialize)]
+pub struct PendingTransaction {
+    pub tx_hash: H256,
+    /// Count of the peers which the transaction is announced to.
+    pub announced_peers: Uint64,
+    /// Count of the requests for the transaction from peers.
+    pub requests: Uint64,
+    /// The time (in milliseconds) when the transaction is announced last time, null if it's
+    /// never announced.
+    pub last_announced_at: Option<Uint64>,
+}
+
 #[derive(Serialize, Debug, Eq, PartialEq)]
 pub struct TransactionWithStatus {
     pub(crate) transaction: Option<TransactionView>,
@@ -369,13 +784,21 @@ pub struct TransactionWithStatus {
 pub struct TxStatus {
     pub status: Status,
     pub block_hash: Option<H256>,
+    /// The reason why the transaction is rejected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
 }
 
 #[derive(Serialize, Debug, Eq, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum Status {
+    /// The transaction is submitted asynchronously and is being verified.
+    Verifying,
     Pending,
+    Proposed,
     Committed,
+    /// The transaction is submitted asynchronously but fails to be verified.
+    Rejected,
     Unknown,
 }
 
@@ -383,14 +806,24 @@ pub struct BlockFilterRpcImpl {
     pub(crate) swc: StorageWithChainData,
 }
 
+pub struct IndexerRpcImpl {
+    pub(crate) block_filter: BlockFilterRpcImpl,
+}
+
 pub struct TransactionRpcImpl {
     pub(crate) swc: StorageWithChainData,
     pub(crate) consensus: Arc<Consensus>,
+    // The max cycles to verify a transaction.
+    pub(crate) max_tx_cycles: core::Cycle,
+    // The threads to verify the transactions which are submitted asynchronously.
+    pub(crate) verify_pool: Arc<rayon::ThreadPool>,
 }
 
 pub struct ChainRpcImpl {
     pub(crate) swc: StorageWithChainData,
     pub(crate) consensus: Arc<Consensus>,
+    // The max cycles to verify a transaction.
+    pub(crate) max_tx_cycles: core::Cycle,
 }
 
 pub struct NetRpcImpl {
@@ -398,6 +831,13 @@ pub struct NetRpcImpl {
     peers: Arc<Peers>,
 }
 
+pub struct AdminRpcImpl {
+    reloader: ConfigReloader,
+    peers: Arc<Peers>,
+    chain: String,
+    started_at: u64,
+}
+
 impl BlockFilterRpc for BlockFilterRpcImpl {
     fn set_scripts(
         &self,
@@ -406,10 +846,18 @@ impl BlockFilterRpc for BlockFilterRpcImpl {
     ) -> Result<()> {
         let mut matched_blocks = self.swc.matched_blocks().write().expect("poisoned");
         let scripts = scripts.into_iter().map(Into::into).collect();
-        self.swc
-            .storage()
-            .update_filter_scripts(scripts, command.map(Into::into).unwrap_or_default());
+        let storage = self.swc.storage();
+        let was_filter_scripts_empty = storage.is_filter_scripts_empty();
+        let min_filtered_block_number = storage.get_min_filtered_block_number();
+        storage.update_filter_scripts(scripts, command.map(Into::into).unwrap_or_default());
         matched_blocks.clear();
+        // Only the lowered range is filtered again for the new scripts, since the other
+        // scripts are filtered from their own block numbers.
+        if was_filter_scripts_empty
+            || storage.get_min_filtered_block_number() < min_filtered_block_number
+        {
+            self.swc.request_filter_rescan();
+        }
         Ok(())
     }
 
@@ -425,6 +873,7 @@ impl BlockFilterRpc for BlockFilterRpcImpl {
         limit: Uint32,
         after_cursor: Option<JsonBytes>,
     ) -> Result<Pagination<Cell>> {
+        ensure_enough_proved_peers(&self.swc)?;
         let (prefix, from_key, direction, skip) = build_query_options(
             &search_key,
             KeyPrefix::CellLockScript,
@@ -437,6 +886,7 @@ impl BlockFilterRpc for BlockFilterRpcImpl {
             return Err(Error::invalid_params("limit should be greater than 0"));
         }
         let with_data = search_key.with_data.unwrap_or(true);
+        let script_search_exact = search_key.script_search_mode == Some(SearchMode::Exact);
         let filter_script_type = match search_key.script_type {
             ScriptType::Lock => ScriptType::Type,
             ScriptType::Type => ScriptType::Lock,
@@ -444,6 +894,7 @@ impl BlockFilterRpc for BlockFilterRpcImpl {
         let (
             filter_prefix,
             filter_script_len_range,
+            filter_output_data,
             filter_output_data_len_range,
             filter_output_capacity_range,
             filter_block_range,
@@ -456,6 +907,10 @@ impl BlockFilterRpc for BlockFilterRpcImpl {
         let cells = iter
             .take_while(|(key, _value)| key.starts_with(&prefix))
             .filter_map(|(key, value)| {
+                // The key is the script, the block number, the tx index and the output index.
+                if script_search_exact && key.len() != prefix.len() + 16 {
+                    return None;
+                }
                 let tx_hash = packed::Byte32::from_slice(&value).expect("stored tx hash");
                 let output_index = u32::from_be_bytes(
                     key[key.len() - 4..]
@@ -534,6 +989,12 @@ impl BlockFilterRpc for BlockFilterRpcImpl {
                     }
                 }
 
+                if let Some((data, mode)) = filter_output_data.as_ref() {
+                    if !match_search_mode(&output_data.raw_data(), data, *mode) {
+                        return None;
+                    }
+                }
+
                 if let Some([r0, r1]) = filter_output_data_len_range {
                     if output_data.len() < r0 || output_data.len() >= r1 {
                         return None;
@@ -595,7 +1056,13 @@ impl BlockFilterRpc for BlockFilterRpcImpl {
             return Err(Error::invalid_params("limit should be greater than 0"));
         }
 
+        let script_search_exact = search_key.script_search_mode == Some(SearchMode::Exact);
         let (filter_script, filter_block_range) = if let Some(filter) = search_key.filter.as_ref() {
+            if filter.output_data.is_some() {
+                return Err(Error::invalid_params(
+                    "doesn't support search_key.filter.output_data parameter",
+                ));
+            }
             if filter.output_data_len_range.is_some() {
                 return Err(Error::invalid_params(
                     "doesn't support search_key.filter.output_data_len_range parameter",
@@ -629,6 +1096,11 @@ impl BlockFilterRpc for BlockFilterRpcImpl {
             let mut last_key = Vec::new();
 
             for (key, value) in iter.take_while(|(key, _value)| key.starts_with(&prefix)) {
+                // The key is the script, the block number, the tx index, the io index and the
+                // io type.
+                if script_search_exact && key.len() != prefix.len() + 17 {
+                    continue;
+                }
                 let tx_hash = packed::Byte32::from_slice(&value).expect("stored tx hash");
                 if tx_with_cells.len() == limit
                     && tx_with_cells.last_mut().unwrap().transaction.hash != tx_hash.unpack()
@@ -743,6 +1215,9 @@ impl BlockFilterRpc for BlockFilterRpcImpl {
             let txs = iter
                 .take_while(|(key, _value)| key.starts_with(&prefix))
                 .filter_map(|(key, value)| {
+                    if script_search_exact && key.len() != prefix.len() + 17 {
+                        return None;
+                    }
                     let tx_hash = packed::Byte32::from_slice(&value).expect("stored tx hash");
                     let tx = packed::Transaction::from_slice(
                         &snapshot
@@ -845,6 +1320,7 @@ impl BlockFilterRpc for BlockFilterRpcImpl {
             Order::Asc,
             None,
         )?;
+        let script_search_exact = search_key.script_search_mode == Some(SearchMode::Exact);
         let filter_script_type = match search_key.script_type {
             ScriptType::Lock => ScriptType::Type,
             ScriptType::Type => ScriptType::Lock,
@@ -852,6 +1328,7 @@ impl BlockFilterRpc for BlockFilterRpcImpl {
         let (
             filter_prefix,
             filter_script_len_range,
+            filter_output_data,
             filter_output_data_len_range,
             filter_output_capacity_range,
             filter_block_range,
@@ -863,6 +1340,9 @@ impl BlockFilterRpc for BlockFilterRpcImpl {
         let capacity: u64 = iter
             .take_while(|(key, _value)| key.starts_with(&prefix))
             .filter_map(|(key, value)| {
+                if script_search_exact && key.len() != prefix.len() + 16 {
+                    return None;
+                }
                 let tx_hash = packed::Byte32::from_slice(&value).expect("stored tx hash");
                 let output_index = u32::from_be_bytes(
                     key[key.len() - 4..]
@@ -936,6 +1416,12 @@ impl BlockFilterRpc for BlockFilterRpcImpl {
                     }
                 }
 
+                if let Some((data, mode)) = filter_output_data.as_ref() {
+                    if !match_search_mode(&output_data.raw_data(), data, *mode) {
+                        return None;
+                    }
+                }
+
                 if let Some([r0, r1]) = filter_output_data_len_range {
                     if output_data.len() < r0 || output_data.len() >= r1 {
                         return None;
@@ -971,6 +1457,95 @@ impl BlockFilterRpc for BlockFilterRpcImpl {
             block_number: tip_header.raw().number().unpack(),
         })
     }
+
+    fn pause_filter_sync(&self) -> Result<()> {
+        self.swc.set_filter_sync_paused(true);
+        Ok(())
+    }
+
+    fn resume_filter_sync(&self) -> Result<()> {
+        self.swc.set_filter_sync_paused(false);
+        Ok(())
+    }
+
+    fn get_filter_sync_stats(&self) -> Result<FilterSyncStats> {
+        let peers = self.swc.peers();
+        let (elapsed, filtered_blocks, matched_blocks, downloaded_bytes) =
+            peers.get_filter_sync_counts();
+        let per_second = |count: u64| count.saturating_mul(1000) / elapsed.max(1);
+        Ok(FilterSyncStats {
+            elapsed: elapsed.into(),
+            filtered_blocks: filtered_blocks.into(),
+            filtered_blocks_per_second: per_second(filtered_blocks).into(),
+            downloaded_bytes: downloaded_bytes.into(),
+            downloaded_bytes_per_second: per_second(downloaded_bytes).into(),
+            matched_blocks: matched_blocks.into(),
+            false_positives: peers.get_filter_false_positives().into(),
+        })
+    }
+
+    fn get_filter_sync_state(&self) -> Result<FilterSyncState> {
+        let peers = self.swc.peers();
+        let filtered_block_number = self.swc.storage().get_min_filtered_block_number();
+        let tip_block_number: core::BlockNumber =
+            self.swc.storage().get_tip_header().raw().number().unpack();
+        let (elapsed, filtered_blocks, _, _) = peers.get_filter_sync_counts();
+        let estimated_time_to_catch_up = if filtered_blocks == 0 {
+            None
+        } else {
+            let remaining = tip_block_number.saturating_sub(filtered_block_number);
+            Some(remaining.saturating_mul(elapsed) / filtered_blocks)
+        };
+        let matched_blocks_in_queue = self.swc.matched_blocks().read().expect("poisoned").len();
+        Ok(FilterSyncState {
+            filtered_block_number: filtered_block_number.into(),
+            tip_block_number: tip_block_number.into(),
+            matched_blocks_in_queue: (matched_blocks_in_queue as u64).into(),
+            false_positives: peers.get_filter_false_positives().into(),
+            estimated_time_to_catch_up: estimated_time_to_catch_up.map(Into::into),
+            peers: peers
+                .get_filter_downloaded_numbers()
+                .into_iter()
+                .map(|(node_id, number)| PeerFilterState {
+                    node_id: node_id.map(|peer_id| peer_id.to_base58()),
+                    downloaded_block_number: number.into(),
+                })
+                .collect(),
+        })
+    }
+
+    fn get_matched_blocks(&self) -> Result<Option<MatchedBlocks>> {
+        let (start_number, blocks_count, db_blocks) =
+            match self.swc.storage().get_earliest_matched_blocks() {
+                Some(matched_blocks) => matched_blocks,
+                None => return Ok(None),
+            };
+        let matched_blocks = self.swc.matched_blocks().read().expect("poisoned");
+        let blocks = db_blocks
+            .into_iter()
+            .map(|(block_hash, proved)| {
+                let block_hash = block_hash.unpack();
+                let (proved, block_opt) = matched_blocks
+                    .get(&block_hash)
+                    .map(|(proved, block_opt)| (*proved, block_opt.as_ref()))
+                    .unwrap_or((proved, None));
+                MatchedBlock {
+                    block_hash,
+                    block_number: block_opt.map(|block| {
+                        let number: core::BlockNumber = block.header().raw().number().unpack();
+                        number.into()
+                    }),
+                    proved,
+                    downloaded: block_opt.is_some(),
+                }
+            })
+            .collect();
+        Ok(Some(MatchedBlocks {
+            start_number: start_number.into(),
+            blocks_count: blocks_count.into(),
+            blocks,
+        }))
+    }
 }
 
 const MAX_ADDRS: usize = 50;
@@ -1001,10 +1576,14 @@ impl NetRpc for NetRpcImpl {
                 })
                 .collect::<Vec<_>>(),
             connections: (self.network_controller.connected_peers().len() as u64).into(),
+            tip_is_stale: self.peers.is_tip_stale(),
+            trusted_node_diverged: self.peers.is_trusted_node_diverged(),
+            filter_false_positives: self.peers.get_filter_false_positives().into(),
         })
     }
 
     fn get_peers(&self) -> Result<Vec<RemoteNode>> {
+        let best_proved_tip_number = self.peers.get_best_proved_tip_number();
         let peers: Vec<RemoteNode> = self
             .network_controller
             .connected_peers()
@@ -1050,6 +1629,18 @@ impl NetRpc for NetRpcImpl {
                         proved_best_known_header: state
                             .get_prove_state()
                             .map(|request| request.get_last_header().header().to_owned().into()),
+                        proved_tip_lag: state.get_prove_state().and_then(|request| {
+                            best_proved_tip_number.map(|best_number| {
+                                best_number
+                                    .saturating_sub(request.get_last_header().header().number())
+                                    .into()
+                            })
+                        }),
+                        announced_tip_number: self
+                            .peers
+                            .get_peer(peer_index)
+                            .and_then(|peer| peer.get_announced_tip_number())
+                            .map(Into::into),
                     }),
                     protocols: peer
                         .protocols
@@ -1059,6 +1650,35 @@ impl NetRpc for NetRpcImpl {
                             version: protocol_version.clone(),
                         })
                         .collect(),
+                    failure_counts: self
+                        .peers
+                        .get_peer(peer_index)
+                        .map(|peer| {
+                            peer.get_failure_counts()
+                                .iter()
+                                .map(|(code, count)| (format!("{:?}", code), (*count).into()))
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    message_counts: self
+                        .peers
+                        .get_peer(peer_index)
+                        .map(|peer| {
+                            let mut message_counts = peer
+                                .get_message_counts()
+                                .iter()
+                                .map(|(protocol_id, counts)| ProtocolMessageCounts {
+                                    id: (protocol_id.value() as u64).into(),
+                                    received_messages: counts.received_messages.into(),
+                                    received_bytes: counts.received_bytes.into(),
+                                    sent_messages: counts.sent_messages.into(),
+                                    sent_bytes: counts.sent_bytes.into(),
+                                })
+                                .collect::<Vec<_>>();
+                            message_counts.sort_by_key(|counts| counts.id.value());
+                            message_counts
+                        })
+                        .unwrap_or_default(),
                 }
             })
             .collect();
@@ -1066,8 +1686,148 @@ impl NetRpc for NetRpcImpl {
     }
 }
 
+impl AdminRpc for AdminRpcImpl {
+    fn reload_config(&self) -> Result<()> {
+        self.reloader
+            .reload()
+            .map_err(|err| Error::invalid_params(err.to_string()))
+    }
+
+    fn get_node_info(&self) -> Result<NodeInfo> {
+        let features = [
+            ("portable", cfg!(feature = "portable")),
+            ("march-native", cfg!(feature = "march-native")),
+            ("test-support", cfg!(feature = "test-support")),
+            ("jemalloc", cfg!(feature = "jemalloc")),
+            ("otlp", cfg!(feature = "otlp")),
+        ]
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| name.to_owned())
+        .collect();
+        Ok(NodeInfo {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            commit: env!("GIT_COMMIT_HASH").to_owned(),
+            features,
+            rocksdb_version: env!("ROCKSDB_CRATE_VERSION").to_owned(),
+            chain: self.chain.clone(),
+            started_at: self.started_at.into(),
+        })
+    }
+
+    fn get_memory_info(&self) -> Result<MemoryInfo> {
+        Ok(MemoryInfo {
+            allocated: memory::allocated().into(),
+            resident: memory::resident().map(Into::into),
+            soft_limit: self.peers.get_memory_soft_limit().into(),
+            under_pressure: self.peers.is_under_memory_pressure(),
+        })
+    }
+}
+
 const MAX_PREFIX_SEARCH_SIZE: usize = u16::max_value() as usize;
 
+// The count of threads to verify the transactions which are submitted asynchronously, so
+// they don't occupy the global thread pool which the protocols use.
+const VERIFY_ASYNC_THREADS: usize = 2;
+
+pub(crate) fn new_verify_pool() -> Arc<rayon::ThreadPool> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(VERIFY_ASYNC_THREADS)
+        .thread_name(|index| format!("VerifyTx{}", index))
+        .build()
+        .expect("build the thread pool to verify transactions");
+    Arc::new(pool)
+}
+
+/// The error code when the proved peers are not enough to treat the data as final.
+pub(crate) const NOT_ENOUGH_PROVED_PEERS_ERROR_CODE: i64 = -1000;
+
+// Attaches the details of the failed script to the error if the transaction fails to verify
+// the scripts.
+fn invalid_transaction_error(
+    transaction: core::TransactionView,
+    swc: &StorageWithChainData,
+    consensus: Arc<Consensus>,
+    max_cycles: core::Cycle,
+    context: &TxVerifyContext,
+    err: ckb_error::Error,
+) -> Error {
+    let mut error = Error::invalid_params(format!("invalid transaction: {:?}", err));
+    if err.kind() == ckb_error::ErrorKind::Script {
+        error.data = diagnose_script_failure(transaction, swc, consensus, max_cycles, context).map(
+            |failure| {
+                serde_json::to_value(ScriptFailureDetails::from(failure))
+                    .expect("serialize script failure should be ok")
+            },
+        );
+    }
+    error
+}
+
+// Puts a verified transaction into the pending pool, replaces the conflicting pending
+// transactions if its fee rate is higher.
+fn accept_verified_transaction(
+    swc: &StorageWithChainData,
+    tx: core::TransactionView,
+    cycles: core::Cycle,
+) -> Result<()> {
+    let fee_rate = calc_fee_rate(tx.clone(), swc)
+        .map_err(|e| Error::invalid_params(format!("invalid transaction: {:?}", e)))?;
+    let mut pending_txs = swc
+        .pending_txs()
+        .write()
+        .expect("pending_txs lock is poisoned");
+    // Replace the conflicting pending transactions if the fee rate is higher.
+    let conflicts = pending_txs.get_conflicts(&tx);
+    if let Some((hash, conflict_fee_rate)) = conflicts
+        .iter()
+        .find(|(_, conflict_fee_rate)| fee_rate <= *conflict_fee_rate)
+    {
+        return Err(Error::invalid_params(format!(
+            "transaction conflicts with the pending transaction {:#x}, \
+            its fee rate {} shannons/KB should be higher than {} shannons/KB",
+            hash, fee_rate, conflict_fee_rate
+        )));
+    }
+    let conflicts = conflicts
+        .into_iter()
+        .map(|(hash, _)| hash)
+        .collect::<Vec<_>>();
+    for hash in pending_txs.remove_replaced(&conflicts) {
+        info!(
+            "pending transaction {:#x} is replaced by {:#x}",
+            hash,
+            tx.hash()
+        );
+    }
+    for hash in pending_txs.push(tx, cycles, fee_rate) {
+        info!(
+            "pending transaction {:#x} is evicted since the pending pool is full",
+            hash
+        );
+        swc.peers()
+            .emit_chain_event(ChainEvent::PendingTxEvicted { hash });
+    }
+
+    Ok(())
+}
+
+// a helper fn to return an error if the proved peers are less than the required count
+fn ensure_enough_proved_peers(swc: &StorageWithChainData) -> Result<()> {
+    if let Some((proved_peers_count, min_proved_peers)) = swc.check_proved_peers() {
+        return Err(Error {
+            code: ErrorCode::ServerError(NOT_ENOUGH_PROVED_PEERS_ERROR_CODE),
+            message: format!(
+                "not enough proved peers: {} proved, at least {} required",
+                proved_peers_count, min_proved_peers
+            ),
+            data: None,
+        });
+    }
+    Ok(())
+}
+
 // a helper fn to build query options from search paramters, returns prefix, from_key, direction and skip offset
 fn build_query_options(
     search_key: &SearchKey,
@@ -1080,6 +1840,11 @@ fn build_query_options(
         ScriptType::Lock => vec![lock_prefix as u8],
         ScriptType::Type => vec![type_prefix as u8],
     };
+    if search_key.script_search_mode == Some(SearchMode::Partial) {
+        return Err(Error::invalid_params(
+            "doesn't support search_key.script_search_mode partial search mode",
+        ));
+    }
     let script: packed::Script = search_key.script.clone().into();
     let args_len = script.args().len();
     if args_len > MAX_PREFIX_SEARCH_SIZE {
@@ -1114,13 +1879,14 @@ fn build_query_options(
     Ok((prefix, from_key, direction, skip))
 }
 
-// a helper fn to build filter options from search paramters, returns prefix, output_data_len_range, output_capacity_range and block_range
+// a helper fn to build filter options from search paramters, returns prefix, script_len_range, output_data, output_data_len_range, output_capacity_range and block_range
 #[allow(clippy::type_complexity)]
 fn build_filter_options(
     search_key: SearchKey,
 ) -> Result<(
     Option<Vec<u8>>,
     Option<[usize; 2]>,
+    Option<(Vec<u8>, SearchMode)>,
     Option<[usize; 2]>,
     Option<[core::Capacity; 2]>,
     Option<[core::BlockNumber; 2]>,
@@ -1148,6 +1914,13 @@ fn build_filter_options(
         ]
     });
 
+    let filter_output_data = filter.output_data.map(|data| {
+        (
+            data.into_bytes().to_vec(),
+            filter.output_data_filter_mode.unwrap_or_default(),
+        )
+    });
+
     let filter_output_data_len_range = filter.output_data_len_range.map(|[r0, r1]| {
         [
             Into::<u64>::into(r0) as usize,
@@ -1165,25 +1938,173 @@ fn build_filter_options(
     Ok((
         filter_script_prefix,
         filter_script_len_range,
+        filter_output_data,
         filter_output_data_len_range,
         filter_output_capacity_range,
         filter_block_range,
     ))
 }
 
+fn match_search_mode(content: &[u8], pattern: &[u8], mode: SearchMode) -> bool {
+    match mode {
+        SearchMode::Prefix => content.starts_with(pattern),
+        SearchMode::Exact => content == pattern,
+        SearchMode::Partial => {
+            pattern.is_empty()
+                || content
+                    .windows(pattern.len())
+                    .any(|window| window == pattern)
+        }
+    }
+}
+
+impl IndexerRpc for IndexerRpcImpl {
+    fn get_indexer_tip(&self) -> Result<Option<IndexerTip>> {
+        let storage = self.block_filter.swc.storage();
+        if storage.is_filter_scripts_empty() {
+            return Ok(None);
+        }
+        let block_number = storage.get_min_filtered_block_number();
+        let block_hash = match storage.get_filtered_block_hash() {
+            Some((number, hash)) if number == block_number => Some(hash),
+            // The filtering is rewound, look up the hash in the headers which are stored.
+            _ => {
+                let tip_header = storage.get_tip_header();
+                let tip_number: core::BlockNumber = tip_header.raw().number().unpack();
+                if tip_number == block_number {
+                    Some(tip_header.calc_header_hash())
+                } else {
+                    storage
+                        .get_last_n_headers()
+                        .into_iter()
+                        .find(|(number, _)| *number == block_number)
+                        .map(|(_, hash)| hash)
+                }
+            }
+        };
+        // The hash is unknown only if the filtering is rewound to a block far behind the tip,
+        // until the next block filters are checked.
+        Ok(block_hash.map(|block_hash| IndexerTip {
+            block_hash: block_hash.unpack(),
+            block_number: block_number.into(),
+        }))
+    }
+
+    fn get_cells(
+        &self,
+        search_key: SearchKey,
+        order: Order,
+        limit: Uint32,
+        after_cursor: Option<JsonBytes>,
+    ) -> Result<Pagination<Cell>> {
+        BlockFilterRpc::get_cells(&self.block_filter, search_key, order, limit, after_cursor)
+    }
+
+    fn get_transactions(
+        &self,
+        search_key: SearchKey,
+        order: Order,
+        limit: Uint32,
+        after_cursor: Option<JsonBytes>,
+    ) -> Result<Pagination<IndexerTx>> {
+        let txs = BlockFilterRpc::get_transactions(
+            &self.block_filter,
+            search_key,
+            order,
+            limit,
+            after_cursor,
+        )?;
+        Ok(Pagination {
+            objects: txs.objects.into_iter().map(Into::into).collect(),
+            last_cursor: txs.last_cursor,
+        })
+    }
+
+    fn get_cells_capacity(&self, search_key: SearchKey) -> Result<Option<CellsCapacity>> {
+        BlockFilterRpc::get_cells_capacity(&self.block_filter, search_key).map(Some)
+    }
+}
+
 impl TransactionRpc for TransactionRpcImpl {
-    fn send_transaction(&self, tx: Transaction) -> Result<H256> {
+    fn send_transaction(
+        &self,
+        tx: Transaction,
+        verify_async: Option<bool>,
+        trusted_cycles: Option<Cycle>,
+    ) -> Result<H256> {
         let tx: packed::Transaction = tx.into();
         let tx = tx.into_view();
-        let cycles = verify_tx(tx.clone(), &self.swc, Arc::clone(&self.consensus))
-            .map_err(|e| Error::invalid_params(format!("invalid transaction: {:?}", e)))?;
-        self.swc
-            .pending_txs()
-            .write()
-            .expect("pending_txs lock is poisoned")
-            .push(tx.clone(), cycles);
-
-        Ok(tx.hash().unpack())
+        let tx_hash: H256 = tx.hash().unpack();
+        if let Some(cycles) = trusted_cycles {
+            // The transaction is verified by the submitter already, only check the structure
+            // and the capacities, since verifying the scripts is too slow on some devices.
+            // The cycles are relayed to the peers, which ban this client if they are wrong.
+            let cycles: core::Cycle = cycles.into();
+            if cycles > self.max_tx_cycles {
+                return Err(Error::invalid_params(format!(
+                    "the cycles {} exceed the max cycles {}",
+                    cycles, self.max_tx_cycles
+                )));
+            }
+            verify_tx_without_scripts(tx.clone(), &self.swc, Arc::clone(&self.consensus))
+                .map_err(|e| Error::invalid_params(format!("invalid transaction: {:?}", e)))?;
+            accept_verified_transaction(&self.swc, tx, cycles)?;
+            return Ok(tx_hash);
+        }
+        if verify_async.unwrap_or(false) {
+            let started = self
+                .swc
+                .pending_txs()
+                .write()
+                .expect("pending_txs lock is poisoned")
+                .start_verifying(tx.data())
+                .map_err(Error::invalid_params)?;
+            if started {
+                let swc = self.swc.clone();
+                let consensus = Arc::clone(&self.consensus);
+                let max_tx_cycles = self.max_tx_cycles;
+                // The result is reported through `get_transaction` and the chain events.
+                self.verify_pool.spawn(move || {
+                    let hash = tx.hash();
+                    let result = verify_tx(tx.clone(), &swc, consensus, max_tx_cycles)
+                        .map_err(|e| format!("invalid transaction: {:?}", e))
+                        .and_then(|cycles| {
+                            accept_verified_transaction(&swc, tx, cycles).map_err(|e| e.message)
+                        });
+                    swc.pending_txs()
+                        .write()
+                        .expect("pending_txs lock is poisoned")
+                        .finish_verifying(&hash, result.as_ref().err().cloned());
+                    let event = match result {
+                        Ok(()) => ChainEvent::TransactionVerified { hash },
+                        Err(reason) => {
+                            info!("transaction {:#x} is rejected: {}", hash, reason);
+                            ChainEvent::TransactionRejected { hash, reason }
+                        }
+                    };
+                    swc.peers().emit_chain_event(event);
+                });
+            }
+            return Ok(tx_hash);
+        }
+        let cycles = verify_tx(
+            tx.clone(),
+            &self.swc,
+            Arc::clone(&self.consensus),
+            self.max_tx_cycles,
+        )
+        .map_err(|e| {
+            invalid_transaction_error(
+                tx.clone(),
+                &self.swc,
+                Arc::clone(&self.consensus),
+                self.max_tx_cycles,
+                &TxVerifyContext::Tip,
+                e,
+            )
+        })?;
+        accept_verified_transaction(&self.swc, tx, cycles)?;
+        Ok(tx_hash)
     }
 
     fn get_transaction(&self, tx_hash: H256) -> Result<TransactionWithStatus> {
@@ -1198,23 +2119,103 @@ impl TransactionRpc for TransactionRpcImpl {
                 tx_status: TxStatus {
                     block_hash: Some(header.into_view().hash().unpack()),
                     status: Status::Committed,
+                    reason: None,
                 },
             });
         }
 
-        if let Some((transaction, cycles, _)) = self
+        // The transactions which are committed but not indexed, since they are unrelated to
+        // the filter scripts.
+        if let Some((transaction, block_hash)) = self
             .swc
             .pending_txs()
             .read()
             .expect("pending_txs lock is poisoned")
-            .get(&tx_hash.pack())
+            .get_committed(&tx_hash.pack())
         {
+            return Ok(TransactionWithStatus {
+                transaction: Some(transaction.into_view().into()),
+                cycles: None,
+                tx_status: TxStatus {
+                    block_hash: Some(block_hash.unpack()),
+                    status: Status::Committed,
+                    reason: None,
+                },
+            });
+        }
+
+        let pending = {
+            let pending_txs = self
+                .swc
+                .pending_txs()
+                .read()
+                .expect("pending_txs lock is poisoned");
+            pending_txs
+                .get(&tx_hash.pack())
+                .map(|(transaction, cycles)| {
+                    let proposed = pending_txs.get_proposed(&tx_hash.pack()).is_some();
+                    (transaction, cycles, proposed)
+                })
+        };
+        if let Some((transaction, cycles, proposed)) = pending {
             return Ok(TransactionWithStatus {
                 transaction: Some(transaction.into_view().into()),
                 cycles: Some(cycles.into()),
+                tx_status: TxStatus {
+                    block_hash: None,
+                    status: if proposed {
+                        Status::Proposed
+                    } else {
+                        Status::Pending
+                    },
+                    reason: None,
+                },
+            });
+        }
+
+        // The transactions which are submitted asynchronously.
+        let submitted = {
+            let pending_txs = self
+                .swc
+                .pending_txs()
+                .read()
+                .expect("pending_txs lock is poisoned");
+            pending_txs
+                .get_verifying(&tx_hash.pack())
+                .map(|transaction| (transaction, Status::Verifying, None))
+                .or_else(|| {
+                    pending_txs
+                        .get_rejected(&tx_hash.pack())
+                        .map(|(transaction, reason)| (transaction, Status::Rejected, Some(reason)))
+                })
+        };
+        if let Some((transaction, status, reason)) = submitted {
+            return Ok(TransactionWithStatus {
+                transaction: Some(transaction.into_view().into()),
+                cycles: None,
+                tx_status: TxStatus {
+                    block_hash: None,
+                    status,
+                    reason,
+                },
+            });
+        }
+
+        // The transactions which are relayed by peers, they are not verified by scripts.
+        if let Some(transaction) = self
+            .swc
+            .pending_txs()
+            .read()
+            .expect("pending_txs lock is poisoned")
+            .get_seen(&tx_hash.pack())
+        {
+            return Ok(TransactionWithStatus {
+                transaction: Some(transaction.into_view().into()),
+                cycles: None,
                 tx_status: TxStatus {
                     block_hash: None,
                     status: Status::Pending,
+                    reason: None,
                 },
             });
         }
@@ -1225,6 +2226,7 @@ impl TransactionRpc for TransactionRpcImpl {
             tx_status: TxStatus {
                 block_hash: None,
                 status: Status::Unknown,
+                reason: None,
             },
         })
     }
@@ -1257,10 +2259,45 @@ impl TransactionRpc for TransactionRpcImpl {
             timestamp: now.into(),
         })
     }
+
+    fn tx_pool_info(&self) -> Result<TxPoolInfo> {
+        let counts = self
+            .swc
+            .pending_txs()
+            .read()
+            .expect("pending_txs lock is poisoned")
+            .get_counts();
+        Ok(TxPoolInfo {
+            pending: (counts.pending as u64).into(),
+            rebroadcasts: counts.rebroadcasted.into(),
+            committed: counts.committed.into(),
+            expired: counts.expired.into(),
+            replaced: counts.replaced.into(),
+        })
+    }
+
+    fn get_pending_transactions(&self) -> Result<Vec<PendingTransaction>> {
+        let relay_stats = self
+            .swc
+            .pending_txs()
+            .read()
+            .expect("pending_txs lock is poisoned")
+            .get_relay_stats();
+        Ok(relay_stats
+            .into_iter()
+            .map(|(tx_hash, stats)| PendingTransaction {
+                tx_hash: tx_hash.unpack(),
+                announced_peers: stats.announced.into(),
+                requests: stats.requested.into(),
+                last_announced_at: stats.announced_at.map(Into::into),
+            })
+            .collect())
+    }
 }
 
 impl ChainRpc for ChainRpcImpl {
     fn get_tip_header(&self) -> Result<HeaderView> {
+        ensure_enough_proved_peers(&self.swc)?;
         Ok(self.swc.storage().get_tip_header().into_view().into())
     }
 
@@ -1299,25 +2336,89 @@ impl ChainRpc for ChainRpcImpl {
         })
     }
 
-    fn estimate_cycles(&self, tx: Transaction) -> Result<EstimateCycles> {
+    fn get_block(&self, block_hash: H256) -> Result<Option<BlockView>> {
+        Ok(self
+            .swc
+            .storage()
+            .get_block(&block_hash.pack())
+            .map(|block| block.into_view().into()))
+    }
+
+    fn estimate_cycles(
+        &self,
+        tx: Transaction,
+        context: Option<VerifyContext>,
+        placeholder_signatures: Option<bool>,
+    ) -> Result<EstimateCycles> {
         let tx: packed::Transaction = tx.into();
         let tx = tx.into_view();
-        let cycles = verify_tx(tx.clone(), &self.swc, Arc::clone(&self.consensus))
-            .map_err(|e| Error::invalid_params(format!("invalid transaction: {:?}", e)))?;
-        Ok(EstimateCycles {
-            cycles: cycles.into(),
-        })
+        let context = match context.unwrap_or(VerifyContext::Tip) {
+            VerifyContext::Tip => TxVerifyContext::Tip,
+            VerifyContext::NextBlock => TxVerifyContext::NextBlock,
+            VerifyContext::Header(block_hash) => self
+                .swc
+                .get_header(&block_hash.pack())
+                .map(TxVerifyContext::Header)
+                .ok_or_else(|| {
+                    Error::invalid_params(format!("header {:#x} is not found", block_hash))
+                })?,
+        };
+        let (result, debug_output) = verify_tx_with_debug_output(
+            tx.clone(),
+            &self.swc,
+            Arc::clone(&self.consensus),
+            self.max_tx_cycles,
+            &context,
+            placeholder_signatures.unwrap_or(false),
+        );
+        let cycles = result.map_err(|e| {
+            invalid_transaction_error(
+                tx,
+                &self.swc,
+                Arc::clone(&self.consensus),
+                self.max_tx_cycles,
+                &context,
+                e,
+            )
+        })?;
+        Ok(EstimateCycles::new(cycles, debug_output))
+    }
+}
+
+/// Creates a span for each RPC method call.
+struct RpcTracing;
+
+impl<M: Metadata> Middleware<M> for RpcTracing {
+    type Future = middleware::NoopFuture;
+    type CallFuture = middleware::NoopCallFuture;
+
+    fn on_call<F, X>(&self, call: Call, meta: M, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: Fn(Call, M) -> X + Send + Sync,
+        X: Future<Output = Option<Output>> + Send + 'static,
+    {
+        let method = match &call {
+            Call::MethodCall(method_call) => method_call.method.clone(),
+            Call::Notification(notification) => notification.method.clone(),
+            Call::Invalid { .. } => return Either::Right(next(call, meta)),
+        };
+        let span = tracing::info_span!("rpc", method = %method);
+        // The synchronous methods are executed when the future is created.
+        let future = span.in_scope(|| next(call, meta));
+        Either::Left(Box::pin(future.instrument(span)))
     }
 }
 
 pub(crate) struct Service {
     listen_address: String,
+    indexer_compatible: bool,
 }
 
 impl Service {
-    pub fn new(listen_address: &str) -> Self {
+    pub fn new(config: &RpcConfig) -> Self {
         Self {
-            listen_address: listen_address.to_string(),
+            listen_address: config.listen_address.clone(),
+            indexer_compatible: config.indexer_compatible,
         }
     }
 
@@ -1328,24 +2429,48 @@ impl Service {
         peers: Arc<Peers>,
         pending_txs: Arc<RwLock<PendingTxs>>,
         consensus: Consensus,
+        max_tx_cycles: core::Cycle,
+        reloader: ConfigReloader,
     ) -> Server {
-        let mut io_handler = IoHandler::new();
+        let mut io_handler = MetaIoHandler::<(), _>::with_middleware(RpcTracing);
         let swc = StorageWithChainData::new(storage, Arc::clone(&peers), Arc::clone(&pending_txs));
+        let chain = consensus.id.clone();
         let consensus = Arc::new(consensus);
         let block_filter_rpc_impl = BlockFilterRpcImpl { swc: swc.clone() };
+        let indexer_rpc_impl = IndexerRpcImpl {
+            block_filter: BlockFilterRpcImpl { swc: swc.clone() },
+        };
         let chain_rpc_impl = ChainRpcImpl {
             swc: swc.clone(),
             consensus: Arc::clone(&consensus),
+            max_tx_cycles,
+        };
+        let transaction_rpc_impl = TransactionRpcImpl {
+            swc,
+            consensus,
+            max_tx_cycles,
+            verify_pool: new_verify_pool(),
         };
-        let transaction_rpc_impl = TransactionRpcImpl { swc, consensus };
         let net_rpc_impl = NetRpcImpl {
             network_controller,
+            peers: Arc::clone(&peers),
+        };
+        let admin_rpc_impl = AdminRpcImpl {
+            reloader,
             peers,
+            chain,
+            started_at: unix_time_as_millis(),
         };
         io_handler.extend_with(block_filter_rpc_impl.to_delegate());
         io_handler.extend_with(chain_rpc_impl.to_delegate());
         io_handler.extend_with(transaction_rpc_impl.to_delegate());
         io_handler.extend_with(net_rpc_impl.to_delegate());
+        io_handler.extend_with(admin_rpc_impl.to_delegate());
+        if self.indexer_compatible {
+            info!("The ckb-indexer compatible RPC is enabled");
+            // It's registered after the others to override the methods with the same names.
+            io_handler.extend_with(indexer_rpc_impl.to_delegate());
+        }
 
         ServerBuilder::new(io_handler)
             .cors(DomainsValidation::AllowOnly(vec![
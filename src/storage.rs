@@ -2,9 +2,10 @@ use std::sync::RwLock;
 use std::{
     collections::{HashMap, HashSet},
     path::Path,
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 
+use ckb_systemtime::unix_time_as_millis;
 use ckb_traits::{
     CellDataProvider, ExtensionProvider, HeaderFields, HeaderFieldsProvider, HeaderProvider,
 };
@@ -20,7 +21,9 @@ use ckb_types::{
     H256, U256,
 };
 
+use linked_hash_map::LinkedHashMap;
 use rocksdb::{prelude::*, Direction, IteratorMode, WriteBatch, DB};
+use tracing::instrument;
 
 use crate::error::Result;
 use crate::protocols::{Peers, PendingTxs};
@@ -30,8 +33,14 @@ const GENESIS_BLOCK_KEY: &str = "GENESIS_BLOCK";
 const FILTER_SCRIPTS_KEY: &str = "FILTER_SCRIPTS";
 const MATCHED_FILTER_BLOCKS_KEY: &str = "MATCHED_BLOCKS";
 const MIN_FILTERED_BLOCK_NUMBER: &str = "MIN_FILTERED_NUMBER";
+const FILTERED_BLOCK_HASH: &str = "FILTERED_BLOCK_HASH";
 const LAST_N_HEADERS_KEY: &str = "LAST_N_HEADERS";
 const MAX_CHECK_POINT_INDEX: &str = "MAX_CHECK_POINT_INDEX";
+const PROVE_REQUEST_KEY: &str = "PROVE_REQUEST";
+const RUNNING_MARKER_KEY: &str = "RUNNING";
+
+// The count of the resolved cells of the cell deps which are cached.
+const MAX_CACHED_CELL_DEPS: usize = 64;
 
 pub struct HeaderWithExtension {
     pub header: Header,
@@ -66,12 +75,37 @@ impl Default for SetScriptsCommand {
     }
 }
 
-#[derive(PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ScriptType {
     Lock,
     Type,
 }
 
+/// A cell of the filter scripts which is created or consumed in a matched block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchedCell {
+    pub out_point: OutPoint,
+    pub output: CellOutput,
+    /// Which script of the cell matches the filter scripts, a cell which matches both of
+    /// them is reported twice.
+    pub script_type: ScriptType,
+    /// The transaction which creates or consumes the cell.
+    pub tx_hash: Byte32,
+}
+
+/// The matched cells of a block, in the order of the transactions.
+#[derive(Debug, Default)]
+pub struct FilteredCells {
+    pub created: Vec<MatchedCell>,
+    pub consumed: Vec<MatchedCell>,
+}
+
+impl FilteredCells {
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.consumed.is_empty()
+    }
+}
+
 struct WrappedBlockView<'a> {
     inner: &'a BlockView,
     index: HashMap<Byte32, usize>,
@@ -100,15 +134,89 @@ impl<'a> FilterDataProvider for WrappedBlockView<'a> {
     }
 }
 
+/// Decides whether the cells of the filter scripts which are created by a transaction are
+/// indexed, to match the transactions by the rules of the applications when the light client
+/// is embedded, e.g. skip the sUDT cells whose amounts are less than a threshold.
+///
+/// The cells which are skipped are never indexed, so their consumption isn't either. The
+/// consumption of the indexed cells is always indexed, so they are still live cells.
+pub trait TransactionMatcher: Send + Sync {
+    /// It's only called for the transactions which create cells of the filter scripts,
+    /// returns false to skip these cells.
+    fn is_matched(&self, tx: &Transaction, block_number: BlockNumber) -> bool;
+}
+
 #[derive(Clone)]
 pub struct Storage {
     pub(crate) db: Arc<DB>,
+    matcher: Option<Arc<dyn TransactionMatcher>>,
+    cell_deps: Arc<CellDepsCache>,
+}
+
+/// The resolved cells of the cell deps, in order of the last access.
+///
+/// The same cell deps (e.g. the dep group of the secp256k1 lock) are used by most of the
+/// transactions, so cache them to avoid loading them from the storage again and again. The
+/// cells which are loaded with or without their data are cached separately.
+///
+/// It's shared by the clones of the storage, and it's cleared when the chain is rolled back.
+#[derive(Default)]
+struct CellDepsCache {
+    cells: Mutex<LinkedHashMap<(OutPoint, bool), CellMeta>>,
 }
 
 impl Storage {
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
-        let db = Arc::new(DB::open_default(path).expect("Failed to open rocksdb"));
-        Self { db }
+        Self::open(path).expect("Failed to open rocksdb")
+    }
+
+    /// The same as [`Storage::new`], but returns the error instead of panicking, e.g. when
+    /// the storage is locked by a running light client.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let db = Arc::new(DB::open_default(path)?);
+        Ok(Self {
+            db,
+            matcher: None,
+            cell_deps: Default::default(),
+        })
+    }
+
+    /// Sets the matcher of the transactions in the matched blocks, it should be set before
+    /// the storage is cloned, since the clones don't share it.
+    pub fn set_transaction_matcher(&mut self, matcher: Arc<dyn TransactionMatcher>) {
+        self.matcher = Some(matcher);
+    }
+
+    pub(crate) fn get_cached_cell_dep(
+        &self,
+        out_point: &OutPoint,
+        eager_load: bool,
+    ) -> Option<CellMeta> {
+        self.cell_deps
+            .cells
+            .lock()
+            .expect("poisoned")
+            .get_refresh(&(out_point.clone(), eager_load))
+            .cloned()
+    }
+
+    pub(crate) fn cache_cell_dep(&self, cell_meta: CellMeta, eager_load: bool) {
+        let mut cells = self.cell_deps.cells.lock().expect("poisoned");
+        cells.insert((cell_meta.out_point.clone(), eager_load), cell_meta);
+        while cells.len() > MAX_CACHED_CELL_DEPS {
+            cells.pop_front();
+        }
+    }
+
+    /// Clears the cached cell deps, since they may be rolled back.
+    pub(crate) fn clear_cached_cell_deps(&self) {
+        self.cell_deps.cells.lock().expect("poisoned").clear();
+    }
+
+    // The cells of the filter scripts are all indexed, unless some of them are skipped by
+    // the transaction matcher.
+    fn is_cell_indexed(&self, key: &[u8]) -> bool {
+        self.matcher.is_none() || self.get(key).map(|value| value.is_some()).unwrap_or(false)
     }
 
     fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>> {
@@ -125,6 +233,146 @@ impl Storage {
     //         .map_err(Into::into)
     // }
 
+    /// Flushes the data in memory into the disk, it's called before exiting.
+    #[instrument(level = "debug", skip_all)]
+    pub fn flush(&self) -> Result<()> {
+        self.db.flush().map_err(Into::into)
+    }
+
+    /// Marks that the light client is running, returns true if the marker exists already,
+    /// i.e. the light client wasn't shut down cleanly last time.
+    pub fn mark_running(&self) -> bool {
+        let key = Key::Meta(RUNNING_MARKER_KEY).into_vec();
+        let exists = self
+            .get(&key)
+            .expect("db get running marker should be ok")
+            .is_some();
+        self.db
+            .put(key, unix_time_as_millis().to_le_bytes())
+            .expect("db put running marker should be ok");
+        exists
+    }
+
+    /// Clears the running marker, it's called when the light client is shut down cleanly.
+    pub fn clear_running_marker(&self) {
+        self.db
+            .delete(Key::Meta(RUNNING_MARKER_KEY).into_vec())
+            .expect("db delete running marker should be ok");
+    }
+
+    /// Checks the meta data which are required to start, e.g. the genesis block, the last
+    /// state, the check points, the filter scripts and the matched blocks, so a corrupted
+    /// storage is reported instead of panicking later.
+    pub fn verify_integrity(&self) -> std::result::Result<(), String> {
+        let get_meta = |name: &str| {
+            self.get(Key::Meta(name).into_vec())
+                .map_err(|err| format!("failed to read {} since {}", name, err))
+        };
+
+        let genesis = get_meta(GENESIS_BLOCK_KEY)?.ok_or("the genesis block is missing")?;
+        if genesis.len() < 32 || genesis.len() % 32 != 0 {
+            return Err("the genesis block is corrupted".to_owned());
+        }
+        let genesis_hash = Byte32::from_slice(&genesis[0..32]).expect("checked length");
+        let genesis_header = self
+            .get(Key::BlockHash(&genesis_hash).into_vec())
+            .map_err(|err| format!("failed to read the genesis header since {}", err))?
+            .ok_or("the genesis header is missing")?;
+        if genesis_header.len() < Header::TOTAL_SIZE {
+            return Err("the genesis header is corrupted".to_owned());
+        }
+
+        let last_state = get_meta(LAST_STATE_KEY)?.ok_or("the last state is missing")?;
+        if last_state.len() < 32 + Header::TOTAL_SIZE {
+            return Err("the last state is corrupted".to_owned());
+        }
+
+        let last_n_headers =
+            get_meta(LAST_N_HEADERS_KEY)?.ok_or("the last n headers are missing")?;
+        if last_n_headers.len() % 40 != 0 {
+            return Err("the last n headers are corrupted".to_owned());
+        }
+
+        let max_check_point_index =
+            get_meta(MAX_CHECK_POINT_INDEX)?.ok_or("the max check point index is missing")?;
+        let max_check_point_index = CpIndex::from_be_bytes(
+            max_check_point_index
+                .as_slice()
+                .try_into()
+                .map_err(|_| "the max check point index is corrupted")?,
+        );
+        if self.get_check_points(max_check_point_index, 1).is_empty() {
+            return Err(format!(
+                "the check point #{} is missing",
+                max_check_point_index
+            ));
+        }
+
+        if let Some(prove_request) = get_meta(PROVE_REQUEST_KEY)? {
+            if prove_request.len() < 2
+                || packed::GetLastStateProofReader::verify(&prove_request[2..], false).is_err()
+            {
+                return Err("the prove request is corrupted".to_owned());
+            }
+        }
+
+        let key_prefix = Key::Meta(FILTER_SCRIPTS_KEY).into_vec();
+        let mode = IteratorMode::From(key_prefix.as_ref(), Direction::Forward);
+        for (key, value) in self
+            .db
+            .iterator(mode)
+            .take_while(|(key, _value)| key.starts_with(&key_prefix))
+        {
+            if key.len() <= key_prefix.len()
+                || packed::ScriptReader::verify(&key[key_prefix.len()..key.len() - 1], false)
+                    .is_err()
+                || key[key.len() - 1] > 1
+                || value.len() != 8
+            {
+                return Err("the filter scripts are corrupted".to_owned());
+            }
+        }
+
+        let key_prefix = Key::Meta(MATCHED_FILTER_BLOCKS_KEY).into_vec();
+        let mode = IteratorMode::From(key_prefix.as_ref(), Direction::Forward);
+        for (_key, value) in self
+            .db
+            .iterator(mode)
+            .take_while(|(key, _value)| key.starts_with(&key_prefix))
+        {
+            if value.len() < 8 || (value.len() - 8) % 33 != 0 {
+                return Err("the matched blocks are corrupted".to_owned());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rolls back the earliest matched blocks if they are applied partially, returns the
+    /// start number of them if they are rolled back.
+    ///
+    /// The matched blocks are removed after they are applied and the block numbers of the
+    /// filter scripts are updated, so they are applied partially if the block numbers are
+    /// not updated. The data of them is deleted, and they will be downloaded and applied
+    /// again.
+    pub fn rollback_partially_applied_blocks(&self) -> Option<BlockNumber> {
+        let (start_number, blocks_count, _) = self.get_earliest_matched_blocks()?;
+        let end_number = start_number + blocks_count - 1;
+        let scripts = self.get_filter_scripts();
+        if scripts.iter().all(|ss| ss.block_number >= end_number) {
+            // All of them are applied, only the removal is lost.
+            self.remove_matched_blocks(start_number);
+            return None;
+        }
+        let mut batch = self.batch();
+        for ss in scripts {
+            self.rollback_script_data(&mut batch, &ss.script, &ss.script_type, start_number);
+        }
+        self.rollback_stored_blocks(&mut batch, start_number);
+        batch.commit().expect("batch commit should be ok");
+        Some(start_number)
+    }
+
     fn batch(&self) -> Batch {
         Batch {
             db: Arc::clone(&self.db),
@@ -193,6 +441,63 @@ impl Storage {
         }
     }
 
+    /// Wipes all data of the chain, e.g. the headers, the prove state, the cells, the
+    /// transactions and the matched blocks, then initializes the storage with the genesis block
+    /// again.
+    ///
+    /// The filter scripts are kept, but they will be filtered again from `from_block` at most,
+    /// since the cells of them were wiped.
+    ///
+    /// It refuses to wipe if the running marker is set, since the light client is running, or
+    /// it wasn't shut down cleanly, clear the marker first to wipe the latter.
+    pub fn reset_chain_data(
+        &self,
+        genesis: Block,
+        from_block: BlockNumber,
+    ) -> std::result::Result<(), String> {
+        if self
+            .get(Key::Meta(RUNNING_MARKER_KEY).into_vec())
+            .map_err(|err| format!("failed to read the running marker since {}", err))?
+            .is_some()
+        {
+            return Err("the light client is running or wasn't shut down cleanly".to_owned());
+        }
+
+        let scripts = self
+            .get_filter_scripts()
+            .into_iter()
+            .map(|mut ss| {
+                ss.block_number = ss.block_number.min(from_block);
+                ss
+            })
+            .collect::<Vec<_>>();
+
+        // Commit the deletions in chunks to avoid holding a huge batch in memory.
+        let mut batch = self.batch();
+        let mut deleted = 0;
+        for (key, _value) in self.db.iterator(IteratorMode::Start) {
+            batch.delete(key).expect("batch delete should be ok");
+            deleted += 1;
+            if deleted % 10_000 == 0 {
+                batch.commit().expect("batch commit should be ok");
+                batch = self.batch();
+            }
+        }
+        batch.commit().expect("batch commit should be ok");
+
+        self.init_genesis_block(genesis);
+        self.update_filter_scripts(scripts, SetScriptsCommand::All);
+        Ok(())
+    }
+
+    /// Returns the hash of the stored genesis block, it's `None` if the storage isn't
+    /// initialized.
+    pub fn get_genesis_hash(&self) -> Option<Byte32> {
+        self.get(Key::Meta(GENESIS_BLOCK_KEY).into_vec())
+            .expect("get genesis block")
+            .and_then(|value| Byte32::from_slice(value.get(0..32)?).ok())
+    }
+
     pub fn get_genesis_block(&self) -> Block {
         let genesis_hash_and_txs_hash = self
             .get(Key::Meta(GENESIS_BLOCK_KEY).into_vec())
@@ -269,6 +574,7 @@ impl Storage {
             .collect()
     }
 
+    #[instrument(level = "debug", skip_all)]
     pub fn update_filter_scripts(&self, scripts: Vec<ScriptStatus>, command: SetScriptsCommand) {
         let mut should_filter_genesis_block = false;
         let mut min_block_number = None;
@@ -369,8 +675,9 @@ impl Storage {
         }
     }
 
-    // get scripts hash that should be filtered below the given block number
-    pub fn get_scripts_hash(&self, block_number: BlockNumber) -> Vec<Byte32> {
+    // get scripts hash that should be filtered below the given block number,
+    // with the block numbers which they are filtered from
+    pub fn get_scripts_hash(&self, block_number: BlockNumber) -> Vec<(BlockNumber, Byte32)> {
         let key_prefix = Key::Meta(FILTER_SCRIPTS_KEY).into_vec();
         let mode = IteratorMode::From(key_prefix.as_ref(), Direction::Forward);
 
@@ -384,7 +691,7 @@ impl Storage {
                 if stored_block_number < block_number {
                     let script = Script::from_slice(&key[key_prefix.len()..key.len() - 1])
                         .expect("stored Script");
-                    Some(script.calc_script_hash())
+                    Some((stored_block_number, script.calc_script_hash()))
                 } else {
                     None
                 }
@@ -392,6 +699,7 @@ impl Storage {
             .collect()
     }
 
+    #[instrument(level = "debug", skip_all)]
     pub fn update_last_state(
         &self,
         total_difficulty: &U256,
@@ -405,6 +713,41 @@ impl Storage {
             .put(key, &value)
             .expect("db put last state should be ok");
         self.update_last_n_headers(last_n_headers);
+        // The prove request is built base on the last state, so it's outdated.
+        self.db
+            .delete(Key::Meta(PROVE_REQUEST_KEY).into_vec())
+            .expect("db delete prove request should be ok");
+    }
+
+    /// Stores the prove request which is built base on the last state, so it
+    /// could be resumed after restart.
+    ///
+    /// The value is the flags `skip_check_tau` and `long_fork_detected`,
+    /// followed by the content of the request.
+    pub fn update_prove_request(
+        &self,
+        content: &packed::GetLastStateProof,
+        skip_check_tau: bool,
+        long_fork_detected: bool,
+    ) {
+        let key = Key::Meta(PROVE_REQUEST_KEY).into_vec();
+        let mut value = vec![u8::from(skip_check_tau), u8::from(long_fork_detected)];
+        value.extend(content.as_slice());
+        self.db
+            .put(key, &value)
+            .expect("db put prove request should be ok");
+    }
+
+    pub fn get_prove_request(&self) -> Option<(packed::GetLastStateProof, bool, bool)> {
+        let key = Key::Meta(PROVE_REQUEST_KEY).into_vec();
+        self.db
+            .get_pinned(&key)
+            .expect("db get prove request should be ok")
+            .map(|data| {
+                let content = packed::GetLastStateProofReader::from_slice_should_be_ok(&data[2..])
+                    .to_entity();
+                (content, data[0] != 0, data[1] != 0)
+            })
     }
 
     pub fn get_last_state(&self) -> (U256, Header) {
@@ -473,6 +816,7 @@ impl Storage {
     }
 
     /// the matched blocks must not empty
+    #[instrument(level = "debug", skip_all)]
     pub fn add_matched_blocks(
         &self,
         start_number: u64,
@@ -563,6 +907,42 @@ impl Storage {
         batch.commit().expect("batch commit should be ok");
     }
 
+    pub fn add_block(&self, block: &Block) {
+        let block_hash = block.calc_header_hash();
+        self.db
+            .put(Key::Block(&block_hash).into_vec(), block.as_slice())
+            .expect("db put block should be ok");
+    }
+
+    pub fn get_block(&self, block_hash: &Byte32) -> Option<Block> {
+        self.get(Key::Block(block_hash).into_vec())
+            .map(|v| v.map(|v| Block::from_slice(&v).expect("stored Block")))
+            .expect("db get should be ok")
+    }
+
+    /// Deletes the stored blocks from `to_number`, they could be on a fork.
+    ///
+    /// The blocks are keyed by the hashes, so all of them are scanned, but only the numbers
+    /// in the headers are read.
+    fn rollback_stored_blocks(&self, batch: &mut Batch, to_number: BlockNumber) {
+        let key_prefix = [KeyPrefix::Block as u8];
+        let mode = IteratorMode::From(key_prefix.as_ref(), Direction::Forward);
+        self.db
+            .iterator(mode)
+            .take_while(|(key, _value)| key.starts_with(&key_prefix))
+            .filter(|(_key, value)| {
+                let block_number: BlockNumber = packed::BlockReader::new_unchecked(value)
+                    .header()
+                    .raw()
+                    .number()
+                    .unpack();
+                block_number >= to_number
+            })
+            .for_each(|(key, _value)| {
+                batch.delete(key).expect("batch delete should be ok");
+            });
+    }
+
     pub fn get_tip_header(&self) -> Header {
         self.get_last_state().1
     }
@@ -584,6 +964,33 @@ impl Storage {
             .expect("db put min filtered block number should be ok");
     }
 
+    /// Returns the number and the hash of the last block which is filtered.
+    ///
+    /// The number is different from the min filtered block number if the filtering is rewound,
+    /// e.g. by setting the scripts with an earlier block number, until the next block filters
+    /// are checked.
+    pub fn get_filtered_block_hash(&self) -> Option<(BlockNumber, Byte32)> {
+        let key = Key::Meta(FILTERED_BLOCK_HASH).into_vec();
+        self.db
+            .get_pinned(&key)
+            .expect("db get filtered block hash should be ok")
+            .map(|data| {
+                let number = u64::from_le_bytes(data[0..8].try_into().unwrap());
+                let hash = Byte32::from_slice(&data[8..]).expect("byte32 block hash");
+                (number, hash)
+            })
+    }
+
+    pub fn update_filtered_block_hash(&self, block_number: BlockNumber, block_hash: &Byte32) {
+        let key = Key::Meta(FILTERED_BLOCK_HASH).into_vec();
+        let mut value = Vec::with_capacity(40);
+        value.extend(block_number.to_le_bytes());
+        value.extend(block_hash.as_slice());
+        self.db
+            .put(key, value)
+            .expect("db put filtered block hash should be ok");
+    }
+
     pub fn get_last_check_point(&self) -> (CpIndex, Byte32) {
         let index = self.get_max_check_point_index();
         let hash = self
@@ -623,6 +1030,7 @@ impl Storage {
             .collect()
     }
 
+    #[instrument(level = "debug", skip_all, fields(start_index = start_index))]
     pub fn update_check_points(&self, start_index: CpIndex, check_points: &[Byte32]) {
         let mut index = start_index;
         let mut batch = self.batch();
@@ -656,14 +1064,73 @@ impl Storage {
         batch.commit().expect("batch commit should be ok");
     }
 
-    pub fn filter_block(&self, block: Block) {
+    /// Returns the filter scripts as a set, to check many transactions against them.
+    pub fn get_filter_script_set(&self) -> HashSet<(Script, ScriptType)> {
+        self.get_filter_scripts()
+            .into_iter()
+            .map(|ss| (ss.script, ss.script_type))
+            .collect()
+    }
+
+    /// Checks whether the transaction is related to the filter scripts, by its outputs or
+    /// the previous outputs of its inputs which are indexed.
+    pub fn is_related_transaction(&self, tx: &Transaction) -> bool {
+        self.is_related_transaction_with(&self.get_filter_script_set(), tx)
+    }
+
+    /// Same as `is_related_transaction`, but the filter scripts are loaded by the caller.
+    pub fn is_related_transaction_with(
+        &self,
+        scripts: &HashSet<(Script, ScriptType)>,
+        tx: &Transaction,
+    ) -> bool {
+        if scripts.is_empty() {
+            return false;
+        }
+        let is_related = |output: &CellOutput| {
+            scripts.contains(&(output.lock(), ScriptType::Lock))
+                || output
+                    .type_()
+                    .to_opt()
+                    .map(|script| scripts.contains(&(script, ScriptType::Type)))
+                    .unwrap_or(false)
+        };
+        tx.raw()
+            .outputs()
+            .into_iter()
+            .any(|output| is_related(&output))
+            || tx.raw().inputs().into_iter().any(|input| {
+                let previous_output = input.previous_output();
+                self.get_transaction(&previous_output.tx_hash())
+                    .and_then(|(_, _, previous_tx)| {
+                        previous_tx
+                            .raw()
+                            .outputs()
+                            .get(previous_output.index().unpack())
+                    })
+                    .map(|output| is_related(&output))
+                    .unwrap_or(false)
+            })
+    }
+
+    /// Indexes the transactions of the block which are related to the filter scripts.
+    ///
+    /// The other transactions are discarded, returns false if no transactions are
+    /// related, i.e. the block is matched by a false positive of the block filter.
+    pub fn filter_block(&self, block: Block) -> bool {
+        !self.filter_block_cells(block).is_empty()
+    }
+
+    /// The same as [`Storage::filter_block`], but returns the matched cells.
+    #[instrument(level = "debug", skip_all)]
+    pub fn filter_block_cells(&self, block: Block) -> FilteredCells {
         let scripts: HashSet<(Script, ScriptType)> = self
             .get_filter_scripts()
             .into_iter()
             .map(|ss| (ss.script, ss.script_type))
             .collect();
         let block_number: BlockNumber = block.header().raw().number().unpack();
-        let mut filter_matched = false;
+        let mut filtered = FilteredCells::default();
         let mut batch = self.batch();
         let mut txs: HashMap<Byte32, (u32, Transaction)> = HashMap::new();
         block
@@ -677,30 +1144,46 @@ impl Storage {
                     .enumerate()
                     .for_each(|(input_index, input)| {
                         let previous_tx_hash = input.previous_output().tx_hash();
+                        // The cells which are created in the same block are indexed, since
+                        // the skipped transactions are not kept.
+                        let previous = self
+                            .get_transaction(&previous_tx_hash)
+                            .map(|(block_number, tx_index, tx)| (block_number, tx_index, tx, false))
+                            .or_else(|| {
+                                txs.get(&previous_tx_hash).map(|(tx_index, tx)| {
+                                    (block_number, *tx_index, tx.clone(), true)
+                                })
+                            });
                         if let Some((
                             generated_by_block_number,
                             generated_by_tx_index,
                             previous_tx,
-                        )) = self.get_transaction(&previous_tx_hash).or(txs
-                            .get(&previous_tx_hash)
-                            .map(|(tx_index, tx)| (block_number, *tx_index, tx.clone())))
+                            in_same_block,
+                        )) = previous
                         {
                             let previous_output_index = input.previous_output().index().unpack();
                             if let Some(previous_output) =
                                 previous_tx.raw().outputs().get(previous_output_index)
                             {
                                 let script = previous_output.lock();
-                                if scripts.contains(&(script.clone(), ScriptType::Lock)) {
-                                    filter_matched = true;
+                                let cell_key = Key::CellLockScript(
+                                    &script,
+                                    generated_by_block_number,
+                                    generated_by_tx_index,
+                                    previous_output_index as OutputIndex,
+                                )
+                                .into_vec();
+                                if scripts.contains(&(script.clone(), ScriptType::Lock))
+                                    && (in_same_block || self.is_cell_indexed(&cell_key))
+                                {
+                                    filtered.consumed.push(MatchedCell {
+                                        out_point: input.previous_output(),
+                                        output: previous_output.clone(),
+                                        script_type: ScriptType::Lock,
+                                        tx_hash: tx.calc_tx_hash(),
+                                    });
                                     // delete utxo
-                                    let key = Key::CellLockScript(
-                                        &script,
-                                        generated_by_block_number,
-                                        generated_by_tx_index,
-                                        previous_output_index as OutputIndex,
-                                    )
-                                    .into_vec();
-                                    batch.delete(key).expect("batch delete should be ok");
+                                    batch.delete(cell_key).expect("batch delete should be ok");
                                     // insert tx history
                                     let key = Key::TxLockScript(
                                         &script,
@@ -721,17 +1204,24 @@ impl Storage {
                                     batch.put_kv(key, value).expect("batch put should be ok");
                                 }
                                 if let Some(script) = previous_output.type_().to_opt() {
-                                    if scripts.contains(&(script.clone(), ScriptType::Type)) {
-                                        filter_matched = true;
+                                    let cell_key = Key::CellTypeScript(
+                                        &script,
+                                        generated_by_block_number,
+                                        generated_by_tx_index,
+                                        previous_output_index as OutputIndex,
+                                    )
+                                    .into_vec();
+                                    if scripts.contains(&(script.clone(), ScriptType::Type))
+                                        && (in_same_block || self.is_cell_indexed(&cell_key))
+                                    {
+                                        filtered.consumed.push(MatchedCell {
+                                            out_point: input.previous_output(),
+                                            output: previous_output.clone(),
+                                            script_type: ScriptType::Type,
+                                            tx_hash: tx.calc_tx_hash(),
+                                        });
                                         // delete utxo
-                                        let key = Key::CellTypeScript(
-                                            &script,
-                                            generated_by_block_number,
-                                            generated_by_tx_index,
-                                            previous_output_index as OutputIndex,
-                                        )
-                                        .into_vec();
-                                        batch.delete(key).expect("batch delete should be ok");
+                                        batch.delete(cell_key).expect("batch delete should be ok");
                                         // insert tx history
                                         let key = Key::TxTypeScript(
                                             &script,
@@ -759,6 +1249,26 @@ impl Storage {
                         }
                     });
 
+                let is_script_cell = |output: &CellOutput| {
+                    scripts.contains(&(output.lock(), ScriptType::Lock))
+                        || output
+                            .type_()
+                            .to_opt()
+                            .map(|script| scripts.contains(&(script, ScriptType::Type)))
+                            .unwrap_or(false)
+                };
+                if let Some(matcher) = self.matcher.as_ref() {
+                    if tx
+                        .raw()
+                        .outputs()
+                        .into_iter()
+                        .any(|output| is_script_cell(&output))
+                        && !matcher.is_matched(&tx, block_number)
+                    {
+                        return;
+                    }
+                }
+
                 tx.raw()
                     .outputs()
                     .into_iter()
@@ -766,8 +1276,13 @@ impl Storage {
                     .for_each(|(output_index, output)| {
                         let script = output.lock();
                         if scripts.contains(&(script.clone(), ScriptType::Lock)) {
-                            filter_matched = true;
                             let tx_hash = tx.calc_tx_hash();
+                            filtered.created.push(MatchedCell {
+                                out_point: OutPoint::new(tx_hash.clone(), output_index as u32),
+                                output: output.clone(),
+                                script_type: ScriptType::Lock,
+                                tx_hash: tx_hash.clone(),
+                            });
                             // insert utxo
                             let key = Key::CellLockScript(
                                 &script,
@@ -798,8 +1313,13 @@ impl Storage {
                         }
                         if let Some(script) = output.type_().to_opt() {
                             if scripts.contains(&(script.clone(), ScriptType::Type)) {
-                                filter_matched = true;
                                 let tx_hash = tx.calc_tx_hash();
+                                filtered.created.push(MatchedCell {
+                                    out_point: OutPoint::new(tx_hash.clone(), output_index as u32),
+                                    output: output.clone(),
+                                    script_type: ScriptType::Type,
+                                    tx_hash: tx_hash.clone(),
+                                });
                                 // insert utxo
                                 let key = Key::CellTypeScript(
                                     &script,
@@ -834,7 +1354,7 @@ impl Storage {
 
                 txs.insert(tx.calc_tx_hash(), (tx_index as u32, tx));
             });
-        if filter_matched {
+        if !filtered.is_empty() {
             let block_hash = block.calc_header_hash();
             let hwe = HeaderWithExtension {
                 header: block.header(),
@@ -851,11 +1371,13 @@ impl Storage {
                 .expect("batch put should be ok");
         }
         batch.commit().expect("batch commit should be ok");
+        filtered
     }
 
     /// Rollback filtered block data to specified block number
     ///
     /// N.B. The specified block will be removed.
+    #[instrument(level = "debug", skip_all, fields(to_number = to_number))]
     pub fn rollback_to_block(&self, to_number: BlockNumber) {
         let scripts = self.get_filter_scripts();
         let mut batch = self.batch();
@@ -863,127 +1385,7 @@ impl Storage {
         for ss in scripts {
             if ss.block_number >= to_number {
                 let script = ss.script;
-                let mut key_prefix = vec![match ss.script_type {
-                    ScriptType::Lock => KeyPrefix::TxLockScript as u8,
-                    ScriptType::Type => KeyPrefix::TxTypeScript as u8,
-                }];
-                key_prefix.extend_from_slice(&extract_raw_data(&script));
-                let mut start_key = key_prefix.clone();
-                start_key.extend_from_slice(BlockNumber::MAX.to_be_bytes().as_ref());
-                let mode = IteratorMode::From(start_key.as_ref(), Direction::Reverse);
-                let key_prefix_len = key_prefix.len();
-
-                self.db
-                    .iterator(mode)
-                    .take_while(|(key, _value)| {
-                        key.starts_with(&key_prefix)
-                            && BlockNumber::from_be_bytes(
-                                key[key_prefix_len..key_prefix_len + 8]
-                                    .try_into()
-                                    .expect("stored BlockNumber"),
-                            ) >= to_number
-                    })
-                    .for_each(|(key, value)| {
-                        let block_number = BlockNumber::from_be_bytes(
-                            key[key_prefix_len..key_prefix_len + 8]
-                                .try_into()
-                                .expect("stored BlockNumber"),
-                        );
-                        log::debug!("rollback {}", block_number);
-                        let tx_index = TxIndex::from_be_bytes(
-                            key[key_prefix_len + 8..key_prefix_len + 12]
-                                .try_into()
-                                .expect("stored TxIndex"),
-                        );
-                        let cell_index = CellIndex::from_be_bytes(
-                            key[key_prefix_len + 12..key_prefix_len + 16]
-                                .try_into()
-                                .expect("stored CellIndex"),
-                        );
-                        let tx_hash =
-                            packed::Byte32Reader::from_slice_should_be_ok(&value).to_entity();
-                        if key[key_prefix_len + 16] == 0 {
-                            let (_, _, tx) = self
-                                .get_transaction(&tx_hash)
-                                .expect("stored transaction history");
-                            let input = tx.raw().inputs().get(cell_index as usize).unwrap();
-                            if let Some((
-                                generated_by_block_number,
-                                generated_by_tx_index,
-                                _previous_tx,
-                            )) = self.get_transaction(&input.previous_output().tx_hash())
-                            {
-                                let key = match ss.script_type {
-                                    ScriptType::Lock => Key::CellLockScript(
-                                        &script,
-                                        generated_by_block_number,
-                                        generated_by_tx_index,
-                                        input.previous_output().index().unpack(),
-                                    ),
-                                    ScriptType::Type => Key::CellTypeScript(
-                                        &script,
-                                        generated_by_block_number,
-                                        generated_by_tx_index,
-                                        input.previous_output().index().unpack(),
-                                    ),
-                                };
-                                batch
-                                    .put_kv(key, input.previous_output().tx_hash().as_slice())
-                                    .expect("batch put should be ok");
-                            };
-                            // delete tx history
-                            let key = match ss.script_type {
-                                ScriptType::Lock => Key::TxLockScript(
-                                    &script,
-                                    block_number,
-                                    tx_index,
-                                    cell_index,
-                                    CellType::Input,
-                                ),
-                                ScriptType::Type => Key::TxTypeScript(
-                                    &script,
-                                    block_number,
-                                    tx_index,
-                                    cell_index,
-                                    CellType::Input,
-                                ),
-                            }
-                            .into_vec();
-                            batch.delete(key).expect("batch delete should be ok");
-                        } else {
-                            // delete utxo
-                            let key = match ss.script_type {
-                                ScriptType::Lock => {
-                                    Key::CellLockScript(&script, block_number, tx_index, cell_index)
-                                }
-                                ScriptType::Type => {
-                                    Key::CellTypeScript(&script, block_number, tx_index, cell_index)
-                                }
-                            }
-                            .into_vec();
-                            batch.delete(key).expect("batch delete should be ok");
-
-                            // delete tx history
-                            let key = match ss.script_type {
-                                ScriptType::Lock => Key::TxLockScript(
-                                    &script,
-                                    block_number,
-                                    tx_index,
-                                    cell_index,
-                                    CellType::Output,
-                                ),
-                                ScriptType::Type => Key::TxTypeScript(
-                                    &script,
-                                    block_number,
-                                    tx_index,
-                                    cell_index,
-                                    CellType::Output,
-                                ),
-                            }
-                            .into_vec();
-                            batch.delete(key).expect("batch delete should be ok");
-                        };
-                    });
+                self.rollback_script_data(&mut batch, &script, &ss.script_type, to_number);
 
                 // update script filter block number
                 {
@@ -999,6 +1401,8 @@ impl Storage {
             }
         }
 
+        self.rollback_stored_blocks(&mut batch, to_number);
+
         // we should also sync block filters again
         if self.get_min_filtered_block_number() >= to_number {
             batch
@@ -1008,8 +1412,142 @@ impl Storage {
                 )
                 .expect("batch put should be ok");
         }
+        if matches!(self.get_filtered_block_hash(), Some((number, _)) if number >= to_number) {
+            batch
+                .delete(Key::Meta(FILTERED_BLOCK_HASH).into_vec())
+                .expect("batch delete should be ok");
+        }
 
         batch.commit().expect("batch commit should be ok");
+        self.clear_cached_cell_deps();
+    }
+
+    /// Deletes the cells and the transaction history of the script from `to_number`, and
+    /// restores the cells which are spent by the deleted transactions.
+    fn rollback_script_data(
+        &self,
+        batch: &mut Batch,
+        script: &Script,
+        script_type: &ScriptType,
+        to_number: BlockNumber,
+    ) {
+        let mut key_prefix = vec![match script_type {
+            ScriptType::Lock => KeyPrefix::TxLockScript as u8,
+            ScriptType::Type => KeyPrefix::TxTypeScript as u8,
+        }];
+        key_prefix.extend_from_slice(&extract_raw_data(script));
+        let mut start_key = key_prefix.clone();
+        start_key.extend_from_slice(BlockNumber::MAX.to_be_bytes().as_ref());
+        let mode = IteratorMode::From(start_key.as_ref(), Direction::Reverse);
+        let key_prefix_len = key_prefix.len();
+
+        self.db
+            .iterator(mode)
+            .take_while(|(key, _value)| {
+                key.starts_with(&key_prefix)
+                    && BlockNumber::from_be_bytes(
+                        key[key_prefix_len..key_prefix_len + 8]
+                            .try_into()
+                            .expect("stored BlockNumber"),
+                    ) >= to_number
+            })
+            .for_each(|(key, value)| {
+                let block_number = BlockNumber::from_be_bytes(
+                    key[key_prefix_len..key_prefix_len + 8]
+                        .try_into()
+                        .expect("stored BlockNumber"),
+                );
+                log::debug!(block_number = block_number; "rollback {}", block_number);
+                let tx_index = TxIndex::from_be_bytes(
+                    key[key_prefix_len + 8..key_prefix_len + 12]
+                        .try_into()
+                        .expect("stored TxIndex"),
+                );
+                let cell_index = CellIndex::from_be_bytes(
+                    key[key_prefix_len + 12..key_prefix_len + 16]
+                        .try_into()
+                        .expect("stored CellIndex"),
+                );
+                let tx_hash = packed::Byte32Reader::from_slice_should_be_ok(&value).to_entity();
+                if key[key_prefix_len + 16] == 0 {
+                    let (_, _, tx) = self
+                        .get_transaction(&tx_hash)
+                        .expect("stored transaction history");
+                    let input = tx.raw().inputs().get(cell_index as usize).unwrap();
+                    if let Some((generated_by_block_number, generated_by_tx_index, _previous_tx)) =
+                        self.get_transaction(&input.previous_output().tx_hash())
+                    {
+                        let key = match script_type {
+                            ScriptType::Lock => Key::CellLockScript(
+                                script,
+                                generated_by_block_number,
+                                generated_by_tx_index,
+                                input.previous_output().index().unpack(),
+                            ),
+                            ScriptType::Type => Key::CellTypeScript(
+                                script,
+                                generated_by_block_number,
+                                generated_by_tx_index,
+                                input.previous_output().index().unpack(),
+                            ),
+                        };
+                        batch
+                            .put_kv(key, input.previous_output().tx_hash().as_slice())
+                            .expect("batch put should be ok");
+                    };
+                    // delete tx history
+                    let key = match script_type {
+                        ScriptType::Lock => Key::TxLockScript(
+                            script,
+                            block_number,
+                            tx_index,
+                            cell_index,
+                            CellType::Input,
+                        ),
+                        ScriptType::Type => Key::TxTypeScript(
+                            script,
+                            block_number,
+                            tx_index,
+                            cell_index,
+                            CellType::Input,
+                        ),
+                    }
+                    .into_vec();
+                    batch.delete(key).expect("batch delete should be ok");
+                } else {
+                    // delete utxo
+                    let key = match script_type {
+                        ScriptType::Lock => {
+                            Key::CellLockScript(script, block_number, tx_index, cell_index)
+                        }
+                        ScriptType::Type => {
+                            Key::CellTypeScript(script, block_number, tx_index, cell_index)
+                        }
+                    }
+                    .into_vec();
+                    batch.delete(key).expect("batch delete should be ok");
+
+                    // delete tx history
+                    let key = match script_type {
+                        ScriptType::Lock => Key::TxLockScript(
+                            script,
+                            block_number,
+                            tx_index,
+                            cell_index,
+                            CellType::Output,
+                        ),
+                        ScriptType::Type => Key::TxTypeScript(
+                            script,
+                            block_number,
+                            tx_index,
+                            cell_index,
+                            CellType::Output,
+                        ),
+                    }
+                    .into_vec();
+                    batch.delete(key).expect("batch delete should be ok");
+                };
+            });
     }
 
     fn get_transaction(&self, tx_hash: &Byte32) -> Option<(BlockNumber, TxIndex, Transaction)> {
@@ -1151,6 +1689,30 @@ impl StorageWithChainData {
     pub(crate) fn matched_blocks(&self) -> &RwLock<HashMap<H256, (bool, Option<packed::Block>)>> {
         self.peers.matched_blocks()
     }
+
+    pub(crate) fn set_filter_sync_paused(&self, paused: bool) {
+        self.peers.set_filter_sync_paused(paused);
+    }
+
+    pub(crate) fn request_filter_rescan(&self) {
+        self.peers.request_filter_rescan();
+    }
+
+    pub(crate) fn peers(&self) -> &Arc<Peers> {
+        &self.peers
+    }
+
+    /// return (proved_peers_count, min_proved_peers) if the proved peers are not enough
+    pub(crate) fn check_proved_peers(&self) -> Option<(usize, usize)> {
+        if self.peers.has_enough_proved_peers() {
+            None
+        } else {
+            Some((
+                self.peers.get_proved_peers_count(),
+                self.peers.get_min_proved_peers(),
+            ))
+        }
+    }
     /// return (added_ts, first_sent, missing)
     pub(crate) fn get_header_fetch_info(&self, block_hash: &H256) -> Option<(u64, u64, bool)> {
         self.peers.get_header_fetch_info(&block_hash.pack())
@@ -1298,6 +1860,7 @@ pub enum CellType {
 /// | 96           | TxLockScript       | TxHash                   |
 /// | 128          | TxTypeScript       | TxHash                   |
 /// | 160          | BlockHash          | HeaderWithExtension      |
+/// | 176          | Block              | Block                    |
 /// | 192          | BlockNumber        | BlockHash                |
 /// | 208          | CheckPointIndex    | BlockFilterHash          |
 /// | 224          | Meta               | Meta                     |
@@ -1310,6 +1873,7 @@ pub enum Key<'a> {
     TxLockScript(&'a Script, BlockNumber, TxIndex, CellIndex, CellType),
     TxTypeScript(&'a Script, BlockNumber, TxIndex, CellIndex, CellType),
     BlockHash(&'a Byte32),
+    Block(&'a Byte32),
     BlockNumber(BlockNumber),
     // The index number for check points.
     CheckPointIndex(CpIndex),
@@ -1333,6 +1897,7 @@ pub enum KeyPrefix {
     TxLockScript = 96,
     TxTypeScript = 128,
     BlockHash = 160,
+    Block = 176,
     BlockNumber = 192,
     CheckPointIndex = 208,
     Meta = 224,
@@ -1381,6 +1946,10 @@ impl<'a> From<Key<'a>> for Vec<u8> {
                 encoded.push(KeyPrefix::BlockHash as u8);
                 encoded.extend_from_slice(block_hash.as_slice());
             }
+            Key::Block(block_hash) => {
+                encoded.push(KeyPrefix::Block as u8);
+                encoded.extend_from_slice(block_hash.as_slice());
+            }
             Key::BlockNumber(block_number) => {
                 encoded.push(KeyPrefix::BlockNumber as u8);
                 encoded.extend_from_slice(&block_number.to_be_bytes());
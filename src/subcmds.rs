@@ -1,160 +1,670 @@
-use std::sync::{Arc, RwLock};
+use std::{fs, net::ToSocketAddrs as _, str::FromStr as _};
 
 use ckb_async_runtime::new_global_runtime;
-use ckb_chain_spec::ChainSpec;
-use ckb_network::{
-    tokio, CKBProtocol, CKBProtocolHandler, Flags, NetworkService, NetworkState, SupportProtocols,
-};
-use ckb_resource::Resource;
+use ckb_jsonrpc_types::HeaderView;
+use ckb_network::tokio;
 use ckb_stop_handler::{broadcast_exit_signals, wait_all_ckb_services_exit};
+use ckb_types::{core::BlockNumber, packed};
 use log::debug;
+use serde::de::DeserializeOwned;
 
 use crate::{
-    config::RunConfig,
-    error::{Error, Result},
-    protocols::{
-        FilterProtocol, LightClientProtocol, Peers, PendingTxs, RelayProtocol, SyncProtocol,
-        CHECK_POINT_INTERVAL,
+    client::{build_consensus, LightClientBuilder},
+    config::{
+        scope_in_data_dir, CheckConfigConfig, InitConfig, ResetStateConfig, RunConfig,
+        ServiceConfig, StatusConfig,
     },
-    service::Service,
+    error::{Error, Result},
+    service::{RemoteNode, ScriptStatus, ScriptType, TxPoolInfo},
     storage::Storage,
+    telemetry,
+    types::{MultiRunEnv, RunEnv},
     utils,
 };
 
+const MAINNET_CONFIG: &str = include_str!("../config/mainnet.toml");
+const TESTNET_CONFIG: &str = include_str!("../config/testnet.toml");
+
+impl InitConfig {
+    pub(crate) fn execute(self) -> Result<()> {
+        log::info!("Init ...");
+
+        if self.config_file.exists() && !self.force {
+            let errmsg = format!(
+                "the config file {} exists, use --force to overwrite it",
+                self.config_file.display()
+            );
+            return Err(Error::config(errmsg));
+        }
+
+        let template = match self.chain.as_str() {
+            "mainnet" => MAINNET_CONFIG,
+            "testnet" => TESTNET_CONFIG,
+            chain => return Err(Error::config(format!("unknown chain {}", chain))),
+        };
+        let store_path = self.data_dir.join("store");
+        let network_path = self.data_dir.join("network");
+        let log_file_path = self.data_dir.join("logs").join("run.log");
+        // The paths are quoted as the TOML basic strings.
+        let config = template
+            .replace(
+                r#"path = "data/store""#,
+                &format!("path = {:?}", store_path.display().to_string()),
+            )
+            .replace(
+                r#"path = "data/network""#,
+                &format!("path = {:?}", network_path.display().to_string()),
+            )
+            .replace(
+                r#"file = "data/logs/run.log""#,
+                &format!("file = {:?}", log_file_path.display().to_string()),
+            );
+        // Ensure the generated config is valid.
+        let run_env = RunEnv::from_str(&config).map_err(Error::config)?;
+
+        if let Some(dir) = self.config_file.parent() {
+            if !dir.as_os_str().is_empty() {
+                utils::fs::need_directory(dir)?;
+            }
+        }
+        fs::write(&self.config_file, config).map_err(|err| {
+            let errmsg = format!(
+                "failed to write {} since {}",
+                self.config_file.display(),
+                err
+            );
+            Error::config(errmsg)
+        })?;
+        utils::fs::need_directory(&run_env.store.path)?;
+        utils::fs::need_directory(&run_env.network.path)?;
+
+        log::info!(
+            "the config file {} is generated for {}",
+            self.config_file.display(),
+            self.chain
+        );
+        Ok(())
+    }
+}
+
 impl RunConfig {
     pub(crate) fn execute(self) -> Result<()> {
+        self.execute_and_notify(|| ())
+    }
+
+    /// The same as [`RunConfig::execute`], but calls `on_started` after all instances are
+    /// started, e.g. to report the state to the service manager.
+    pub(crate) fn execute_and_notify<F: FnOnce()>(mut self, on_started: F) -> Result<()> {
         log::info!("Run ...");
 
-        utils::fs::need_directory(&self.run_env.network.path)?;
+        if let Some(url) = self.dev_node_rpc_url.clone() {
+            if self.multi_run_env.instances.len() > 1 {
+                let errmsg = "the dev mode is not allowed for multiple instances";
+                return Err(Error::config(errmsg));
+            }
+            apply_dev_mode(&mut self.multi_run_env.instances[0], &url)?;
+        }
+        check_instances_conflicts(&self.multi_run_env.instances)?;
 
-        let storage = Storage::new(&self.run_env.store.path);
-        let chain_spec = ChainSpec::load_from(&match self.run_env.chain.as_str() {
-            "mainnet" => Resource::bundled("specs/mainnet.toml".to_string()),
-            "testnet" => Resource::bundled("specs/testnet.toml".to_string()),
-            path => Resource::file_system(path.into()),
-        })
-        .expect("load spec should be OK");
-        let consensus = chain_spec
-            .build_consensus()
-            .expect("build consensus should be OK");
-        storage.init_genesis_block(consensus.genesis_block().data());
-
-        let pending_txs = Arc::new(RwLock::new(PendingTxs::default()));
-        let max_outbound_peers = self.run_env.network.max_outbound_peers;
-        let network_state = NetworkState::from_config(self.run_env.network)
-            .map(|network_state| {
-                Arc::new(network_state.required_flags(
-                    Flags::DISCOVERY
-                        | Flags::SYNC
-                        | Flags::RELAY
-                        | Flags::LIGHT_CLIENT
-                        | Flags::BLOCK_FILTER,
-                ))
+        let (mut handle, mut handle_stop_rx, _stop_handler) = new_global_runtime();
+        // It's dropped after the storage is flushed, so the spans of the shutdown are exported.
+        let _telemetry = handle.enter(|| telemetry::init(&self.multi_run_env.tracing))?;
+
+        let skip_pow = self.dev_node_rpc_url.is_some();
+        let mut clients = Vec::with_capacity(self.multi_run_env.instances.len());
+        let mut start_error = None;
+        for (index, run_env) in self.multi_run_env.instances.into_iter().enumerate() {
+            log::info!("Start the instance #{} for chain {}", index, run_env.chain);
+            match LightClientBuilder::new(run_env)
+                .skip_pow(skip_pow)
+                .config_file(&self.config_file, index)
+                .start(&handle)
+            {
+                Ok(client) => clients.push(client),
+                Err(err) => {
+                    log::error!(
+                        "failed to start the instance #{} since {}, stop the started ones",
+                        index,
+                        err
+                    );
+                    start_error = Some(err);
+                    break;
+                }
+            }
+        }
+
+        if start_error.is_some() {
+            // Shut down the started instances as usual, so their storages are marked as
+            // shut down cleanly.
+            broadcast_exit_signals();
+        } else {
+            on_started();
+
+            #[cfg(unix)]
+            {
+                crate::systemd::notify_ready();
+                let peers = clients
+                    .iter()
+                    .map(|client| client.peers())
+                    .cloned()
+                    .collect();
+                crate::systemd::start_watchdog(&handle, peers);
+                let reloaders = clients
+                    .iter()
+                    .map(|client| client.reloader().clone())
+                    .collect();
+                handle.spawn(async move {
+                    if let Err(err) = handle_signals(reloaders).await {
+                        log::error!("failed to handle the signals since {}", err);
+                        broadcast_exit_signals();
+                    }
+                });
+            }
+            #[cfg(not(unix))]
+            ctrlc::set_handler(move || {
+                log::info!("Received the exit signal, shutting down ...");
+                broadcast_exit_signals();
             })
             .map_err(|err| {
-                let errmsg = format!("failed to initialize network state since {}", err);
+                let errmsg = format!("failed to set the exit signal handler since {}", err);
                 Error::runtime(errmsg)
             })?;
-        let required_protocol_ids = vec![
-            SupportProtocols::Sync.protocol_id(),
-            SupportProtocols::LightClient.protocol_id(),
-            SupportProtocols::Filter.protocol_id(),
-        ];
-
-        let peers = Arc::new(Peers::new(
-            max_outbound_peers,
-            CHECK_POINT_INTERVAL,
-            storage.get_last_check_point(),
-        ));
-        let sync_protocol = SyncProtocol::new(storage.clone(), Arc::clone(&peers));
-        let relay_protocol_v2 = RelayProtocol::new(
-            pending_txs.clone(),
-            Arc::clone(&peers),
-            consensus.clone(),
-            storage.clone(),
-            false,
-        );
-        let relay_protocol_v3 = RelayProtocol::new(
-            pending_txs.clone(),
-            Arc::clone(&peers),
-            consensus.clone(),
-            storage.clone(),
-            true,
-        );
-        let light_client: Box<dyn CKBProtocolHandler> = Box::new(LightClientProtocol::new(
-            storage.clone(),
-            Arc::clone(&peers),
-            consensus.clone(),
-        ));
-        let filter_protocol = FilterProtocol::new(storage.clone(), Arc::clone(&peers));
-
-        let protocols = vec![
-            CKBProtocol::new_with_support_protocol(
-                SupportProtocols::Sync,
-                Box::new(sync_protocol),
-                Arc::clone(&network_state),
-            ),
-            CKBProtocol::new_with_support_protocol(
-                SupportProtocols::RelayV2,
-                Box::new(relay_protocol_v2),
-                Arc::clone(&network_state),
-            ),
-            CKBProtocol::new_with_support_protocol(
-                SupportProtocols::RelayV3,
-                Box::new(relay_protocol_v3),
-                Arc::clone(&network_state),
-            ),
-            CKBProtocol::new_with_support_protocol(
-                SupportProtocols::LightClient,
-                light_client,
-                Arc::clone(&network_state),
-            ),
-            CKBProtocol::new_with_support_protocol(
-                SupportProtocols::Filter,
-                Box::new(filter_protocol),
-                Arc::clone(&network_state),
-            ),
-        ];
+        }
 
-        let (mut handle, mut handle_stop_rx, _stop_handler) = new_global_runtime();
+        wait_all_ckb_services_exit();
+        #[cfg(unix)]
+        crate::systemd::notify_stopping();
 
-        let network_controller = NetworkService::new(
-            Arc::clone(&network_state),
-            protocols,
-            required_protocol_ids,
+        // Stop accepting the RPC requests first, since they could write the storage.
+        for client in &clients {
+            client.close_rpc();
+        }
+        handle.drop_guard();
+
+        tokio::task::block_in_place(|| {
+            debug!("Waiting all tokio tasks finished ...");
+            handle_stop_rx.blocking_recv();
+        });
+
+        for client in clients {
+            client.close()?;
+        }
+        log::info!("The storage is flushed");
+
+        start_error.map_or(Ok(()), Err)
+    }
+}
+
+/// The instances share the runtime, but each of them has its own storage, network and RPC
+/// server, so these should not be the same.
+///
+/// The paths are compared after they are canonicalized, e.g. `data/store` and
+/// `./data/../data/store` are the same.
+pub(crate) fn check_instances_conflicts(instances: &[RunEnv]) -> Result<()> {
+    let paths = instances
+        .iter()
+        .map(|run_env| {
             (
-                consensus.identify_name(),
-                clap::crate_version!().to_owned(),
-                Flags::DISCOVERY,
-            ),
-        )
-        .start(&handle)
-        .map_err(|err| {
-            let errmsg = format!("failed to start network since {}", err);
-            Error::runtime(errmsg)
-        })?;
+                utils::fs::canonicalize_path(&run_env.store.path),
+                utils::fs::canonicalize_path(&run_env.network.path),
+            )
+        })
+        .collect::<Vec<_>>();
+    for (index, run_env) in instances.iter().enumerate() {
+        for (other_index, other) in instances[..index].iter().enumerate() {
+            let listen_address = run_env
+                .network
+                .listen_addresses
+                .iter()
+                .find(|address| other.network.listen_addresses.contains(address));
+            let conflict = if paths[index].0 == paths[other_index].0 {
+                Some(format!("store.path {}", run_env.store.path.display()))
+            } else if paths[index].1 == paths[other_index].1 {
+                Some(format!("network.path {}", run_env.network.path.display()))
+            } else if let Some(address) = listen_address {
+                Some(format!("network.listen_addresses {}", address))
+            } else if run_env.rpc.listen_address == other.rpc.listen_address {
+                Some(format!("rpc.listen_address {}", run_env.rpc.listen_address))
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                let errmsg = format!("the instances have the same {}", conflict);
+                return Err(Error::config(errmsg));
+            }
+        }
+    }
+    Ok(())
+}
 
-        let service = Service::new(&self.run_env.rpc.listen_address);
-        let rpc_server = service.start(network_controller, storage, peers, pending_txs, consensus);
+/// Adjusts the config for a local dev chain: connect to the local full node only, and
+/// lower the timeouts since the blocks are produced fast.
+fn apply_dev_mode(run_env: &mut RunEnv, node_rpc_url: &str) -> Result<()> {
+    if matches!(run_env.chain.as_str(), "mainnet" | "testnet") {
+        let errmsg = format!(
+            "the dev mode is only for the dev chains, but the chain is {}",
+            run_env.chain
+        );
+        return Err(Error::config(errmsg));
+    }
 
-        ctrlc::set_handler(move || {
-            broadcast_exit_signals();
+    let node_info: serde_json::Value =
+        utils::rpc::call_blocking(node_rpc_url, "local_node_info", serde_json::json!([]))
+            .and_then(|node_info| node_info.ok_or_else(|| "no node info".to_owned()))
+            .map_err(|err| {
+                let errmsg = format!(
+                    "failed to get the node info from the local full node {} since {}",
+                    node_rpc_url, err
+                );
+                Error::config(errmsg)
+            })?;
+    let node_id = node_info["node_id"]
+        .as_str()
+        .ok_or_else(|| Error::config("the local full node has no node id"))?;
+    // The full node listens on all interfaces usually, so only the port is used.
+    let port = node_info["addresses"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|address| address["address"].as_str())
+        .find_map(|address| {
+            let mut parts = address.split('/');
+            parts.by_ref().find(|part| *part == "tcp")?;
+            parts.next()?.parse::<u16>().ok()
         })
-        .map_err(|err| {
-            let errmsg = format!("failed to set Ctrl-C handler since {}", err);
+        .ok_or_else(|| Error::config("the local full node has no TCP address"))?;
+    let bootnode = format!("/ip4/127.0.0.1/tcp/{}/p2p/{}", port, node_id);
+    run_env.network.bootnodes = vec![bootnode.parse().map_err(|_| {
+        let errmsg = format!("invalid address {} of the local full node", bootnode);
+        Error::config(errmsg)
+    })?];
+    run_env.network.ping_interval_secs = 10;
+    run_env.network.ping_timeout_secs = 60;
+    run_env.network.connect_outbound_interval_secs = 1;
+
+    let config = &mut run_env.light_client;
+    config.stale_peer_timeout_secs = 30;
+    config.min_block_filter_peers = 1;
+    config.pending_tx_ttl_secs = 10 * 60;
+    config.relay_transaction_max_delay_ms = 0;
+
+    log::info!("Dev mode: connect to the local full node {}", bootnode);
+    Ok(())
+}
+
+impl ServiceConfig {
+    #[cfg(windows)]
+    pub(crate) fn execute(self) -> Result<()> {
+        match self {
+            Self::Install { name, config_file } => crate::win_service::install(&name, &config_file),
+            Self::Uninstall { name } => crate::win_service::uninstall(&name),
+            Self::Run { name, run_config } => crate::win_service::run(name, run_config),
+        }
+    }
+
+    #[cfg(not(windows))]
+    pub(crate) fn execute(self) -> Result<()> {
+        Err(Error::config(
+            "the Windows service is only supported on Windows",
+        ))
+    }
+}
+
+impl ResetStateConfig {
+    pub(crate) fn execute(self) -> Result<()> {
+        log::info!("Reset state ...");
+
+        let consensus = build_consensus(&self.run_env, false)?;
+        let storage = Storage::open(&self.run_env.store.path).map_err(|err| {
+            let errmsg = format!(
+                "failed to open the store {} since {}, stop the light client first",
+                self.run_env.store.path.display(),
+                err
+            );
             Error::runtime(errmsg)
         })?;
+        if self.force {
+            storage.clear_running_marker();
+        }
+        let scripts_count = storage.get_filter_scripts().len();
+        storage
+            .reset_chain_data(consensus.genesis_block().data(), self.from_block)
+            .map_err(|err| {
+                let errmsg = format!(
+                    "refuse to wipe the chain data since {}, add --force to wipe it after an \
+                    unclean shutdown",
+                    err
+                );
+                Error::runtime(errmsg)
+            })?;
 
-        wait_all_ckb_services_exit();
+        log::info!(
+            "The chain data in {} is wiped, {} filter scripts are kept and will be filtered \
+            again from block {} at most",
+            self.run_env.store.path.display(),
+            scripts_count,
+            self.from_block
+        );
+        Ok(())
+    }
+}
 
-        handle.drop_guard();
-        rpc_server.close();
+impl StatusConfig {
+    pub(crate) fn execute(self) -> Result<()> {
+        log::info!("Status ...");
 
-        tokio::task::block_in_place(|| {
-            debug!("Waiting all tokio tasks finished ...");
-            handle_stop_rx.blocking_recv();
-        });
+        let url = format!("http://{}", self.run_env.rpc.listen_address);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| {
+                let errmsg = format!("failed to create the runtime since {}", err);
+                Error::runtime(errmsg)
+            })?;
+        let (tip_header, peers, scripts, tx_pool_info) = runtime
+            .block_on(async {
+                let client = hyper::Client::new();
+                let tip_header: HeaderView = call_rpc(&client, &url, "get_tip_header").await?;
+                let peers: Vec<RemoteNode> = call_rpc(&client, &url, "get_peers").await?;
+                let scripts: Vec<ScriptStatus> = call_rpc(&client, &url, "get_scripts").await?;
+                let tx_pool_info: TxPoolInfo = call_rpc(&client, &url, "tx_pool_info").await?;
+                Ok::<_, String>((tip_header, peers, scripts, tx_pool_info))
+            })
+            .map_err(|err| {
+                let errmsg = format!("failed to query the light client at {} since {}", url, err);
+                Error::runtime(errmsg)
+            })?;
+        let storage_size = utils::fs::directory_size(&self.run_env.store.path).map_err(|err| {
+            let errmsg = format!(
+                "failed to get the size of {} since {}",
+                self.run_env.store.path.display(),
+                err
+            );
+            Error::runtime(errmsg)
+        })?;
 
+        let tip_number: BlockNumber = tip_header.inner.number.into();
+        println!("chain:        {}", self.run_env.chain);
+        println!("tip:          #{} {:#x}", tip_number, tip_header.hash);
+        println!(
+            "peers:        {} ({} synced)",
+            peers.len(),
+            peers
+                .iter()
+                .filter(|peer| peer.sync_state.is_some())
+                .count()
+        );
+        println!(
+            "pending txs:  {} (committed {}, expired {}, replaced {})",
+            tx_pool_info.pending.value(),
+            tx_pool_info.committed.value(),
+            tx_pool_info.expired.value(),
+            tx_pool_info.replaced.value()
+        );
+        println!(
+            "storage size: {:.2} MiB",
+            storage_size as f64 / 1024.0 / 1024.0
+        );
+        println!("scripts:      {}", scripts.len());
+        for ss in scripts {
+            let script_type = match ss.script_type {
+                ScriptType::Lock => "lock",
+                ScriptType::Type => "type",
+            };
+            let block_number: BlockNumber = ss.block_number.into();
+            let script: packed::Script = ss.script.into();
+            println!(
+                "  {} {:#x} synced to #{} (lag {} blocks)",
+                script_type,
+                script.calc_script_hash(),
+                block_number,
+                tip_number.saturating_sub(block_number)
+            );
+        }
         Ok(())
     }
 }
+
+async fn call_rpc<T: DeserializeOwned>(
+    client: &hyper::Client<hyper::client::HttpConnector>,
+    url: &str,
+    method: &str,
+) -> std::result::Result<T, String> {
+    utils::rpc::call(client, url, method, serde_json::json!([]))
+        .await?
+        .ok_or_else(|| format!("{} returns null", method))
+}
+
+/// The keys which are still accepted but have no effect, with the hints to replace or
+/// remove them.
+const DEPRECATED_KEYS: &[(&str, &str)] = &[
+    (
+        "network.sync",
+        "the light client doesn't sync the blocks as a full node, remove it",
+    ),
+    (
+        "network.support_protocols",
+        "the protocols of the light client are fixed, remove it",
+    ),
+];
+
+impl CheckConfigConfig {
+    pub(crate) fn execute(self) -> Result<()> {
+        log::info!("Check config ...");
+
+        let file = self.config_file.display();
+        let content = fs::read_to_string(&self.config_file)
+            .map_err(|err| Error::config(format!("failed to read {} since {}", file, err)))?;
+        let raw: toml::Value = toml::from_str(&content)
+            .map_err(|err| Error::config(format!("failed to parse {} since {}", file, err)))?;
+
+        let mut errors = Vec::new();
+        let mut warnings = find_deprecated_keys(&raw);
+        match MultiRunEnv::from_str(&content) {
+            Ok(mut multi_run_env) => {
+                if let Some(dir) = &self.data_dir {
+                    if multi_run_env.instances.len() > 1 {
+                        errors.push(
+                            "--data-dir and --profile are not allowed for multiple instances"
+                                .to_owned(),
+                        );
+                    } else {
+                        scope_in_data_dir(&mut multi_run_env.instances[0], dir);
+                    }
+                }
+                if let Some(log_file) = &multi_run_env.logger.file {
+                    if let Some(dir) = log_file.parent() {
+                        if let Err(err) = utils::fs::check_directory(dir) {
+                            errors.push(format!("logger.file: {}", err));
+                        }
+                    }
+                }
+                if let Err(err) = check_instances_conflicts(&multi_run_env.instances) {
+                    errors.push(err.to_string());
+                }
+                let multiple = multi_run_env.instances.len() > 1;
+                for (index, run_env) in multi_run_env.instances.iter().enumerate() {
+                    let (instance_errors, instance_warnings) = check_run_env(run_env);
+                    let prefix = if multiple {
+                        format!("instance #{}: ", index)
+                    } else {
+                        String::new()
+                    };
+                    errors.extend(
+                        instance_errors
+                            .into_iter()
+                            .map(|e| format!("{}{}", prefix, e)),
+                    );
+                    warnings.extend(
+                        instance_warnings
+                            .into_iter()
+                            .map(|w| format!("{}{}", prefix, w)),
+                    );
+                }
+            }
+            Err(err) => errors.push(err),
+        }
+
+        for warning in &warnings {
+            println!("warning: {}", warning);
+        }
+        for error in &errors {
+            println!("error: {}", error);
+        }
+        if errors.is_empty() {
+            println!(
+                "The config file {} is valid ({} warnings)",
+                file,
+                warnings.len()
+            );
+            Ok(())
+        } else {
+            let errmsg = format!("found {} errors in the config file {}", errors.len(), file);
+            Err(Error::config(errmsg))
+        }
+    }
+}
+
+/// Finds the deprecated keys in the config, and in each of the `[[instances]]` sections.
+fn find_deprecated_keys(raw: &toml::Value) -> Vec<String> {
+    let tables = if let Some(instances) = raw.get("instances").and_then(|v| v.as_array()) {
+        instances
+            .iter()
+            .enumerate()
+            .map(|(index, instance)| (format!("instances[{}].", index), instance))
+            .collect::<Vec<_>>()
+    } else {
+        vec![(String::new(), raw)]
+    };
+    let mut warnings = Vec::new();
+    for (prefix, table) in tables {
+        for (key, hint) in DEPRECATED_KEYS {
+            if key
+                .split('.')
+                .try_fold(table, |value, part| value.get(part))
+                .is_some()
+            {
+                warnings.push(format!("{}{} is deprecated, {}", prefix, key, hint));
+            }
+        }
+    }
+    warnings
+}
+
+/// Checks an instance without starting it, returns the errors and the warnings.
+///
+/// The store is only opened to compare its genesis block if it exists, and it's skipped if
+/// the store is in use by a running light client.
+fn check_run_env(run_env: &RunEnv) -> (Vec<String>, Vec<String>) {
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+
+    if let Err(err) = run_env.light_client.validate() {
+        errors.push(format!("light_client: {}", err));
+    }
+    for (name, path) in [
+        ("store.path", &run_env.store.path),
+        ("network.path", &run_env.network.path),
+    ] {
+        if let Err(err) = utils::fs::check_directory(path) {
+            errors.push(format!("{}: {}", name, err));
+        }
+    }
+    if let Some(dir) = run_env
+        .audit_log
+        .path
+        .as_ref()
+        .and_then(|path| path.parent())
+    {
+        if let Err(err) = utils::fs::check_directory(dir) {
+            errors.push(format!("audit_log.path: {}", err));
+        }
+    }
+
+    match run_env.rpc.listen_address.to_socket_addrs() {
+        Ok(mut addrs) => {
+            if addrs.any(|addr| !addr.ip().is_loopback()) {
+                warnings.push(format!(
+                    "rpc.listen_address {} is not a loopback address, exposing the RPC to the \
+                    public network is not recommended",
+                    run_env.rpc.listen_address
+                ));
+            }
+        }
+        Err(err) => errors.push(format!(
+            "rpc.listen_address {} is invalid since {}",
+            run_env.rpc.listen_address, err
+        )),
+    }
+    if run_env.network.bootnodes.is_empty() {
+        warnings.push(
+            "network.bootnodes is empty, only the peers in the peer store could be connected"
+                .to_owned(),
+        );
+    }
+
+    match build_consensus(run_env, false) {
+        Ok(consensus) if run_env.store.path.exists() => match Storage::open(&run_env.store.path) {
+            Ok(storage) => {
+                let expected = consensus.genesis_hash();
+                if let Some(stored) = storage
+                    .get_genesis_hash()
+                    .filter(|stored| stored != &expected)
+                {
+                    errors.push(format!(
+                        "the store {} is initialized with the genesis block {:#x}, but it's \
+                        {:#x} in the chain spec, use another store path or run the \
+                        reset-state subcommand to wipe it",
+                        run_env.store.path.display(),
+                        stored,
+                        expected
+                    ));
+                }
+            }
+            Err(err) => warnings.push(format!(
+                "skip checking the store {} since it can't be opened, maybe it's in use: {}",
+                run_env.store.path.display(),
+                err
+            )),
+        },
+        Ok(_) => {}
+        Err(err) => errors.push(err.to_string()),
+    }
+
+    (errors, warnings)
+}
+
+/// SIGINT and SIGTERM stop the light client, so it exits in the same way when it's stopped
+/// by systemd or docker; SIGHUP reloads the config file.
+///
+/// It exits as well if the light client is stopped for other reasons, otherwise the tokio
+/// tasks are never finished.
+#[cfg(unix)]
+async fn handle_signals(reloaders: Vec<crate::reload::ConfigReloader>) -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sighup = signal(SignalKind::hangup())?;
+    let stop_rx = ckb_stop_handler::new_tokio_exit_rx();
+    loop {
+        tokio::select! {
+            _ = sigint.recv() => break,
+            _ = sigterm.recv() => break,
+            _ = stop_rx.cancelled() => {
+                debug!("The signal handler received exit signal, exit now");
+                return Ok(());
+            }
+            _ = sighup.recv() => {
+                for reloader in &reloaders {
+                    if let Err(err) = reloader.reload() {
+                        log::error!("failed to reload the config since {}", err);
+                    }
+                }
+            }
+        }
+    }
+    log::info!("Received the exit signal, shutting down ...");
+    broadcast_exit_signals();
+    Ok(())
+}
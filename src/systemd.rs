@@ -0,0 +1,69 @@
+//! Notify systemd of the state of the light client, for the services with `Type=notify`.
+//!
+//! Nothing is sent if the light client isn't started by systemd, i.e. `NOTIFY_SOCKET` isn't
+//! set.
+
+use std::{sync::Arc, time::Duration};
+
+use ckb_async_runtime::Handle;
+use ckb_stop_handler::new_tokio_exit_rx;
+use ckb_systemtime::unix_time_as_millis;
+use log::{info, warn};
+use sd_notify::NotifyState;
+
+use crate::protocols::Peers;
+
+/// Notifies that the storage is opened and the network service is started.
+pub(crate) fn notify_ready() {
+    notify(&[NotifyState::Ready]);
+}
+
+pub(crate) fn notify_stopping() {
+    notify(&[NotifyState::Stopping]);
+}
+
+fn notify(states: &[NotifyState]) {
+    if let Err(err) = sd_notify::notify(false, states) {
+        warn!("failed to notify systemd since {}", err);
+    }
+}
+
+/// Sends the watchdog pings at half of `WatchdogSec`, if the watchdog is enabled.
+///
+/// A ping is sent only when the sync loops of all instances refreshed the peers within
+/// `WatchdogSec`, so systemd restarts the light client if any of them gets stuck.
+pub(crate) fn start_watchdog(handle: &Handle, peers: Vec<Arc<Peers>>) {
+    let mut timeout_usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut timeout_usec) {
+        return;
+    }
+    let timeout = Duration::from_micros(timeout_usec);
+    info!(
+        "The systemd watchdog is enabled, the timeout is {:?}",
+        timeout
+    );
+    let stop_rx = new_tokio_exit_rx();
+    handle.spawn(async move {
+        let mut interval = tokio::time::interval(timeout / 2);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let now = unix_time_as_millis();
+                    let stuck = peers.iter().any(|peers| {
+                        now.saturating_sub(peers.get_last_refreshed_at())
+                            > timeout.as_millis() as u64
+                    });
+                    if stuck {
+                        warn!("The sync loop is stuck, stop sending the watchdog pings");
+                    } else {
+                        notify(&[NotifyState::Watchdog]);
+                    }
+                }
+                _ = stop_rx.cancelled() => {
+                    info!("Systemd watchdog received exit signal, exit now");
+                    break;
+                }
+            }
+        }
+    });
+}
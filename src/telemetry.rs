@@ -0,0 +1,88 @@
+//! Export the tracing spans to an OpenTelemetry collector.
+//!
+//! The spans are created for the protocol messages, the storage operations and the RPC
+//! methods. They cost nothing if no subscriber is installed, so they are always compiled, and
+//! only the exporter requires the feature `otlp`.
+
+use crate::{
+    error::{Error, Result},
+    types::TracingConfig,
+};
+
+/// Flushes the remaining spans when it's dropped.
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+pub(crate) struct TelemetryGuard;
+
+#[cfg(feature = "otlp")]
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        opentelemetry::global::shutdown_tracer_provider();
+    }
+}
+
+/// Installs the exporter as the global subscriber of the spans if `otlp_endpoint` is set.
+///
+/// It should be called in the context of the tokio runtime, since the spans are exported in
+/// the background.
+pub(crate) fn init(config: &TracingConfig) -> Result<Option<TelemetryGuard>> {
+    let endpoint = if let Some(endpoint) = config.otlp_endpoint.as_ref() {
+        endpoint
+    } else {
+        return Ok(None);
+    };
+    if !(0.0..=1.0).contains(&config.sample_ratio) {
+        let errmsg = format!(
+            "tracing.sample_ratio {} should be between 0 and 1",
+            config.sample_ratio
+        );
+        return Err(Error::config(errmsg));
+    }
+    install(endpoint, config)?;
+    log::info!("Export the tracing spans to {}", endpoint);
+    Ok(Some(TelemetryGuard))
+}
+
+#[cfg(feature = "otlp")]
+fn install(endpoint: &str, config: &TracingConfig) -> Result<()> {
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{
+        runtime,
+        trace::{self, Sampler},
+        Resource,
+    };
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint);
+    let trace_config = trace::config()
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sample_ratio,
+        ))))
+        .with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            config.service_name.clone(),
+        )]));
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(trace_config)
+        .install_batch(runtime::Tokio)
+        .map_err(|err| {
+            let errmsg = format!("failed to install the OTLP exporter since {}", err);
+            Error::runtime(errmsg)
+        })?;
+    let subscriber = Registry::default().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber).map_err(|err| {
+        let errmsg = format!("failed to set the tracing subscriber since {}", err);
+        Error::runtime(errmsg)
+    })
+}
+
+#[cfg(not(feature = "otlp"))]
+fn install(_endpoint: &str, _config: &TracingConfig) -> Result<()> {
+    Err(Error::config(
+        "tracing.otlp_endpoint requires the feature otlp, rebuild with `--features otlp`",
+    ))
+}
@@ -0,0 +1,9 @@
+//! The mocks of the network, which are used to test the protocols without a devnet.
+//!
+//! Enable the feature `test-support` to use them out of the unit tests, for
+//! example, to script a full node in-process and drive `LightClientProtocol`
+//! with the messages it would send.
+
+mod network_context;
+
+pub use network_context::MockNetworkContext;
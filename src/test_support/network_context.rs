@@ -1,4 +1,4 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashSet;
 use std::future::Future;
 use std::pin::Pin;
@@ -17,9 +17,14 @@ struct MockProtocolContext {
     sent_messages: RefCell<Vec<(ProtocolId, PeerIndex, P2pBytes)>>,
     banned_peers: RefCell<Vec<(PeerIndex, Duration, String)>>,
     connected_peers: RefCell<HashSet<PeerIndex>>,
+    ckb2023: Cell<bool>,
 }
 
-pub(crate) struct MockNetworkContext {
+/// A mock of the network context for a single protocol.
+///
+/// It records the sent messages and the banned peers instead of sending them out.
+/// It's not thread-safe, only use it in a single thread.
+pub struct MockNetworkContext {
     inner: Arc<MockProtocolContext>,
 }
 
@@ -34,26 +39,37 @@ impl MockProtocolContext {
             sent_messages: Default::default(),
             banned_peers: Default::default(),
             connected_peers: Default::default(),
+            ckb2023: Default::default(),
         }
     }
 }
 
 impl MockNetworkContext {
-    pub(crate) fn new(protocol: SupportProtocols) -> Self {
+    pub fn new(protocol: SupportProtocols) -> Self {
         let context = MockProtocolContext::new(protocol);
         let inner = Arc::new(context);
         Self { inner }
     }
 
-    pub(crate) fn sent_messages(&self) -> &RefCell<Vec<(ProtocolId, PeerIndex, P2pBytes)>> {
+    pub fn sent_messages(&self) -> &RefCell<Vec<(ProtocolId, PeerIndex, P2pBytes)>> {
         &self.inner.sent_messages
     }
 
+    /// Marks the peer as connected, so it will be returned by `connected_peers()`.
+    pub fn connect_peer(&self, peer_index: PeerIndex) {
+        self.inner.connected_peers.borrow_mut().insert(peer_index);
+    }
+
+    /// Sets whether the CKB2023 hardfork is activated, it's not by default.
+    pub fn set_ckb2023(&self, ckb2023: bool) {
+        self.inner.ckb2023.set(ckb2023);
+    }
+
     fn banned_peers(&self) -> &RefCell<Vec<(PeerIndex, Duration, String)>> {
         &self.inner.banned_peers
     }
 
-    pub(crate) fn has_banned(&self, target: PeerIndex) -> Option<(Duration, String)> {
+    pub fn has_banned(&self, target: PeerIndex) -> Option<(Duration, String)> {
         self.banned_peers()
             .borrow()
             .iter()
@@ -61,25 +77,27 @@ impl MockNetworkContext {
             .map(|(_, duration, reason)| (duration.clone(), reason.clone()))
     }
 
-    pub(crate) fn not_banned(&self, target: PeerIndex) -> bool {
+    pub fn not_banned(&self, target: PeerIndex) -> bool {
         self.has_banned(target)
             .map(|(_, reason)| {
-                eprintln!("banned reason is {}", reason);
+                log::warn!("peer {} is banned since {}", target, reason);
                 false
             })
             .unwrap_or(true)
     }
 
-    pub(crate) fn banned_since(&self, target: PeerIndex, code: StatusCode) -> bool {
+    pub fn banned_since(&self, target: PeerIndex, code: StatusCode) -> bool {
         self.has_banned(target)
             .map(|(_, reason)| {
                 let status: Status = code.into();
                 let expected_reason = status.to_string();
                 let is_correct_reason = reason.starts_with(&expected_reason);
                 if !is_correct_reason {
-                    eprintln!(
-                        "banned reason is incorrect, expect {}, got {}",
-                        expected_reason, reason
+                    log::warn!(
+                        "peer {} is banned since {}, but {} is expected",
+                        target,
+                        reason,
+                        expected_reason
                     );
                 }
                 is_correct_reason
@@ -87,42 +105,42 @@ impl MockNetworkContext {
             .unwrap_or(false)
     }
 
-    pub(crate) fn context(&self) -> Arc<dyn CKBProtocolContext + Sync> {
+    pub fn context(&self) -> Arc<dyn CKBProtocolContext + Sync> {
         Arc::clone(&self.inner) as Arc<dyn CKBProtocolContext + Sync>
     }
 }
 
 #[async_trait]
 impl CKBProtocolContext for MockProtocolContext {
+    // The notifications are never triggered, call `protocol.notify(token)` directly to test
+    // them instead.
     async fn set_notify(&self, _interval: Duration, _token: u64) -> Result<(), Error> {
-        // NOTE: no need to mock this function, just call protocol.notity(token) in
-        // test code to test the functionality of the protocol.
-        unimplemented!()
+        Ok(())
     }
     async fn remove_notify(&self, _token: u64) -> Result<(), Error> {
-        unimplemented!()
+        Ok(())
     }
     async fn async_quick_send_message(
         &self,
-        _proto_id: ProtocolId,
-        _peer_index: PeerIndex,
-        _data: P2pBytes,
+        proto_id: ProtocolId,
+        peer_index: PeerIndex,
+        data: P2pBytes,
     ) -> Result<(), Error> {
-        unimplemented!();
+        self.send_message(proto_id, peer_index, data)
     }
     async fn async_quick_send_message_to(
         &self,
-        _peer_index: PeerIndex,
-        _data: P2pBytes,
+        peer_index: PeerIndex,
+        data: P2pBytes,
     ) -> Result<(), Error> {
-        unimplemented!();
+        self.send_message_to(peer_index, data)
     }
     async fn async_quick_filter_broadcast(
         &self,
-        _target: TargetSession,
-        _data: P2pBytes,
+        target: TargetSession,
+        data: P2pBytes,
     ) -> Result<(), Error> {
-        unimplemented!();
+        self.filter_broadcast(target, data)
     }
     async fn async_future_task(
         &self,
@@ -162,16 +180,16 @@ impl CKBProtocolContext for MockProtocolContext {
 
     async fn async_filter_broadcast(
         &self,
-        _target: TargetSession,
-        _data: P2pBytes,
+        target: TargetSession,
+        data: P2pBytes,
     ) -> Result<(), Error> {
-        unimplemented!();
+        self.filter_broadcast(target, data)
     }
-    async fn async_disconnect(&self, _peer_index: PeerIndex, _message: &str) -> Result<(), Error> {
-        unimplemented!();
+    async fn async_disconnect(&self, peer_index: PeerIndex, message: &str) -> Result<(), Error> {
+        self.disconnect(peer_index, message)
     }
-    fn quick_filter_broadcast(&self, _target: TargetSession, _data: P2pBytes) -> Result<(), Error> {
-        unimplemented!();
+    fn quick_filter_broadcast(&self, target: TargetSession, data: P2pBytes) -> Result<(), Error> {
+        self.filter_broadcast(target, data)
     }
     fn future_task(
         &self,
@@ -196,25 +214,38 @@ impl CKBProtocolContext for MockProtocolContext {
         self.send_message(protocol_id, peer_index, data)
     }
 
-    fn filter_broadcast(&self, _target: TargetSession, _data: P2pBytes) -> Result<(), Error> {
-        unimplemented!();
+    // The message is recorded as sent to each target peer which is connected.
+    fn filter_broadcast(&self, target: TargetSession, data: P2pBytes) -> Result<(), Error> {
+        let connected_peers = self.connected_peers();
+        let peers: Vec<PeerIndex> = match target {
+            TargetSession::All => connected_peers,
+            TargetSession::Single(peer_index) => vec![peer_index],
+            TargetSession::Multi(peers) => peers.collect(),
+            TargetSession::Filter(mut filter) => connected_peers
+                .into_iter()
+                .filter(|peer_index| filter(peer_index))
+                .collect(),
+        };
+        let protocol_id = self.protocol_id();
+        for peer_index in peers {
+            self.send_message(protocol_id, peer_index, data.clone())?;
+        }
+        Ok(())
     }
     fn disconnect(&self, peer_index: PeerIndex, _message: &str) -> Result<(), Error> {
         self.connected_peers.borrow_mut().remove(&peer_index);
         Ok(())
     }
     fn get_peer(&self, _peer_index: PeerIndex) -> Option<Peer> {
-        unimplemented!();
-    }
-    fn with_peer_mut(&self, _peer_index: PeerIndex, _f: Box<dyn FnOnce(&mut Peer)>) {
-        unimplemented!();
+        None
     }
+    // No peer info is mocked, see `get_peer`.
+    fn with_peer_mut(&self, _peer_index: PeerIndex, _f: Box<dyn FnOnce(&mut Peer)>) {}
     fn connected_peers(&self) -> Vec<PeerIndex> {
         self.connected_peers.borrow().iter().cloned().collect()
     }
-    fn report_peer(&self, _peer_index: PeerIndex, _behaviour: Behaviour) {
-        unimplemented!();
-    }
+    // The behaviours only change the scores of the peers, which are not mocked.
+    fn report_peer(&self, _peer_index: PeerIndex, _behaviour: Behaviour) {}
     fn ban_peer(&self, peer_index: PeerIndex, duration: Duration, reason: String) {
         self.banned_peers
             .borrow_mut()
@@ -225,6 +256,6 @@ impl CKBProtocolContext for MockProtocolContext {
     }
 
     fn ckb2023(&self) -> bool {
-        unimplemented!();
+        self.ckb2023.get()
     }
 }
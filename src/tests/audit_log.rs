@@ -0,0 +1,108 @@
+use ckb_types::{
+    core::capacity_bytes,
+    h256,
+    packed::{CellOutputBuilder, OutPoint},
+    prelude::*,
+};
+
+use crate::{
+    audit_log::{format_event, AuditLog},
+    protocols::ChainEvent,
+    storage::{MatchedCell, ScriptType},
+    tests::utils::create_peers,
+};
+
+#[test]
+fn format_audited_events() {
+    let tx_hash = h256!("0x1").pack();
+    let block_hash = h256!("0x2").pack();
+    let cell = MatchedCell {
+        out_point: OutPoint::new(tx_hash.clone(), 1),
+        output: CellOutputBuilder::default()
+            .capacity(capacity_bytes!(100).pack())
+            .build(),
+        script_type: ScriptType::Lock,
+        tx_hash: tx_hash.clone(),
+    };
+
+    let line = format_event(
+        &ChainEvent::CellFound {
+            cell: cell.clone(),
+            block_number: 16,
+            block_hash: block_hash.clone(),
+        },
+        1000,
+    )
+    .unwrap();
+    let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(value["event"], "cell_created");
+    assert_eq!(value["timestamp"], "0x3e8");
+    assert_eq!(value["block_number"], "0x10");
+    assert_eq!(value["block_hash"], format!("{:#x}", block_hash));
+    assert_eq!(value["tx_hash"], format!("{:#x}", tx_hash));
+    assert_eq!(value["out_point"]["index"], "0x1");
+    assert_eq!(value["output"]["capacity"], "0x2540be400");
+    assert_eq!(value["script_type"], "lock");
+
+    let line = format_event(
+        &ChainEvent::CellConsumed {
+            cell,
+            block_number: 17,
+            block_hash: block_hash.clone(),
+        },
+        1000,
+    )
+    .unwrap();
+    let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(value["event"], "cell_consumed");
+    assert_eq!(value["block_number"], "0x11");
+
+    let line = format_event(
+        &ChainEvent::TransactionCommitted {
+            hash: tx_hash,
+            block_number: 18,
+            block_hash: block_hash.clone(),
+        },
+        1000,
+    )
+    .unwrap();
+    let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(value["event"], "transaction_committed");
+    assert_eq!(value["block_number"], "0x12");
+
+    let line = format_event(&ChainEvent::Reorg { rollback_to: 15 }, 1000).unwrap();
+    let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(value["event"], "reorg");
+    assert_eq!(value["rollback_to"], "0xf");
+
+    // The other events aren't audited.
+    assert!(format_event(
+        &ChainEvent::TipUpdated {
+            number: 18,
+            hash: block_hash,
+        },
+        1000,
+    )
+    .is_none());
+}
+
+#[test]
+fn write_remaining_events_when_closed() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    let peers = create_peers();
+    let audit_log = AuditLog::new(path.clone(), &peers).start().unwrap();
+
+    // More than the capacity of the chain events channel, none of them is lost.
+    for rollback_to in 0..2000 {
+        peers.emit_chain_event(ChainEvent::Reorg { rollback_to });
+    }
+    peers.close_chain_events();
+    audit_log.join().unwrap();
+
+    let content = std::fs::read_to_string(path).unwrap();
+    assert_eq!(content.lines().count(), 2000);
+    // No events after the channel is closed.
+    peers.emit_chain_event(ChainEvent::Reorg { rollback_to: 10 });
+    assert!(peers.subscribe_chain_events().try_recv().is_err());
+}
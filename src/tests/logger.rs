@@ -0,0 +1,73 @@
+use std::{fs, io::Write as _, thread, time::Duration};
+
+use log::Level;
+
+use crate::logger::{format_json, RotatingFile};
+
+#[test]
+fn rotate_log_file_by_size() {
+    let tmp_dir = tempfile::Builder::new()
+        .prefix("rotate_log_file")
+        .tempdir()
+        .unwrap();
+    let path = tmp_dir.path().join("logs").join("run.log");
+    let mut file = RotatingFile::open(&path, 16, Duration::ZERO, 2).unwrap();
+
+    file.write_all(b"0123456789\n").unwrap();
+    assert!(file.rotated_files().unwrap().is_empty());
+
+    for _ in 0..3 {
+        // The rotated files are named by the rotation time in milliseconds.
+        thread::sleep(Duration::from_millis(2));
+        file.write_all(b"0123456789\n").unwrap();
+    }
+    file.flush().unwrap();
+
+    let rotated = file.rotated_files().unwrap();
+    assert_eq!(
+        rotated.len(),
+        2,
+        "the oldest rotated file should be removed"
+    );
+    for path in rotated {
+        assert_eq!(fs::read(path).unwrap(), b"0123456789\n");
+    }
+    assert_eq!(fs::read(&path).unwrap(), b"0123456789\n");
+}
+
+#[test]
+fn format_log_as_json() {
+    let key_values: &[(&str, u64)] = &[("peer_index", 3), ("block_number", 1024)];
+    let line = format_json(
+        "2023-12-08T00:00:00.000Z",
+        Level::Warn,
+        "ckb_light_client::protocols::light_client",
+        "peer SessionId(3): proved tip#1024 is stale",
+        &key_values,
+    );
+    assert_eq!(
+        line,
+        serde_json::json!({
+            "timestamp": "2023-12-08T00:00:00.000Z",
+            "level": "WARN",
+            "module": "ckb_light_client::protocols::light_client",
+            "message": "peer SessionId(3): proved tip#1024 is stale",
+            "peer_index": 3,
+            "block_number": 1024,
+        })
+    );
+
+    // The numbers in the message aren't taken as the fields, and the fixed fields can't be
+    // overridden.
+    let key_values: &[(&str, &str)] = &[("block_number", "100"), ("level", "ERROR")];
+    let line = format_json(
+        "2023-12-08T00:00:00.000Z",
+        Level::Info,
+        "ckb_light_client::protocols::light_client",
+        "rollback to block#100 since peer 2 sent an invalid proof",
+        &key_values,
+    );
+    assert_eq!(line["block_number"], "100");
+    assert_eq!(line["level"], "INFO");
+    assert!(line.get("peer_index").is_none());
+}
@@ -0,0 +1,55 @@
+use std::sync::{Arc, RwLock};
+
+use ckb_types::{core::TransactionBuilder, packed, prelude::*};
+
+use crate::{
+    memory::{self, MemoryMonitor},
+    protocols::PendingTxs,
+    tests::utils::{create_peers, new_storage},
+};
+
+#[test]
+fn stop_downloading_under_memory_pressure() {
+    let storage = new_storage("memory-monitor");
+    let peers = create_peers();
+    let pending_txs = Arc::new(RwLock::new(PendingTxs::default()));
+    let monitor = MemoryMonitor::new(storage, peers.clone(), Arc::clone(&pending_txs));
+    assert!(memory::allocated() > 0);
+
+    // No limit.
+    monitor.check();
+    assert!(!peers.is_under_memory_pressure());
+
+    // The downloaded matched blocks and the seen transactions are dropped.
+    let block = packed::Block::default();
+    let block_hash = block.header().calc_header_hash();
+    peers.add_matched_blocks(
+        &mut peers.matched_blocks().write().unwrap(),
+        vec![(block_hash, true)],
+    );
+    assert_eq!(
+        peers.add_block(&mut peers.matched_blocks().write().unwrap(), block),
+        Some(true)
+    );
+    let tx = TransactionBuilder::default().build();
+    pending_txs.write().unwrap().add_seen(tx.data());
+    peers.set_memory_soft_limit(1);
+    monitor.check();
+    assert!(peers.is_under_memory_pressure());
+    assert!(!peers.all_matched_blocks_downloaded(&peers.matched_blocks().read().unwrap()));
+    assert!(pending_txs.read().unwrap().get_seen(&tx.hash()).is_none());
+
+    // Resumed once the heap drops below the soft limit, even if the resident memory doesn't.
+    peers.set_memory_soft_limit(memory::allocated() * 2);
+    monitor.check();
+    assert!(!peers.is_under_memory_pressure());
+
+    peers.set_memory_soft_limit(u64::MAX);
+    monitor.check();
+    assert!(!peers.is_under_memory_pressure());
+
+    peers.set_memory_soft_limit(0);
+    peers.set_under_memory_pressure(true);
+    monitor.check();
+    assert!(!peers.is_under_memory_pressure());
+}
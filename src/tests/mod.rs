@@ -4,9 +4,15 @@ mod prelude;
 pub(crate) mod utils;
 
 // The unit tests for modules which are in the root path of this crate.
+mod audit_log;
+mod logger;
+mod memory;
 mod protocols;
 mod service;
 mod storage;
+mod subcmds;
+mod trusted_node;
+mod types;
 mod verify;
 
 use ckb_types::{
@@ -171,6 +171,9 @@ pub(crate) trait ChainExt {
             max_outbound_peers,
             CHECK_POINT_INTERVAL,
             self.client_storage().get_last_check_point(),
+            0,
+            0,
+            Default::default(),
         );
         Arc::new(peers)
     }
@@ -178,19 +181,22 @@ pub(crate) trait ChainExt {
     fn create_light_client_protocol(&self, peers: Arc<Peers>) -> LightClientProtocol {
         let storage = self.client_storage().to_owned();
         let consensus = self.consensus().to_owned();
-        let mut protocol = LightClientProtocol::new(storage, peers, consensus);
+        let config = Default::default();
+        let mut protocol = LightClientProtocol::new(storage, peers, consensus, &config);
         protocol.set_mmr_activated_epoch(1);
         protocol
     }
 
     fn create_filter_protocol(&self, peers: Arc<Peers>) -> FilterProtocol {
         let storage = self.client_storage().to_owned();
-        FilterProtocol::new(storage, peers)
+        let config = Default::default();
+        FilterProtocol::new(storage, peers, &config)
     }
 
     fn create_sync_protocol(&self, peers: Arc<Peers>) -> SyncProtocol {
         let storage = self.client_storage().to_owned();
-        SyncProtocol::new(storage, peers)
+        let config = Default::default();
+        SyncProtocol::new(storage, peers, Default::default(), &config)
     }
 }
 
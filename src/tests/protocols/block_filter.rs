@@ -1,9 +1,10 @@
 use std::sync::Arc;
 use std::sync::RwLock;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use ckb_network::{bytes::Bytes, CKBProtocolHandler, PeerIndex, SupportProtocols};
 use ckb_store::ChainStore as _;
+use ckb_systemtime::faketime;
 use ckb_types::{
     core::{EpochNumberWithFraction, HeaderBuilder},
     packed::{self, Script},
@@ -15,11 +16,12 @@ use ckb_types::{
 use crate::storage::SetScriptsCommand;
 use crate::storage::{ScriptStatus, ScriptType};
 use crate::{
-    protocols::{BAD_MESSAGE_BAN_TIME, GET_BLOCK_FILTERS_TOKEN},
+    protocols::{FilterProtocol, BAD_MESSAGE_BAN_TIME, GET_BLOCK_FILTERS_TOKEN},
     tests::{
         prelude::*,
         utils::{setup, MockChain, MockNetworkContext},
     },
+    types::LightClientConfig,
 };
 
 #[tokio::test]
@@ -531,6 +533,500 @@ async fn test_block_filter_notify_ask_filters() {
     );
 }
 
+#[tokio::test]
+async fn test_block_filter_notify_paused() {
+    let chain = MockChain::new_with_dummy_pow("test-block-filter");
+    let nc = MockNetworkContext::new(SupportProtocols::Filter);
+
+    let min_filtered_block_number = 3;
+    chain.client_storage().update_filter_scripts(
+        vec![ScriptStatus {
+            script: Script::default(),
+            script_type: ScriptType::Lock,
+            block_number: min_filtered_block_number,
+        }],
+        Default::default(),
+    );
+
+    let peer_index = PeerIndex::new(3);
+    let peers = {
+        let tip_header = VerifiableHeader::new(
+            HeaderBuilder::default()
+                .epoch(EpochNumberWithFraction::new(0, 0, 100).full_value().pack())
+                .number((min_filtered_block_number + 1).pack())
+                .build(),
+            Default::default(),
+            None,
+            Default::default(),
+        );
+        let peers = chain.create_peers();
+        peers.add_peer(peer_index);
+        peers.mock_prove_state(peer_index, tip_header).unwrap();
+        peers
+    };
+    let mut protocol = chain.create_filter_protocol(Arc::clone(&peers));
+
+    peers.mock_latest_block_filter_hashes(
+        peer_index,
+        0,
+        vec![Default::default(); min_filtered_block_number as usize + 1],
+    );
+    peers.set_filter_sync_paused(true);
+    protocol.notify(nc.context(), GET_BLOCK_FILTERS_TOKEN).await;
+    assert!(nc.sent_messages().borrow().is_empty());
+
+    peers.set_filter_sync_paused(false);
+    protocol.notify(nc.context(), GET_BLOCK_FILTERS_TOKEN).await;
+    assert_eq!(nc.sent_messages().borrow().len(), 1);
+}
+
+#[tokio::test]
+async fn test_block_filter_notify_rescan() {
+    let chain = MockChain::new_with_dummy_pow("test-block-filter");
+    let nc = MockNetworkContext::new(SupportProtocols::Filter);
+
+    let min_filtered_block_number = 10;
+    chain.client_storage().update_filter_scripts(
+        vec![ScriptStatus {
+            script: Script::default(),
+            script_type: ScriptType::Lock,
+            block_number: min_filtered_block_number,
+        }],
+        Default::default(),
+    );
+
+    let peer_index = PeerIndex::new(3);
+    let peers = {
+        let tip_header = VerifiableHeader::new(
+            HeaderBuilder::default()
+                .epoch(EpochNumberWithFraction::new(0, 0, 100).full_value().pack())
+                .number((min_filtered_block_number + 1).pack())
+                .build(),
+            Default::default(),
+            None,
+            Default::default(),
+        );
+        let peers = chain.create_peers();
+        peers.add_peer(peer_index);
+        peers.mock_prove_state(peer_index, tip_header).unwrap();
+        peers
+    };
+    let mut protocol = chain.create_filter_protocol(Arc::clone(&peers));
+
+    peers.mock_latest_block_filter_hashes(
+        peer_index,
+        0,
+        vec![Default::default(); min_filtered_block_number as usize + 1],
+    );
+    // The block filters were just requested.
+    protocol.update_min_filtered_block_number(min_filtered_block_number);
+    protocol.notify(nc.context(), GET_BLOCK_FILTERS_TOKEN).await;
+    assert!(nc.sent_messages().borrow().is_empty());
+
+    let new_block_number = 5;
+    chain.client_storage().update_filter_scripts(
+        vec![ScriptStatus {
+            script: Script::new_builder().args(vec![1u8].pack()).build(),
+            script_type: ScriptType::Lock,
+            block_number: new_block_number,
+        }],
+        SetScriptsCommand::Partial,
+    );
+    peers.request_filter_rescan();
+    protocol.notify(nc.context(), GET_BLOCK_FILTERS_TOKEN).await;
+    let start_numbers = nc
+        .sent_messages()
+        .borrow()
+        .iter()
+        .filter_map(|(_, _, data)| {
+            let message = packed::BlockFilterMessage::from_slice(data).unwrap();
+            match message.to_enum() {
+                packed::BlockFilterMessageUnion::GetBlockFilters(content) => {
+                    Some(content.start_number().unpack())
+                }
+                _ => None,
+            }
+        })
+        .collect::<Vec<u64>>();
+    assert_eq!(start_numbers, vec![new_block_number + 1]);
+    assert!(!peers.take_filter_rescan_request());
+}
+
+#[tokio::test]
+async fn test_block_filter_notify_timeout_and_failover() {
+    let chain = MockChain::new_with_dummy_pow("test-block-filter");
+    let nc = MockNetworkContext::new(SupportProtocols::Filter);
+
+    let min_filtered_block_number = 3;
+    chain.client_storage().update_filter_scripts(
+        vec![ScriptStatus {
+            script: Script::default(),
+            script_type: ScriptType::Lock,
+            block_number: min_filtered_block_number,
+        }],
+        Default::default(),
+    );
+
+    let peer_indexes = [PeerIndex::new(3), PeerIndex::new(4)];
+    let peers = {
+        let tip_header = VerifiableHeader::new(
+            HeaderBuilder::default()
+                .epoch(EpochNumberWithFraction::new(0, 0, 100).full_value().pack())
+                .number((min_filtered_block_number + 1).pack())
+                .build(),
+            Default::default(),
+            None,
+            Default::default(),
+        );
+        let peers = chain.create_peers();
+        for peer_index in peer_indexes {
+            peers.add_peer(peer_index);
+            peers
+                .mock_prove_state(peer_index, tip_header.clone())
+                .unwrap();
+        }
+        peers
+    };
+    let mut protocol = chain.create_filter_protocol(Arc::clone(&peers));
+
+    let get_block_filters_peers = || {
+        nc.sent_messages()
+            .borrow()
+            .iter()
+            .filter_map(|(_, peer, data)| {
+                let message = packed::BlockFilterMessage::from_slice(data).unwrap();
+                match message.to_enum() {
+                    packed::BlockFilterMessageUnion::GetBlockFilters(_) => Some(*peer),
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+
+    protocol.notify(nc.context(), GET_BLOCK_FILTERS_TOKEN).await;
+    let sent_peers = get_block_filters_peers();
+    assert_eq!(sent_peers.len(), 1);
+    let timeout_peer = sent_peers[0];
+    let other_peer = peer_indexes
+        .into_iter()
+        .find(|peer| *peer != timeout_peer)
+        .unwrap();
+
+    assert!(!protocol.check_filter_requests_timeout(Instant::now()));
+    assert_eq!(peers.get_filter_timeouts(&timeout_peer), 0);
+    let later = Instant::now() + Duration::from_secs(16);
+    assert!(protocol.check_filter_requests_timeout(later));
+    assert_eq!(peers.get_filter_timeouts(&timeout_peer), 1);
+    // The timeout request is removed, it's not counted twice.
+    assert!(!protocol.check_filter_requests_timeout(later));
+
+    // Fail over to the other peer.
+    protocol.try_send_get_block_filters(nc.context(), true);
+    assert_eq!(get_block_filters_peers(), vec![timeout_peer, other_peer]);
+
+    // Stop using the peer which requests time out too many times in a row.
+    peers.add_filter_timeout(timeout_peer);
+    assert!(!peers.is_filter_unreliable(&timeout_peer));
+    peers.add_filter_timeout(timeout_peer);
+    assert!(peers.is_filter_unreliable(&timeout_peer));
+    assert!(!peers.is_filter_unreliable(&other_peer));
+}
+
+#[tokio::test]
+async fn test_block_filter_notify_download_rate_limited() {
+    let chain = MockChain::new_with_dummy_pow("test-block-filter");
+    let nc = MockNetworkContext::new(SupportProtocols::Filter);
+
+    let min_filtered_block_number = 3;
+    chain.client_storage().update_filter_scripts(
+        vec![ScriptStatus {
+            script: Script::default(),
+            script_type: ScriptType::Lock,
+            block_number: min_filtered_block_number,
+        }],
+        Default::default(),
+    );
+
+    let peer_index = PeerIndex::new(3);
+    let peers = {
+        let tip_header = VerifiableHeader::new(
+            HeaderBuilder::default()
+                .epoch(EpochNumberWithFraction::new(0, 0, 100).full_value().pack())
+                .number((min_filtered_block_number + 1).pack())
+                .build(),
+            Default::default(),
+            None,
+            Default::default(),
+        );
+        let peers = chain.create_peers();
+        peers.add_peer(peer_index);
+        peers.mock_prove_state(peer_index, tip_header).unwrap();
+        peers
+    };
+    let mut protocol = chain.create_filter_protocol(Arc::clone(&peers));
+
+    peers.mock_latest_block_filter_hashes(
+        peer_index,
+        0,
+        vec![Default::default(); min_filtered_block_number as usize + 1],
+    );
+
+    let start_ts = 1_000_000;
+    let faketime_guard = faketime();
+    faketime_guard.set_faketime(start_ts);
+    peers.set_max_download_rate(1000);
+    peers.record_downloaded_bytes(1500);
+    protocol.notify(nc.context(), GET_BLOCK_FILTERS_TOKEN).await;
+    assert!(nc.sent_messages().borrow().is_empty());
+
+    // 500 bytes over the limit are paid off after half a second.
+    faketime_guard.set_faketime(start_ts + 499);
+    protocol.notify(nc.context(), GET_BLOCK_FILTERS_TOKEN).await;
+    assert!(nc.sent_messages().borrow().is_empty());
+
+    faketime_guard.set_faketime(start_ts + 500);
+    protocol.notify(nc.context(), GET_BLOCK_FILTERS_TOKEN).await;
+    assert_eq!(nc.sent_messages().borrow().len(), 1);
+}
+
+#[tokio::test]
+async fn test_block_filter_notify_ask_filters_in_parallel() {
+    let chain = MockChain::new_with_dummy_pow("test-block-filter");
+    let nc = MockNetworkContext::new(SupportProtocols::Filter);
+
+    let min_filtered_block_number = 3;
+    let proved_number = 2100;
+    chain.client_storage().update_filter_scripts(
+        vec![ScriptStatus {
+            script: Script::default(),
+            script_type: ScriptType::Lock,
+            block_number: min_filtered_block_number,
+        }],
+        Default::default(),
+    );
+
+    let tip_header = VerifiableHeader::new(
+        HeaderBuilder::default()
+            .epoch(EpochNumberWithFraction::new(0, 0, 100).full_value().pack())
+            .number(proved_number.pack())
+            .build(),
+        Default::default(),
+        None,
+        Default::default(),
+    );
+    chain
+        .client_storage()
+        .update_last_state(&U256::one(), &tip_header.header().data(), &[]);
+    let peer_index_1 = PeerIndex::new(3);
+    let peer_index_2 = PeerIndex::new(4);
+    let peers = chain.create_peers();
+    for peer_index in [peer_index_1, peer_index_2] {
+        peers.add_peer(peer_index);
+        peers
+            .mock_prove_state(peer_index, tip_header.clone())
+            .unwrap();
+        peers.mock_latest_block_filter_hashes(
+            peer_index,
+            0,
+            vec![Default::default(); proved_number as usize],
+        );
+    }
+    let mut protocol = chain.create_filter_protocol(Arc::clone(&peers));
+
+    protocol.notify(nc.context(), GET_BLOCK_FILTERS_TOKEN).await;
+
+    // Each range is requested from a different peer.
+    let mut requests = nc
+        .sent_messages()
+        .borrow()
+        .iter()
+        .map(|(_, peer_index, data)| {
+            let message = packed::BlockFilterMessage::from_slice(data).unwrap();
+            let start_number: u64 = match message.to_enum() {
+                packed::BlockFilterMessageUnion::GetBlockFilters(content) => {
+                    content.start_number().unpack()
+                }
+                _ => panic!("unexpected message"),
+            };
+            (start_number, *peer_index)
+        })
+        .collect::<Vec<_>>();
+    requests.sort();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].0, min_filtered_block_number + 1);
+    assert_eq!(requests[1].0, min_filtered_block_number + 1 + 1000);
+    assert_ne!(requests[0].1, requests[1].1);
+
+    // Only the block filters which are requested in parallel are kept.
+    let block_filters = |start_number: u64| {
+        packed::BlockFilters::new_builder()
+            .start_number(start_number.pack())
+            .build()
+    };
+    let (start_number, peer_index) = requests[1];
+    let other_peer_index = requests[0].1;
+    assert!(!protocol.add_pending_block_filters(other_peer_index, block_filters(start_number)));
+    assert!(!protocol.add_pending_block_filters(peer_index, block_filters(start_number + 1)));
+    assert!(protocol.add_pending_block_filters(peer_index, block_filters(start_number)));
+    assert!(protocol
+        .take_pending_block_filters(start_number - 1)
+        .is_none());
+    let (pending_peer_index, _) = protocol
+        .take_pending_block_filters(start_number)
+        .expect("pending block filters");
+    assert_eq!(pending_peer_index, peer_index);
+}
+
+#[tokio::test]
+async fn test_block_filter_notify_adaptive_lookahead() {
+    let chain = MockChain::new_with_dummy_pow("test-block-filter");
+    let nc = MockNetworkContext::new(SupportProtocols::Filter);
+
+    let min_filtered_block_number = 3;
+    let proved_number = 4100;
+    chain.client_storage().update_filter_scripts(
+        vec![ScriptStatus {
+            script: Script::default(),
+            script_type: ScriptType::Lock,
+            block_number: min_filtered_block_number,
+        }],
+        Default::default(),
+    );
+
+    let tip_header = VerifiableHeader::new(
+        HeaderBuilder::default()
+            .epoch(EpochNumberWithFraction::new(0, 0, 100).full_value().pack())
+            .number(proved_number.pack())
+            .build(),
+        Default::default(),
+        None,
+        Default::default(),
+    );
+    chain
+        .client_storage()
+        .update_last_state(&U256::one(), &tip_header.header().data(), &[]);
+    let peers = chain.create_peers();
+    for index in 3..8 {
+        let peer_index = PeerIndex::new(index);
+        peers.add_peer(peer_index);
+        peers
+            .mock_prove_state(peer_index, tip_header.clone())
+            .unwrap();
+        peers.mock_latest_block_filter_hashes(
+            peer_index,
+            0,
+            vec![Default::default(); proved_number as usize],
+        );
+    }
+    let mut protocol = chain.create_filter_protocol(Arc::clone(&peers));
+    assert_eq!(protocol.get_block_filters_lookahead(), 3000);
+
+    // Shrink the lookahead, as the peers respond slow or fail.
+    protocol.decrease_block_filters_lookahead();
+    assert_eq!(protocol.get_block_filters_lookahead(), 1500);
+    protocol.notify(nc.context(), GET_BLOCK_FILTERS_TOKEN).await;
+    let requests = nc
+        .sent_messages()
+        .borrow()
+        .iter()
+        .map(|(_, peer_index, data)| {
+            let message = packed::BlockFilterMessage::from_slice(data).unwrap();
+            let start_number: u64 = match message.to_enum() {
+                packed::BlockFilterMessageUnion::GetBlockFilters(content) => {
+                    content.start_number().unpack()
+                }
+                _ => panic!("unexpected message"),
+            };
+            (start_number, *peer_index)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(requests.len(), 2);
+
+    // Grow the lookahead back, as the peer responds fast.
+    let (start_number, peer_index) = requests.into_iter().max().expect("requested in parallel");
+    let block_filters = packed::BlockFilters::new_builder()
+        .start_number(start_number.pack())
+        .build();
+    assert!(protocol.add_pending_block_filters(peer_index, block_filters));
+    assert_eq!(protocol.get_block_filters_lookahead(), 2500);
+}
+
+#[tokio::test]
+async fn test_block_filter_notify_ask_filters_with_custom_batch_size_and_lookahead() {
+    let chain = MockChain::new_with_dummy_pow("test-block-filter");
+    let nc = MockNetworkContext::new(SupportProtocols::Filter);
+
+    let min_filtered_block_number = 3;
+    let proved_number = 2100;
+    chain.client_storage().update_filter_scripts(
+        vec![ScriptStatus {
+            script: Script::default(),
+            script_type: ScriptType::Lock,
+            block_number: min_filtered_block_number,
+        }],
+        Default::default(),
+    );
+
+    let tip_header = VerifiableHeader::new(
+        HeaderBuilder::default()
+            .epoch(EpochNumberWithFraction::new(0, 0, 100).full_value().pack())
+            .number(proved_number.pack())
+            .build(),
+        Default::default(),
+        None,
+        Default::default(),
+    );
+    chain
+        .client_storage()
+        .update_last_state(&U256::one(), &tip_header.header().data(), &[]);
+    let peers = chain.create_peers();
+    for index in 3..8 {
+        let peer_index = PeerIndex::new(index);
+        peers.add_peer(peer_index);
+        peers
+            .mock_prove_state(peer_index, tip_header.clone())
+            .unwrap();
+        peers.mock_latest_block_filter_hashes(
+            peer_index,
+            0,
+            vec![Default::default(); proved_number as usize],
+        );
+    }
+    let config = LightClientConfig {
+        block_filters_batch_size: 400,
+        block_filters_lookahead: 800,
+        ..Default::default()
+    };
+    let mut protocol = FilterProtocol::new(
+        chain.client_storage().to_owned(),
+        Arc::clone(&peers),
+        &config,
+    );
+
+    protocol.notify(nc.context(), GET_BLOCK_FILTERS_TOKEN).await;
+
+    let mut start_numbers = nc
+        .sent_messages()
+        .borrow()
+        .iter()
+        .map(|(_, _, data)| {
+            let message = packed::BlockFilterMessage::from_slice(data).unwrap();
+            match message.to_enum() {
+                packed::BlockFilterMessageUnion::GetBlockFilters(content) => {
+                    content.start_number().unpack()
+                }
+                _ => panic!("unexpected message"),
+            }
+        })
+        .collect::<Vec<u64>>();
+    start_numbers.sort();
+    let start_number = min_filtered_block_number + 1;
+    assert_eq!(
+        start_numbers,
+        vec![start_number, start_number + 400, start_number + 800]
+    );
+}
+
 #[tokio::test]
 async fn test_block_filter_notify_no_proved_peers() {
     let chain = MockChain::new_with_dummy_pow("test-block-filter");
@@ -1,4 +1,6 @@
-use ckb_network::{bytes::Bytes, CKBProtocolHandler, PeerIndex, SupportProtocols};
+use std::sync::Arc;
+
+use ckb_network::{bytes::Bytes, CKBProtocolHandler, PeerId, PeerIndex, SupportProtocols};
 use ckb_systemtime::{faketime, unix_time_as_millis};
 use ckb_types::{
     core::{BlockNumber, EpochNumberWithFraction, HeaderBuilder},
@@ -10,15 +12,21 @@ use ckb_types::{
 
 use crate::{
     protocols::{
-        light_client::constant::{
-            GET_IDLE_BLOCKS_TOKEN, REFRESH_PEERS_DURATION, REFRESH_PEERS_TOKEN,
+        light_client::{
+            constant::{
+                GET_IDLE_BLOCKS_TOKEN, MAX_REFRESH_LAST_STATE_INTERVAL, REFRESH_PEERS_DURATION,
+                REFRESH_PEERS_TOKEN,
+            },
+            mmr_activated_epoch,
         },
-        PeerState, BAD_MESSAGE_BAN_TIME,
+        ChainEvent, LightClientProtocol, PeerState, Peers, RequestKind, RequestLimits, StatusCode,
+        BAD_MESSAGE_BAN_TIME, CHECK_POINT_INTERVAL,
     },
     tests::{
         prelude::*,
         utils::{setup, MockChain, MockNetworkContext},
     },
+    types::LightClientConfig,
 };
 
 mod send_blocks_proof;
@@ -44,6 +52,413 @@ async fn malformed_message() {
     );
 }
 
+#[tokio::test]
+async fn count_failures_for_peer() {
+    let chain = MockChain::new_with_dummy_pow("test-light-client");
+    let nc = MockNetworkContext::new(SupportProtocols::LightClient);
+
+    let peer_index = PeerIndex::new(3);
+    let peers = chain.create_peers();
+    peers.add_peer(peer_index);
+    let mut protocol = chain.create_light_client_protocol(Arc::clone(&peers));
+
+    for _ in 0..2 {
+        let data = Bytes::from(vec![2, 3, 4, 5]);
+        protocol.received(nc.context(), peer_index, data).await;
+    }
+
+    let peer = peers.get_peer(&peer_index).expect("has peer");
+    let failure_counts = peer.get_failure_counts();
+    assert_eq!(failure_counts.len(), 1);
+    assert_eq!(
+        failure_counts.get(&StatusCode::MalformedProtocolMessage),
+        Some(&2)
+    );
+}
+
+#[tokio::test]
+async fn adapt_refresh_last_state_interval() {
+    let chain = MockChain::new_with_dummy_pow("test-light-client");
+    let nc = MockNetworkContext::new(SupportProtocols::LightClient);
+
+    let peers = chain.create_peers();
+    let mut protocol = chain.create_light_client_protocol(peers);
+    let storage = chain.client_storage();
+
+    let min_interval = REFRESH_PEERS_DURATION.as_millis() as u64;
+    let max_interval = MAX_REFRESH_LAST_STATE_INTERVAL.as_millis() as u64;
+    assert_eq!(protocol.refresh_last_state_interval(), min_interval);
+
+    // The proved tip is advanced since it's the first refresh.
+    protocol.notify(nc.context(), REFRESH_PEERS_TOKEN).await;
+    assert_eq!(protocol.refresh_last_state_interval(), min_interval);
+
+    // Back off when the proved tip doesn't advance.
+    protocol.notify(nc.context(), REFRESH_PEERS_TOKEN).await;
+    assert_eq!(protocol.refresh_last_state_interval(), min_interval * 2);
+    for _ in 0..10 {
+        protocol.notify(nc.context(), REFRESH_PEERS_TOKEN).await;
+    }
+    assert_eq!(protocol.refresh_last_state_interval(), max_interval);
+
+    // Poll aggressively when the proved tip advances.
+    let (total_difficulty, tip_header) = storage.get_last_state();
+    storage.update_last_state(&(&total_difficulty + U256::one()), &tip_header, &[]);
+    protocol.notify(nc.context(), REFRESH_PEERS_TOKEN).await;
+    assert_eq!(protocol.refresh_last_state_interval(), min_interval);
+}
+
+#[test]
+fn detect_stale_tip() {
+    let chain = MockChain::new_with_dummy_pow("test-light-client");
+    let storage = chain.client_storage();
+
+    let peers = chain.create_peers();
+    let protocol = chain.create_light_client_protocol(Arc::clone(&peers));
+    let mut events = peers.subscribe_chain_events();
+
+    let timeout = 60 * 60 * 1000;
+    let tip_ts = 100 * timeout;
+    let (total_difficulty, _) = storage.get_last_state();
+    let tip_header = HeaderBuilder::default()
+        .number(10.pack())
+        .timestamp(tip_ts.pack())
+        .build();
+    storage.update_last_state(&total_difficulty, &tip_header.data(), &[]);
+
+    protocol.check_stale_tip(tip_ts + timeout);
+    assert!(!peers.is_tip_stale());
+    assert!(events.try_recv().is_err());
+
+    protocol.check_stale_tip(tip_ts + timeout + 1);
+    assert!(peers.is_tip_stale());
+    assert_eq!(
+        events.try_recv().unwrap(),
+        ChainEvent::TipStale {
+            number: 10,
+            timestamp: tip_ts,
+        }
+    );
+
+    // Only emit the event when the tip becomes stale.
+    protocol.check_stale_tip(tip_ts + timeout + 2);
+    assert!(peers.is_tip_stale());
+    assert!(events.try_recv().is_err());
+
+    protocol.check_stale_tip(tip_ts);
+    assert!(!peers.is_tip_stale());
+}
+
+#[tokio::test]
+async fn emit_peer_lost_event() {
+    let chain = MockChain::new_with_dummy_pow("test-light-client");
+    let nc = MockNetworkContext::new(SupportProtocols::LightClient);
+
+    let peers = chain.create_peers();
+    let mut protocol = chain.create_light_client_protocol(Arc::clone(&peers));
+    let mut events = peers.subscribe_chain_events();
+
+    let peer_index = PeerIndex::new(1);
+    protocol.connected(nc.context(), peer_index, "").await;
+    protocol.disconnected(nc.context(), peer_index).await;
+    assert_eq!(events.try_recv().unwrap(), ChainEvent::PeerLost(peer_index));
+
+    // No events for the unknown peers.
+    protocol.disconnected(nc.context(), PeerIndex::new(2)).await;
+    assert!(events.try_recv().is_err());
+}
+
+#[test]
+fn skip_duplicate_sessions_for_proof_syncing() {
+    let chain = MockChain::new_with_dummy_pow("test-light-client");
+    let peers = chain.create_peers();
+
+    let node_id = PeerId::random();
+    let peer_index_1 = PeerIndex::new(1);
+    let peer_index_2 = PeerIndex::new(2);
+    let peer_index_3 = PeerIndex::new(3);
+    for peer_index in [peer_index_1, peer_index_2, peer_index_3] {
+        peers.add_peer(peer_index);
+    }
+
+    assert_eq!(peers.update_node_id(peer_index_1, node_id.clone()), None);
+    assert_eq!(
+        peers.update_node_id(peer_index_2, node_id.clone()),
+        Some(peer_index_1)
+    );
+    assert_eq!(peers.update_node_id(peer_index_3, PeerId::random()), None);
+    assert!(!peers.get_peer(&peer_index_1).unwrap().is_duplicate());
+    assert!(peers.get_peer(&peer_index_2).unwrap().is_duplicate());
+
+    let mut require_new_state = peers.get_peers_which_require_new_state(unix_time_as_millis());
+    require_new_state.sort();
+    assert_eq!(require_new_state, vec![peer_index_1, peer_index_3]);
+
+    // Duplicate sessions can not take over others.
+    assert_eq!(peers.take_over_by_duplicate_session(peer_index_2), None);
+    assert_eq!(peers.take_over_by_duplicate_session(peer_index_3), None);
+
+    assert_eq!(
+        peers.take_over_by_duplicate_session(peer_index_1),
+        Some(peer_index_2)
+    );
+    peers.remove_peer(peer_index_1);
+    assert!(!peers.get_peer(&peer_index_2).unwrap().is_duplicate());
+}
+
+#[test]
+fn cache_invalid_headers() {
+    let chain = MockChain::new_with_dummy_pow("test-light-client");
+    let peers = chain.create_peers();
+    let protocol = chain.create_light_client_protocol(peers);
+
+    let build_verifiable_header = |number: BlockNumber, total_difficulty: u64| {
+        let header = HeaderBuilder::default().number(number.pack()).build();
+        let parent_chain_root = packed::HeaderDigest::new_builder()
+            .total_difficulty(U256::from(total_difficulty).pack())
+            .build();
+        VerifiableHeader::new(header, Default::default(), None, parent_chain_root)
+    };
+
+    // The PoW failure is matched by the header only.
+    let pow_failed = build_verifiable_header(10, 100);
+    assert!(protocol
+        .check_invalid_headers_cache(pow_failed.header(), None)
+        .is_ok());
+    protocol.add_invalid_header(pow_failed.header(), None);
+    let status = protocol
+        .check_invalid_headers_cache(pow_failed.header(), None)
+        .unwrap_err();
+    assert_eq!(status.code(), StatusCode::InvalidNonce);
+    let status = protocol
+        .check_invalid_headers_cache(pow_failed.header(), Some(&pow_failed))
+        .unwrap_err();
+    assert_eq!(status.code(), StatusCode::InvalidNonce);
+
+    // The chain root failure is only matched by the same verifiable data.
+    let chain_root_failed = build_verifiable_header(11, 100);
+    let another = build_verifiable_header(11, 200);
+    assert_eq!(chain_root_failed.header().hash(), another.header().hash());
+    protocol.add_invalid_header(chain_root_failed.header(), Some(&chain_root_failed));
+    let status = protocol
+        .check_invalid_headers_cache(chain_root_failed.header(), Some(&chain_root_failed))
+        .unwrap_err();
+    assert_eq!(status.code(), StatusCode::InvalidChainRoot);
+    assert!(protocol
+        .check_invalid_headers_cache(another.header(), Some(&another))
+        .is_ok());
+    assert!(protocol
+        .check_invalid_headers_cache(chain_root_failed.header(), None)
+        .is_ok());
+}
+
+#[test]
+fn prefer_low_latency_peers() {
+    let chain = MockChain::new_with_dummy_pow("test-light-client");
+    let peers = chain.create_peers();
+
+    let slow_peer = PeerIndex::new(1);
+    let fast_peer = PeerIndex::new(2);
+    let new_peer = PeerIndex::new(3);
+    for peer_index in [slow_peer, fast_peer, new_peer] {
+        peers.add_peer(peer_index);
+    }
+
+    let start_ts = unix_time_as_millis();
+    let faketime_guard = faketime();
+    for (peer_index, latency) in [(slow_peer, 800), (fast_peer, 100)] {
+        faketime_guard.set_faketime(start_ts);
+        peers.update_txs_proof_request(peer_index, Some(Default::default()));
+        faketime_guard.set_faketime(start_ts + latency);
+        peers.update_txs_proof_request(peer_index, None);
+    }
+
+    let get_latency = |peer_index: PeerIndex, kind: RequestKind| {
+        peers.get_peer(&peer_index).unwrap().get_latency(kind)
+    };
+    assert_eq!(
+        get_latency(slow_peer, RequestKind::TransactionsProof),
+        Some(800)
+    );
+    assert_eq!(
+        get_latency(fast_peer, RequestKind::TransactionsProof),
+        Some(100)
+    );
+    assert_eq!(get_latency(new_peer, RequestKind::TransactionsProof), None);
+    assert_eq!(get_latency(fast_peer, RequestKind::BlocksProof), None);
+
+    // The latency is smoothed.
+    faketime_guard.set_faketime(start_ts);
+    peers.update_txs_proof_request(fast_peer, Some(Default::default()));
+    faketime_guard.set_faketime(start_ts + 500);
+    peers.update_txs_proof_request(fast_peer, None);
+    assert_eq!(
+        get_latency(fast_peer, RequestKind::TransactionsProof),
+        Some(200)
+    );
+
+    let mut peer_indexes = vec![slow_peer, fast_peer, new_peer];
+    peers.sort_by_latency(&mut peer_indexes, RequestKind::TransactionsProof);
+    assert_eq!(peer_indexes, vec![new_peer, fast_peer, slow_peer]);
+}
+
+#[test]
+fn required_peers_count_for_block_filters() {
+    let min_block_filter_peers = 2;
+    let peers = Peers::new(
+        1,
+        CHECK_POINT_INTERVAL,
+        (0, Default::default()),
+        0,
+        min_block_filter_peers,
+        Default::default(),
+    );
+    // Capped by the max outbound peers.
+    assert_eq!(peers.required_peers_count(), 1);
+    for (max_outbound_peers, expected) in [(2, 2), (3, 2), (4, 2), (5, 3), (8, 4)] {
+        peers.set_max_outbound_peers(max_outbound_peers);
+        assert_eq!(peers.required_peers_count(), expected);
+    }
+}
+
+#[test]
+fn stop_using_peers_with_too_many_false_positives() {
+    let peers = Peers::new(
+        1,
+        CHECK_POINT_INTERVAL,
+        (0, Default::default()),
+        0,
+        0,
+        Default::default(),
+    );
+    let peer_index = PeerIndex::new(1);
+    peers.add_peer(peer_index);
+
+    let block_hashes = (0..10)
+        .map(|_| H256(rand::random()).pack())
+        .collect::<Vec<packed::Byte32>>();
+    peers.add_matched_block_sources(peer_index, &block_hashes);
+    // The block which is downloaded from an unknown source is ignored.
+    peers.check_matched_block_source(&H256(rand::random()).pack(), true);
+    for block_hash in &block_hashes[..7] {
+        peers.check_matched_block_source(block_hash, true);
+        assert!(!peers.is_filter_unreliable(&peer_index));
+    }
+    peers.check_matched_block_source(&block_hashes[7], true);
+    assert!(peers.is_filter_unreliable(&peer_index));
+    let failure_counts = peers
+        .get_peer(&peer_index)
+        .unwrap()
+        .get_failure_counts()
+        .clone();
+    assert_eq!(
+        failure_counts.get(&StatusCode::BlockFiltersHaveTooManyFalsePositives),
+        Some(&1)
+    );
+}
+
+#[test]
+fn limit_outstanding_requests() {
+    let request_limits = RequestLimits {
+        per_peer: 2,
+        blocks_proof: 2,
+        txs_proof: 1,
+    };
+    let peers = Peers::new(
+        1,
+        CHECK_POINT_INTERVAL,
+        (0, Default::default()),
+        0,
+        0,
+        request_limits,
+    );
+
+    let peer1 = PeerIndex::new(1);
+    let peer2 = PeerIndex::new(2);
+    for peer_index in [peer1, peer2] {
+        peers.add_peer(peer_index);
+    }
+    assert_eq!(
+        peers.get_remaining_requests_count(RequestKind::TransactionsProof),
+        1
+    );
+    assert!(peers.could_request(&peer1, RequestKind::TransactionsProof));
+    assert!(!peers.could_request(&PeerIndex::new(3), RequestKind::TransactionsProof));
+
+    // Reach the global limit.
+    peers.update_txs_proof_request(peer1, Some(Default::default()));
+    assert_eq!(
+        peers.get_remaining_requests_count(RequestKind::TransactionsProof),
+        0
+    );
+    assert!(!peers.could_request(&peer1, RequestKind::TransactionsProof));
+    assert!(!peers.could_request(&peer2, RequestKind::TransactionsProof));
+
+    // Reach the limit for the peer.
+    assert!(peers.could_request(&peer1, RequestKind::BlocksProof));
+    peers.update_blocks_proof_request(peer1, Some(Default::default()), false);
+    assert!(!peers.could_request(&peer1, RequestKind::Blocks));
+    assert!(peers.could_request(&peer2, RequestKind::Blocks));
+    assert!(peers.could_request(&peer2, RequestKind::BlocksProof));
+
+    peers.update_txs_proof_request(peer1, None);
+    assert!(peers.could_request(&peer1, RequestKind::Blocks));
+    assert!(peers.could_request(&peer2, RequestKind::TransactionsProof));
+}
+
+#[test]
+fn default_outstanding_requests_per_peer_limits() {
+    let peers = Peers::new(
+        1,
+        CHECK_POINT_INTERVAL,
+        (0, Default::default()),
+        0,
+        0,
+        RequestLimits::default(),
+    );
+    let peer_index = PeerIndex::new(1);
+    peers.add_peer(peer_index);
+
+    // Each peer has at most one outstanding request for each kind, the default limit should
+    // be less than the count of the kinds.
+    peers.update_blocks_proof_request(peer_index, Some(Default::default()), false);
+    assert!(peers.could_request(&peer_index, RequestKind::TransactionsProof));
+    peers.update_txs_proof_request(peer_index, Some(Default::default()));
+    assert!(!peers.could_request(&peer_index, RequestKind::Blocks));
+}
+
+#[test]
+fn mmr_activated_epoch_from_consensus() {
+    let chain = MockChain::new_with_default_pow("test-light-client");
+    assert_eq!(mmr_activated_epoch(chain.consensus()), 5711);
+
+    let chain = MockChain::new_with_dummy_pow("test-light-client");
+    assert_eq!(mmr_activated_epoch(chain.consensus()), 0);
+}
+
+#[test]
+fn override_mmr_activated_epoch() {
+    let chain = MockChain::new_with_dummy_pow("test-light-client");
+    let new_protocol = |config: &LightClientConfig| {
+        LightClientProtocol::new(
+            chain.client_storage().to_owned(),
+            chain.create_peers(),
+            chain.consensus().to_owned(),
+            config,
+        )
+    };
+
+    let protocol = new_protocol(&Default::default());
+    assert_eq!(protocol.mmr_activated_epoch(), 0);
+
+    let config = LightClientConfig {
+        hardfork_mmr_activated_epoch: Some(10),
+        ..Default::default()
+    };
+    let protocol = new_protocol(&config);
+    assert_eq!(protocol.mmr_activated_epoch(), 10);
+}
+
 #[test]
 fn build_prove_request_content() {
     let chain = MockChain::new_with_dummy_pow("test-light-client");
@@ -161,6 +576,139 @@ fn build_prove_request_content() {
     }
 }
 
+#[test]
+fn get_peers_which_are_stale() {
+    let chain = MockChain::new_with_dummy_pow("test-light-client");
+    let peers = chain.create_peers();
+
+    let stale_peer = PeerIndex::new(1);
+    let best_peer = PeerIndex::new(2);
+    let timeout = 60 * 1000;
+
+    let build_verifiable_header = |number: BlockNumber, total_difficulty: u64| {
+        let header = HeaderBuilder::default().number(number.pack()).build();
+        let parent_chain_root = packed::HeaderDigest::new_builder()
+            .total_difficulty(U256::from(total_difficulty).pack())
+            .build();
+        VerifiableHeader::new(header, Default::default(), None, parent_chain_root)
+    };
+
+    let start_ts = unix_time_as_millis();
+    let faketime_guard = faketime();
+    faketime_guard.set_faketime(start_ts);
+
+    assert!(peers.get_best_proved_tip_number().is_none());
+    for peer_index in [stale_peer, best_peer] {
+        peers.add_peer(peer_index);
+        peers
+            .mock_prove_state(peer_index, build_verifiable_header(10, 100))
+            .unwrap();
+    }
+    // All peers have the same tip, no peer is stale.
+    assert!(peers
+        .get_peers_which_are_stale(start_ts + timeout + 1, timeout)
+        .is_empty());
+
+    let advanced_ts = start_ts + timeout / 2;
+    faketime_guard.set_faketime(advanced_ts);
+    peers
+        .mock_prove_state(best_peer, build_verifiable_header(11, 200))
+        .unwrap();
+    assert_eq!(peers.get_best_proved_tip_number(), Some(11));
+    // Not reach the timeout.
+    assert!(peers
+        .get_peers_which_are_stale(start_ts + timeout, timeout)
+        .is_empty());
+    // The peer which doesn't advance is stale.
+    assert_eq!(
+        peers.get_peers_which_are_stale(start_ts + timeout + 1, timeout),
+        vec![stale_peer]
+    );
+    // The best peer is never stale.
+    assert_eq!(
+        peers.get_peers_which_are_stale(advanced_ts + timeout * 2, timeout),
+        vec![stale_peer]
+    );
+}
+
+#[test]
+fn get_peers_which_announce_better_tips() {
+    let chain = MockChain::new_with_dummy_pow("test-light-client");
+    let peers = chain.create_peers();
+
+    let peer_index = PeerIndex::new(1);
+    let timeout = 60 * 1000;
+
+    let build_verifiable_header = |number: BlockNumber, total_difficulty: u64| {
+        let header = HeaderBuilder::default().number(number.pack()).build();
+        let parent_chain_root = packed::HeaderDigest::new_builder()
+            .total_difficulty(U256::from(total_difficulty).pack())
+            .build();
+        VerifiableHeader::new(header, Default::default(), None, parent_chain_root)
+    };
+
+    let start_ts = unix_time_as_millis();
+    let faketime_guard = faketime();
+    faketime_guard.set_faketime(start_ts);
+
+    peers.add_peer(peer_index);
+    peers
+        .mock_prove_state(peer_index, build_verifiable_header(10, 100))
+        .unwrap();
+    // The announced tip is not ahead of the proved tip.
+    peers.update_announced_tip(peer_index, 10);
+    assert!(peers
+        .get_peers_which_are_stale(start_ts + timeout + 1, timeout)
+        .is_empty());
+
+    let announced_ts = start_ts + timeout / 2;
+    faketime_guard.set_faketime(announced_ts);
+    peers.update_announced_tip(peer_index, 11);
+    assert_eq!(
+        peers
+            .get_peer(&peer_index)
+            .unwrap()
+            .get_announced_tip_number(),
+        Some(11)
+    );
+    // Not reach the timeout since the better tip is announced.
+    assert!(peers
+        .get_peers_which_are_stale(announced_ts + timeout, timeout)
+        .is_empty());
+    // The timestamp is kept when more tips are announced.
+    faketime_guard.set_faketime(announced_ts + timeout);
+    peers.update_announced_tip(peer_index, 12);
+    assert_eq!(
+        peers.get_peers_which_are_stale(announced_ts + timeout + 1, timeout),
+        vec![peer_index]
+    );
+
+    // The peer proves the announced tip.
+    peers
+        .mock_prove_state(peer_index, build_verifiable_header(12, 300))
+        .unwrap();
+    assert!(peers
+        .get_peers_which_are_stale(announced_ts + timeout * 3, timeout)
+        .is_empty());
+}
+
+#[test]
+fn take_tip_announcements() {
+    let chain = MockChain::new_with_dummy_pow("test-light-client");
+    let peers = chain.create_peers();
+
+    let peer_index = PeerIndex::new(1);
+    let unknown_peer = PeerIndex::new(2);
+    peers.add_peer(peer_index);
+
+    peers.add_tip_announcement(peer_index);
+    peers.add_tip_announcement(peer_index);
+    peers.add_tip_announcement(unknown_peer);
+    assert_eq!(peers.take_tip_announcements(), (vec![peer_index], vec![]));
+    // The announcements are consumed.
+    assert_eq!(peers.take_tip_announcements(), (vec![], vec![]));
+}
+
 #[tokio::test]
 async fn test_light_client_get_idle_matched_blocks() {
     let chain = MockChain::new_with_dummy_pow("test-light-client");
@@ -232,6 +780,70 @@ async fn test_light_client_get_idle_matched_blocks() {
     );
 }
 
+#[tokio::test]
+async fn test_light_client_coalesce_fetching_headers() {
+    let chain = MockChain::new_with_dummy_pow("test-light-client");
+    let nc = MockNetworkContext::new(SupportProtocols::LightClient);
+
+    let peer_index = PeerIndex::new(3);
+    let tip_header = VerifiableHeader::new(
+        HeaderBuilder::default()
+            .epoch(EpochNumberWithFraction::new(0, 0, 100).full_value().pack())
+            .number(3u64.pack())
+            .build(),
+        Default::default(),
+        None,
+        Default::default(),
+    );
+    chain
+        .client_storage()
+        .update_last_state(&U256::one(), &tip_header.header().data(), &[]);
+    let tip_hash = tip_header.header().hash();
+    let peers = {
+        let peers = chain.create_peers();
+        peers.add_peer(peer_index);
+        peers.mock_prove_state(peer_index, tip_header).unwrap();
+        peers
+    };
+    let unproved_block_hash = H256(rand::random()).pack();
+    let fetching_header_hash = H256(rand::random()).pack();
+    {
+        let mut matched_blocks = peers.matched_blocks().write().expect("poisoned");
+        peers.add_matched_blocks(
+            &mut matched_blocks,
+            vec![(unproved_block_hash.clone(), false)],
+        );
+    }
+    peers.add_fetch_header(fetching_header_hash.clone(), 111);
+
+    let mut protocol = chain.create_light_client_protocol(Arc::clone(&peers));
+    protocol.notify(nc.context(), GET_IDLE_BLOCKS_TOKEN).await;
+
+    let content = packed::GetBlocksProof::new_builder()
+        .block_hashes(vec![unproved_block_hash.clone(), fetching_header_hash.clone()].pack())
+        .last_hash(tip_hash)
+        .build();
+    let get_blocks_proof_message = packed::LightClientMessage::new_builder()
+        .set(content)
+        .build()
+        .as_bytes();
+    assert_eq!(
+        nc.sent_messages().borrow().clone(),
+        vec![(
+            SupportProtocols::LightClient.protocol_id(),
+            peer_index,
+            get_blocks_proof_message,
+        )]
+    );
+
+    let peer = peers.get_peer(&peer_index).expect("has peer");
+    let request = peer.get_blocks_proof_request().expect("has request");
+    assert!(request.should_get_blocks());
+    assert!(!request.is_header_only(&unproved_block_hash));
+    assert!(request.is_header_only(&fetching_header_hash));
+    assert!(peers.get_headers_to_fetch().is_empty());
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn refresh_all_peers() {
     setup();
@@ -528,3 +528,21 @@ async fn test_send_headers_txs_request() {
     assert_eq!(peers.get_txs_to_fetch().len(), 1);
     assert_eq!(peers.get_headers_to_fetch().len(), 1);
 }
+
+#[test]
+fn test_fetching_txs_missing_until_all_peers_respond() {
+    let chain = MockChain::new_with_dummy_pow("test-send-txs");
+    let peers = chain.create_peers();
+    let peer_index_1 = PeerIndex::new(1);
+    let peer_index_2 = PeerIndex::new(2);
+    let tx_hash = h256!("0xbb33").pack();
+
+    peers.add_fetch_tx(tx_hash.clone(), 111);
+    peers.fetching_idle_txs(&[tx_hash.clone()], &[peer_index_1, peer_index_2], 222);
+
+    peers.mark_fetching_txs_missing(peer_index_1, &[tx_hash.clone()]);
+    assert!(!peers.fetching_txs().get(&tx_hash).unwrap().missing());
+
+    peers.mark_fetching_txs_missing(peer_index_2, &[tx_hash.clone()]);
+    assert!(peers.fetching_txs().get(&tx_hash).unwrap().missing());
+}
@@ -1,3 +1,4 @@
 mod block_filter;
 mod light_client;
+mod relayer;
 mod synchronizer;
@@ -0,0 +1,567 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use ckb_chain_spec::consensus::Consensus;
+use ckb_network::{CKBProtocolHandler, PeerId, PeerIndex, SupportProtocols};
+use ckb_types::{
+    core::{capacity_bytes, BlockBuilder, Capacity, TransactionBuilder},
+    h256,
+    packed::{self, CellInput, CellOutput, OutPoint, Script},
+    prelude::*,
+};
+
+use crate::protocols::{PendingTxs, RelayProtocol};
+use crate::storage::{ScriptStatus, ScriptType};
+use crate::tests::utils::{create_peers, new_storage, MockNetworkContext};
+use crate::types::{LightClientConfig, PendingTxsEviction};
+
+// The count of the pending transactions, the count of the rebroadcasts, and the counts of the
+// transactions which are committed, expired or replaced.
+fn counts(pending_txs: &PendingTxs) -> (usize, u64, u64, u64, u64) {
+    let counts = pending_txs.get_counts();
+    (
+        counts.pending,
+        counts.rebroadcasted,
+        counts.committed,
+        counts.expired,
+        counts.replaced,
+    )
+}
+
+#[test]
+fn test_pending_txs_rebroadcast() {
+    let mut pending_txs = PendingTxs::new(64);
+    let tx1 = TransactionBuilder::default().version(1.pack()).build();
+    let tx2 = TransactionBuilder::default().version(2.pack()).build();
+    let tx3 = TransactionBuilder::default().version(3.pack()).build();
+    pending_txs.push(tx1.clone(), 1, 1000);
+    pending_txs.push(tx2.clone(), 2, 1000);
+    let now = Instant::now();
+
+    // Not rebroadcast until the initial interval is elapsed.
+    assert_eq!(pending_txs.check_rebroadcast(now, |_| false).0, 0);
+    let now = now + Duration::from_secs(30);
+    assert_eq!(pending_txs.check_rebroadcast(now, |_| false).0, 2);
+    // The interval is doubled.
+    let now = now + Duration::from_secs(30);
+    assert_eq!(pending_txs.check_rebroadcast(now, |_| false).0, 0);
+    let now = now + Duration::from_secs(30);
+    assert_eq!(pending_txs.check_rebroadcast(now, |_| false).0, 2);
+    assert_eq!(counts(&pending_txs), (2, 4, 0, 0, 0));
+
+    // The committed transactions are removed.
+    let tx1_hash = tx1.hash();
+    pending_txs.check_rebroadcast(now, |hash| hash == &tx1_hash);
+    assert!(pending_txs.get(&tx1.hash()).is_none());
+    assert_eq!(counts(&pending_txs), (1, 4, 1, 0, 0));
+
+    // The expired transactions are removed.
+    pending_txs.push(tx3.clone(), 3, 1000);
+    let now = Instant::now() + Duration::from_secs(24 * 60 * 60);
+    let (_, expired) = pending_txs.check_rebroadcast(now, |_| false);
+    assert_eq!(expired, vec![tx2.hash(), tx3.hash()]);
+    assert!(pending_txs.get(&tx2.hash()).is_none());
+    assert!(pending_txs.get(&tx3.hash()).is_none());
+    assert_eq!(counts(&pending_txs), (0, 4, 1, 2, 0));
+}
+
+#[test]
+fn test_pending_txs_broadcast_fanout() {
+    let mut pending_txs = PendingTxs::new(64);
+    let tx = TransactionBuilder::default().build();
+    pending_txs.push(tx.clone(), 1, 1000);
+    let peer1 = PeerId::random();
+    let peer2 = PeerId::random();
+
+    assert_eq!(
+        pending_txs.fetch_transaction_hashes_for_broadcast(peer1.clone(), 1),
+        vec![tx.hash()]
+    );
+    // The fan-out is reached.
+    assert!(pending_txs
+        .fetch_transaction_hashes_for_broadcast(peer2.clone(), 1)
+        .is_empty());
+    // Not announced to the same peer twice.
+    assert!(pending_txs
+        .fetch_transaction_hashes_for_broadcast(peer1, 0)
+        .is_empty());
+    // Zero means all peers.
+    assert_eq!(
+        pending_txs.fetch_transaction_hashes_for_broadcast(peer2, 0),
+        vec![tx.hash()]
+    );
+}
+
+#[test]
+fn test_pending_txs_seen() {
+    let mut pending_txs = PendingTxs::new(64);
+    let tx = TransactionBuilder::default().build();
+    assert!(!pending_txs.contains(&tx.hash()));
+
+    pending_txs.add_seen(tx.data());
+    assert!(pending_txs.contains(&tx.hash()));
+    assert_eq!(pending_txs.get_seen(&tx.hash()), Some(tx.data()));
+    // The seen transactions are not relayed.
+    assert!(pending_txs.get(&tx.hash()).is_none());
+
+    // The committed seen transactions are removed.
+    let tx_hash = tx.hash();
+    pending_txs.check_rebroadcast(Instant::now(), |hash| hash == &tx_hash);
+    assert!(!pending_txs.contains(&tx.hash()));
+    assert_eq!(counts(&pending_txs), (0, 0, 0, 0, 0));
+}
+
+#[test]
+fn test_pending_txs_relay_stats() {
+    let mut pending_txs = PendingTxs::new(64);
+    let tx = TransactionBuilder::default().build();
+    pending_txs.push(tx.clone(), 1, 1000);
+    let stats = pending_txs.get_relay_stats();
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].0, tx.hash());
+    assert_eq!(stats[0].1, Default::default());
+
+    pending_txs.fetch_transaction_hashes_for_broadcast(PeerId::random(), 0);
+    pending_txs.fetch_transaction_hashes_for_broadcast(PeerId::random(), 0);
+    pending_txs.add_requested(&tx.hash());
+    // Not counted for the unknown transactions.
+    pending_txs.add_requested(
+        &TransactionBuilder::default()
+            .version(1.pack())
+            .build()
+            .hash(),
+    );
+
+    let stats = pending_txs.get_relay_stats();
+    assert_eq!(stats.len(), 1);
+    let (tx_hash, stats) = stats[0].clone();
+    assert_eq!(tx_hash, tx.hash());
+    assert_eq!(stats.announced, 2);
+    assert_eq!(stats.requested, 1);
+    assert!(stats.announced_at.is_some());
+}
+
+#[test]
+fn test_pending_txs_conflicts() {
+    let mut pending_txs = PendingTxs::new(64);
+    let input = packed::CellInput::new(packed::OutPoint::new(Default::default(), 0), 0);
+    let other_input = packed::CellInput::new(packed::OutPoint::new(Default::default(), 1), 0);
+    let tx1 = TransactionBuilder::default().input(input.clone()).build();
+    let tx2 = TransactionBuilder::default().input(other_input).build();
+    pending_txs.push(tx1.clone(), 1, 1000);
+    pending_txs.push(tx2, 1, 2000);
+
+    let tx3 = TransactionBuilder::default()
+        .input(input)
+        .version(1.pack())
+        .build();
+    assert_eq!(pending_txs.get_conflicts(&tx3), vec![(tx1.hash(), 1000)]);
+
+    assert_eq!(pending_txs.remove_replaced(&[tx1.hash()]), vec![tx1.hash()]);
+    pending_txs.push(tx3.clone(), 1, 1500);
+    assert!(pending_txs.get(&tx1.hash()).is_none());
+    assert!(pending_txs.get(&tx3.hash()).is_some());
+    assert_eq!(counts(&pending_txs), (2, 0, 0, 0, 1));
+}
+
+#[test]
+fn test_pending_txs_eviction() {
+    let txs = (0..4u32)
+        .map(|version| {
+            TransactionBuilder::default()
+                .version(version.pack())
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    let mut pending_txs = PendingTxs::new(2);
+    assert!(pending_txs.push(txs[0].clone(), 1, 2000).is_empty());
+    assert!(pending_txs.push(txs[1].clone(), 1, 1000).is_empty());
+    assert_eq!(
+        pending_txs.push(txs[2].clone(), 1, 500),
+        vec![txs[0].hash()]
+    );
+
+    let mut pending_txs = PendingTxs::new(2);
+    pending_txs.set_eviction(PendingTxsEviction::LowestFeeRate);
+    pending_txs.push(txs[0].clone(), 1, 2000);
+    pending_txs.push(txs[1].clone(), 1, 1000);
+    assert_eq!(
+        pending_txs.push(txs[2].clone(), 1, 3000),
+        vec![txs[1].hash()]
+    );
+    // The transaction which is just pushed is never evicted.
+    assert_eq!(
+        pending_txs.push(txs[3].clone(), 1, 500),
+        vec![txs[0].hash()]
+    );
+    assert!(pending_txs.get(&txs[3].hash()).is_some());
+}
+
+#[test]
+fn test_pending_txs_ttl() {
+    let mut pending_txs = PendingTxs::new(64);
+    pending_txs.set_ttl(Duration::from_secs(60));
+    let tx = TransactionBuilder::default().build();
+    pending_txs.push(tx.clone(), 1, 1000);
+
+    let (_, expired) = pending_txs.check_rebroadcast(Instant::now(), |_| false);
+    assert!(expired.is_empty());
+    let now = Instant::now() + Duration::from_secs(60);
+    let (_, expired) = pending_txs.check_rebroadcast(now, |_| false);
+    assert_eq!(expired, vec![tx.hash()]);
+}
+
+#[test]
+fn test_pending_txs_chained() {
+    let parent = TransactionBuilder::default()
+        .output(Default::default())
+        .output_data(Default::default())
+        .build();
+    let child = TransactionBuilder::default()
+        .input(packed::CellInput::new(
+            packed::OutPoint::new(parent.hash(), 0),
+            0,
+        ))
+        .build();
+    let other = TransactionBuilder::default().version(1.pack()).build();
+
+    // The parent is announced along with the child, even if its fan-out is reached.
+    let mut pending_txs = PendingTxs::new(64);
+    pending_txs.push(parent.clone(), 1, 1000);
+    assert_eq!(
+        pending_txs.fetch_transaction_hashes_for_broadcast(PeerId::random(), 1),
+        vec![parent.hash()]
+    );
+    pending_txs.push(child.clone(), 1, 1000);
+    assert_eq!(
+        pending_txs.fetch_transaction_hashes_for_broadcast(PeerId::random(), 1),
+        vec![parent.hash(), child.hash()]
+    );
+
+    // The descendants are removed along with the replaced transaction.
+    pending_txs.push(other.clone(), 1, 1000);
+    assert_eq!(
+        pending_txs.remove_replaced(&[parent.hash()]),
+        vec![parent.hash(), child.hash()]
+    );
+    assert!(pending_txs.get(&child.hash()).is_none());
+    assert!(pending_txs.get(&other.hash()).is_some());
+
+    // The descendants are evicted along with the evicted transaction.
+    let mut pending_txs = PendingTxs::new(2);
+    pending_txs.push(parent.clone(), 1, 1000);
+    pending_txs.push(child.clone(), 1, 1000);
+    assert_eq!(
+        pending_txs.push(other.clone(), 1, 1000),
+        vec![parent.hash(), child.hash()]
+    );
+}
+
+#[test]
+fn test_pending_txs_commit_block() {
+    let mut pending_txs = PendingTxs::new(64);
+    let tx1 = TransactionBuilder::default().version(1.pack()).build();
+    let tx2 = TransactionBuilder::default().version(2.pack()).build();
+    let tx3 = TransactionBuilder::default().version(3.pack()).build();
+    pending_txs.push(tx1.clone(), 1, 1000);
+    pending_txs.push(tx2.clone(), 2, 1000);
+    pending_txs.add_seen(tx3.data());
+
+    let block = BlockBuilder::default()
+        .transaction(tx1.clone())
+        .transaction(tx3.clone())
+        .build();
+    assert_eq!(
+        pending_txs.commit_block(&block.data()),
+        vec![tx1.hash(), tx3.hash()]
+    );
+    assert!(pending_txs.get(&tx1.hash()).is_none());
+    assert!(!pending_txs.contains(&tx3.hash()));
+    assert!(pending_txs.get(&tx2.hash()).is_some());
+    assert_eq!(
+        pending_txs.get_committed(&tx1.hash()),
+        Some((tx1.data(), block.hash()))
+    );
+    assert!(pending_txs.get_committed(&tx2.hash()).is_none());
+    assert_eq!(counts(&pending_txs), (1, 0, 1, 0, 0));
+}
+
+#[test]
+fn test_pending_txs_relay_delay() {
+    let mut pending_txs = PendingTxs::new(64);
+    pending_txs.set_max_relay_delay(Duration::from_secs(60 * 60));
+    // The delay is chosen randomly, it's hardly less than 1 second.
+    let tx = TransactionBuilder::default().build();
+    pending_txs.push(tx.clone(), 1, 1000);
+    let peer = PeerId::random();
+    assert!(pending_txs
+        .fetch_transaction_hashes_for_broadcast(peer.clone(), 0)
+        .is_empty());
+
+    // Not delayed when the max delay is zero.
+    pending_txs.set_max_relay_delay(Duration::ZERO);
+    let tx = TransactionBuilder::default().version(1.pack()).build();
+    pending_txs.push(tx.clone(), 1, 1000);
+    assert_eq!(
+        pending_txs.fetch_transaction_hashes_for_broadcast(peer, 0),
+        vec![tx.hash()]
+    );
+}
+
+#[test]
+fn test_pending_txs_propose_block() {
+    let mut pending_txs = PendingTxs::new(64);
+    let tx1 = TransactionBuilder::default().version(1.pack()).build();
+    let tx2 = TransactionBuilder::default().version(2.pack()).build();
+    let tx3 = TransactionBuilder::default().version(3.pack()).build();
+    pending_txs.push(tx1.clone(), 1, 1000);
+    pending_txs.push(tx2.clone(), 2, 1000);
+    pending_txs.push(tx3.clone(), 3, 1000);
+
+    let uncle = BlockBuilder::default()
+        .proposal(tx2.proposal_short_id())
+        .build()
+        .as_uncle();
+    let block = BlockBuilder::default()
+        .proposal(tx1.proposal_short_id())
+        .uncle(uncle)
+        .build();
+    assert_eq!(
+        pending_txs.propose_block(&block.data()),
+        vec![tx1.hash(), tx2.hash()]
+    );
+    assert_eq!(pending_txs.get_proposed(&tx1.hash()), Some(block.hash()));
+    assert!(pending_txs.get_proposed(&tx3.hash()).is_none());
+    // Not proposed twice.
+    assert!(pending_txs.propose_block(&block.data()).is_empty());
+
+    // The committed transactions are not proposed anymore.
+    let block = BlockBuilder::default().transaction(tx1.clone()).build();
+    pending_txs.commit_block(&block.data());
+    assert!(pending_txs.get_proposed(&tx1.hash()).is_none());
+}
+
+#[test]
+fn test_pending_txs_verifying() {
+    let mut pending_txs = PendingTxs::new(64);
+    let tx1 = TransactionBuilder::default().build();
+    let tx2 = TransactionBuilder::default().version(1.pack()).build();
+
+    assert!(pending_txs.start_verifying(tx1.data()).unwrap());
+    assert!(pending_txs.start_verifying(tx2.data()).unwrap());
+    // Already verifying.
+    assert!(!pending_txs.start_verifying(tx1.data()).unwrap());
+    assert_eq!(pending_txs.get_verifying(&tx1.hash()), Some(tx1.data()));
+
+    pending_txs.push(tx1.clone(), 1, 1000);
+    pending_txs.finish_verifying(&tx1.hash(), None);
+    pending_txs.finish_verifying(&tx2.hash(), Some("invalid".to_owned()));
+    assert!(pending_txs.get_verifying(&tx1.hash()).is_none());
+    assert!(pending_txs.get_rejected(&tx1.hash()).is_none());
+    assert_eq!(
+        pending_txs.get_rejected(&tx2.hash()),
+        Some((tx2.data(), "invalid".to_owned()))
+    );
+    // Already pending.
+    assert!(!pending_txs.start_verifying(tx1.data()).unwrap());
+    // Submitted again after rejected.
+    assert!(pending_txs.start_verifying(tx2.data()).unwrap());
+    assert!(pending_txs.get_rejected(&tx2.hash()).is_none());
+}
+
+#[test]
+fn test_pending_txs_verifying_limit() {
+    let mut pending_txs = PendingTxs::new(2);
+    let tx1 = TransactionBuilder::default().version(1.pack()).build();
+    let tx2 = TransactionBuilder::default().version(2.pack()).build();
+    let tx3 = TransactionBuilder::default().version(3.pack()).build();
+
+    assert!(pending_txs.start_verifying(tx1.data()).unwrap());
+    assert!(pending_txs.start_verifying(tx2.data()).unwrap());
+    // Too many transactions are being verified.
+    assert!(pending_txs.start_verifying(tx3.data()).is_err());
+    // The verifying ones are still accepted as duplicates.
+    assert!(!pending_txs.start_verifying(tx1.data()).unwrap());
+
+    pending_txs.finish_verifying(&tx1.hash(), None);
+    assert!(pending_txs.start_verifying(tx3.data()).unwrap());
+}
+
+#[tokio::test]
+async fn test_relayed_txs_without_pending_txs() {
+    let storage = new_storage("relayed-txs-without-pending-txs");
+    let script = Script::new_builder()
+        .code_hash(h256!("0x1").pack())
+        .args(b"relayed".to_vec().pack())
+        .build();
+    storage.update_filter_scripts(
+        vec![ScriptStatus {
+            script: script.clone(),
+            script_type: ScriptType::Lock,
+            block_number: 0,
+        }],
+        Default::default(),
+    );
+    let peer_index = PeerIndex::new(1);
+    let peers = create_peers();
+    peers.add_peer(peer_index);
+    let pending_txs = Arc::new(RwLock::new(PendingTxs::new(64)));
+    let tx = TransactionBuilder::default()
+        .input(CellInput::new(OutPoint::new(h256!("0x2").pack(), 0), 0))
+        .output(
+            CellOutput::new_builder()
+                .capacity(capacity_bytes!(100).pack())
+                .lock(script)
+                .build(),
+        )
+        .output_data(Default::default())
+        .build();
+    let content = packed::RelayTransactionHashes::new_builder()
+        .tx_hashes(vec![tx.hash()].pack())
+        .build();
+    let message = packed::RelayMessage::new_builder().set(content).build();
+
+    // The relayed transactions are not tracked by default.
+    {
+        let mut protocol = RelayProtocol::new(
+            Arc::clone(&pending_txs),
+            Arc::clone(&peers),
+            Consensus::default(),
+            storage.clone(),
+            true,
+            &LightClientConfig::default(),
+        );
+        let nc = MockNetworkContext::new(SupportProtocols::RelayV3);
+        protocol
+            .received(nc.context(), peer_index, message.as_bytes())
+            .await;
+        assert!(nc.sent_messages().borrow().is_empty());
+    }
+
+    let config = LightClientConfig {
+        track_relayed_transactions: true,
+        ..Default::default()
+    };
+    let mut protocol = RelayProtocol::new(
+        Arc::clone(&pending_txs),
+        peers,
+        Consensus::default(),
+        storage,
+        true,
+        &config,
+    );
+    let nc = MockNetworkContext::new(SupportProtocols::RelayV3);
+
+    // Nothing is submitted, the relayed transactions are still requested.
+    protocol
+        .received(nc.context(), peer_index, message.as_bytes())
+        .await;
+    assert!(nc.not_banned(peer_index));
+    assert_eq!(nc.sent_messages().borrow().len(), 1);
+    {
+        let data = &nc.sent_messages().borrow()[0].2;
+        let message = packed::RelayMessageReader::new_unchecked(data);
+        let content = if let packed::RelayMessageUnionReader::GetRelayTransactions(content) =
+            message.to_enum()
+        {
+            content
+        } else {
+            panic!("unexpected message");
+        };
+        assert_eq!(content.tx_hashes().to_entity(), vec![tx.hash()].pack());
+    }
+
+    let content = packed::RelayTransactions::new_builder()
+        .transactions(
+            vec![packed::RelayTransaction::new_builder()
+                .transaction(tx.data())
+                .cycles(1000u64.pack())
+                .build()]
+            .pack(),
+        )
+        .build();
+    let message = packed::RelayMessage::new_builder().set(content).build();
+    protocol
+        .received(nc.context(), peer_index, message.as_bytes())
+        .await;
+    assert_eq!(
+        pending_txs.read().unwrap().get_seen(&tx.hash()),
+        Some(tx.data())
+    );
+    assert!(nc.not_banned(peer_index));
+}
+
+#[tokio::test]
+async fn test_relayed_tx_hashes_budget_per_peer() {
+    let storage = new_storage("relayed-tx-hashes-budget-per-peer");
+    storage.update_filter_scripts(
+        vec![ScriptStatus {
+            script: Script::default(),
+            script_type: ScriptType::Lock,
+            block_number: 0,
+        }],
+        Default::default(),
+    );
+    let peer_index = PeerIndex::new(1);
+    let peers = create_peers();
+    peers.add_peer(peer_index);
+    let config = LightClientConfig {
+        track_relayed_transactions: true,
+        ..Default::default()
+    };
+    let mut protocol = RelayProtocol::new(
+        Arc::new(RwLock::new(PendingTxs::new(64))),
+        peers,
+        Consensus::default(),
+        storage,
+        true,
+        &config,
+    );
+    let nc = MockNetworkContext::new(SupportProtocols::RelayV3);
+    let relay_tx_hashes = |versions: std::ops::Range<u32>| {
+        let tx_hashes = versions
+            .map(|version| {
+                TransactionBuilder::default()
+                    .version(version.pack())
+                    .build()
+                    .hash()
+            })
+            .collect::<Vec<_>>();
+        let content = packed::RelayTransactionHashes::new_builder()
+            .tx_hashes(tx_hashes.pack())
+            .build();
+        packed::RelayMessage::new_builder().set(content).build()
+    };
+
+    // Only a part of the relayed hashes are requested in an interval.
+    protocol
+        .received(nc.context(), peer_index, relay_tx_hashes(0..100).as_bytes())
+        .await;
+    assert_eq!(nc.sent_messages().borrow().len(), 1);
+    {
+        let data = &nc.sent_messages().borrow()[0].2;
+        let message = packed::RelayMessageReader::new_unchecked(data);
+        let content = if let packed::RelayMessageUnionReader::GetRelayTransactions(content) =
+            message.to_enum()
+        {
+            content
+        } else {
+            panic!("unexpected message");
+        };
+        assert_eq!(content.tx_hashes().len(), 64);
+    }
+
+    // The budget is used up.
+    protocol
+        .received(
+            nc.context(),
+            peer_index,
+            relay_tx_hashes(100..110).as_bytes(),
+        )
+        .await;
+    assert_eq!(nc.sent_messages().borrow().len(), 1);
+    assert!(nc.not_banned(peer_index));
+}
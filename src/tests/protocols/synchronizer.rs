@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use ckb_network::{CKBProtocolHandler, PeerIndex, SupportProtocols};
+use ckb_network::{bytes::Bytes, CKBProtocolHandler, PeerIndex, SupportProtocols};
 use ckb_types::{
     core::BlockBuilder,
     packed::{self, Script},
@@ -8,6 +8,7 @@ use ckb_types::{
 };
 
 use crate::{
+    protocols::StatusCode,
     storage::{ScriptStatus, ScriptType},
     tests::{
         prelude::*,
@@ -86,3 +87,114 @@ async fn test_sync_add_block() {
     assert_eq!(storage_filtered_block_number, filtered_block_number);
     assert!(nc.sent_messages().borrow().is_empty());
 }
+
+#[tokio::test]
+async fn test_sync_respond_get_headers() {
+    let chain = MockChain::new_with_dummy_pow("test-sync");
+    let nc = MockNetworkContext::new(SupportProtocols::Sync);
+    let peer_index = PeerIndex::new(3);
+    let peers = chain.create_peers();
+    peers.add_peer(peer_index);
+    let mut protocol = chain.create_sync_protocol(Arc::clone(&peers));
+
+    let message = {
+        let content = packed::GetHeaders::new_builder().build();
+        packed::SyncMessage::new_builder()
+            .set(content)
+            .build()
+            .as_bytes()
+    };
+    protocol.received(nc.context(), peer_index, message).await;
+    assert!(nc.not_banned(peer_index));
+    {
+        let sent_messages = nc.sent_messages().borrow();
+        assert_eq!(sent_messages.len(), 1);
+        let message = packed::SyncMessage::from_slice(&sent_messages[0].2).unwrap();
+        match message.to_enum() {
+            packed::SyncMessageUnion::SendHeaders(content) => {
+                assert!(content.headers().is_empty());
+            }
+            _ => panic!("unexpected message"),
+        }
+    }
+
+    // Don't respond the InIBD messages.
+    let message = {
+        let content = packed::InIBD::new_builder().build();
+        packed::SyncMessage::new_builder()
+            .set(content)
+            .build()
+            .as_bytes()
+    };
+    protocol.received(nc.context(), peer_index, message).await;
+    assert_eq!(nc.sent_messages().borrow().len(), 1);
+
+    let counts = peers
+        .get_peer(&peer_index)
+        .unwrap()
+        .get_message_counts()
+        .get(&SupportProtocols::Sync.protocol_id())
+        .cloned()
+        .unwrap();
+    assert_eq!(counts.received_messages, 2);
+    assert_eq!(counts.sent_messages, 1);
+    assert_eq!(
+        counts.sent_bytes,
+        nc.sent_messages().borrow()[0].2.len() as u64
+    );
+}
+
+#[tokio::test]
+async fn test_sync_unrequested_blocks() {
+    let chain = MockChain::new_with_dummy_pow("test-sync");
+    let nc = MockNetworkContext::new(SupportProtocols::Sync);
+    let peer_index = PeerIndex::new(3);
+    nc.connect_peer(peer_index);
+    let peers = chain.create_peers();
+    peers.add_peer(peer_index);
+    let mut protocol = chain.create_sync_protocol(Arc::clone(&peers));
+
+    let message = {
+        let content = packed::SendBlock::new_builder()
+            .block(BlockBuilder::default().build().data())
+            .build();
+        packed::SyncMessage::new_builder()
+            .set(content)
+            .build()
+            .as_bytes()
+    };
+    for _ in 0..15 {
+        protocol
+            .received(nc.context(), peer_index, message.clone())
+            .await;
+    }
+    assert!(nc.not_banned(peer_index));
+    assert_eq!(nc.context().connected_peers(), vec![peer_index]);
+    assert_eq!(
+        peers
+            .get_peer(&peer_index)
+            .unwrap()
+            .get_failure_counts()
+            .get(&StatusCode::Ignore),
+        Some(&15)
+    );
+
+    // Disconnect the peer which sends too many unrequested blocks.
+    protocol.received(nc.context(), peer_index, message).await;
+    assert!(nc.not_banned(peer_index));
+    assert!(nc.context().connected_peers().is_empty());
+}
+
+#[tokio::test]
+async fn test_sync_malformed_message() {
+    let chain = MockChain::new_with_dummy_pow("test-sync");
+    let nc = MockNetworkContext::new(SupportProtocols::Sync);
+    let peer_index = PeerIndex::new(3);
+    let peers = chain.create_peers();
+    peers.add_peer(peer_index);
+    let mut protocol = chain.create_sync_protocol(Arc::clone(&peers));
+
+    let data = Bytes::from(vec![2, 3, 4, 5]);
+    protocol.received(nc.context(), peer_index, data).await;
+    assert!(nc.has_banned(peer_index).is_some());
+}
@@ -1,4 +1,12 @@
-use ckb_types::packed::Script;
+use std::sync::Arc;
+
+use ckb_chain_spec::consensus::Consensus;
+use ckb_types::{
+    core::{cell::CellMeta, BlockBuilder, HeaderBuilder, TransactionBuilder},
+    packed::{self, Block, CellOutput, Script},
+    prelude::*,
+    U256,
+};
 
 use crate::storage;
 use crate::tests::utils::new_storage;
@@ -24,3 +32,428 @@ fn test_forget_update_min_filtred_number() {
     );
     assert_eq!(storage.get_min_filtered_block_number(), 33);
 }
+
+#[test]
+fn test_add_and_get_block() {
+    let storage = new_storage("add_and_get_block");
+    let block: Block = BlockBuilder::default().number(10.pack()).build().data();
+    let block_hash = block.calc_header_hash();
+    assert!(storage.get_block(&block_hash).is_none());
+    storage.add_block(&block);
+    let stored = storage.get_block(&block_hash).expect("stored block");
+    assert_eq!(stored.as_slice(), block.as_slice());
+}
+
+#[test]
+fn test_rollback_deletes_stored_blocks_on_fork() {
+    let storage = new_storage("rollback_deletes_stored_blocks_on_fork");
+    let build_block = |number: u64, timestamp: u64| -> Block {
+        BlockBuilder::default()
+            .number(number.pack())
+            .timestamp(timestamp.pack())
+            .build()
+            .data()
+    };
+    let kept = build_block(10, 0);
+    let forked = vec![build_block(11, 0), build_block(11, 1), build_block(12, 0)];
+    storage.add_block(&kept);
+    for block in &forked {
+        storage.add_block(block);
+    }
+
+    // A reorg which rolls back to block#11.
+    storage.rollback_to_block(11);
+    assert!(storage.get_block(&kept.calc_header_hash()).is_some());
+    for block in &forked {
+        assert!(storage.get_block(&block.calc_header_hash()).is_none());
+    }
+}
+
+#[test]
+fn test_prove_request_is_cleared_when_last_state_updated() {
+    let storage = new_storage("prove_request_is_cleared");
+    assert!(storage.get_prove_request().is_none());
+
+    let content = packed::GetLastStateProof::new_builder()
+        .start_number(5.pack())
+        .last_n_blocks(10.pack())
+        .build();
+    storage.update_prove_request(&content, true, false);
+    let (stored, skip_check_tau, long_fork_detected) =
+        storage.get_prove_request().expect("stored prove request");
+    assert_eq!(stored.as_slice(), content.as_slice());
+    assert!(skip_check_tau);
+    assert!(!long_fork_detected);
+
+    let header = HeaderBuilder::default().number(20.pack()).build();
+    storage.update_last_state(&U256::one(), &header.data(), &[]);
+    assert!(storage.get_prove_request().is_none());
+}
+
+#[test]
+fn test_filter_block_discards_false_positives() {
+    let storage = new_storage("filter_block_discards_false_positives");
+    let script = Script::new_builder().args(vec![1u8].pack()).build();
+    let other_script = Script::new_builder().args(vec![2u8].pack()).build();
+    storage.update_filter_scripts(
+        vec![storage::ScriptStatus {
+            script: script.clone(),
+            script_type: storage::ScriptType::Lock,
+            block_number: 1,
+        }],
+        Default::default(),
+    );
+    let build_block = |number: u64, lock: &Script| {
+        let tx = TransactionBuilder::default()
+            .output(CellOutput::new_builder().lock(lock.clone()).build())
+            .output_data(Default::default())
+            .build();
+        let block = BlockBuilder::default()
+            .number(number.pack())
+            .transaction(tx.clone())
+            .build();
+        (block.data(), tx.hash())
+    };
+
+    let (block, tx_hash) = build_block(10, &other_script);
+    assert!(!storage.filter_block(block));
+    assert!(storage.get_transaction_with_header(&tx_hash).is_none());
+
+    let (block, tx_hash) = build_block(11, &script);
+    assert!(storage.filter_block(block));
+    assert!(storage.get_transaction_with_header(&tx_hash).is_some());
+}
+
+#[test]
+fn test_filter_block_returns_matched_cells() {
+    let storage = new_storage("filter_block_returns_matched_cells");
+    let script = Script::new_builder().args(vec![1u8].pack()).build();
+    storage.update_filter_scripts(
+        vec![storage::ScriptStatus {
+            script: script.clone(),
+            script_type: storage::ScriptType::Lock,
+            block_number: 1,
+        }],
+        Default::default(),
+    );
+    let output = CellOutput::new_builder().lock(script).build();
+    let tx1 = TransactionBuilder::default()
+        .output(output.clone())
+        .output_data(Default::default())
+        .build();
+    let out_point = packed::OutPoint::new(tx1.hash(), 0);
+    let tx2 = TransactionBuilder::default()
+        .input(packed::CellInput::new(out_point.clone(), 0))
+        .output(CellOutput::default())
+        .output_data(Default::default())
+        .build();
+    let block = BlockBuilder::default()
+        .number(10.pack())
+        .transaction(tx1.clone())
+        .transaction(tx2.clone())
+        .build();
+
+    let filtered = storage.filter_block_cells(block.data());
+    let expected = storage::MatchedCell {
+        out_point,
+        output,
+        script_type: storage::ScriptType::Lock,
+        tx_hash: tx1.hash(),
+    };
+    assert_eq!(filtered.created, vec![expected.clone()]);
+    let expected = storage::MatchedCell {
+        tx_hash: tx2.hash(),
+        ..expected
+    };
+    assert_eq!(filtered.consumed, vec![expected]);
+}
+
+#[test]
+fn test_filter_block_with_transaction_matcher() {
+    struct MinCapacity(u64);
+    impl storage::TransactionMatcher for MinCapacity {
+        fn is_matched(&self, tx: &packed::Transaction, _block_number: u64) -> bool {
+            tx.raw().outputs().into_iter().any(|output| {
+                let capacity: u64 = output.capacity().unpack();
+                capacity >= self.0
+            })
+        }
+    }
+
+    let mut storage = new_storage("filter_block_with_transaction_matcher");
+    storage.set_transaction_matcher(Arc::new(MinCapacity(100)));
+    let script = Script::new_builder().args(vec![1u8].pack()).build();
+    storage.update_filter_scripts(
+        vec![storage::ScriptStatus {
+            script: script.clone(),
+            script_type: storage::ScriptType::Lock,
+            block_number: 1,
+        }],
+        Default::default(),
+    );
+    let build_tx = |input: Option<&packed::Transaction>, capacity: u64| {
+        let builder = TransactionBuilder::default();
+        let builder = if let Some(tx) = input {
+            builder.input(packed::CellInput::new(
+                packed::OutPoint::new(tx.calc_tx_hash(), 0),
+                0,
+            ))
+        } else {
+            builder
+        };
+        builder
+            .output(
+                CellOutput::new_builder()
+                    .lock(script.clone())
+                    .capacity(capacity.pack())
+                    .build(),
+            )
+            .output_data(Default::default())
+            .build()
+            .data()
+    };
+    let build_block = |number: u64, tx: &packed::Transaction| {
+        BlockBuilder::default()
+            .number(number.pack())
+            .transaction(tx.clone().into_view())
+            .build()
+            .data()
+    };
+
+    let matched_tx = build_tx(None, 100);
+    let filtered = storage.filter_block_cells(build_block(10, &matched_tx));
+    assert_eq!(filtered.created.len(), 1);
+
+    // The consumption is indexed, but the created cell is skipped.
+    let skipped_tx = build_tx(Some(&matched_tx), 99);
+    let filtered = storage.filter_block_cells(build_block(11, &skipped_tx));
+    assert!(filtered.created.is_empty());
+    assert_eq!(filtered.consumed.len(), 1);
+    assert!(storage
+        .get_transaction_with_header(&skipped_tx.calc_tx_hash())
+        .is_some());
+
+    // The consumption of the skipped cell isn't indexed.
+    let spending_tx = build_tx(Some(&skipped_tx), 99);
+    let filtered = storage.filter_block_cells(build_block(12, &spending_tx));
+    assert!(filtered.is_empty());
+    assert!(storage
+        .get_transaction_with_header(&spending_tx.calc_tx_hash())
+        .is_none());
+}
+
+#[test]
+fn test_is_related_transaction() {
+    let storage = new_storage("is_related_transaction");
+    let script = Script::new_builder().args(vec![1u8].pack()).build();
+    let other_script = Script::new_builder().args(vec![2u8].pack()).build();
+    let build_tx = |lock: &Script| {
+        TransactionBuilder::default()
+            .output(CellOutput::new_builder().lock(lock.clone()).build())
+            .output_data(Default::default())
+            .build()
+    };
+
+    let tx = build_tx(&script);
+    assert!(!storage.is_related_transaction(&tx.data()));
+
+    storage.update_filter_scripts(
+        vec![storage::ScriptStatus {
+            script: script.clone(),
+            script_type: storage::ScriptType::Lock,
+            block_number: 1,
+        }],
+        Default::default(),
+    );
+    assert!(storage.is_related_transaction(&tx.data()));
+    assert!(!storage.is_related_transaction(&build_tx(&other_script).data()));
+
+    // Spends a cell of the filter scripts.
+    let block = BlockBuilder::default()
+        .number(10u64.pack())
+        .transaction(tx.clone())
+        .build();
+    assert!(storage.filter_block(block.data()));
+    let spending_tx = TransactionBuilder::default()
+        .input(packed::CellInput::new(
+            packed::OutPoint::new(tx.hash(), 0),
+            0,
+        ))
+        .output(CellOutput::new_builder().lock(other_script).build())
+        .output_data(Default::default())
+        .build();
+    assert!(storage.is_related_transaction(&spending_tx.data()));
+}
+
+#[test]
+fn test_reset_chain_data_keeps_filter_scripts() {
+    let storage = new_storage("reset_chain_data");
+    let genesis = Consensus::default().genesis_block().data();
+    storage.init_genesis_block(genesis.clone());
+    storage.update_filter_scripts(
+        vec![
+            storage::ScriptStatus {
+                script: Script::default(),
+                script_type: storage::ScriptType::Lock,
+                block_number: 30,
+            },
+            storage::ScriptStatus {
+                script: Script::default(),
+                script_type: storage::ScriptType::Type,
+                block_number: 5,
+            },
+        ],
+        Default::default(),
+    );
+    let block: Block = BlockBuilder::default().number(10.pack()).build().data();
+    storage.add_block(&block);
+    storage.update_min_filtered_block_number(100);
+
+    storage.reset_chain_data(genesis.clone(), 20).unwrap();
+
+    assert!(storage.get_block(&block.calc_header_hash()).is_none());
+    assert_eq!(
+        storage.get_genesis_block().as_slice(),
+        genesis.as_slice(),
+        "the storage should be initialized with the genesis block again"
+    );
+    assert_eq!(
+        storage.get_tip_header().as_slice(),
+        genesis.header().as_slice()
+    );
+    let mut block_numbers = storage
+        .get_filter_scripts()
+        .into_iter()
+        .map(|ss| ss.block_number)
+        .collect::<Vec<_>>();
+    block_numbers.sort_unstable();
+    assert_eq!(block_numbers, vec![5, 20]);
+    assert_eq!(storage.get_min_filtered_block_number(), 5);
+}
+
+#[test]
+fn test_reset_chain_data_then_reopen() {
+    let dir = tempfile::tempdir().unwrap();
+    let genesis = Consensus::default().genesis_block().data();
+    let script = Script::new_builder().args(vec![1u8].pack()).build();
+    {
+        let storage = storage::Storage::open(dir.path()).unwrap();
+        storage.init_genesis_block(genesis.clone());
+        storage.update_filter_scripts(
+            vec![storage::ScriptStatus {
+                script: script.clone(),
+                script_type: storage::ScriptType::Lock,
+                block_number: 30,
+            }],
+            Default::default(),
+        );
+
+        // Not shut down cleanly.
+        storage.mark_running();
+        assert!(storage.reset_chain_data(genesis.clone(), 20).is_err());
+        assert_eq!(storage.get_filter_scripts()[0].block_number, 30);
+
+        storage.clear_running_marker();
+        storage.reset_chain_data(genesis.clone(), 20).unwrap();
+        storage.flush().unwrap();
+    }
+
+    let storage = storage::Storage::open(dir.path()).unwrap();
+    assert!(!storage.mark_running());
+    assert!(storage.verify_integrity().is_ok());
+    assert_eq!(storage.get_genesis_hash(), Some(genesis.calc_header_hash()));
+    let scripts = storage.get_filter_scripts();
+    assert_eq!(scripts.len(), 1);
+    assert_eq!(scripts[0].script, script);
+    assert_eq!(scripts[0].block_number, 20);
+}
+
+#[test]
+fn test_recover_from_unclean_shutdown() {
+    let storage = new_storage("recover_from_unclean_shutdown");
+    assert!(storage.verify_integrity().is_err());
+    storage.init_genesis_block(Consensus::default().genesis_block().data());
+    assert!(storage.verify_integrity().is_ok());
+
+    assert!(!storage.mark_running());
+    assert!(storage.mark_running());
+    storage.clear_running_marker();
+    assert!(!storage.mark_running());
+
+    let script = Script::new_builder().args(vec![1u8].pack()).build();
+    storage.update_filter_scripts(
+        vec![storage::ScriptStatus {
+            script: script.clone(),
+            script_type: storage::ScriptType::Lock,
+            block_number: 9,
+        }],
+        Default::default(),
+    );
+    let tx = TransactionBuilder::default()
+        .output(CellOutput::new_builder().lock(script.clone()).build())
+        .output_data(Default::default())
+        .build();
+    let block = BlockBuilder::default()
+        .number(11.pack())
+        .transaction(tx)
+        .build()
+        .data();
+    storage.add_matched_blocks(10, 5, vec![(block.calc_header_hash(), true)]);
+
+    // Interrupted after the block is applied.
+    assert!(storage.filter_block(block.clone()));
+    let cell_key = storage::Key::CellLockScript(&script, 11, 0, 0).into_vec();
+    assert!(storage.db.get(&cell_key).unwrap().is_some());
+    assert_eq!(storage.rollback_partially_applied_blocks(), Some(10));
+    assert!(storage.db.get(&cell_key).unwrap().is_none());
+    assert!(storage.get_earliest_matched_blocks().is_some());
+
+    // Interrupted after the block numbers of the scripts are updated.
+    assert!(storage.filter_block(block));
+    storage.update_block_number(14);
+    assert_eq!(storage.rollback_partially_applied_blocks(), None);
+    assert!(storage.db.get(&cell_key).unwrap().is_some());
+    assert!(storage.get_earliest_matched_blocks().is_none());
+}
+
+#[test]
+fn cache_cell_deps() {
+    let storage = new_storage("cache_cell_deps");
+
+    let cell_meta = |index: u32| {
+        let out_point = packed::OutPoint::new(Default::default(), index);
+        CellMeta {
+            out_point,
+            cell_output: Default::default(),
+            transaction_info: None,
+            data_bytes: 0,
+            mem_cell_data: None,
+            mem_cell_data_hash: None,
+        }
+    };
+    for index in 0..100 {
+        storage.cache_cell_dep(cell_meta(index), false);
+    }
+    // The earliest cells are evicted.
+    assert!(storage
+        .get_cached_cell_dep(&cell_meta(0).out_point, false)
+        .is_none());
+    assert_eq!(
+        storage.get_cached_cell_dep(&cell_meta(99).out_point, false),
+        Some(cell_meta(99))
+    );
+    // The cells which are loaded without their data aren't used for the eager loads.
+    assert!(storage
+        .get_cached_cell_dep(&cell_meta(99).out_point, true)
+        .is_none());
+
+    // The clones share the cache, and it's cleared when the chain is rolled back.
+    let cloned = storage.clone();
+    assert!(cloned
+        .get_cached_cell_dep(&cell_meta(99).out_point, false)
+        .is_some());
+    storage.rollback_to_block(1);
+    assert!(cloned
+        .get_cached_cell_dep(&cell_meta(99).out_point, false)
+        .is_none());
+}
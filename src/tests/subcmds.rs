@@ -0,0 +1,31 @@
+use crate::{subcmds::check_instances_conflicts, types::RunEnv};
+
+#[test]
+fn instances_conflicts() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = include_str!("../../config/testnet.toml");
+    let mut instance = RunEnv::from_str_with_overrides(config, std::iter::empty()).unwrap();
+    instance.store.path = dir.path().join("testnet/store");
+    instance.network.path = dir.path().join("testnet/network");
+    let mut other = instance.clone();
+    other.store.path = dir.path().join("mainnet/store");
+    other.network.path = dir.path().join("mainnet/network");
+    other.network.listen_addresses = vec!["/ip4/0.0.0.0/tcp/8119".parse().unwrap()];
+    other.rpc.listen_address = "127.0.0.1:9001".to_owned();
+    check_instances_conflicts(&[instance.clone(), other.clone()]).unwrap();
+
+    // The same path in different spellings.
+    let mut conflicted = other.clone();
+    conflicted.store.path = dir.path().join("mainnet/../testnet/./store");
+    let err = check_instances_conflicts(&[instance.clone(), conflicted]).unwrap_err();
+    assert!(err.to_string().contains("store.path"), "{}", err);
+
+    let mut conflicted = other;
+    conflicted.network.listen_addresses = instance.network.listen_addresses.clone();
+    let err = check_instances_conflicts(&[instance, conflicted]).unwrap_err();
+    assert!(
+        err.to_string().contains("network.listen_addresses"),
+        "{}",
+        err
+    );
+}
@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use ckb_types::{h256, prelude::*};
+
+use crate::{
+    protocols::ChainEvent,
+    tests::utils::{create_peers, new_storage},
+    trusted_node::TrustedNodeChecker,
+};
+
+#[test]
+fn check_proved_tip_with_trusted_node() {
+    let storage = new_storage("trusted-node");
+    let peers = create_peers();
+    let mut events = peers.subscribe_chain_events();
+    let checker = TrustedNodeChecker::new(
+        "http://127.0.0.1:8114".to_owned(),
+        Duration::from_secs(60),
+        storage,
+        peers.clone(),
+    );
+
+    let hash = h256!("0xaa").pack();
+    let another_hash = h256!("0xbb").pack();
+
+    // Skip when the trusted node doesn't have the header.
+    checker.check(10, &hash, None);
+    assert!(!peers.is_trusted_node_diverged());
+
+    checker.check(10, &hash, Some(hash.clone()));
+    assert!(!peers.is_trusted_node_diverged());
+    assert!(events.try_recv().is_err());
+
+    checker.check(11, &hash, Some(another_hash.clone()));
+    assert!(peers.is_trusted_node_diverged());
+    assert_eq!(
+        events.try_recv().unwrap(),
+        ChainEvent::TrustedNodeDiverged {
+            number: 11,
+            hash: hash.clone(),
+            trusted_hash: another_hash.clone(),
+        }
+    );
+
+    // Only emit the event when the proved tip starts to diverge.
+    checker.check(12, &hash, Some(another_hash));
+    assert!(peers.is_trusted_node_diverged());
+    assert!(events.try_recv().is_err());
+
+    checker.check(13, &hash, Some(hash.clone()));
+    assert!(!peers.is_trusted_node_diverged());
+}
@@ -0,0 +1,193 @@
+use ckb_chain_spec::ChainSpec;
+use ckb_resource::Resource;
+
+use crate::types::{ClientVersion, LightClientConfig, MultiRunEnv, RunEnv};
+
+#[test]
+fn parse_client_version() {
+    let expected: ClientVersion = "0.113.0".parse().unwrap();
+    for s in [
+        "0.113.0",
+        "v0.113.0",
+        "0.113.0-rc1",
+        "0.113.0 (e9c3b0f 2023-12-08)",
+    ] {
+        assert_eq!(s.parse::<ClientVersion>().unwrap(), expected);
+    }
+    for s in ["", "0.113", "0.113.0.1", "x.y.z"] {
+        assert!(s.parse::<ClientVersion>().is_err());
+    }
+
+    let older: ClientVersion = "0.111.2".parse().unwrap();
+    let newer: ClientVersion = "0.113.1".parse().unwrap();
+    assert!(older < expected);
+    assert!(expected < newer);
+    assert_eq!(expected.to_string(), "0.113.0");
+}
+
+#[test]
+fn apply_hardfork_overrides() {
+    let load_spec = |resource: Resource| ChainSpec::load_from(&resource).unwrap();
+    let dev_spec = || {
+        load_spec(Resource::file_system(
+            "src/tests/specs/dummy_pow.toml".into(),
+        ))
+    };
+    let mut config = LightClientConfig::default();
+
+    // No overrides.
+    let mut chain_spec = dev_spec();
+    chain_spec.params.hardfork = None;
+    config.apply_hardfork_overrides(&mut chain_spec).unwrap();
+    assert!(chain_spec.params.hardfork.is_none());
+
+    config.hardfork_ckb2023_epoch = Some(10);
+    config.apply_hardfork_overrides(&mut chain_spec).unwrap();
+    assert_eq!(
+        chain_spec.params.hardfork.as_ref().unwrap().ckb2023,
+        Some(10)
+    );
+
+    // Conflicts with the chain spec, which sets the activation epoch to 2^32.
+    let mut chain_spec = dev_spec();
+    assert!(config.apply_hardfork_overrides(&mut chain_spec).is_err());
+    config.hardfork_ckb2023_epoch = Some(1 << 32);
+    config.apply_hardfork_overrides(&mut chain_spec).unwrap();
+
+    // The MMR activation epoch isn't a field of the chain spec.
+    let mut config = LightClientConfig {
+        hardfork_mmr_activated_epoch: Some(10),
+        ..Default::default()
+    };
+    let mut chain_spec = dev_spec();
+    config.apply_hardfork_overrides(&mut chain_spec).unwrap();
+    config.hardfork_mmr_activated_epoch = Some(1 << 24);
+    assert!(config.apply_hardfork_overrides(&mut chain_spec).is_err());
+    config.hardfork_mmr_activated_epoch = Some(10);
+
+    // Not allowed for the public chains.
+    let mut chain_spec = load_spec(Resource::bundled("specs/testnet.toml".to_owned()));
+    assert!(config.apply_hardfork_overrides(&mut chain_spec).is_err());
+}
+
+#[test]
+fn validate_light_client_config() {
+    LightClientConfig::default().validate().unwrap();
+
+    let invalid: Vec<fn(&mut LightClientConfig)> = vec![
+        |config| config.stale_peer_timeout_secs = 0,
+        |config| config.stale_peer_timeout_secs = 29,
+        |config| config.matched_blocks_in_transit_per_peer = 0,
+        |config| config.max_outstanding_requests_per_peer = 0,
+        |config| config.block_filters_batch_size = 0,
+        |config| config.block_filters_batch_size = 1001,
+        |config| config.pending_txs_limit = 0,
+        |config| config.max_tx_verify_cycles = Some(0),
+        |config| config.relay_transaction_max_delay_ms = 60 * 1000 + 1,
+        |config| config.trusted_node_rpc_url = Some("https://127.0.0.1:8114".to_owned()),
+    ];
+    for update in invalid {
+        let mut config = LightClientConfig::default();
+        update(&mut config);
+        assert!(config.validate().is_err());
+    }
+
+    let config = LightClientConfig {
+        trusted_node_rpc_url: Some("http://127.0.0.1:8114".to_owned()),
+        ..Default::default()
+    };
+    config.validate().unwrap();
+}
+
+#[test]
+fn override_config_by_env_vars() {
+    let config = include_str!("../../config/testnet.toml");
+    let vars = [
+        ("CKB_LC_RPC__LISTEN_ADDRESS", "0.0.0.0:9000"),
+        ("CKB_LC_STORE__PATH", "/var/lib/light-client/store"),
+        ("CKB_LC_LIGHT_CLIENT__MAX_DOWNLOAD_RATE", "1024"),
+        ("CKB_LC_LIGHT_CLIENT__PAUSE_FILTER_SYNC", "true"),
+        ("CKB_LC_LOGGER__MODULES__CKB_LIGHT_CLIENT", "debug"),
+        ("OTHER_RPC__LISTEN_ADDRESS", "ignored"),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_owned(), value.to_owned()));
+    let run_env = RunEnv::from_str_with_overrides(config, vars).unwrap();
+    assert_eq!(run_env.chain, "testnet");
+    assert_eq!(run_env.rpc.listen_address, "0.0.0.0:9000");
+    assert_eq!(
+        run_env.store.path.to_str(),
+        Some("/var/lib/light-client/store")
+    );
+    assert_eq!(run_env.light_client.max_download_rate, 1024);
+    assert!(run_env.light_client.pause_filter_sync);
+    assert_eq!(
+        run_env.logger.modules.get("ckb_light_client"),
+        Some(&"debug".to_owned())
+    );
+
+    let vars = [("CKB_LC_RPC__LISTEN_ADDRESS__PORT", "9000")]
+        .into_iter()
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+    assert!(RunEnv::from_str_with_overrides(config, vars).is_err());
+}
+
+#[test]
+fn parse_multiple_instances() {
+    let config = include_str!("../../config/testnet.toml");
+    let no_vars = || std::iter::empty::<(String, String)>();
+
+    let multi = MultiRunEnv::from_str_with_overrides(config, no_vars()).unwrap();
+    assert_eq!(multi.instances.len(), 1);
+    assert_eq!(multi.instances[0].chain, "testnet");
+
+    let mut instance: toml::Value = toml::from_str(config).unwrap();
+    let logger = instance.as_table_mut().unwrap().remove("logger").unwrap();
+    let mut other = instance.clone();
+    other["chain"] = "mainnet".into();
+    other["rpc"]["listen_address"] = "127.0.0.1:9001".into();
+    let mut document = toml::value::Table::new();
+    document.insert("instances".to_owned(), vec![instance, other].into());
+    let config = toml::to_string(&document).unwrap();
+    let multi = MultiRunEnv::from_str_with_overrides(&config, no_vars()).unwrap();
+    assert_eq!(multi.instances.len(), 2);
+    assert_eq!(multi.instances[0].chain, "testnet");
+    assert_eq!(multi.instances[1].chain, "mainnet");
+    assert_eq!(multi.instances[1].rpc.listen_address, "127.0.0.1:9001");
+
+    assert!(MultiRunEnv::from_str_with_overrides("instances = []", no_vars()).is_err());
+
+    // The logger is shared by the instances, it's not allowed in them.
+    other["logger"] = logger;
+    let mut document = toml::value::Table::new();
+    document.insert("instances".to_owned(), vec![instance, other].into());
+    let config = toml::to_string(&document).unwrap();
+    let err = MultiRunEnv::from_str_with_overrides(&config, no_vars()).unwrap_err();
+    assert!(
+        err.starts_with("instances[1].logger is not allowed"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn parse_tracing_config() {
+    let config = include_str!("../../config/testnet.toml");
+    let vars = [
+        ("CKB_LC_TRACING__OTLP_ENDPOINT", "http://127.0.0.1:4317"),
+        ("CKB_LC_TRACING__SAMPLE_RATIO", "0.5"),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_owned(), value.to_owned()));
+    let multi = MultiRunEnv::from_str_with_overrides(config, vars).unwrap();
+    assert_eq!(
+        multi.tracing.otlp_endpoint.as_deref(),
+        Some("http://127.0.0.1:4317")
+    );
+    assert_eq!(multi.tracing.service_name, "ckb-light-client");
+    assert_eq!(multi.tracing.sample_ratio, 0.5);
+
+    let mut tracing = multi.tracing;
+    tracing.sample_ratio = 2.0;
+    assert!(crate::telemetry::init(&tracing).is_err());
+}
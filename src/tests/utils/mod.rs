@@ -4,10 +4,9 @@ use env_logger::{Builder, Target};
 use log::LevelFilter;
 
 mod chain;
-mod network_context;
 
+pub(crate) use crate::test_support::MockNetworkContext;
 pub(crate) use chain::MockChain;
-pub(crate) use network_context::MockNetworkContext;
 
 use crate::{protocols::Peers, protocols::CHECK_POINT_INTERVAL, storage::Storage};
 
@@ -32,6 +31,9 @@ pub(crate) fn create_peers() -> Arc<Peers> {
         max_outbound_peers,
         CHECK_POINT_INTERVAL,
         (0, Default::default()),
+        0,
+        0,
+        Default::default(),
     );
     Arc::new(peers)
 }
@@ -1,14 +1,26 @@
 use std::sync::Arc;
 
 use ckb_jsonrpc_types::{Block, Script, Transaction};
-use ckb_types::{packed, prelude::IntoTransactionView as _};
+use ckb_script::ScriptGroupType;
+use ckb_types::{
+    bytes::Bytes,
+    core::{EpochNumberWithFraction, HeaderBuilder},
+    packed,
+    prelude::*,
+    U256,
+};
 
 use crate::{
     storage::{ScriptStatus, ScriptType, StorageWithChainData},
     tests::{prelude::*, utils::MockChain},
-    verify::verify_tx,
+    verify::{
+        diagnose_script_failure, tx_verify_env, verify_tx, verify_tx_with_debug_output,
+        verify_tx_without_scripts, TxVerifyContext,
+    },
 };
 
+const MAX_CYCLES: u64 = 70_000_000;
+
 #[test]
 fn verify_valid_transaction() {
     let chain = MockChain::new_with_default_pow("verify_valid_transaction");
@@ -33,12 +45,130 @@ fn verify_valid_transaction() {
 
     let swc =
         StorageWithChainData::new(storage.to_owned(), chain.create_peers(), Default::default());
-    let result = verify_tx(transaction.into_view(), &swc, consensus).unwrap();
+    // The cycles exceed the limit.
+    let error = verify_tx(
+        transaction.clone().into_view(),
+        &swc,
+        Arc::clone(&consensus),
+        1_000_000,
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("ExceededMaximumCycles"));
+    let result = verify_tx(transaction.into_view(), &swc, consensus, MAX_CYCLES).unwrap();
     // please note that the cycle (1682789) of this transaction displayed on the explorer is wrong
     // it's fixed in https://github.com/nervosnetwork/ckb/pull/4218
     assert_eq!(1691692, result);
 }
 
+#[test]
+fn diagnose_invalid_signature() {
+    let chain = MockChain::new_with_default_pow("diagnose_invalid_signature");
+    let storage = chain.client_storage();
+    let consensus = Arc::new(chain.consensus().clone());
+
+    // https://pudge.explorer.nervos.org/address/ckt1qzda0cr08m85hc8jlnfp3zer7xulejywt49kt2rr0vthywaa50xwsq0l2z2v9305wm7rs5gqrpsf507ey8wj3tggtl4sj
+    let script: packed::Script = serde_json::from_str::<Script>(r#"{"code_hash": "0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8","hash_type": "type","args": "0xff5094c2c5f476fc38510018609a3fd921dd28ad"}"#).unwrap().into();
+    let scripts = vec![ScriptStatus {
+        script,
+        script_type: ScriptType::Lock,
+        block_number: 0,
+    }];
+    storage.update_filter_scripts(scripts, Default::default());
+
+    // https://pudge.explorer.nervos.org/block/261
+    let block: packed::Block = serde_json::from_str::<Block>(r#"{"header":{"compact_target":"0x1e015555","dao":"0x18f067d6835aa12e81d52889fd862300aa4aa421700c0000003ef78768fcfe06","epoch":"0x3e80105000000","extra_hash":"0x0000000000000000000000000000000000000000000000000000000000000000","nonce":"0x32daf82076f991d5b69674ed257385eb","number":"0x105","parent_hash":"0xe883cd26172309608574ab5e7fad5dbdb4c82d6dcbac407f3d81b4b50f46f513","proposals_hash":"0x0000000000000000000000000000000000000000000000000000000000000000","timestamp":"0x1723baeb815","transactions_root":"0xf7250b8db808b34d96276a5b146a93b14372ff58abe4eb8927c6955446bca748","version":"0x0"},"proposals":[],"transactions":[{"cell_deps":[],"header_deps":[],"inputs":[{"previous_output":{"index":"0xffffffff","tx_hash":"0x0000000000000000000000000000000000000000000000000000000000000000"},"since":"0x105"}],"outputs":[{"capacity":"0x2ecbd5b8aa","lock":{"args":"0xda648442dbb7347e467d1d09da13e5cd3a0ef0e1","code_hash":"0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8","hash_type":"type"},"type":null}],"outputs_data":["0x"],"version":"0x0","witnesses":["0x5d0000000c00000055000000490000001000000030000000310000009bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce80114000000da648442dbb7347e467d1d09da13e5cd3a0ef0e104000000deadbeef"]},{"cell_deps":[{"dep_type":"dep_group","out_point":{"index":"0x0","tx_hash":"0xf8de3bb47d055cdf460d93a2a6e1b05f7432f9777c8c474abf4eec1d4aee5d37"}}],"header_deps":[],"inputs":[{"previous_output":{"index":"0x7","tx_hash":"0x8f8c79eb6671709633fe6a46de93c0fedc9c1b8a6527a18d3983879542635c9f"},"since":"0x0"}],"outputs":[{"capacity":"0x470de4df820000","lock":{"args":"0xff5094c2c5f476fc38510018609a3fd921dd28ad","code_hash":"0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8","hash_type":"type"},"type":null},{"capacity":"0xb61134e5a35e800","lock":{"args":"0x64257f00b6b63e987609fa9be2d0c86d351020fb","code_hash":"0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8","hash_type":"type"},"type":null}],"outputs_data":["0x","0x"],"version":"0x0","witnesses":["0x5500000010000000550000005500000041000000af34b54bebf8c5971da6a880f2df5a186c3f8d0b5c9a1fe1a90c95b8a4fb89ef3bab1ccec13797dcb3fee80400f953227dd7741227e08032e3598e16ccdaa49c00"]}],"uncles":[]}"#).unwrap().into();
+    storage.filter_block(block);
+
+    // https://pudge.explorer.nervos.org/transaction/0xf34f4eaac4a662927fb52d4cb608e603150b9e0678a0f5ed941e3cfd5b68fb30
+    // The last byte of the signature is modified.
+    let transaction: packed::Transaction = serde_json::from_str::<Transaction>(r#"{"cell_deps":[{"dep_type":"dep_group","out_point":{"index":"0x0","tx_hash":"0xf8de3bb47d055cdf460d93a2a6e1b05f7432f9777c8c474abf4eec1d4aee5d37"}}],"header_deps":[],"inputs":[{"previous_output":{"index":"0x7","tx_hash":"0x8f8c79eb6671709633fe6a46de93c0fedc9c1b8a6527a18d3983879542635c9f"},"since":"0x0"}],"outputs":[{"capacity":"0x470de4df820000","lock":{"args":"0xff5094c2c5f476fc38510018609a3fd921dd28ad","code_hash":"0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8","hash_type":"type"},"type":null},{"capacity":"0xb61134e5a35e800","lock":{"args":"0x64257f00b6b63e987609fa9be2d0c86d351020fb","code_hash":"0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8","hash_type":"type"},"type":null}],"outputs_data":["0x","0x"],"version":"0x0","witnesses":["0x5500000010000000550000005500000041000000af34b54bebf8c5971da6a880f2df5a186c3f8d0b5c9a1fe1a90c95b8a4fb89ef3bab1ccec13797dcb3fee80400f953227dd7741227e08032e3598e16ccdaa49c01"]}"#).unwrap().into();
+
+    let swc =
+        StorageWithChainData::new(storage.to_owned(), chain.create_peers(), Default::default());
+    let error = verify_tx(
+        transaction.clone().into_view(),
+        &swc,
+        Arc::clone(&consensus),
+        MAX_CYCLES,
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), ckb_error::ErrorKind::Script);
+    // The scripts are skipped.
+    assert!(verify_tx_without_scripts(
+        transaction.clone().into_view(),
+        &swc,
+        Arc::clone(&consensus)
+    )
+    .is_ok());
+
+    let failure = diagnose_script_failure(
+        transaction.into_view(),
+        &swc,
+        consensus,
+        MAX_CYCLES,
+        &TxVerifyContext::Tip,
+    )
+    .unwrap();
+    assert_eq!(failure.group_type, ScriptGroupType::Lock);
+    assert_eq!(failure.cell_index, 0);
+    assert!(failure.is_input);
+    assert!(failure.exit_code.is_some());
+    assert!(failure.cycles.is_none());
+}
+
+#[test]
+fn estimate_with_placeholder_signature() {
+    let chain = MockChain::new_with_default_pow("estimate_with_placeholder_signature");
+    let storage = chain.client_storage();
+    let consensus = Arc::new(chain.consensus().clone());
+
+    // https://pudge.explorer.nervos.org/address/ckt1qzda0cr08m85hc8jlnfp3zer7xulejywt49kt2rr0vthywaa50xwsq0l2z2v9305wm7rs5gqrpsf507ey8wj3tggtl4sj
+    let script: packed::Script = serde_json::from_str::<Script>(r#"{"code_hash": "0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8","hash_type": "type","args": "0xff5094c2c5f476fc38510018609a3fd921dd28ad"}"#).unwrap().into();
+    let scripts = vec![ScriptStatus {
+        script,
+        script_type: ScriptType::Lock,
+        block_number: 0,
+    }];
+    storage.update_filter_scripts(scripts, Default::default());
+
+    // https://pudge.explorer.nervos.org/block/261
+    let block: packed::Block = serde_json::from_str::<Block>(r#"{"header":{"compact_target":"0x1e015555","dao":"0x18f067d6835aa12e81d52889fd862300aa4aa421700c0000003ef78768fcfe06","epoch":"0x3e80105000000","extra_hash":"0x0000000000000000000000000000000000000000000000000000000000000000","nonce":"0x32daf82076f991d5b69674ed257385eb","number":"0x105","parent_hash":"0xe883cd26172309608574ab5e7fad5dbdb4c82d6dcbac407f3d81b4b50f46f513","proposals_hash":"0x0000000000000000000000000000000000000000000000000000000000000000","timestamp":"0x1723baeb815","transactions_root":"0xf7250b8db808b34d96276a5b146a93b14372ff58abe4eb8927c6955446bca748","version":"0x0"},"proposals":[],"transactions":[{"cell_deps":[],"header_deps":[],"inputs":[{"previous_output":{"index":"0xffffffff","tx_hash":"0x0000000000000000000000000000000000000000000000000000000000000000"},"since":"0x105"}],"outputs":[{"capacity":"0x2ecbd5b8aa","lock":{"args":"0xda648442dbb7347e467d1d09da13e5cd3a0ef0e1","code_hash":"0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8","hash_type":"type"},"type":null}],"outputs_data":["0x"],"version":"0x0","witnesses":["0x5d0000000c00000055000000490000001000000030000000310000009bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce80114000000da648442dbb7347e467d1d09da13e5cd3a0ef0e104000000deadbeef"]},{"cell_deps":[{"dep_type":"dep_group","out_point":{"index":"0x0","tx_hash":"0xf8de3bb47d055cdf460d93a2a6e1b05f7432f9777c8c474abf4eec1d4aee5d37"}}],"header_deps":[],"inputs":[{"previous_output":{"index":"0x7","tx_hash":"0x8f8c79eb6671709633fe6a46de93c0fedc9c1b8a6527a18d3983879542635c9f"},"since":"0x0"}],"outputs":[{"capacity":"0x470de4df820000","lock":{"args":"0xff5094c2c5f476fc38510018609a3fd921dd28ad","code_hash":"0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8","hash_type":"type"},"type":null},{"capacity":"0xb61134e5a35e800","lock":{"args":"0x64257f00b6b63e987609fa9be2d0c86d351020fb","code_hash":"0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8","hash_type":"type"},"type":null}],"outputs_data":["0x","0x"],"version":"0x0","witnesses":["0x5500000010000000550000005500000041000000af34b54bebf8c5971da6a880f2df5a186c3f8d0b5c9a1fe1a90c95b8a4fb89ef3bab1ccec13797dcb3fee80400f953227dd7741227e08032e3598e16ccdaa49c00"]}],"uncles":[]}"#).unwrap().into();
+    storage.filter_block(block);
+
+    // https://pudge.explorer.nervos.org/transaction/0xf34f4eaac4a662927fb52d4cb608e603150b9e0678a0f5ed941e3cfd5b68fb30
+    let transaction: packed::Transaction = serde_json::from_str::<Transaction>(r#"{"cell_deps":[{"dep_type":"dep_group","out_point":{"index":"0x0","tx_hash":"0xf8de3bb47d055cdf460d93a2a6e1b05f7432f9777c8c474abf4eec1d4aee5d37"}}],"header_deps":[],"inputs":[{"previous_output":{"index":"0x7","tx_hash":"0x8f8c79eb6671709633fe6a46de93c0fedc9c1b8a6527a18d3983879542635c9f"},"since":"0x0"}],"outputs":[{"capacity":"0x470de4df820000","lock":{"args":"0xff5094c2c5f476fc38510018609a3fd921dd28ad","code_hash":"0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8","hash_type":"type"},"type":null},{"capacity":"0xb61134e5a35e800","lock":{"args":"0x64257f00b6b63e987609fa9be2d0c86d351020fb","code_hash":"0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8","hash_type":"type"},"type":null}],"outputs_data":["0x","0x"],"version":"0x0","witnesses":["0x5500000010000000550000005500000041000000af34b54bebf8c5971da6a880f2df5a186c3f8d0b5c9a1fe1a90c95b8a4fb89ef3bab1ccec13797dcb3fee80400f953227dd7741227e08032e3598e16ccdaa49c01"]}"#).unwrap().into();
+
+    let swc =
+        StorageWithChainData::new(storage.to_owned(), chain.create_peers(), Default::default());
+    let witness = packed::WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let transaction = transaction
+        .into_view()
+        .as_advanced_builder()
+        .set_witnesses(vec![witness.as_bytes().pack()])
+        .build();
+
+    let (result, _) = verify_tx_with_debug_output(
+        transaction.clone(),
+        &swc,
+        Arc::clone(&consensus),
+        MAX_CYCLES,
+        &TxVerifyContext::Tip,
+        false,
+    );
+    assert_eq!(result.unwrap_err().kind(), ckb_error::ErrorKind::Script);
+    let (result, _) = verify_tx_with_debug_output(
+        transaction,
+        &swc,
+        consensus,
+        MAX_CYCLES,
+        &TxVerifyContext::Tip,
+        true,
+    );
+    assert!(result.unwrap() > 0);
+}
+
 #[test]
 fn non_contextual_transaction_verifier() {
     let chain = MockChain::new_with_default_pow("non_contextual_transaction_verifier");
@@ -50,11 +180,46 @@ fn non_contextual_transaction_verifier() {
     // duplicate cell deps base on a valid transaction
     // https://pudge.explorer.nervos.org/transaction/0xf34f4eaac4a662927fb52d4cb608e603150b9e0678a0f5ed941e3cfd5b68fb30
     let transaction: packed::Transaction = serde_json::from_str::<Transaction>(r#"{"cell_deps":[{"dep_type":"dep_group","out_point":{"index":"0x0","tx_hash":"0xf8de3bb47d055cdf460d93a2a6e1b05f7432f9777c8c474abf4eec1d4aee5d37"}}, {"dep_type":"dep_group","out_point":{"index":"0x0","tx_hash":"0xf8de3bb47d055cdf460d93a2a6e1b05f7432f9777c8c474abf4eec1d4aee5d37"}}],"header_deps":[],"inputs":[{"previous_output":{"index":"0x7","tx_hash":"0x8f8c79eb6671709633fe6a46de93c0fedc9c1b8a6527a18d3983879542635c9f"},"since":"0x0"}],"outputs":[{"capacity":"0x470de4df820000","lock":{"args":"0xff5094c2c5f476fc38510018609a3fd921dd28ad","code_hash":"0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8","hash_type":"type"},"type":null},{"capacity":"0xb61134e5a35e800","lock":{"args":"0x64257f00b6b63e987609fa9be2d0c86d351020fb","code_hash":"0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8","hash_type":"type"},"type":null}],"outputs_data":["0x","0x"],"version":"0x0","witnesses":["0x5500000010000000550000005500000041000000af34b54bebf8c5971da6a880f2df5a186c3f8d0b5c9a1fe1a90c95b8a4fb89ef3bab1ccec13797dcb3fee80400f953227dd7741227e08032e3598e16ccdaa49c00"]}"#).unwrap().into();
-    let error = verify_tx(transaction.into_view(), &swc, Arc::clone(&consensus)).unwrap_err();
+    let error = verify_tx(
+        transaction.into_view(),
+        &swc,
+        Arc::clone(&consensus),
+        MAX_CYCLES,
+    )
+    .unwrap_err();
     assert!(error.to_string().contains("DuplicateCellDeps"));
 
     // insufficient cell capacity
     let transaction: packed::Transaction = serde_json::from_str::<Transaction>(r#"{"cell_deps":[{"dep_type":"dep_group","out_point":{"index":"0x0","tx_hash":"0xf8de3bb47d055cdf460d93a2a6e1b05f7432f9777c8c474abf4eec1d4aee5d37"}}],"header_deps":[],"inputs":[{"previous_output":{"index":"0x7","tx_hash":"0x8f8c79eb6671709633fe6a46de93c0fedc9c1b8a6527a18d3983879542635c9f"},"since":"0x0"}],"outputs":[{"capacity":"0x470de4df820000","lock":{"args":"0xff5094c2c5f476fc38510018609a3fd921dd28ad","code_hash":"0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8","hash_type":"type"},"type":null},{"capacity":"0xb6113","lock":{"args":"0x64257f00b6b63e987609fa9be2d0c86d351020fb","code_hash":"0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8","hash_type":"type"},"type":null}],"outputs_data":["0x","0x"],"version":"0x0","witnesses":["0x5500000010000000550000005500000041000000af34b54bebf8c5971da6a880f2df5a186c3f8d0b5c9a1fe1a90c95b8a4fb89ef3bab1ccec13797dcb3fee80400f953227dd7741227e08032e3598e16ccdaa49c00"]}"#).unwrap().into();
-    let error = verify_tx(transaction.into_view(), &swc, consensus).unwrap_err();
+    let error = verify_tx(transaction.into_view(), &swc, consensus, MAX_CYCLES).unwrap_err();
     assert!(error.to_string().contains("InsufficientCellCapacity"));
 }
+
+#[test]
+fn tx_verify_env_from_tip() {
+    let chain = MockChain::new_with_default_pow("tx_verify_env_from_tip");
+    let storage = chain.client_storage();
+    let swc =
+        StorageWithChainData::new(storage.to_owned(), chain.create_peers(), Default::default());
+
+    // The epochs after the hardfork should be used, rather than the one of the genesis block.
+    // The next block of the second tip is in the next epoch.
+    for (number, epoch, next_epoch_number) in [
+        (1000u64, EpochNumberWithFraction::new(1, 0, 1000), 1),
+        (9999, EpochNumberWithFraction::new(9, 999, 1000), 10),
+    ] {
+        let tip_header = HeaderBuilder::default()
+            .number(number.pack())
+            .epoch(epoch.full_value().pack())
+            .build();
+        storage.update_last_state(&U256::one(), &tip_header.data(), &[]);
+
+        let tx_env = tx_verify_env(&swc, &TxVerifyContext::Tip);
+        assert_eq!(tx_env.parent_hash(), tip_header.hash());
+        assert_eq!(tx_env.epoch(), epoch);
+
+        let tx_env = tx_verify_env(&swc, &TxVerifyContext::NextBlock);
+        assert_eq!(tx_env.parent_hash(), tip_header.hash());
+        assert_eq!(tx_env.epoch().number(), next_epoch_number);
+    }
+}
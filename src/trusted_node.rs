@@ -0,0 +1,133 @@
+//! Cross-check the proved tip with a trusted CKB full node.
+//!
+//! This is an extra layer to detect eclipse attacks: if all connected peers
+//! are malicious, the proved chain diverges from the chain of the trusted node.
+
+use std::{sync::Arc, time::Duration};
+
+use ckb_async_runtime::Handle;
+use ckb_jsonrpc_types::HeaderView;
+use ckb_stop_handler::new_tokio_exit_rx;
+use ckb_types::{core::BlockNumber, packed::Byte32, prelude::*};
+use hyper::Client;
+use log::{debug, error, info, warn};
+
+use crate::{
+    protocols::{ChainEvent, Peers},
+    storage::Storage,
+    utils,
+};
+
+pub(crate) struct TrustedNodeChecker {
+    rpc_url: String,
+    interval: Duration,
+    storage: Storage,
+    peers: Arc<Peers>,
+}
+
+impl TrustedNodeChecker {
+    pub(crate) fn new(
+        rpc_url: String,
+        interval: Duration,
+        storage: Storage,
+        peers: Arc<Peers>,
+    ) -> Self {
+        Self {
+            rpc_url,
+            interval,
+            storage,
+            peers,
+        }
+    }
+
+    pub(crate) fn start(self, handle: &Handle) {
+        info!(
+            "Cross-check the proved tip with the trusted node {}",
+            self.rpc_url
+        );
+        let stop_rx = new_tokio_exit_rx();
+        handle.spawn(async move {
+            let client = Client::new();
+            let mut interval = tokio::time::interval(self.interval);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        let tip_header = self.storage.get_tip_header().into_view();
+                        // Don't wait for a slow trusted node when the client is closing.
+                        let number = tip_header.number();
+                        let result = tokio::select! {
+                            result = self.get_header_by_number(&client, number) => result,
+                            _ = stop_rx.cancelled() => {
+                                info!("TrustedNodeChecker received exit signal, exit now");
+                                break;
+                            }
+                        };
+                        match result {
+                            Ok(trusted_header) => self.check(
+                                tip_header.number(),
+                                &tip_header.hash(),
+                                trusted_header.map(|header| header.hash.pack()),
+                            ),
+                            Err(err) => warn!(
+                                "failed to get the header from the trusted node since {}",
+                                err
+                            ),
+                        }
+                    }
+                    _ = stop_rx.cancelled() => {
+                        info!("TrustedNodeChecker received exit signal, exit now");
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    async fn get_header_by_number(
+        &self,
+        client: &Client<hyper::client::HttpConnector>,
+        number: BlockNumber,
+    ) -> Result<Option<HeaderView>, String> {
+        utils::rpc::call(
+            client,
+            &self.rpc_url,
+            "get_header_by_number",
+            serde_json::json!([format!("{:#x}", number)]),
+        )
+        .await
+    }
+
+    /// Compares the proved tip with the header at the same height in the trusted node.
+    ///
+    /// Skip the check if the trusted node doesn't have the header at that height yet.
+    pub(crate) fn check(&self, number: BlockNumber, hash: &Byte32, trusted_hash: Option<Byte32>) {
+        let trusted_hash = if let Some(trusted_hash) = trusted_hash {
+            trusted_hash
+        } else {
+            debug!(
+                "the trusted node doesn't have the header#{} yet, skip the check",
+                number
+            );
+            return;
+        };
+        let is_diverged = hash != &trusted_hash;
+        let was_diverged = self.peers.set_trusted_node_diverged(is_diverged);
+        if is_diverged {
+            error!(
+                block_number = number;
+                "proved tip#{} {:#x} diverges from the trusted node {:#x}",
+                number, hash, trusted_hash
+            );
+            if !was_diverged {
+                self.peers
+                    .emit_chain_event(ChainEvent::TrustedNodeDiverged {
+                        number,
+                        hash: hash.to_owned(),
+                        trusted_hash,
+                    });
+            }
+        } else if was_diverged {
+            info!(block_number = number; "proved tip#{} matches the trusted node again", number);
+        }
+    }
+}
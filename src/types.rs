@@ -1,15 +1,44 @@
-use std::{fmt, path::PathBuf, result::Result as StdResult, str::FromStr};
+use std::{
+    collections::BTreeMap, env, fmt, path::PathBuf, result::Result as StdResult, str::FromStr,
+};
 
 use ckb_app_config::NetworkConfig;
+use ckb_chain_spec::{hardfork::HardForkConfig, ChainSpec};
+use ckb_constant::{
+    hardfork::{mainnet, testnet},
+    sync::INIT_BLOCKS_IN_TRANSIT_PER_PEER,
+};
+use ckb_types::{
+    core::{BlockNumber, Cycle, EpochNumber, EpochNumberWithFraction},
+    H256,
+};
 use serde::{Deserialize, Serialize};
 
+use crate::protocols::BLOCK_FILTERS_BATCH_SIZE;
+
+// The last states are requested from the peers every few seconds, a shorter timeout would
+// disconnect the peers before they have a chance to prove a new tip.
+const MIN_STALE_PEER_TIMEOUT_SECS: u64 = 30;
+
+/// The config of a light client, it's parsed from the config file.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(deny_unknown_fields)]
-pub(crate) struct RunEnv {
+pub struct RunEnv {
     pub(crate) chain: String,
+    /// The blake2b-256 hash of the content of the chain spec file, it's required if the
+    /// `chain` is a URL.
+    pub(crate) chain_spec_hash: Option<H256>,
     pub(crate) store: StoreConfig,
     pub(crate) network: NetworkConfig,
     pub(crate) rpc: RpcConfig,
+    #[serde(default)]
+    pub(crate) light_client: LightClientConfig,
+    #[serde(default)]
+    pub(crate) logger: LoggerConfig,
+    #[serde(default)]
+    pub(crate) tracing: TracingConfig,
+    #[serde(default)]
+    pub(crate) audit_log: AuditLogConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -22,13 +51,516 @@ pub(crate) struct StoreConfig {
 #[serde(deny_unknown_fields)]
 pub(crate) struct RpcConfig {
     pub(crate) listen_address: String,
+    /// Register the methods of ckb-indexer, see [`crate::service::IndexerRpc`].
+    #[serde(default)]
+    pub(crate) indexer_compatible: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LoggerConfig {
+    /// The log filter, in the same syntax as `RUST_LOG`, e.g. "info,ckb_light_client=debug".
+    ///
+    /// `RUST_LOG` takes precedence over it if it's set.
+    pub(crate) filter: Option<String>,
+    /// The level overrides for modules, e.g. `"ckb_light_client::protocols::light_client" =
+    /// "trace"`.
+    pub(crate) modules: BTreeMap<String, String>,
+    /// The format of the logs.
+    pub(crate) format: LogFormat,
+    /// Write the logs into the standard output.
+    pub(crate) log_to_stdout: bool,
+    /// Write the logs into this file, if it's set.
+    pub(crate) file: Option<PathBuf>,
+    /// Rotate the log file when its size exceeds this (in MiB), zero disables it.
+    pub(crate) max_file_size_mb: u64,
+    /// Rotate the log file when it has been opened for this duration (in seconds), zero
+    /// disables it.
+    pub(crate) rotate_interval_secs: u64,
+    /// The max count of the rotated log files to keep, zero keeps all of them.
+    pub(crate) max_rotated_files: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LogFormat {
+    /// The plain text, as the format of `env_logger`.
+    Text,
+    /// One JSON object per line, includes the fields: "timestamp", "level", "module",
+    /// "message", and the key-value fields of the record, e.g. "peer_index" and "block_number".
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        Self::Text
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct AuditLogConfig {
+    /// Append the matched cells, the committed transactions and the reorgs into this JSONL
+    /// file if it's set, it could be a named pipe.
+    pub(crate) path: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct TracingConfig {
+    /// Export the spans to this OTLP (gRPC) endpoint if it's set, e.g.
+    /// "http://127.0.0.1:4317", it requires the feature `otlp`.
+    pub(crate) otlp_endpoint: Option<String>,
+    /// The `service.name` of the exported spans.
+    pub(crate) service_name: String,
+    /// The ratio of the traces to export, from 0 to 1.
+    pub(crate) sample_ratio: f64,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: "ckb-light-client".to_owned(),
+            sample_ratio: 1.0,
+        }
+    }
+}
+
+impl Default for LoggerConfig {
+    fn default() -> Self {
+        Self {
+            filter: None,
+            modules: BTreeMap::new(),
+            format: LogFormat::default(),
+            log_to_stdout: true,
+            file: None,
+            max_file_size_mb: 100,
+            rotate_interval_secs: 0,
+            max_rotated_files: 10,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LightClientConfig {
+    /// Disconnect a proved peer if its proved tip doesn't advance in this
+    /// duration (in seconds) while other peers advance, it should be at least 30.
+    pub(crate) stale_peer_timeout_secs: u64,
+    /// Store the whole matched blocks, not only the filtered transactions.
+    pub(crate) store_matched_blocks: bool,
+    /// Don't download the block filters and the matched blocks on start, until the
+    /// `resume_filter_sync` RPC is called. The headers are still proved.
+    pub(crate) pause_filter_sync: bool,
+    /// The max download rate, in bytes per second, of the block filters and the
+    /// matched blocks. Zero means no limit.
+    pub(crate) max_download_rate: u64,
+    /// The soft limit (in MiB) of the memory of the process, zero means no limit.
+    ///
+    /// The matched blocks are not downloaded and the caches are shrunk when the memory
+    /// exceeds it, until the heap drops below 90% of it.
+    pub(crate) memory_soft_limit_mb: u64,
+    /// The max count of block filters which are processed in each `BlockFilters`
+    /// message, the following ranges are requested in parallel in batches of this size.
+    ///
+    /// It should be in `1..=1000`, since the full node sends 1000 block filters at most.
+    pub(crate) block_filters_batch_size: BlockNumber,
+    /// How far ahead (in blocks) of the next block to be filtered the block filters
+    /// could be requested from other peers in parallel, zero disables it.
+    ///
+    /// It's the max one, the lookahead in use shrinks when the responses are slow or
+    /// invalid, and grows back when they are fast.
+    pub(crate) block_filters_lookahead: BlockNumber,
+    /// The max count of matched blocks which are requested from each peer at once.
+    pub(crate) matched_blocks_in_transit_per_peer: usize,
+    /// Log the requested difficulties, the difficulty boundary and the returned
+    /// sampled headers for each committed proof, under the log target
+    /// `sampling_audit`.
+    pub(crate) audit_sampling: bool,
+    /// Don't use the peers for proofs if their client versions are less than this.
+    pub(crate) min_remote_client_version: Option<ClientVersion>,
+    /// The count of proved peers which are required before the RPC treats the
+    /// proved data as final, e.g. the tip header and the cells. Zero disables it.
+    pub(crate) min_proved_peers: usize,
+    /// Treat the proved tip as stale if its timestamp falls behind the
+    /// wall-clock time more than this duration (in seconds).
+    pub(crate) stale_tip_timeout_secs: u64,
+    /// The RPC URL of a trusted CKB full node, only HTTP is supported.
+    ///
+    /// Compare the proved tip with the header at the same height in it periodically.
+    pub(crate) trusted_node_rpc_url: Option<String>,
+    /// The interval (in seconds) of comparing the proved tip with the trusted node.
+    pub(crate) trusted_node_check_interval_secs: u64,
+    /// The max count of outstanding requests of all kinds for each peer.
+    pub(crate) max_outstanding_requests_per_peer: usize,
+    /// The max count of outstanding `GetBlocksProof` requests for all peers.
+    pub(crate) max_outstanding_blocks_proof_requests: usize,
+    /// The max count of outstanding `GetTransactionsProof` requests for all peers.
+    pub(crate) max_outstanding_txs_proof_requests: usize,
+    /// The min count of peers which should agree on the check points and the block
+    /// filter hashes before trusting them, it's capped by the max outbound peers.
+    pub(crate) min_block_filter_peers: usize,
+    /// The count of connected peers which each pending transaction is announced to,
+    /// they are chosen randomly. Zero means all peers.
+    pub(crate) relay_transaction_fanout: usize,
+    /// The max delay (in milliseconds) before a submitted transaction is announced for the
+    /// first time, the actual delay is chosen randomly. Zero means no delay.
+    pub(crate) relay_transaction_max_delay_ms: u64,
+    /// Request the transactions which are relayed by peers and keep the ones related to the
+    /// filter scripts as seen, before they are committed.
+    ///
+    /// The relay protocol is kept opened while there are filter scripts, and the requested
+    /// transactions are limited for each peer, but it still costs some bandwidth.
+    pub(crate) track_relayed_transactions: bool,
+    /// The max count of the pending transactions which are waiting for relay.
+    pub(crate) pending_txs_limit: usize,
+    /// Drop the pending transactions if they are not committed in this duration (in seconds).
+    pub(crate) pending_tx_ttl_secs: u64,
+    /// Which pending transaction is evicted when the pending pool is full.
+    pub(crate) pending_txs_eviction: PendingTxsEviction,
+    /// The max cycles to verify a transaction which is submitted or estimated, the max
+    /// block cycles of the consensus is used if it's not set.
+    pub(crate) max_tx_verify_cycles: Option<Cycle>,
+    /// Override the activation epoch of the CKB2023 hardfork (which selects the VM version
+    /// and the syscalls of the scripts) in the chain spec, only for the custom chains.
+    pub(crate) hardfork_ckb2023_epoch: Option<EpochNumber>,
+    /// Override the activation epoch of the chain root MMR, only for the custom chains.
+    ///
+    /// The chain spec can't set it, it's activated since the genesis for the custom chains.
+    pub(crate) hardfork_mmr_activated_epoch: Option<EpochNumber>,
+}
+
+/// The eviction policy of the pending pool, the transaction which is just submitted is
+/// never evicted.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PendingTxsEviction {
+    /// Evict the oldest pending transaction.
+    #[default]
+    Oldest,
+    /// Evict the pending transaction with the lowest fee rate.
+    LowestFeeRate,
+}
+
+/// The version of a CKB client, only the major, minor and patch parts are kept.
+///
+/// It could be parsed from the client version in the identify info, e.g.
+/// `0.113.0 (e9c3b0f 2023-12-08)` or `0.113.0-rc1`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct ClientVersion {
+    major: u64,
+    minor: u64,
+    patch: u64,
+}
+
+impl Default for LightClientConfig {
+    fn default() -> Self {
+        Self {
+            stale_peer_timeout_secs: 5 * 60,
+            store_matched_blocks: false,
+            pause_filter_sync: false,
+            max_download_rate: 0,
+            memory_soft_limit_mb: 0,
+            block_filters_batch_size: BLOCK_FILTERS_BATCH_SIZE,
+            block_filters_lookahead: 3 * BLOCK_FILTERS_BATCH_SIZE,
+            matched_blocks_in_transit_per_peer: INIT_BLOCKS_IN_TRANSIT_PER_PEER,
+            audit_sampling: false,
+            min_remote_client_version: None,
+            min_proved_peers: 0,
+            stale_tip_timeout_secs: 60 * 60,
+            trusted_node_rpc_url: None,
+            trusted_node_check_interval_secs: 60,
+            max_outstanding_requests_per_peer: 2,
+            max_outstanding_blocks_proof_requests: 16,
+            max_outstanding_txs_proof_requests: 16,
+            min_block_filter_peers: 2,
+            relay_transaction_fanout: 0,
+            relay_transaction_max_delay_ms: 0,
+            track_relayed_transactions: false,
+            pending_txs_limit: 64,
+            pending_tx_ttl_secs: 24 * 60 * 60,
+            pending_txs_eviction: PendingTxsEviction::default(),
+            max_tx_verify_cycles: None,
+            hardfork_ckb2023_epoch: None,
+            hardfork_mmr_activated_epoch: None,
+        }
+    }
+}
+
+impl LightClientConfig {
+    /// Checks the values which can't be expressed by the types.
+    pub(crate) fn validate(&self) -> StdResult<(), String> {
+        if self.stale_peer_timeout_secs < MIN_STALE_PEER_TIMEOUT_SECS {
+            return Err(format!(
+                "stale_peer_timeout_secs should not be less than {}",
+                MIN_STALE_PEER_TIMEOUT_SECS
+            ));
+        }
+        if self.matched_blocks_in_transit_per_peer == 0 {
+            return Err("matched_blocks_in_transit_per_peer should be greater than 0".to_owned());
+        }
+        if self.max_outstanding_requests_per_peer == 0 {
+            return Err("max_outstanding_requests_per_peer should be greater than 0".to_owned());
+        }
+        if self.block_filters_batch_size == 0
+            || self.block_filters_batch_size > BLOCK_FILTERS_BATCH_SIZE
+        {
+            return Err(format!(
+                "block_filters_batch_size should be in 1..={}",
+                BLOCK_FILTERS_BATCH_SIZE
+            ));
+        }
+        if self.pending_txs_limit == 0 {
+            return Err("pending_txs_limit should be greater than 0".to_owned());
+        }
+        if self.max_tx_verify_cycles == Some(0) {
+            return Err("max_tx_verify_cycles should be greater than 0".to_owned());
+        }
+        if self.relay_transaction_max_delay_ms > 60 * 1000 {
+            return Err(
+                "relay_transaction_max_delay_ms should not be greater than 60000".to_owned(),
+            );
+        }
+        if let Some(url) = &self.trusted_node_rpc_url {
+            if !url.starts_with("http://") {
+                return Err(format!(
+                    "trusted_node_rpc_url should be an HTTP URL, but got {}",
+                    url
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the overrides of the hardfork activation epochs into the chain spec.
+    ///
+    /// The public chains have fixed activation schedules, so the overrides are not
+    /// allowed for them. If the chain spec sets the activation epoch already, the
+    /// override should be the same.
+    ///
+    /// The MMR activation epoch isn't a field of the chain spec, it's only checked here and
+    /// read by the light client protocol.
+    pub(crate) fn apply_hardfork_overrides(
+        &self,
+        chain_spec: &mut ChainSpec,
+    ) -> StdResult<(), String> {
+        if self.hardfork_ckb2023_epoch.is_none() && self.hardfork_mmr_activated_epoch.is_none() {
+            return Ok(());
+        }
+        if chain_spec.name == mainnet::CHAIN_SPEC_NAME
+            || chain_spec.name == testnet::CHAIN_SPEC_NAME
+        {
+            return Err(format!(
+                "the hardfork overrides are not allowed for the chain {}",
+                chain_spec.name
+            ));
+        }
+        if let Some(epoch) = self.hardfork_mmr_activated_epoch {
+            if epoch > EpochNumberWithFraction::NUMBER_MAXIMUM_VALUE {
+                return Err(format!(
+                    "hardfork_mmr_activated_epoch {} should be less than {}",
+                    epoch,
+                    EpochNumberWithFraction::NUMBER_MAXIMUM_VALUE + 1
+                ));
+            }
+        }
+        let ckb2023 = if let Some(epoch) = self.hardfork_ckb2023_epoch {
+            epoch
+        } else {
+            return Ok(());
+        };
+        let hardfork = chain_spec
+            .params
+            .hardfork
+            .get_or_insert_with(HardForkConfig::default);
+        match hardfork.ckb2023 {
+            Some(epoch) if epoch != ckb2023 => Err(format!(
+                "the activation epoch of CKB2023 is {} in the chain spec, but {} in the config",
+                epoch, ckb2023
+            )),
+            _ => {
+                hardfork.ckb2023 = Some(ckb2023);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for ClientVersion {
+    type Err = String;
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        let version = s
+            .split_whitespace()
+            .next()
+            .map(|version| version.trim_start_matches('v'))
+            .and_then(|version| version.split(|c| c == '-' || c == '+').next())
+            .ok_or_else(|| format!("client version is empty: {:?}", s))?;
+        let numbers = version
+            .split('.')
+            .map(u64::from_str)
+            .collect::<StdResult<Vec<_>, _>>()
+            .map_err(|err| format!("failed to parse client version {:?}: {}", s, err))?;
+        if let [major, minor, patch] = numbers[..] {
+            Ok(Self {
+                major,
+                minor,
+                patch,
+            })
+        } else {
+            Err(format!("client version should be x.y.z, but got {:?}", s))
+        }
+    }
+}
+
+impl TryFrom<String> for ClientVersion {
+    type Error = String;
+    fn try_from(s: String) -> StdResult<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ClientVersion> for String {
+    fn from(version: ClientVersion) -> Self {
+        version.to_string()
+    }
+}
+
+impl fmt::Display for ClientVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The prefix of the environment variables which override the config, the nested keys are
+/// separated by double underscores, e.g. `CKB_LC_RPC__LISTEN_ADDRESS` overrides
+/// `listen_address` in the `[rpc]` section.
+pub(crate) const ENV_OVERRIDE_PREFIX: &str = "CKB_LC_";
+
+impl RunEnv {
+    /// Parses the config, then overrides it with the variables which names start with
+    /// [`ENV_OVERRIDE_PREFIX`].
+    ///
+    /// The values are parsed as TOML values, e.g. `true`, `100` or `["a", "b"]`, and they
+    /// are treated as strings if they are not valid TOML values.
+    pub(crate) fn from_str_with_overrides<I>(s: &str, vars: I) -> StdResult<Self, String>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        parse_with_overrides(s, vars)?
+            .try_into()
+            .map_err(|err: toml::de::Error| err.to_string())
+    }
 }
 
 impl FromStr for RunEnv {
-    type Err = toml::de::Error;
+    type Err = String;
     fn from_str(s: &str) -> StdResult<Self, Self::Err> {
-        toml::from_str(s)
+        Self::from_str_with_overrides(s, env_vars())
+    }
+}
+
+/// The config of multiple independent instances in one process, each of them has its own
+/// storage, network and RPC.
+///
+/// The instances are listed in the `[[instances]]` sections, and the `[logger]` and the
+/// `[tracing]` sections are shared by all of them. A config without `[[instances]]` is
+/// parsed as a single instance.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct MultiRunEnv {
+    #[serde(default)]
+    pub(crate) logger: LoggerConfig,
+    #[serde(default)]
+    pub(crate) tracing: TracingConfig,
+    pub(crate) instances: Vec<RunEnv>,
+}
+
+impl MultiRunEnv {
+    pub(crate) fn from_str_with_overrides<I>(s: &str, vars: I) -> StdResult<Self, String>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let config = parse_with_overrides(s, vars)?;
+        if let Some(instances) = config.get("instances") {
+            // The shared sections would be ignored silently in the instances.
+            for (index, instance) in instances.as_array().into_iter().flatten().enumerate() {
+                for section in ["logger", "tracing"] {
+                    if instance.get(section).is_some() {
+                        return Err(format!(
+                            "instances[{}].{} is not allowed, use the [{}] section at the top \
+                            level, which is shared by all instances",
+                            index, section, section
+                        ));
+                    }
+                }
+            }
+            let multi: Self = config
+                .try_into()
+                .map_err(|err: toml::de::Error| err.to_string())?;
+            if multi.instances.is_empty() {
+                return Err("instances should not be empty".to_owned());
+            }
+            Ok(multi)
+        } else {
+            let run_env: RunEnv = config
+                .try_into()
+                .map_err(|err: toml::de::Error| err.to_string())?;
+            Ok(Self {
+                logger: run_env.logger.clone(),
+                tracing: run_env.tracing.clone(),
+                instances: vec![run_env],
+            })
+        }
+    }
+}
+
+impl FromStr for MultiRunEnv {
+    type Err = String;
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        Self::from_str_with_overrides(s, env_vars())
+    }
+}
+
+fn env_vars() -> impl Iterator<Item = (String, String)> {
+    env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+}
+
+fn parse_with_overrides<I>(s: &str, vars: I) -> StdResult<toml::Value, String>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut config: toml::Value = toml::from_str(s).map_err(|err| err.to_string())?;
+    for (name, raw) in vars {
+        let path = if let Some(path) = name.strip_prefix(ENV_OVERRIDE_PREFIX) {
+            path
+        } else {
+            continue;
+        };
+        let keys = path.split("__").map(str::to_lowercase).collect::<Vec<_>>();
+        if keys.iter().any(String::is_empty) {
+            return Err(format!("invalid environment variable {}", name));
+        }
+        let value = toml::from_str::<toml::value::Table>(&format!("value = {}", raw))
+            .ok()
+            .and_then(|mut table| table.remove("value"))
+            .unwrap_or(toml::Value::String(raw));
+        let (last_key, parent_keys) = keys.split_last().expect("keys should not be empty");
+        let mut table = config
+            .as_table_mut()
+            .ok_or_else(|| "the config should be a table".to_owned())?;
+        for key in parent_keys {
+            table = table
+                .entry(key.to_owned())
+                .or_insert_with(|| toml::Value::Table(Default::default()))
+                .as_table_mut()
+                .ok_or_else(|| format!("{} overrides the non-table key {}", name, key))?;
+        }
+        table.insert(last_key.to_owned(), value);
     }
+    Ok(config)
 }
 
 impl fmt::Display for RunEnv {
@@ -1,4 +1,7 @@
-use std::{fs, path::Path};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
 
 use path_clean::PathClean as _;
 
@@ -28,3 +31,71 @@ pub(crate) fn need_directory<P: AsRef<Path>>(path: P) -> Result<()> {
     }
     Ok(())
 }
+
+/// Checks the directory could be used without creating it, i.e. it's a writable directory, or
+/// it could be created in its nearest existing ancestor.
+pub(crate) fn check_directory<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    let existing = path
+        .ancestors()
+        .find(|dir| !dir.as_os_str().is_empty() && dir.exists())
+        .unwrap_or_else(|| Path::new("."));
+    if !existing.is_dir() {
+        let errmsg = if existing == path {
+            format!("the path [{}] exists but not a directory", path.display())
+        } else {
+            format!(
+                "the path [{}] can't be created since [{}] is not a directory",
+                path.display(),
+                existing.display()
+            )
+        };
+        return Err(Error::config(errmsg));
+    }
+    let readonly = fs::metadata(existing)
+        .map(|metadata| metadata.permissions().readonly())
+        .map_err(|err| {
+            let errmsg = format!("failed to access [{}] since {}", existing.display(), err);
+            Error::config(errmsg)
+        })?;
+    if readonly {
+        let errmsg = format!("the directory [{}] is read-only", existing.display());
+        return Err(Error::config(errmsg));
+    }
+    Ok(())
+}
+
+/// Returns the total size of the files in the directory, recursively.
+pub(crate) fn directory_size<P: AsRef<Path>>(path: P) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += directory_size(entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Returns the absolute path without `.`, `..` and symbolic links, so the different spellings
+/// of the same path are equal. Unlike `fs::canonicalize`, the path doesn't have to exist.
+pub(crate) fn canonicalize_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    let absolute = env::current_dir()
+        .map(|dir| dir.join(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+        .clean();
+    // Resolve the symbolic links in the nearest existing ancestor.
+    for ancestor in absolute.ancestors() {
+        if let Ok(canonical) = fs::canonicalize(ancestor) {
+            let rest = absolute
+                .strip_prefix(ancestor)
+                .expect("the ancestor should be a prefix");
+            return canonical.join(rest);
+        }
+    }
+    absolute
+}
@@ -0,0 +1,42 @@
+//! Download files over HTTP or HTTPS.
+
+use std::time::Duration;
+
+use hyper::{body, Client, Uri};
+use hyper_rustls::HttpsConnectorBuilder;
+
+/// The chain spec is small, a download which takes longer than this is stalled.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Downloads the content at `url`, it blocks the current thread, so don't call it in an
+/// asynchronous context.
+///
+/// Returns an error if the download isn't finished in time.
+pub(crate) fn download(url: &str) -> Result<Vec<u8>, String> {
+    let uri = url.parse::<Uri>().map_err(|err| err.to_string())?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| err.to_string())?;
+    let download = async {
+        let connector = HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        let client = Client::builder().build::<_, hyper::Body>(connector);
+        let response = client.get(uri).await.map_err(|err| err.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("unexpected status {}", response.status()));
+        }
+        body::to_bytes(response.into_body())
+            .await
+            .map(|bytes| bytes.to_vec())
+            .map_err(|err| err.to_string())
+    };
+    runtime.block_on(async {
+        tokio::time::timeout(DOWNLOAD_TIMEOUT, download)
+            .await
+            .map_err(|_| format!("timed out after {:?}", DOWNLOAD_TIMEOUT))?
+    })
+}
@@ -1,2 +1,4 @@
 pub(crate) mod fs;
+pub(crate) mod http;
 pub(crate) mod network;
+pub(crate) mod rpc;
@@ -1,8 +1,8 @@
-use crate::protocols::{Peers, GET_BLOCKS_PROOF_LIMIT};
+use crate::protocols::{Peers, RequestKind, GET_BLOCKS_PROOF_LIMIT};
 use ckb_network::{CKBProtocolContext, SupportProtocols};
+use ckb_systemtime::unix_time_as_millis;
 use ckb_types::{packed, prelude::*, H256};
 use log::{debug, error};
-use rand::seq::SliceRandom;
 use std::collections::HashMap;
 use std::sync::Arc;
 
@@ -13,39 +13,60 @@ pub(crate) fn prove_or_download_matched_blocks(
     nc: &dyn CKBProtocolContext,
     init_blocks_in_transit_per_peer: usize,
 ) {
-    let best_peers: Vec<_> = peers.get_best_proved_peers(best_tip);
+    if peers.is_filter_sync_paused() {
+        debug!("filter sync is paused, don't prove or download matched blocks");
+        return;
+    }
+    let mut best_peers: Vec<_> = peers.get_best_proved_peers(best_tip);
     let last_hash = best_tip.calc_header_hash();
 
+    // Prefer the peers which respond faster.
+    peers.sort_by_latency(&mut best_peers, RequestKind::BlocksProof);
+
     loop {
         if let Some(peer_index) = best_peers
             .iter()
-            .filter(|peer_index| {
-                peers
-                    .get_peer(peer_index)
-                    .map(|peer| peer.get_blocks_proof_request().is_none())
-                    .unwrap_or(false)
-            })
-            .collect::<Vec<_>>()
-            .choose(&mut rand::thread_rng())
-            .cloned()
+            .find(|peer_index| peers.could_request(peer_index, RequestKind::BlocksProof))
         {
             let blocks_to_prove =
                 peers.get_matched_blocks_to_prove(matched_blocks, GET_BLOCKS_PROOF_LIMIT);
             if !blocks_to_prove.is_empty() {
+                // Coalesce the headers which are waiting to be fetched into the same request.
+                let headers_to_fetch = peers
+                    .get_headers_to_fetch()
+                    .into_iter()
+                    .filter(|hash| !blocks_to_prove.contains(hash))
+                    .take(GET_BLOCKS_PROOF_LIMIT - blocks_to_prove.len())
+                    .collect::<Vec<_>>();
                 debug!(
-                    "send get blocks proof request to peer: {}, count={}",
+                    "send get blocks proof request to peer: {}, count={}, headers={}",
                     peer_index,
-                    blocks_to_prove.len()
+                    blocks_to_prove.len(),
+                    headers_to_fetch.len()
                 );
+                let block_hashes = blocks_to_prove
+                    .into_iter()
+                    .chain(headers_to_fetch.iter().cloned())
+                    .collect::<Vec<_>>();
                 let content = packed::GetBlocksProof::new_builder()
-                    .block_hashes(blocks_to_prove.pack())
+                    .block_hashes(block_hashes.pack())
                     .last_hash(last_hash.clone())
                     .build();
                 let message = packed::LightClientMessage::new_builder()
                     .set(content.clone())
                     .build()
                     .as_bytes();
-                peers.update_blocks_proof_request(*peer_index, Some(content), true);
+                peers.update_coalesced_blocks_proof_request(
+                    *peer_index,
+                    content,
+                    &headers_to_fetch,
+                );
+                peers.fetching_idle_headers(&headers_to_fetch, unix_time_as_millis());
+                peers.record_sent_message(
+                    *peer_index,
+                    SupportProtocols::LightClient.protocol_id(),
+                    message.len(),
+                );
                 if let Err(err) = nc.send_message(
                     SupportProtocols::LightClient.protocol_id(),
                     *peer_index,
@@ -64,18 +85,19 @@ pub(crate) fn prove_or_download_matched_blocks(
         }
     }
 
+    if !peers.could_download() {
+        debug!("download rate exceeds the limit, don't download matched blocks");
+        return;
+    }
+    if peers.is_under_memory_pressure() {
+        debug!("memory exceeds the soft limit, don't download matched blocks");
+        return;
+    }
+    peers.sort_by_latency(&mut best_peers, RequestKind::Blocks);
     loop {
         if let Some(peer_index) = best_peers
             .iter()
-            .filter(|peer_index| {
-                peers
-                    .get_peer(peer_index)
-                    .map(|peer| peer.get_blocks_request().is_none())
-                    .unwrap_or(false)
-            })
-            .collect::<Vec<_>>()
-            .choose(&mut rand::thread_rng())
-            .cloned()
+            .find(|peer_index| peers.could_request(peer_index, RequestKind::Blocks))
         {
             let blocks_to_download = peers
                 .get_matched_blocks_to_download(matched_blocks, init_blocks_in_transit_per_peer);
@@ -93,6 +115,11 @@ pub(crate) fn prove_or_download_matched_blocks(
                     .set(content)
                     .build()
                     .as_bytes();
+                peers.record_sent_message(
+                    *peer_index,
+                    SupportProtocols::Sync.protocol_id(),
+                    message.len(),
+                );
                 if let Err(err) =
                     nc.send_message(SupportProtocols::Sync.protocol_id(), *peer_index, message)
                 {
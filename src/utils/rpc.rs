@@ -0,0 +1,77 @@
+//! A minimal JSON-RPC client over HTTP.
+
+use std::time::Duration;
+
+use hyper::{body, client::HttpConnector, Body, Client, Request};
+use serde::{de::DeserializeOwned, Deserialize};
+
+#[derive(Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<serde_json::Value>,
+}
+
+/// The hyper client has no timeout, a stalled server would block the caller forever.
+const CALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Calls the `method` of the JSON-RPC server at `url`.
+///
+/// Returns `None` if the result is null, and an error if the server doesn't respond in
+/// time.
+pub(crate) async fn call<T: DeserializeOwned>(
+    client: &Client<HttpConnector>,
+    url: &str,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<Option<T>, String> {
+    tokio::time::timeout(
+        CALL_TIMEOUT,
+        call_without_timeout(client, url, method, params),
+    )
+    .await
+    .map_err(|_| format!("{} timed out after {:?}", method, CALL_TIMEOUT))?
+}
+
+async fn call_without_timeout<T: DeserializeOwned>(
+    client: &Client<HttpConnector>,
+    url: &str,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<Option<T>, String> {
+    let content = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": params,
+        "id": 1,
+    });
+    let request = Request::post(url)
+        .header("content-type", "application/json")
+        .body(Body::from(content.to_string()))
+        .map_err(|err| err.to_string())?;
+    let response = client
+        .request(request)
+        .await
+        .map_err(|err| err.to_string())?;
+    let bytes = body::to_bytes(response.into_body())
+        .await
+        .map_err(|err| err.to_string())?;
+    let response: RpcResponse<T> = serde_json::from_slice(&bytes).map_err(|err| err.to_string())?;
+    if let Some(error) = response.error {
+        return Err(error.to_string());
+    }
+    Ok(response.result)
+}
+
+/// Calls the `method` in a temporary runtime, it blocks the current thread, so don't call it
+/// in an asynchronous context.
+pub(crate) fn call_blocking<T: DeserializeOwned>(
+    url: &str,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<Option<T>, String> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| err.to_string())?;
+    runtime.block_on(async { call(&Client::new(), url, method, params).await })
+}
@@ -1,64 +1,277 @@
 use std::{
-    collections::{hash_map::Entry, HashMap, HashSet},
-    sync::Arc,
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    mem,
+    sync::{Arc, Mutex},
 };
 
 use ckb_chain_spec::consensus::Consensus;
 use ckb_error::Error;
-use ckb_script::TxVerifyEnv;
+use ckb_script::{
+    ScriptError, ScriptGroup, ScriptGroupType, TransactionScriptsVerifier, TxVerifyEnv,
+};
+use ckb_systemtime::unix_time_as_millis;
+use ckb_traits::{CellDataProvider, ExtensionProvider, HeaderFieldsProvider, HeaderProvider};
 use ckb_types::{
     core::{
         cell::{CellMeta, CellProvider, CellStatus, ResolvedTransaction},
         error::OutPointError,
-        Cycle, DepType, TransactionView,
+        Cycle, DepType, EpochNumberWithFraction, HeaderView, TransactionView,
     },
-    packed::{OutPoint, OutPointVec},
-    prelude::{Entity, IntoHeaderView},
+    packed::{Byte32, OutPoint, OutPointVec, Script, WitnessArgs},
+    prelude::{Entity, IntoHeaderView, Pack},
 };
 use ckb_verification::{
-    CapacityVerifier, NonContextualTransactionVerifier, ScriptVerifier,
-    TimeRelativeTransactionVerifier,
+    CapacityVerifier, NonContextualTransactionVerifier, TimeRelativeTransactionVerifier,
 };
+use rayon::prelude::{IntoParallelRefIterator as _, ParallelIterator as _};
 
 use crate::storage::StorageWithChainData;
 
+// The count of the last debug messages of the failed script which are kept.
+const MAX_SCRIPT_DEBUG_MESSAGES: usize = 16;
+// The max total size in bytes of the captured debug messages of the scripts.
+const MAX_SCRIPT_DEBUG_OUTPUT_SIZE: usize = 64 * 1024;
+
 /// Light client can only verify non-cellbase transaction,
 /// can not reuse the `ContextualTransactionVerifier` in ckb_verification crate which is used to verify cellbase also.
-pub struct ContextualTransactionVerifier {
-    pub(crate) time_relative: TimeRelativeTransactionVerifier<StorageWithChainData>,
+pub struct ContextualTransactionVerifier<DL> {
+    pub(crate) time_relative: TimeRelativeTransactionVerifier<DL>,
     pub(crate) capacity: CapacityVerifier,
-    pub(crate) script: ScriptVerifier<StorageWithChainData>,
+    pub(crate) script: ParallelScriptVerifier<DL>,
 }
 
-impl ContextualTransactionVerifier {
+impl<DL: VerifyDataLoader> ContextualTransactionVerifier<DL> {
     /// Creates a new ContextualTransactionVerifier
     pub fn new(
         rtx: Arc<ResolvedTransaction>,
         consensus: Arc<Consensus>,
-        swc: &StorageWithChainData,
+        data_loader: &DL,
         tx_env: Arc<TxVerifyEnv>,
     ) -> Self {
         ContextualTransactionVerifier {
             time_relative: TimeRelativeTransactionVerifier::new(
                 Arc::clone(&rtx),
                 Arc::clone(&consensus),
-                swc.clone(),
-                Arc::clone(&tx_env),
-            ),
-            script: ScriptVerifier::new(
-                Arc::clone(&rtx),
-                swc.clone(),
-                Arc::clone(&consensus),
+                data_loader.clone(),
                 Arc::clone(&tx_env),
             ),
+            script: ParallelScriptVerifier {
+                rtx: Arc::clone(&rtx),
+                data_loader: data_loader.clone(),
+                consensus: Arc::clone(&consensus),
+                tx_env: Arc::clone(&tx_env),
+                estimate_only: false,
+            },
             capacity: CapacityVerifier::new(Arc::clone(&rtx), consensus.dao_type_hash()),
         }
     }
 
+    /// Sets the estimate-only mode, in which the lock script groups whose signatures are
+    /// placeholders are treated as success.
+    ///
+    /// A placeholder is a `WitnessArgs` whose `lock` is filled with zeros, as the dummy
+    /// witnesses of the unsigned transactions to estimate the fees. The cycles of such a group
+    /// are the cycles consumed until the script exits, so they are less than the actual cycles
+    /// when the signature is valid.
+    pub fn set_estimate_only(&mut self, estimate_only: bool) {
+        self.script.estimate_only = estimate_only;
+    }
+
     pub fn verify(&self, max_cycles: Cycle) -> Result<Cycle, Error> {
         self.time_relative.verify()?;
         self.capacity.verify()?;
-        self.script.verify(max_cycles)
+        self.script.verify(max_cycles, None)
+    }
+
+    /// Verifies the transaction except the scripts.
+    pub fn verify_without_scripts(&self) -> Result<(), Error> {
+        self.time_relative.verify()?;
+        self.capacity.verify()
+    }
+
+    /// Verifies the transaction, and captures the debug messages which are printed by the
+    /// scripts.
+    pub fn verify_with_debug_output(
+        &self,
+        max_cycles: Cycle,
+        debug_output: &mut ScriptDebugOutput,
+    ) -> Result<Cycle, Error> {
+        self.time_relative.verify()?;
+        self.capacity.verify()?;
+        self.script.verify(max_cycles, Some(debug_output))
+    }
+}
+
+/// Verifies the script groups of a transaction concurrently.
+///
+/// The script groups are independent of each other, so a transaction which has several heavy
+/// locks could be verified in a fraction of the time on multicore devices. The groups are run in
+/// the global thread pool, so the count of the concurrent workers is bounded.
+pub(crate) struct ParallelScriptVerifier<DL> {
+    rtx: Arc<ResolvedTransaction>,
+    data_loader: DL,
+    consensus: Arc<Consensus>,
+    tx_env: Arc<TxVerifyEnv>,
+    estimate_only: bool,
+}
+
+impl<DL: VerifyDataLoader> ParallelScriptVerifier<DL> {
+    fn scripts_verifier(&self) -> TransactionScriptsVerifier<DL> {
+        TransactionScriptsVerifier::new(
+            Arc::clone(&self.rtx),
+            self.data_loader.clone(),
+            Arc::clone(&self.consensus),
+            Arc::clone(&self.tx_env),
+        )
+    }
+
+    pub(crate) fn verify(
+        &self,
+        max_cycles: Cycle,
+        mut debug_output: Option<&mut ScriptDebugOutput>,
+    ) -> Result<Cycle, Error> {
+        let verifier = self.scripts_verifier();
+        let groups = verifier
+            .groups()
+            .map(|(script_hash, group)| (script_hash.clone(), group.group_type))
+            .collect::<Vec<_>>();
+        let capture = debug_output.is_some();
+        let results = groups
+            .par_iter()
+            .map(|(script_hash, group_type)| {
+                // The binaries are loaded lazily by the verifier, so it can't be shared between
+                // the workers.
+                let mut verifier = self.scripts_verifier();
+                let group_output: Arc<Mutex<ScriptDebugOutput>> = Default::default();
+                if capture {
+                    let group_output = Arc::clone(&group_output);
+                    verifier.set_debug_printer(move |script_hash: &Byte32, message: &str| {
+                        group_output
+                            .lock()
+                            .expect("poisoned")
+                            .push(script_hash, message);
+                    });
+                }
+                let group = verifier
+                    .find_script_group(*group_type, script_hash)
+                    .expect("the script group should exist");
+                let result = if self.estimate_only && has_placeholder_signature(&self.rtx, group) {
+                    // The exit code is ignored, since the signature is a placeholder.
+                    verifier
+                        .detailed_run(group, max_cycles)
+                        .map(|(_, cycles)| cycles)
+                        .map_err(|err| ScriptError::VMInternalError(format!("{:?}", err)))
+                } else {
+                    verifier.verify_single(*group_type, script_hash, max_cycles)
+                };
+                let group_output = mem::take(&mut *group_output.lock().expect("poisoned"));
+                (result, group_output)
+            })
+            .collect::<Vec<_>>();
+        // Report the first failed group in order, as same as the sequential verification.
+        let mut cycles: Cycle = 0;
+        for ((script_hash, group_type), (result, group_output)) in groups.iter().zip(results) {
+            if let Some(debug_output) = debug_output.as_mut() {
+                debug_output.append(group_output);
+            }
+            let group = verifier
+                .find_script_group(*group_type, script_hash)
+                .expect("the script group should exist");
+            let cycle = result.map_err(|err| err.source(group))?;
+            cycles = cycles
+                .checked_add(cycle)
+                .filter(|cycles| *cycles <= max_cycles)
+                .ok_or_else(|| ScriptError::ExceededMaximumCycles(max_cycles).source(group))?;
+        }
+        Ok(cycles)
+    }
+}
+
+// Checks whether the signature of the lock script group is a placeholder, i.e. the `lock` of the
+// first witness of the group is filled with zeros.
+fn has_placeholder_signature(rtx: &ResolvedTransaction, group: &ScriptGroup) -> bool {
+    if group.group_type != ScriptGroupType::Lock {
+        return false;
+    }
+    group
+        .input_indices
+        .first()
+        .and_then(|index| rtx.transaction.witnesses().get(*index))
+        .and_then(|witness| WitnessArgs::from_slice(&witness.raw_data()).ok())
+        .and_then(|witness_args| witness_args.lock().to_opt())
+        .map(|lock| {
+            let lock = lock.raw_data();
+            !lock.is_empty() && lock.iter().all(|byte| *byte == 0)
+        })
+        .unwrap_or(false)
+}
+
+/// The debug messages which are printed by the scripts via the debug syscall.
+///
+/// The total size of the messages is capped, the messages after the cap are dropped.
+#[derive(Default)]
+pub struct ScriptDebugOutput {
+    pub(crate) messages: Vec<(Byte32, String)>,
+    size: usize,
+    /// Whether some messages are dropped since the size exceeds the cap.
+    pub(crate) truncated: bool,
+}
+
+impl ScriptDebugOutput {
+    fn push(&mut self, script_hash: &Byte32, message: &str) {
+        if self.truncated || self.size + message.len() > MAX_SCRIPT_DEBUG_OUTPUT_SIZE {
+            self.truncated = true;
+            return;
+        }
+        self.size += message.len();
+        self.messages
+            .push((script_hash.clone(), message.to_owned()));
+    }
+
+    fn append(&mut self, other: Self) {
+        for (script_hash, message) in &other.messages {
+            self.push(script_hash, message);
+        }
+        self.truncated |= other.truncated;
+    }
+}
+
+/// The data which is required to verify a transaction.
+///
+/// Implement it for the own cell sets to verify candidate transactions through `verify_tx_with`.
+pub trait VerifyDataLoader:
+    CellProvider
+    + CellDataProvider
+    + HeaderProvider
+    + HeaderFieldsProvider
+    + ExtensionProvider
+    + Clone
+    + Send
+    + Sync
+    + 'static
+{
+    /// Gets a cell of the cell deps.
+    ///
+    /// The same cell deps are used by most of the transactions, override it to cache them.
+    fn cell_dep(&self, out_point: &OutPoint, eager_load: bool) -> CellStatus {
+        self.cell(out_point, eager_load)
+    }
+}
+
+impl VerifyDataLoader for StorageWithChainData {
+    fn cell_dep(&self, out_point: &OutPoint, eager_load: bool) -> CellStatus {
+        if let Some(cell_meta) = self.storage().get_cached_cell_dep(out_point, eager_load) {
+            return CellStatus::Live(cell_meta);
+        }
+        let cell_status = self.cell(out_point, eager_load);
+        if let CellStatus::Live(ref cell_meta) = cell_status {
+            // The cells of the pending transactions are not cached, since they may be dropped.
+            if cell_meta.transaction_info.is_some() {
+                self.storage()
+                    .cache_cell_dep(cell_meta.to_owned(), eager_load);
+            }
+        }
+        cell_status
     }
 }
 
@@ -66,18 +279,223 @@ pub fn verify_tx(
     transaction: TransactionView,
     swc: &StorageWithChainData,
     consensus: Arc<Consensus>,
+    max_cycles: Cycle,
+) -> Result<Cycle, Error> {
+    let (_, tip_header) = swc.storage().get_last_state();
+    verify_tx_with(
+        transaction,
+        swc,
+        consensus,
+        &tip_header.into_view(),
+        max_cycles,
+    )
+}
+
+/// Verifies a transaction against the cells and the headers which are provided by the caller,
+/// rather than the storage of the light client.
+///
+/// The transaction is verified as it will be submitted after the tip header.
+pub fn verify_tx_with<DL: VerifyDataLoader>(
+    transaction: TransactionView,
+    data_loader: &DL,
+    consensus: Arc<Consensus>,
+    tip_header: &HeaderView,
+    max_cycles: Cycle,
 ) -> Result<Cycle, Error> {
     NonContextualTransactionVerifier::new(&transaction, &consensus).verify()?;
 
+    let rtx = resolve_tx(data_loader, transaction)?;
+    let tx_env = TxVerifyEnv::new_submit(tip_header);
+    ContextualTransactionVerifier::new(Arc::new(rtx), consensus, data_loader, Arc::new(tx_env))
+        .verify(max_cycles)
+}
+
+/// Verifies a transaction as `verify_tx` but skips the scripts, only for the transactions which
+/// are verified by the submitter already.
+pub fn verify_tx_without_scripts(
+    transaction: TransactionView,
+    swc: &StorageWithChainData,
+    consensus: Arc<Consensus>,
+) -> Result<(), Error> {
+    NonContextualTransactionVerifier::new(&transaction, &consensus).verify()?;
+
     let rtx = resolve_tx(swc, transaction)?;
-    let (_, tip_header) = swc.storage().get_last_state();
-    let tx_env = TxVerifyEnv::new_submit(&tip_header.into_view());
-    ContextualTransactionVerifier::new(Arc::new(rtx), Arc::clone(&consensus), swc, Arc::new(tx_env))
-        .verify(consensus.max_block_cycles())
+    let tx_env = tx_verify_env(swc, &TxVerifyContext::Tip);
+    ContextualTransactionVerifier::new(Arc::new(rtx), consensus, swc, Arc::new(tx_env))
+        .verify_without_scripts()
 }
 
-fn resolve_tx(
+/// Verifies a transaction as `verify_tx`, and captures the debug messages which are printed by
+/// the scripts, whether the verification succeeds or not.
+pub fn verify_tx_with_debug_output(
+    transaction: TransactionView,
     swc: &StorageWithChainData,
+    consensus: Arc<Consensus>,
+    max_cycles: Cycle,
+    context: &TxVerifyContext,
+    estimate_only: bool,
+) -> (Result<Cycle, Error>, ScriptDebugOutput) {
+    let mut debug_output = ScriptDebugOutput::default();
+    let result = NonContextualTransactionVerifier::new(&transaction, &consensus)
+        .verify()
+        .and_then(|_| resolve_tx(swc, transaction).map_err(Into::into))
+        .and_then(|rtx| {
+            let tx_env = tx_verify_env(swc, context);
+            let mut verifier = ContextualTransactionVerifier::new(
+                Arc::new(rtx),
+                Arc::clone(&consensus),
+                swc,
+                Arc::new(tx_env),
+            );
+            verifier.set_estimate_only(estimate_only);
+            verifier.verify_with_debug_output(max_cycles, &mut debug_output)
+        });
+    (result, debug_output)
+}
+
+/// The details of the script group which fails to verify a transaction.
+pub struct ScriptFailure {
+    pub(crate) group_type: ScriptGroupType,
+    /// The index of the first input (or output, if the group has no inputs) in the group.
+    pub(crate) cell_index: usize,
+    pub(crate) is_input: bool,
+    pub(crate) script: Script,
+    /// The exit code of the script, only available when the script returns a non-zero code.
+    pub(crate) exit_code: Option<i8>,
+    /// The cycles consumed by the script, only available when it exceeds the max cycles.
+    pub(crate) cycles: Option<Cycle>,
+    pub(crate) error: String,
+    /// The last debug messages which are printed by the script.
+    pub(crate) debug_messages: Vec<String>,
+}
+
+/// Verifies the script groups of a transaction one by one, and returns the details of the
+/// first failed one.
+///
+/// It's slower than `verify_tx`, only use it to diagnose a transaction which fails.
+pub fn diagnose_script_failure(
+    transaction: TransactionView,
+    swc: &StorageWithChainData,
+    consensus: Arc<Consensus>,
+    max_cycles: Cycle,
+    context: &TxVerifyContext,
+) -> Option<ScriptFailure> {
+    let rtx = resolve_tx(swc, transaction).ok()?;
+    let tx_env = tx_verify_env(swc, context);
+    let debug_messages: Arc<Mutex<HashMap<Byte32, VecDeque<String>>>> = Default::default();
+    let mut verifier =
+        TransactionScriptsVerifier::new(Arc::new(rtx), swc.clone(), consensus, Arc::new(tx_env));
+    {
+        let debug_messages = Arc::clone(&debug_messages);
+        verifier.set_debug_printer(move |script_hash: &Byte32, message: &str| {
+            let mut debug_messages = debug_messages.lock().expect("poisoned");
+            let messages = debug_messages.entry(script_hash.clone()).or_default();
+            messages.push_back(message.to_owned());
+            if messages.len() > MAX_SCRIPT_DEBUG_MESSAGES {
+                messages.pop_front();
+            }
+        });
+    }
+    verifier.groups().find_map(|(script_hash, group)| {
+        let err = verifier
+            .verify_single(group.group_type, script_hash, max_cycles)
+            .err()?;
+        let (exit_code, cycles) = match &err {
+            ScriptError::ValidationFailure(_, exit_code) => (Some(*exit_code), None),
+            ScriptError::ExceededMaximumCycles(cycles) => (None, Some(*cycles)),
+            _ => (None, None),
+        };
+        let (cell_index, is_input) = group
+            .input_indices
+            .first()
+            .map(|index| (*index, true))
+            .or_else(|| group.output_indices.first().map(|index| (*index, false)))
+            .unwrap_or_default();
+        let debug_messages = debug_messages
+            .lock()
+            .expect("poisoned")
+            .remove(script_hash)
+            .map(Vec::from)
+            .unwrap_or_default();
+        Some(ScriptFailure {
+            group_type: group.group_type,
+            cell_index,
+            is_input,
+            script: group.script.clone(),
+            exit_code,
+            cycles,
+            error: err.to_string(),
+            debug_messages,
+        })
+    })
+}
+
+/// Calculates the fee rate (in shannons per KB) of a verified transaction.
+pub fn calc_fee_rate(
+    transaction: TransactionView,
+    swc: &StorageWithChainData,
+) -> Result<u64, OutPointError> {
+    let size = transaction.data().serialized_size_in_block() as u64;
+    let rtx = resolve_tx(swc, transaction)?;
+    // The capacities are checked when the transaction is verified.
+    let fee = rtx
+        .inputs_capacity()
+        .and_then(|inputs| inputs.safe_sub(rtx.outputs_capacity()?))
+        .map(|fee| fee.as_u64())
+        .unwrap_or_default();
+    Ok(fee.saturating_mul(1000) / size.max(1))
+}
+
+/// The block against which the `since` and the epoch related rules of a transaction are
+/// evaluated.
+pub enum TxVerifyContext {
+    /// The transaction will be submitted after the proved tip, as same as the tx pool of the
+    /// full nodes.
+    Tip,
+    /// The transaction is committed in the next block of the proved tip.
+    NextBlock,
+    /// The transaction is committed in the block of the header.
+    Header(HeaderView),
+}
+
+/// Creates the environment to verify a transaction in the context.
+///
+/// The VM version and the syscalls (e.g. the spawn syscalls since CKB2023) which are available
+/// for a script are selected by the epoch of the block which will commit the transaction, so the
+/// environment is based on the proved tip rather than the genesis block; otherwise, transactions
+/// which use the new features would be rejected after the hardfork.
+pub(crate) fn tx_verify_env(swc: &StorageWithChainData, context: &TxVerifyContext) -> TxVerifyEnv {
+    match context {
+        TxVerifyContext::Tip => {
+            let (_, tip_header) = swc.storage().get_last_state();
+            TxVerifyEnv::new_submit(&tip_header.into_view())
+        }
+        TxVerifyContext::NextBlock => {
+            let (_, tip_header) = swc.storage().get_last_state();
+            let tip_header = tip_header.into_view();
+            let epoch = tip_header.epoch();
+            // The length of the next epoch is unknown yet, assume it's the same as the current
+            // one, only the epoch number matters for the epoch related rules.
+            let next_epoch = if epoch.index() + 1 < epoch.length() {
+                EpochNumberWithFraction::new(epoch.number(), epoch.index() + 1, epoch.length())
+            } else {
+                EpochNumberWithFraction::new(epoch.number() + 1, 0, epoch.length())
+            };
+            let next_header = tip_header
+                .as_advanced_builder()
+                .parent_hash(tip_header.hash())
+                .number((tip_header.number() + 1).pack())
+                .epoch(next_epoch.full_value().pack())
+                .timestamp(tip_header.timestamp().max(unix_time_as_millis()).pack())
+                .build();
+            TxVerifyEnv::new_commit(&next_header)
+        }
+        TxVerifyContext::Header(header) => TxVerifyEnv::new_commit(header),
+    }
+}
+
+fn resolve_tx<DL: VerifyDataLoader>(
+    data_loader: &DL,
     transaction: TransactionView,
 ) -> Result<ResolvedTransaction, OutPointError> {
     let (mut resolved_inputs, mut resolved_cell_deps, mut resolved_dep_groups) = (
@@ -89,11 +507,15 @@ fn resolve_tx(
 
     let mut resolved_cells: HashMap<(OutPoint, bool), CellMeta> = HashMap::new();
     let mut resolve_cell =
-        |out_point: &OutPoint, eager_load: bool| -> Result<CellMeta, OutPointError> {
+        |out_point: &OutPoint, eager_load: bool, is_dep: bool| -> Result<CellMeta, OutPointError> {
             match resolved_cells.entry((out_point.clone(), eager_load)) {
                 Entry::Occupied(entry) => Ok(entry.get().clone()),
                 Entry::Vacant(entry) => {
-                    let cell_status = swc.cell(out_point, eager_load);
+                    let cell_status = if is_dep {
+                        data_loader.cell_dep(out_point, eager_load)
+                    } else {
+                        data_loader.cell(out_point, eager_load)
+                    };
                     match cell_status {
                         CellStatus::Dead => Err(OutPointError::Dead(out_point.clone())),
                         CellStatus::Unknown => Err(OutPointError::Unknown(out_point.clone())),
@@ -110,13 +532,13 @@ fn resolve_tx(
         if !current_inputs.insert(out_point.to_owned()) {
             return Err(OutPointError::Dead(out_point));
         }
-        resolved_inputs.push(resolve_cell(&out_point, false)?);
+        resolved_inputs.push(resolve_cell(&out_point, false, false)?);
     }
 
     for cell_dep in transaction.cell_deps_iter() {
         if cell_dep.dep_type() == DepType::DepGroup.into() {
             let outpoint = cell_dep.out_point();
-            let dep_group = resolve_cell(&outpoint, true)?;
+            let dep_group = resolve_cell(&outpoint, true, true)?;
             let data = dep_group
                 .mem_cell_data
                 .as_ref()
@@ -125,11 +547,11 @@ fn resolve_tx(
                 parse_dep_group_data(data).map_err(|_| OutPointError::InvalidDepGroup(outpoint))?;
 
             for sub_out_point in sub_out_points.into_iter() {
-                resolved_cell_deps.push(resolve_cell(&sub_out_point, false)?);
+                resolved_cell_deps.push(resolve_cell(&sub_out_point, false, true)?);
             }
             resolved_dep_groups.push(dep_group);
         } else {
-            resolved_cell_deps.push(resolve_cell(&cell_dep.out_point(), false)?);
+            resolved_cell_deps.push(resolve_cell(&cell_dep.out_point(), false, true)?);
         }
     }
 
@@ -0,0 +1,181 @@
+//! Run the light client as a native Windows service.
+//!
+//! The service runs `ckb-light-client service run`, and the stop and the shutdown controls
+//! stop the light client in the same way as the exit signals.
+
+use std::{env, ffi::OsString, path::Path, sync::Mutex, time::Duration};
+
+use ckb_stop_handler::broadcast_exit_signals;
+use log::{error, info};
+use windows_service::{
+    define_windows_service,
+    service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+        ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    },
+    service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+    service_dispatcher,
+    service_manager::{ServiceManager, ServiceManagerAccess},
+};
+
+use crate::{
+    config::RunConfig,
+    error::{Error, Result},
+};
+
+/// The config of the service to run, it's taken by the service main function, since the
+/// service control manager calls it without any context.
+static SERVICE: Mutex<Option<(String, RunConfig)>> = Mutex::new(None);
+
+define_windows_service!(ffi_service_main, service_main);
+
+pub(crate) fn install(name: &str, config_file: &Path) -> Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .map_err(service_error("connect to the service manager"))?;
+    let executable_path = env::current_exe().map_err(|err| {
+        let errmsg = format!("failed to get the path of the executable since {}", err);
+        Error::runtime(errmsg)
+    })?;
+    let info = ServiceInfo {
+        name: OsString::from(name),
+        display_name: OsString::from(format!("CKB Light Client ({})", name)),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path,
+        launch_arguments: vec![
+            OsString::from("service"),
+            OsString::from("run"),
+            OsString::from("--name"),
+            OsString::from(name),
+            OsString::from("--config-file"),
+            config_file.as_os_str().to_owned(),
+        ],
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG)
+        .map_err(service_error("install the service"))?;
+    service
+        .set_description(clap::crate_description!())
+        .map_err(service_error("set the description of the service"))?;
+    info!(
+        "The service {} is installed with the config file {}",
+        name,
+        config_file.display()
+    );
+    Ok(())
+}
+
+pub(crate) fn uninstall(name: &str) -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .map_err(service_error("connect to the service manager"))?;
+    let service = manager
+        .open_service(
+            name,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .map_err(service_error("open the service"))?;
+    let status = service
+        .query_status()
+        .map_err(service_error("query the status of the service"))?;
+    if status.current_state != ServiceState::Stopped {
+        service.stop().map_err(service_error("stop the service"))?;
+    }
+    // The service is removed after it's stopped and all handles of it are closed.
+    service
+        .delete()
+        .map_err(service_error("uninstall the service"))?;
+    info!("The service {} is uninstalled", name);
+    Ok(())
+}
+
+/// Hands over the current thread to the service control manager, it returns after the
+/// service is stopped.
+pub(crate) fn run(name: String, run_config: RunConfig) -> Result<()> {
+    *SERVICE.lock().expect("poisoned") = Some((name.clone(), run_config));
+    service_dispatcher::start(&name, ffi_service_main)
+        .map_err(service_error("start the service dispatcher"))
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    let (name, run_config) = match SERVICE.lock().expect("poisoned").take() {
+        Some(service) => service,
+        None => return,
+    };
+    if let Err(err) = run_service(&name, run_config) {
+        error!("the service {} failed since {}", name, err);
+    }
+}
+
+fn run_service(name: &str, run_config: RunConfig) -> Result<()> {
+    let status_handle = service_control_handler::register(name, |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            info!("Received the stop control, shutting down ...");
+            broadcast_exit_signals();
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })
+    .map_err(service_error("register the service control handler"))?;
+
+    set_status(
+        &status_handle,
+        ServiceState::StartPending,
+        ServiceExitCode::Win32(0),
+    )?;
+    let result = run_config.execute_and_notify(|| {
+        if let Err(err) = set_status(
+            &status_handle,
+            ServiceState::Running,
+            ServiceExitCode::Win32(0),
+        ) {
+            error!("{}", err);
+        }
+    });
+    let exit_code = if result.is_ok() {
+        ServiceExitCode::Win32(0)
+    } else {
+        ServiceExitCode::ServiceSpecific(1)
+    };
+    set_status(&status_handle, ServiceState::Stopped, exit_code)?;
+    result
+}
+
+fn set_status(
+    status_handle: &ServiceStatusHandle,
+    state: ServiceState,
+    exit_code: ServiceExitCode,
+) -> Result<()> {
+    // Accept the stop controls only after all instances are started.
+    let controls_accepted = if state == ServiceState::Running {
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+    } else {
+        ServiceControlAccept::empty()
+    };
+    let status = ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code,
+        checkpoint: 0,
+        wait_hint: Duration::from_secs(30),
+        process_id: None,
+    };
+    status_handle
+        .set_service_status(status)
+        .map_err(service_error("set the status of the service"))
+}
+
+fn service_error(action: &'static str) -> impl Fn(windows_service::Error) -> Error {
+    move |err| {
+        let errmsg = format!("failed to {} since {}", action, err);
+        Error::runtime(errmsg)
+    }
+}